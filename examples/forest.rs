@@ -0,0 +1,86 @@
+use rand::{Rng, SeedableRng};
+use rand_xorshift::XorShiftRng;
+use teeracer::*;
+
+/// A cone of `sides` triangles around the y axis, from a base of `radius` at `bottom` up to `top`.
+fn cone(sides: usize, radius: f32, bottom: f32, top: f32) -> Vec<Triangle<f32>> {
+    let apex = Three::new(0.0, top, 0.0);
+    let rim = |i: usize| {
+        let angle = std::f32::consts::TAU * i as f32 / sides as f32;
+        Three::new(radius * angle.cos(), bottom, radius * angle.sin())
+    };
+    (0..sides)
+        .map(|i| Triangle::new(rim(i + 1), rim(i), apex))
+        .collect()
+}
+
+fn main() -> Result<(), image::error::ImageError> {
+    let camera = Camera::new(
+        FieldOfView::Degrees(50.0),
+        ImageShape {
+            width: 800,
+            height: 450,
+        },
+    )
+    .at(0.0, 4.0, 12.0)
+    .looking_at(Three::new(0.0, 0.0, -10.0), Three::new(0.0, 1.0, 0.0));
+
+    let mut scene = Scene::new();
+    let ground = scene.add_material(Diffuse::rgb(0.5, 0.45, 0.35));
+    let bark = scene.add_named_material("bark", Diffuse::rgb(0.3, 0.2, 0.1));
+    let needles = scene.add_named_material("needles", Diffuse::rgb(0.15, 0.45, 0.2));
+    let autumn = scene.add_named_material("autumn", Diffuse::rgb(0.8, 0.4, 0.1));
+    let sky = scene.add_material(Light::new(Three::new(0.7, 0.8, 1.0), 1.0));
+
+    // one tree, whose meshes are shared by every tree in the forest
+    let mut crown = cone(12, 0.8, 0.4, 2.0);
+    crown.extend(cone(12, 0.6, 1.2, 2.6));
+    let tree = Node::new("tree")
+        .with_child(Node::new("trunk").with_object(Mesh::new(cone(12, 0.15, 0.0, 0.6)), bark))
+        .with_child(Node::new("crown").with_object(Mesh::new(crown), needles));
+
+    let mut rng = XorShiftRng::seed_from_u64(7);
+    // 10,000 trees, with only the two meshes of the one above in memory
+    let mut forest = Node::new("forest");
+    for row in 0..100 {
+        for column in 0..100 {
+            let position = Three::new(
+                (column as f32 - 49.5) * 2.0 + rng.gen_range(-0.6..0.6),
+                -1.0,
+                -(row as f32) * 2.0 + rng.gen_range(-0.6..0.6),
+            );
+            let transform = Transform::translation(position)
+                * Transform::rotation(&Three::new(0.0, 1.0, 0.0), rng.gen_range(0.0..360.0))
+                * Transform::scaling(Three::from(rng.gen_range(0.6..1.2)));
+            forest = forest.with_child(
+                Node::new(format!("{} {}", row, column))
+                    .with_transform(transform)
+                    .with_child(tree.clone()),
+            );
+        }
+    }
+    // one tree in the front is turning
+    if let Some(crown) = forest.find_mut("1 49/tree/crown") {
+        crown.material = Some(autumn);
+    }
+    scene.add_node(&forest);
+
+    scene.add_object(
+        Plane::facing_pos_y().transformed(&Transform::translation(Three::new(0.0, -1.0, 0.0))),
+        ground,
+    );
+    scene.add_object(Sphere::new(Three::new(0.0, 0.0, 0.0), 100.0), sky);
+    scene.add_light(DirectionalLight {
+        direction: Three::new(-1.0, -1.5, -0.5),
+        rgb: Three::new(1.0, 0.95, 0.8),
+        power: 2.0,
+    });
+
+    let tracer = PathTracer {
+        depth: 6,
+        ..Default::default()
+    };
+    render::<PathTracer, f32, XorShiftRng>(tracer, scene, camera, 64).save("forest.png")?;
+
+    Ok(())
+}
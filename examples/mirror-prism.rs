@@ -18,30 +18,44 @@ fn main() -> Result<(), image::error::ImageError> {
     let mirror = scene.add_material(Mirror::perfect());
     let red = scene.add_material(Diffuse::rgb(1.0, 0.25, 0.25));
     let blue = scene.add_material(Diffuse::rgb(0.25, 0.25, 1.0));
-    let white_light = scene.add_material(Light {
-        rgb: Three::new(1.0, 1.0, 1.0),
-        power: 5.0,
-    });
+    let white_light = scene.add_material(Light::new(Three::new(1.0, 1.0, 1.0), 5.0));
 
     // lights
     scene.add_object(Sphere::unit_at(0.0, 3.0, -3.0), white_light);
 
     // objects
-    scene.add_object(
-        Prism::unit_facing_pos_z()
-            .rotated_around(&Three::new(0.0, 1.0, 0.0), 45.0)
-            .shifted(Three::new(0.0, -2.0, -2.0)),
-        mirror,
-    );
+    // turned around the middle of the prism, which starts half a unit behind the origin
+    let placement = Transform::translation(Three::new(0.0, -2.0, -2.5))
+        * Transform::rotation(&Three::new(0.0, 1.0, 0.0), 45.0)
+        * Transform::translation(Three::new(0.0, 0.0, 0.5));
+    scene.add_object(Prism::unit_facing_pos_z().transformed(&placement), mirror);
 
     // box
-    scene.add_object(Plane::facing_pos_x().shifted_back(5.0), red); // LEFT
-    scene.add_object(Plane::facing_neg_x().shifted_back(5.0), blue); // RIGHT
-    scene.add_object(Plane::facing_pos_y().shifted_back(2.0), white); // BOTTOM
-    scene.add_object(Plane::facing_neg_y().shifted_back(4.0), white); // TOP
-    scene.add_object(Plane::facing_pos_z().shifted_back(7.0), mirror); // FRONT
+    scene.add_object(
+        Plane::facing_pos_x().transformed(&Transform::translation(Three::new(-5.0, 0.0, 0.0))),
+        red,
+    ); // LEFT
+    scene.add_object(
+        Plane::facing_neg_x().transformed(&Transform::translation(Three::new(5.0, 0.0, 0.0))),
+        blue,
+    ); // RIGHT
+    scene.add_object(
+        Plane::facing_pos_y().transformed(&Transform::translation(Three::new(0.0, -2.0, 0.0))),
+        white,
+    ); // BOTTOM
+    scene.add_object(
+        Plane::facing_neg_y().transformed(&Transform::translation(Three::new(0.0, 4.0, 0.0))),
+        white,
+    ); // TOP
+    scene.add_object(
+        Plane::facing_pos_z().transformed(&Transform::translation(Three::new(0.0, 0.0, -7.0))),
+        mirror,
+    ); // FRONT
 
-    let tracer = PathTracer { depth: 10 };
+    let tracer = PathTracer {
+        depth: 10,
+        ..Default::default()
+    };
     render::<PathTracer, f32, XorShiftRng>(tracer, scene, camera, 1000).save("mirror-prism.png")?;
 
     Ok(())
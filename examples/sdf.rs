@@ -0,0 +1,76 @@
+use rand_xorshift::XorShiftRng;
+use teeracer::*;
+
+fn main() -> Result<(), image::error::ImageError> {
+    let camera = Camera::new(
+        FieldOfView::Degrees(40.0),
+        ImageShape {
+            width: 800,
+            height: 450,
+        },
+    )
+    .at(0.0, 1.0, 6.0)
+    .looking_at(Three::new(0.0, 0.3, 0.0), Three::new(0.0, 1.0, 0.0));
+
+    let mut scene = Scene::new();
+    let white = scene.add_material(Diffuse::rgb(0.8, 0.8, 0.8));
+    let orange = scene.add_material(Diffuse::rgb(0.9, 0.45, 0.15));
+    let gold = scene.add_material(Mirror::tinted(1.0, 0.8, 0.4));
+    let sky = scene.add_material(Light::new(Three::new(0.7, 0.8, 1.0), 1.0));
+
+    // blobs: a capsule melting into a rounded box, with a ring growing out of it
+    let blobs = sdf::smooth_union(
+        sdf::smooth_union(
+            sdf::round_box(Three::new(0.0, -0.5, 0.0), Three::new(0.5, 0.5, 0.5), 0.1),
+            sdf::capsule(Three::new(-0.3, 0.0, 0.3), Three::new(0.4, 0.9, -0.2), 0.25),
+            0.3,
+        ),
+        sdf::torus(Three::new(0.0, -0.5, 0.0), 0.8, 0.12),
+        0.2,
+    );
+    let blobs = sdf::smooth_subtraction(blobs, sdf::sphere(Three::new(0.5, 0.0, 0.5), 0.3), 0.1);
+    scene.add_object(
+        SdfShape::new(
+            move |p: &Three<f32>| blobs(&(*p - Three::new(-1.4, 0.0, 0.0))),
+            1.0,
+            Aabb {
+                min: Three::new(-2.4, -1.1, -1.0),
+                max: Three::new(-0.4, 1.3, 1.0),
+            },
+        ),
+        orange,
+    );
+
+    let bulb = sdf::mandelbulb(8);
+    scene.add_object(
+        SdfShape::new(
+            move |p: &Three<f32>| bulb(&(*p - Three::new(1.4, 0.2, 0.0))),
+            2.0,
+            Aabb {
+                min: Three::new(0.2, -1.0, -1.2),
+                max: Three::new(2.6, 1.4, 1.2),
+            },
+        )
+        .with_precision(1e-3),
+        gold,
+    );
+
+    scene.add_object(
+        Plane::facing_pos_y().transformed(&Transform::translation(Three::new(0.0, -1.1, 0.0))),
+        white,
+    );
+    scene.add_object(Sphere::new(Three::new(0.0, 0.0, 0.0), 100.0), sky);
+    scene.add_light(DirectionalLight {
+        direction: Three::new(-1.0, -2.0, -1.0),
+        rgb: Three::new(1.0, 0.95, 0.8),
+        power: 2.0,
+    });
+
+    let tracer = PathTracer {
+        depth: 6,
+        ..Default::default()
+    };
+    render::<PathTracer, f32, XorShiftRng>(tracer, scene, camera, 64).save("sdf.png")?;
+
+    Ok(())
+}
@@ -21,31 +21,49 @@ fn main() -> Result<(), image::error::ImageError> {
     let water = scene.add_material(Dielectric::transparent(1.33));
     let crown_glass = scene.add_material(Dielectric::transparent(1.52));
     let diamond = scene.add_material(Dielectric::transparent(2.417));
-    let white_light = scene.add_material(Light {
-        rgb: Three::new(1.0, 1.0, 1.0),
-        power: 5.0,
-    });
+    let white_light = scene.add_material(Light::new(Three::new(1.0, 1.0, 1.0), 5.0));
 
     // lights
     scene.add_object(Sphere::unit_at(0.0, 3.0, -3.0), white_light);
 
     // objects
     scene.add_object(Sphere::unit_at(-2.5, 0.5, -3.0), green_mirror);
-    scene.add_object(Sphere::unit_at(2.0, 0.5, -5.0).scaled(1.5), red);
-    scene.add_object(Sphere::unit_at(-2.0, 2.0, -6.0).scaled(2.0), blue);
-    scene.add_object(Sphere::unit_at(-1.0, -0.5, -2.5).scaled(0.5), water);
-    scene.add_object(Sphere::unit_at(0.0, -0.75, -2.5).scaled(0.5), crown_glass);
-    scene.add_object(Sphere::unit_at(1.0, -1.0, -2.5).scaled(0.5), diamond);
+    scene.add_object(Sphere::new(Three::new(2.0, 0.5, -5.0), 1.5), red);
+    scene.add_object(Sphere::new(Three::new(-2.0, 2.0, -6.0), 2.0), blue);
+    scene.add_object(Sphere::new(Three::new(-1.0, -0.5, -2.5), 0.5), water);
+    scene.add_object(Sphere::new(Three::new(0.0, -0.75, -2.5), 0.5), crown_glass);
+    scene.add_object(Sphere::new(Three::new(1.0, -1.0, -2.5), 0.5), diamond);
 
     // surrounding box
-    scene.add_object(Plane::facing_pos_x().shifted_back(5.0), red); // LEFT
-    scene.add_object(Plane::facing_neg_x().shifted_back(5.0), blue); // RIGHT
-    scene.add_object(Plane::facing_pos_y().shifted_back(2.0), white); // BOTTOM
-    scene.add_object(Plane::facing_neg_y().shifted_back(4.0), white); // TOP
-    scene.add_object(Plane::facing_pos_z().shifted_back(7.0), white); // FRONT
-    scene.add_object(Plane::facing_neg_z().shifted_back(7.0), white); // BACK
-
-    let tracer = PathTracer { depth: 10 };
+    scene.add_object(
+        Plane::facing_pos_x().transformed(&Transform::translation(Three::new(-5.0, 0.0, 0.0))),
+        red,
+    ); // LEFT
+    scene.add_object(
+        Plane::facing_neg_x().transformed(&Transform::translation(Three::new(5.0, 0.0, 0.0))),
+        blue,
+    ); // RIGHT
+    scene.add_object(
+        Plane::facing_pos_y().transformed(&Transform::translation(Three::new(0.0, -2.0, 0.0))),
+        white,
+    ); // BOTTOM
+    scene.add_object(
+        Plane::facing_neg_y().transformed(&Transform::translation(Three::new(0.0, 4.0, 0.0))),
+        white,
+    ); // TOP
+    scene.add_object(
+        Plane::facing_pos_z().transformed(&Transform::translation(Three::new(0.0, 0.0, -7.0))),
+        white,
+    ); // FRONT
+    scene.add_object(
+        Plane::facing_neg_z().transformed(&Transform::translation(Three::new(0.0, 0.0, 7.0))),
+        white,
+    ); // BACK
+
+    let tracer = PathTracer {
+        depth: 10,
+        ..Default::default()
+    };
     render::<PathTracer, f32, XorShiftRng>(tracer, scene, camera, 1000).save("spheres.png")?;
 
     Ok(())
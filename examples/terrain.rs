@@ -0,0 +1,87 @@
+use rand::{Rng, SeedableRng};
+use rand_xorshift::XorShiftRng;
+use teeracer::*;
+
+/// Rolling hills from 0 to 1, made of random waves that get smaller as they get shorter.
+fn hills(size: usize, rng: &mut XorShiftRng) -> Vec<f32> {
+    let waves: Vec<(f32, f32, f32, f32)> = (0..24)
+        .map(|i| {
+            let frequency = 1.5 * 1.25f32.powi(i);
+            let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+            let phase = rng.gen_range(0.0..std::f32::consts::TAU);
+            (
+                frequency * angle.cos(),
+                frequency * angle.sin(),
+                phase,
+                1.0 / frequency,
+            )
+        })
+        .collect();
+    let heights: Vec<f32> = (0..size * size)
+        .map(|k| {
+            let (x, z) = (
+                (k % size) as f32 / size as f32,
+                (k / size) as f32 / size as f32,
+            );
+            waves
+                .iter()
+                .map(|(fx, fz, phase, amplitude)| {
+                    amplitude * (fx * x * 6.0 + fz * z * 6.0 + phase).sin()
+                })
+                .sum()
+        })
+        .collect();
+    let (low, high) = heights
+        .iter()
+        .fold((f32::MAX, f32::MIN), |(low, high), &h| {
+            (low.min(h), high.max(h))
+        });
+    heights.iter().map(|h| (h - low) / (high - low)).collect()
+}
+
+fn main() -> Result<(), image::error::ImageError> {
+    let camera = Camera::new(
+        FieldOfView::Degrees(50.0),
+        ImageShape {
+            width: 800,
+            height: 450,
+        },
+    )
+    .at(0.0, 5.0, 12.0)
+    .looking_at(Three::new(0.0, 0.0, -6.0), Three::new(0.0, 1.0, 0.0));
+
+    let mut scene = Scene::new();
+    let grass = scene.add_material(Diffuse::rgb(0.35, 0.5, 0.2));
+    let water = scene.add_material(Dielectric::transparent(1.33));
+    let sky = scene.add_material(Light::new(Three::new(0.7, 0.8, 1.0), 1.0));
+
+    // half a million triangles' worth of terrain, without storing any of them
+    let size = 512;
+    let mut rng = XorShiftRng::seed_from_u64(3);
+    let terrain = Heightfield::new(
+        size,
+        size,
+        hills(size, &mut rng),
+        Three::new(-20.0, -2.0, -30.0),
+        Three::new(40.0, 5.0, 40.0),
+    );
+    scene.add_object(terrain, grass);
+    scene.add_object(
+        Plane::facing_pos_y().transformed(&Transform::translation(Three::new(0.0, 0.2, 0.0))),
+        water,
+    );
+    scene.add_object(Sphere::new(Three::new(0.0, 0.0, 0.0), 100.0), sky);
+    scene.add_light(DirectionalLight {
+        direction: Three::new(-1.0, -0.6, -0.4),
+        rgb: Three::new(1.0, 0.9, 0.7),
+        power: 2.0,
+    });
+
+    let tracer = PathTracer {
+        depth: 6,
+        ..Default::default()
+    };
+    render::<PathTracer, f32, XorShiftRng>(tracer, scene, camera, 64).save("terrain.png")?;
+
+    Ok(())
+}
@@ -0,0 +1,314 @@
+use crate::data::{CanHit, Ray, Three};
+use crate::scene::Scene;
+use exr::prelude::{AnyChannel, AnyChannels, FlatSamples, Image, SmallVec, WritableImage};
+use image::error::{EncodingError, ImageFormatHint};
+use image::{ImageBuffer, ImageError, ImageResult, Luma, Rgb, RgbImage};
+use num_traits::{cast, Float, FloatConst};
+use std::ops::AddAssign;
+use std::path::Path;
+
+/// Arbitrary output variables: what the camera sees first through each pixel, rendered in the
+/// same pass as the color image for compositing and denoising.
+///
+/// Albedo, normal, position and depth are averaged over the samples of a pixel that hit
+/// something. Object and material indices can't be averaged, so they come from the pixel's
+/// first sample that hit something. Pixels where nothing was hit have an infinite depth and no
+/// indices.
+#[derive(Debug, Clone)]
+pub struct Aovs<F> {
+    pub width: usize,
+    pub height: usize,
+    pub albedo: Vec<Three<F>>,
+    /// Surface normal, in world space.
+    pub normal: Vec<Three<F>>,
+    pub position: Vec<Three<F>>,
+    /// Distance from the camera, i.e. `Hit::distance`.
+    pub depth: Vec<F>,
+    pub object_index: Vec<Option<usize>>,
+    pub material_index: Vec<Option<usize>>,
+    hits: Vec<usize>,
+    /// Which sample of each pixel the indices are from, as samples can arrive in any order.
+    index_sample: Vec<usize>,
+}
+
+/// The first hit of one camera ray.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AovSample<F> {
+    albedo: Three<F>,
+    normal: Three<F>,
+    position: Three<F>,
+    depth: F,
+    object_index: usize,
+    material_index: usize,
+}
+
+impl<F> AovSample<F>
+where
+    F: Float + FloatConst,
+{
+    pub(crate) fn trace(ray: &Ray<F>, scene: &Scene<F>) -> Option<Self> {
+        let hit = ray.shoot_at(scene, F::from(1e-3f64).unwrap(), F::infinity())?;
+        Some(Self {
+            albedo: scene.material_for(hit.object_index).albedo(hit.uv),
+            normal: hit.normal,
+            position: hit.position,
+            depth: hit.distance,
+            object_index: hit.object_index,
+            material_index: scene.material_idx(hit.object_index).index(),
+        })
+    }
+}
+
+impl<F> Aovs<F>
+where
+    F: Float + AddAssign,
+{
+    pub(crate) fn new(width: usize, height: usize) -> Self {
+        let num_pixels = width * height;
+        Self {
+            width,
+            height,
+            albedo: vec![Three::zeros(); num_pixels],
+            normal: vec![Three::zeros(); num_pixels],
+            position: vec![Three::zeros(); num_pixels],
+            depth: vec![F::zero(); num_pixels],
+            object_index: vec![None; num_pixels],
+            material_index: vec![None; num_pixels],
+            hits: vec![0; num_pixels],
+            index_sample: vec![usize::MAX; num_pixels],
+        }
+    }
+
+    /// Adds a sample to the running sums of `pixel_idx`, where it's the pixel's sample number
+    /// `sample_idx`.
+    pub(crate) fn add(&mut self, pixel_idx: usize, sample: &AovSample<F>, sample_idx: usize) {
+        self.albedo[pixel_idx] += sample.albedo;
+        self.normal[pixel_idx] += sample.normal;
+        self.position[pixel_idx] += sample.position;
+        self.depth[pixel_idx] += sample.depth;
+        self.hits[pixel_idx] += 1;
+        if sample_idx < self.index_sample[pixel_idx] {
+            self.index_sample[pixel_idx] = sample_idx;
+            self.object_index[pixel_idx] = Some(sample.object_index);
+            self.material_index[pixel_idx] = Some(sample.material_index);
+        }
+    }
+
+    /// Turns the running sums into averages.
+    pub(crate) fn finish(&mut self) {
+        for pixel_idx in 0..self.hits.len() {
+            let hits = self.hits[pixel_idx];
+            if hits == 0 {
+                self.depth[pixel_idx] = F::infinity();
+                continue;
+            }
+            let hits: F = cast(hits).unwrap();
+            self.albedo[pixel_idx] = self.albedo[pixel_idx] / hits;
+            self.normal[pixel_idx] = self.normal[pixel_idx].normalized();
+            self.position[pixel_idx] = self.position[pixel_idx] / hits;
+            self.depth[pixel_idx] = self.depth[pixel_idx] / hits;
+        }
+    }
+}
+
+impl<F> Aovs<F>
+where
+    F: Float,
+    Three<F>: Into<Rgb<u8>>,
+{
+    pub fn albedo_image(&self) -> RgbImage {
+        self.rgb_image(|idx| self.albedo[idx])
+    }
+
+    /// Normals mapped from [-1, 1] to [0, 1].
+    pub fn normal_image(&self) -> RgbImage {
+        let half = F::from(0.5f64).unwrap();
+        self.rgb_image(|idx| self.normal[idx] * half + Three::new(half, half, half))
+    }
+
+    /// Positions scaled so the bounding box of everything visible fills [0, 1].
+    pub fn position_image(&self) -> RgbImage {
+        let (min, max) = self
+            .position
+            .iter()
+            .zip(self.depth.iter())
+            .filter(|(_, depth)| depth.is_finite())
+            .fold(
+                (
+                    Three::new(F::infinity(), F::infinity(), F::infinity()),
+                    Three::new(F::neg_infinity(), F::neg_infinity(), F::neg_infinity()),
+                ),
+                |(min, max), (p, _)| {
+                    (
+                        Three::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+                        Three::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)),
+                    )
+                },
+            );
+        let scale = |x: F, min: F, max: F| {
+            if max > min {
+                (x - min) / (max - min)
+            } else {
+                F::zero()
+            }
+        };
+        self.rgb_image(|idx| {
+            let p = self.position[idx];
+            Three::new(
+                scale(p.x, min.x, max.x),
+                scale(p.y, min.y, max.y),
+                scale(p.z, min.z, max.z),
+            )
+        })
+    }
+
+    /// Depth scaled so the farthest hit is white. Pixels where nothing was hit are white too.
+    pub fn depth_image(&self) -> ImageBuffer<Luma<u16>, Vec<u16>> {
+        let max_depth = self
+            .depth
+            .iter()
+            .filter(|depth| depth.is_finite())
+            .fold(F::zero(), |max, &depth| max.max(depth));
+        let white = F::from(u16::MAX).unwrap();
+        ImageBuffer::from_fn(self.width as u32, self.height as u32, |x, y| {
+            let depth = self.depth[y as usize * self.width + x as usize];
+            let value = if depth.is_finite() && max_depth > F::zero() {
+                depth / max_depth
+            } else {
+                F::one()
+            };
+            Luma([(value * white).round().to_u16().unwrap_or(u16::MAX)])
+        })
+    }
+
+    /// Object indices, offset by one so pixels where nothing was hit are 0.
+    pub fn object_index_image(&self) -> ImageBuffer<Luma<u16>, Vec<u16>> {
+        self.index_image(&self.object_index)
+    }
+
+    /// Material indices, offset by one so pixels where nothing was hit are 0.
+    pub fn material_index_image(&self) -> ImageBuffer<Luma<u16>, Vec<u16>> {
+        self.index_image(&self.material_index)
+    }
+
+    /// Positions as 32 bit floats in the red, green and blue channels of an OpenEXR file.
+    pub fn save_position_exr<P: AsRef<Path>>(&self, path: P) -> ImageResult<()> {
+        let channel = |name: &str, coordinate: fn(&Three<F>) -> F| {
+            let values = self
+                .position
+                .iter()
+                .map(|p| coordinate(p).to_f32().unwrap());
+            AnyChannel::new(name, FlatSamples::F32(values.collect()))
+        };
+        self.save_exr(
+            path,
+            vec![
+                channel("R", |p| p.x),
+                channel("G", |p| p.y),
+                channel("B", |p| p.z),
+            ],
+        )
+    }
+
+    /// Depth as 32 bit floats in the `Z` channel of an OpenEXR file, infinite where nothing was
+    /// hit.
+    pub fn save_depth_exr<P: AsRef<Path>>(&self, path: P) -> ImageResult<()> {
+        let values = self.depth.iter().map(|depth| depth.to_f32().unwrap());
+        self.save_exr(
+            path,
+            vec![AnyChannel::new("Z", FlatSamples::F32(values.collect()))],
+        )
+    }
+
+    /// Saves every buffer as a separate image in `dir`, named after the buffer. Position and
+    /// depth are saved as OpenEXR files, so they keep their full precision for compositing.
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> ImageResult<()> {
+        let dir = dir.as_ref();
+        self.albedo_image().save(dir.join("albedo.png"))?;
+        self.normal_image().save(dir.join("normal.png"))?;
+        self.save_position_exr(dir.join("position.exr"))?;
+        self.save_depth_exr(dir.join("depth.exr"))?;
+        self.object_index_image()
+            .save(dir.join("object_index.png"))?;
+        self.material_index_image()
+            .save(dir.join("material_index.png"))?;
+        Ok(())
+    }
+
+    fn save_exr<P: AsRef<Path>>(
+        &self,
+        path: P,
+        channels: Vec<AnyChannel<FlatSamples>>,
+    ) -> ImageResult<()> {
+        Image::from_channels(
+            (self.width, self.height),
+            AnyChannels::sort(SmallVec::from_vec(channels)),
+        )
+        .write()
+        .to_file(path)
+        .map_err(|err| {
+            ImageError::Encoding(EncodingError::new(
+                ImageFormatHint::Name("OpenEXR".to_string()),
+                err,
+            ))
+        })
+    }
+
+    fn rgb_image<C>(&self, color: C) -> RgbImage
+    where
+        C: Fn(usize) -> Three<F>,
+    {
+        RgbImage::from_fn(self.width as u32, self.height as u32, |x, y| {
+            color(y as usize * self.width + x as usize).into()
+        })
+    }
+
+    fn index_image(&self, indices: &[Option<usize>]) -> ImageBuffer<Luma<u16>, Vec<u16>> {
+        ImageBuffer::from_fn(self.width as u32, self.height as u32, |x, y| {
+            let idx = indices[y as usize * self.width + x as usize];
+            Luma([idx.map_or(0, |idx| (idx + 1).min(u16::MAX as usize) as u16)])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(object_index: usize) -> AovSample<f64> {
+        AovSample {
+            albedo: Three::ones(),
+            normal: Three::new(0.0, 1.0, 0.0),
+            position: Three::zeros(),
+            depth: 1.0,
+            object_index,
+            material_index: object_index,
+        }
+    }
+
+    #[test]
+    fn indices_come_from_the_first_sample_that_hits() {
+        let mut aovs = Aovs::new(1, 1);
+        // the first sample missed, and the others arrive out of order
+        aovs.add(0, &sample(2), 2);
+        aovs.add(0, &sample(1), 1);
+        aovs.add(0, &sample(3), 3);
+        aovs.finish();
+        assert_eq!(aovs.object_index[0], Some(1));
+        assert_eq!(aovs.material_index[0], Some(1));
+    }
+
+    #[test]
+    fn depth_keeps_its_precision() {
+        let mut aovs = Aovs::new(2, 1);
+        aovs.add(0, &sample(0), 0);
+        aovs.finish();
+        aovs.depth[0] = 1234.5678;
+        let path = std::env::temp_dir().join("teeracer_depth_test.exr");
+        aovs.save_depth_exr(&path).unwrap();
+        let image = exr::prelude::read_first_flat_layer_from_file(&path).unwrap();
+        let depth = &image.layer_data.channel_data.list[0].sample_data;
+        assert_eq!(depth.value_by_flat_index(0).to_f32(), 1234.5678);
+        assert_eq!(depth.value_by_flat_index(1).to_f32(), f32::INFINITY);
+    }
+}
@@ -0,0 +1,836 @@
+use crate::data::{Camera, CanHit, Hit, LightInteraction, Material, Ray, Surface, Three};
+use crate::light_sampling::Emitter;
+use crate::lights::{DeltaLight, LightSource};
+use crate::pdf::{power_heuristic, CosineHemisphereDistribution, HemisphereDistribution};
+use crate::scene::{Scene, SceneTracer};
+use crate::tracer::{dielectric_interaction, facing_normal, mirror_interaction};
+use num_traits::{Float, FloatConst};
+use rand::prelude::*;
+use rand_distr::uniform::SampleUniform;
+use rand_distr::{Distribution, Standard};
+use std::ops::{AddAssign, MulAssign};
+
+/// Traces one path from the camera and one from a light, then connects every vertex of the
+/// first to every vertex of the second. Each way of building the same path is weighted with
+/// multiple importance sampling, so light that is hard to find from the camera (e.g. through
+/// glass, or from small lights) is found from the light instead.
+///
+/// Light path vertices that connect directly to the camera land on other pixels of the image,
+/// so `trace` leaves them out, and only `render` (through `trace_with_splats`) includes them.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct BidirectionalPathTracer {
+    /// Maximum number of segments in a path, like `PathTracer::depth`.
+    pub depth: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum VertexKind {
+    Camera,
+    Light(Emitter),
+    Surface { object_index: usize },
+}
+
+/// The ray a subpath left the scene along.
+#[derive(Debug, Clone, Copy)]
+struct Escape<F> {
+    direction: Three<F>,
+    /// Throughput of the subpath, which the light arriving along `direction` is scaled by.
+    beta: Three<F>,
+    /// Solid angle density the subpath's last vertex sampled `direction` with, zero after a
+    /// mirror or dielectric.
+    pdf: F,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Vertex<F> {
+    kind: VertexKind,
+    position: Three<F>,
+    /// Zero for vertices that aren't on a surface, i.e. the camera and point lights.
+    normal: Three<F>,
+    uv: (F, F),
+    /// Unit vector pointing back along the subpath the vertex is on.
+    wo: Three<F>,
+    /// Throughput of the subpath up to and including this vertex.
+    beta: Three<F>,
+    /// Whether the vertex scattered with a mirror or dielectric, which can't be connected to.
+    delta: bool,
+    /// Area density of sampling this vertex from the previous vertex of its subpath.
+    pdf_fwd: F,
+    /// Area density of sampling this vertex if the path was traced in the other direction.
+    pdf_rev: F,
+}
+
+impl<F> Vertex<F>
+where
+    F: Float + FloatConst + SampleUniform,
+    Standard: Distribution<F>,
+{
+    fn camera(position: Three<F>, beta: Three<F>) -> Self {
+        Self {
+            kind: VertexKind::Camera,
+            position,
+            normal: Three::zeros(),
+            uv: (F::zero(), F::zero()),
+            wo: Three::zeros(),
+            beta,
+            delta: false,
+            pdf_fwd: F::zero(),
+            pdf_rev: F::zero(),
+        }
+    }
+
+    fn light(emitter: Emitter, position: Three<F>, normal: Three<F>, beta: Three<F>) -> Self {
+        Self {
+            kind: VertexKind::Light(emitter),
+            normal,
+            ..Self::camera(position, beta)
+        }
+    }
+
+    fn surface(hit: &Hit<F>, wo: Three<F>, beta: Three<F>) -> Self {
+        Self {
+            kind: VertexKind::Surface {
+                object_index: hit.object_index,
+            },
+            position: hit.position,
+            normal: hit.normal,
+            uv: hit.uv,
+            wo,
+            beta,
+            delta: false,
+            pdf_fwd: F::zero(),
+            pdf_rev: F::zero(),
+        }
+    }
+
+    fn is_on_surface(&self) -> bool {
+        self.normal != Three::zeros()
+    }
+
+    fn is_delta_light(&self) -> bool {
+        matches!(self.kind, VertexKind::Light(Emitter::Delta(_)))
+    }
+
+    /// The light at this vertex, including emissive objects hit by a subpath.
+    fn emitter(&self, scene: &Scene<F>) -> Option<Emitter> {
+        match self.kind {
+            VertexKind::Camera => None,
+            VertexKind::Light(emitter) => Some(emitter),
+            VertexKind::Surface { object_index } => {
+                scene.emissive_index(object_index).map(Emitter::Area)
+            }
+        }
+    }
+
+    /// Whether a path can be joined at this vertex by a connecting segment.
+    fn is_connectible(&self, scene: &Scene<F>) -> bool {
+        match self.kind {
+            VertexKind::Camera => true,
+            VertexKind::Light(Emitter::Area(_)) => true,
+            VertexKind::Light(Emitter::Delta(light_idx)) => {
+                !matches!(scene.lights()[light_idx], DeltaLight::Directional(_))
+            }
+            VertexKind::Surface { object_index } => {
+                matches!(scene.material_for(object_index), Material::Diffuse(_))
+            }
+        }
+    }
+
+    /// BSDF for light scattered from `wo` towards `next`.
+    fn f(&self, scene: &Scene<F>, next: &Vertex<F>) -> Three<F> {
+        let object_index = match self.kind {
+            VertexKind::Surface { object_index } => object_index,
+            _ => return Three::zeros(),
+        };
+        self.f_towards(
+            scene,
+            object_index,
+            &(next.position - self.position).normalized(),
+        )
+    }
+
+    fn f_towards(&self, scene: &Scene<F>, object_index: usize, wi: &Three<F>) -> Three<F> {
+        match scene.material_for(object_index) {
+            Material::Diffuse(diffuse) if same_side(&self.normal, &self.wo, wi) => {
+                diffuse.albedo(self.uv) * F::FRAC_1_PI()
+            }
+            _ => Three::zeros(),
+        }
+    }
+
+    /// Converts the solid angle density `pdf` of sampling `next` from this vertex to an area
+    /// density at `next`.
+    fn convert_density(&self, pdf: F, next: &Vertex<F>) -> F {
+        let w = next.position - self.position;
+        let inv_distance_squared = w.length_squared().recip();
+        let mut pdf = pdf * inv_distance_squared;
+        if next.is_on_surface() {
+            pdf = pdf * next.normal.dot(&(w * inv_distance_squared.sqrt())).abs();
+        }
+        pdf
+    }
+
+    /// Area density of this vertex sampling `next` at `time`, when it was reached from `prev`.
+    fn pdf(
+        &self,
+        scene: &Scene<F>,
+        camera: Option<&Camera<F>>,
+        time: F,
+        prev: Option<&Vertex<F>>,
+        next: &Vertex<F>,
+    ) -> F {
+        let wn = (next.position - self.position).normalized();
+        let pdf = match self.kind {
+            VertexKind::Light(_) => return self.pdf_light(scene, time, next),
+            VertexKind::Camera => camera.map_or(F::zero(), |camera| camera.direction_pdf(&wn)),
+            VertexKind::Surface { object_index } => {
+                let wp = prev.map_or(self.wo, |prev| (prev.position - self.position).normalized());
+                match scene.material_for(object_index) {
+                    Material::Diffuse(_) if same_side(&self.normal, &wp, &wn) => {
+                        wn.dot(&self.normal).abs() * F::FRAC_1_PI()
+                    }
+                    _ => F::zero(),
+                }
+            }
+        };
+        self.convert_density(pdf, next)
+    }
+
+    /// Area density of the light at this vertex emitting towards `next` at `time`.
+    fn pdf_light(&self, scene: &Scene<F>, time: F, next: &Vertex<F>) -> F {
+        let emitter = match self.emitter(scene) {
+            Some(emitter) => emitter,
+            None => return F::zero(),
+        };
+        let w = next.position - self.position;
+        let inv_distance_squared = w.length_squared().recip();
+        let w = w * inv_distance_squared.sqrt();
+        let (_, pdf_direction) = scene.emission_pdf(emitter, &self.position, &w, time);
+        let mut pdf = pdf_direction * inv_distance_squared;
+        if next.is_on_surface() {
+            pdf = pdf * next.normal.dot(&w).abs();
+        }
+        pdf
+    }
+
+    /// Area density of a light path starting at this vertex at `time`.
+    fn pdf_light_origin(&self, scene: &Scene<F>, time: F) -> F {
+        match self.emitter(scene) {
+            Some(emitter) => {
+                let (pdf_position, _) =
+                    scene.emission_pdf(emitter, &self.position, &self.normal, time);
+                scene.emitter_pmf(emitter) * pdf_position
+            }
+            None => F::zero(),
+        }
+    }
+
+    /// Radiance emitted from this vertex towards `towards`.
+    fn le(&self, scene: &Scene<F>, towards: &Vertex<F>) -> Three<F> {
+        let object_index = match self.kind {
+            VertexKind::Surface { object_index } => object_index,
+            _ => return Three::zeros(),
+        };
+        let direction = (self.position - towards.position).normalized();
+        match scene.material_for(object_index) {
+            Material::Light(light) if light.emits_towards(&direction, &self.normal) => {
+                light.radiance(scene.object(object_index).area(), self.uv)
+            }
+            _ => Three::zeros(),
+        }
+    }
+}
+
+fn same_side<F: Float>(normal: &Three<F>, a: &Three<F>, b: &Three<F>) -> bool {
+    (a.dot(normal) > F::zero()) == (b.dot(normal) > F::zero())
+}
+
+fn is_black<F: Float>(c: &Three<F>) -> bool {
+    c.x <= F::zero() && c.y <= F::zero() && c.z <= F::zero()
+}
+
+fn visible<F>(scene: &Scene<F>, a: &Three<F>, b: &Three<F>, time: F) -> bool
+where
+    F: Float + FloatConst,
+{
+    let t_min = F::from(1e-3f64).unwrap();
+    let to_b = b - a;
+    let distance = to_b.length();
+    let ray = Ray {
+        origin: *a,
+        direction: to_b / distance,
+        time,
+    };
+    ray.shoot_at(scene, t_min, distance - t_min).is_none()
+}
+
+impl<F> SceneTracer<F> for BidirectionalPathTracer
+where
+    F: Float + SampleUniform + MulAssign + AddAssign + FloatConst,
+    Standard: Distribution<F>,
+{
+    fn trace<R>(&self, ray: Ray<F>, scene: &Scene<F>, rng: &mut R) -> Option<Three<F>>
+    where
+        R: Rng,
+    {
+        Some(self.li(ray, scene, None, rng, &mut Vec::new()))
+    }
+
+    fn trace_with_splats<R>(
+        &self,
+        ray: Ray<F>,
+        scene: &Scene<F>,
+        camera: &Camera<F>,
+        rng: &mut R,
+        splats: &mut Vec<(usize, Three<F>)>,
+    ) -> Option<Three<F>>
+    where
+        R: Rng,
+    {
+        Some(self.li(ray, scene, Some(camera), rng, splats))
+    }
+}
+
+impl BidirectionalPathTracer {
+    fn li<F, R>(
+        &self,
+        ray: Ray<F>,
+        scene: &Scene<F>,
+        camera: Option<&Camera<F>>,
+        rng: &mut R,
+        splats: &mut Vec<(usize, Three<F>)>,
+    ) -> Three<F>
+    where
+        R: Rng,
+        F: Float + SampleUniform + MulAssign + AddAssign + FloatConst,
+        Standard: Distribution<F>,
+    {
+        let mut radiance = Three::zeros();
+        if self.depth == 0 {
+            return radiance;
+        }
+        let max_bounces = self.depth - 1;
+
+        // both subpaths see the scene at the time of the camera ray
+        let time = ray.time;
+        let mut camera_path = vec![Vertex::camera(ray.origin, Three::ones())];
+        let pdf_direction = camera.map_or(F::one(), |camera| camera.direction_pdf(&ray.direction));
+        let escape = random_walk(
+            scene,
+            ray,
+            Three::ones(),
+            pdf_direction,
+            self.depth,
+            rng,
+            &mut camera_path,
+        );
+
+        if let Some(escape) = escape {
+            radiance += escaped_light(scene, &camera_path, &escape);
+        }
+
+        let mut light_path = Vec::new();
+        light_subpath(scene, max_bounces, time, rng, &mut light_path);
+
+        for t in 1..=camera_path.len() {
+            if t >= 2 && t - 1 <= max_bounces {
+                let vertex = &camera_path[t - 1];
+                radiance += directional_light_contribution(scene, vertex, time, rng)
+                    + environment_light_contribution(scene, vertex, time, rng);
+            }
+            for s in 0..=light_path.len() {
+                if (s == 1 && t == 1) || s + t < 2 || s + t - 2 > max_bounces {
+                    continue;
+                }
+                let path = Connection {
+                    scene,
+                    camera,
+                    time,
+                    light_path: &light_path[..s],
+                    camera_path: &camera_path[..t],
+                };
+                match path.connect(rng) {
+                    Some((contribution, None)) => radiance += contribution,
+                    Some((contribution, Some(pixel_idx))) => splats.push((pixel_idx, contribution)),
+                    None => {}
+                }
+            }
+        }
+        radiance
+    }
+}
+
+/// Starts a subpath at a light chosen proportional to its power.
+fn light_subpath<F, R>(
+    scene: &Scene<F>,
+    max_bounces: usize,
+    time: F,
+    rng: &mut R,
+    path: &mut Vec<Vertex<F>>,
+) where
+    R: Rng,
+    F: Float + SampleUniform + MulAssign + FloatConst,
+    Standard: Distribution<F>,
+{
+    let (emitter, pmf) = match scene.sample_emitter(Standard.sample(rng)) {
+        Some(choice) => choice,
+        None => return,
+    };
+    let sample = match scene.sample_emission(emitter, time, rng) {
+        Some(sample) => sample,
+        None => return,
+    };
+    if sample.pdf_position <= F::zero()
+        || sample.pdf_direction <= F::zero()
+        || is_black(&sample.radiance)
+    {
+        return;
+    }
+    let normal = sample.normal.unwrap_or_else(Three::zeros);
+    let mut vertex = Vertex::light(emitter, sample.origin, normal, sample.radiance);
+    vertex.pdf_fwd = pmf * sample.pdf_position;
+    path.push(vertex);
+
+    let cos_theta = sample
+        .normal
+        .map_or(F::one(), |n| n.dot(&sample.direction).abs());
+    let beta = sample.radiance * (cos_theta / (pmf * sample.pdf_position * sample.pdf_direction));
+    let ray = Ray {
+        origin: sample.origin,
+        direction: sample.direction,
+        time,
+    };
+    random_walk(
+        scene,
+        ray,
+        beta,
+        sample.pdf_direction,
+        max_bounces,
+        rng,
+        path,
+    );
+}
+
+/// Extends `path` by following `ray` for at most `max_vertices` surface hits. `pdf` is the solid
+/// angle density the last vertex of `path` sampled `ray` with. Returns the ray that left the
+/// scene, if the walk ended that way.
+fn random_walk<F, R>(
+    scene: &Scene<F>,
+    mut ray: Ray<F>,
+    mut beta: Three<F>,
+    mut pdf: F,
+    max_vertices: usize,
+    rng: &mut R,
+    path: &mut Vec<Vertex<F>>,
+) -> Option<Escape<F>>
+where
+    R: Rng,
+    F: Float + SampleUniform + MulAssign + FloatConst,
+    Standard: Distribution<F>,
+{
+    let t_min = F::from(1e-3f64).unwrap();
+    let t_max = F::infinity();
+    for _ in 0..max_vertices {
+        let hit = match ray.shoot_at(scene, t_min, t_max) {
+            Some(hit) => hit,
+            None => {
+                return Some(Escape {
+                    direction: ray.direction,
+                    beta,
+                    pdf,
+                })
+            }
+        };
+        let prev = path.len() - 1;
+        let mut vertex = Vertex::surface(&hit, -ray.direction, beta);
+        vertex.pdf_fwd = path[prev].convert_density(pdf, &vertex);
+        path.push(vertex);
+
+        let (direction, pdf_fwd, pdf_rev) = match scene.material_for(hit.object_index) {
+            Material::Diffuse(diffuse) => {
+                let normal = facing_normal(&ray.direction, &hit.normal);
+                let dist = CosineHemisphereDistribution::oriented_towards(normal);
+                let direction = dist.sample(rng);
+                // f * cos_theta / pdf
+                beta *= diffuse.albedo(hit.uv);
+                (direction, dist.pdf(&direction), dist.pdf(&-ray.direction))
+            }
+            material => {
+                let interaction = match material {
+                    Material::Mirror(m) => mirror_interaction(m, &ray.direction, &hit.normal),
+                    Material::Dielectric(m) => {
+                        dielectric_interaction(m, &ray.direction, &hit.normal, rng)
+                    }
+                    // lights absorb everything that hits them
+                    _ => break,
+                };
+                match interaction {
+                    LightInteraction::Scatter {
+                        direction,
+                        attenuation,
+                    } => {
+                        beta *= attenuation;
+                        path[prev + 1].delta = true;
+                        (direction, F::zero(), F::zero())
+                    }
+                    LightInteraction::Emit { .. } => break,
+                }
+            }
+        };
+        if is_black(&beta) {
+            break;
+        }
+        path[prev].pdf_rev = path[prev + 1].convert_density(pdf_rev, &path[prev]);
+        pdf = pdf_fwd;
+        ray = Ray {
+            origin: hit.position,
+            direction,
+            time: ray.time,
+        };
+    }
+    None
+}
+
+/// Light from the environment at the end of a camera subpath that left the scene, weighted
+/// against `environment_light_contribution` finding it from the last vertex.
+fn escaped_light<F>(scene: &Scene<F>, camera_path: &[Vertex<F>], escape: &Escape<F>) -> Three<F>
+where
+    F: Float + FloatConst + SampleUniform,
+    Standard: Distribution<F>,
+{
+    let environment = match scene.environment() {
+        Some(environment) => environment,
+        None => return Three::zeros(),
+    };
+    // the camera and mirrors can't be lit by sampling the environment
+    let last = &camera_path[camera_path.len() - 1];
+    let weight = match (scene.environment_sampler(), last.kind) {
+        (Some(sampler), VertexKind::Surface { .. }) if escape.pdf > F::zero() => {
+            power_heuristic(escape.pdf, sampler.pdf(environment, &escape.direction))
+        }
+        _ => F::one(),
+    };
+    escape.beta * environment.radiance(&escape.direction) * weight
+}
+
+/// Light from the environment at a camera subpath vertex, sampled by the scene's
+/// `EnvironmentSampler`. Light subpaths don't start from the environment, so this and camera
+/// subpaths escaping to it are the only strategies that find it, and they're weighted against
+/// each other alone.
+fn environment_light_contribution<F, R>(
+    scene: &Scene<F>,
+    vertex: &Vertex<F>,
+    time: F,
+    rng: &mut R,
+) -> Three<F>
+where
+    R: Rng,
+    F: Float + SampleUniform + FloatConst,
+    Standard: Distribution<F>,
+{
+    let object_index = match vertex.kind {
+        VertexKind::Surface { object_index } => object_index,
+        _ => return Three::zeros(),
+    };
+    let (environment, sampler) = match (scene.environment(), scene.environment_sampler()) {
+        (Some(environment), Some(sampler)) => (environment, sampler),
+        _ => return Three::zeros(),
+    };
+    let sample = match sampler.sample(environment, rng) {
+        Some(sample) => sample,
+        None => return Three::zeros(),
+    };
+    let f = vertex.f_towards(scene, object_index, &sample.direction);
+    if is_black(&f) {
+        return Three::zeros();
+    }
+    let shadow_ray = Ray {
+        origin: vertex.position,
+        direction: sample.direction,
+        time,
+    };
+    if shadow_ray
+        .shoot_at(scene, F::from(1e-3f64).unwrap(), F::infinity())
+        .is_some()
+    {
+        return Three::zeros();
+    }
+    let cos_theta = sample.direction.dot(&vertex.normal).abs();
+    let bsdf_pdf = cos_theta * F::FRAC_1_PI();
+    vertex.beta * f * sample.radiance * (cos_theta * power_heuristic(sample.pdf, bsdf_pdf))
+        / sample.pdf
+}
+
+/// Light from directional lights at a camera subpath vertex. No other strategy can find these
+/// lights, so it needs no weighting.
+fn directional_light_contribution<F, R>(
+    scene: &Scene<F>,
+    vertex: &Vertex<F>,
+    time: F,
+    rng: &mut R,
+) -> Three<F>
+where
+    R: Rng,
+    F: Float + SampleUniform + AddAssign + FloatConst,
+    Standard: Distribution<F>,
+{
+    let mut radiance = Three::zeros();
+    let object_index = match vertex.kind {
+        VertexKind::Surface { object_index } => object_index,
+        _ => return radiance,
+    };
+    for light in scene.lights() {
+        if !matches!(light, DeltaLight::Directional(_)) {
+            continue;
+        }
+        let sample = match light.sample_incident(&vertex.position, rng) {
+            Some(sample) => sample,
+            None => continue,
+        };
+        let f = vertex.f_towards(scene, object_index, &sample.direction);
+        if is_black(&f) {
+            continue;
+        }
+        let shadow_ray = Ray {
+            origin: vertex.position,
+            direction: sample.direction,
+            time,
+        };
+        if shadow_ray
+            .shoot_at(scene, F::from(1e-3f64).unwrap(), sample.distance)
+            .is_none()
+        {
+            let cos_theta = sample.direction.dot(&vertex.normal).abs();
+            radiance += vertex.beta * f * sample.radiance * (cos_theta / sample.pdf);
+        }
+    }
+    radiance
+}
+
+/// The first `s` vertices of a light subpath joined to the first `t` of a camera subpath.
+struct Connection<'a, F> {
+    scene: &'a Scene<F>,
+    camera: Option<&'a Camera<F>>,
+    /// When both subpaths were traced.
+    time: F,
+    light_path: &'a [Vertex<F>],
+    camera_path: &'a [Vertex<F>],
+}
+
+impl<'a, F> Connection<'a, F>
+where
+    F: Float + SampleUniform + MulAssign + AddAssign + FloatConst,
+    Standard: Distribution<F>,
+{
+    /// Weighted contribution of the path, and the pixel it lands on if that isn't the pixel the
+    /// camera subpath went through.
+    fn connect<R: Rng>(&self, rng: &mut R) -> Option<(Three<F>, Option<usize>)> {
+        let scene = self.scene;
+        let (s, t) = (self.light_path.len(), self.camera_path.len());
+        let pt = &self.camera_path[t - 1];
+        let mut sampled = None;
+        let mut pixel_idx = None;
+
+        let radiance = if s == 0 {
+            // the camera subpath found a light by itself
+            pt.le(scene, &self.camera_path[t - 2]) * pt.beta
+        } else if t == 1 {
+            // connect the light subpath to the camera
+            let camera = self.camera?;
+            let qs = &self.light_path[s - 1];
+            if !qs.is_connectible(scene) {
+                return None;
+            }
+            let (x_screen, y_screen) = camera.raster_position(&qs.position)?;
+            let to_camera = pt.position - qs.position;
+            let distance_squared = to_camera.length_squared();
+            let wi = to_camera.normalized();
+            let cos_camera = (-wi).dot(&camera.forward());
+            let pdf = distance_squared / cos_camera;
+            let vertex = Vertex::camera(pt.position, Three::from(camera.importance(&-wi) / pdf));
+            let mut radiance = qs.beta * qs.f(scene, &vertex) * vertex.beta;
+            if qs.is_on_surface() {
+                radiance *= wi.dot(&qs.normal).abs();
+            }
+            if is_black(&radiance) || !visible(scene, &qs.position, &pt.position, self.time) {
+                return None;
+            }
+            pixel_idx = Some(camera.pixel_index(x_screen, y_screen));
+            sampled = Some(vertex);
+            radiance
+        } else if s == 1 {
+            // sample a new point on a light, like `PathTracer` does
+            if !pt.is_connectible(scene) {
+                return None;
+            }
+            let vertex = self.sample_light(pt, rng)?;
+            let wi = (vertex.position - pt.position).normalized();
+            let mut radiance = pt.beta * pt.f(scene, &vertex) * vertex.beta;
+            if pt.is_on_surface() {
+                radiance *= wi.dot(&pt.normal).abs();
+            }
+            if is_black(&radiance) || !visible(scene, &pt.position, &vertex.position, self.time) {
+                return None;
+            }
+            sampled = Some(vertex);
+            radiance
+        } else {
+            let qs = &self.light_path[s - 1];
+            if !qs.is_connectible(scene) || !pt.is_connectible(scene) {
+                return None;
+            }
+            let radiance = qs.beta * qs.f(scene, pt) * pt.f(scene, qs) * pt.beta;
+            if is_black(&radiance) {
+                return None;
+            }
+            radiance * self.geometry_term(qs, pt)?
+        };
+        if is_black(&radiance) {
+            return None;
+        }
+        Some((radiance * self.mis_weight(sampled), pixel_idx))
+    }
+
+    /// A light vertex sampled as seen from `pt`, with the throughput of the connection in `beta`.
+    fn sample_light<R: Rng>(&self, pt: &Vertex<F>, rng: &mut R) -> Option<Vertex<F>> {
+        let scene = self.scene;
+        let (emitter, pmf) = scene.sample_emitter(Standard.sample(rng))?;
+        let mut vertex = match emitter {
+            Emitter::Area(light_idx) => {
+                let (obj_idx, obj) = &scene.emissive_objects()[light_idx];
+                let light = match scene.material_for(*obj_idx) {
+                    Material::Light(light) => light,
+                    _ => unreachable!(),
+                };
+                let placed = obj.at_time(self.time);
+                let point = placed.sample_point_on_surface(rng);
+                let normal = placed.normal_at_point(&point);
+                let to_light = point - pt.position;
+                let distance_squared = to_light.length_squared();
+                let wi = to_light.normalized();
+                let cos_light = wi.dot(&normal).abs();
+                if cos_light <= F::zero() || !light.emits_towards(&wi, &normal) {
+                    return None;
+                }
+                let emission = light.radiance(obj.area(), placed.uv_at_point(&point));
+                let pdf = distance_squared / (cos_light * obj.area());
+                Vertex::light(emitter, point, normal, emission / (pdf * pmf))
+            }
+            Emitter::Delta(light_idx) => {
+                let sample = scene.lights()[light_idx].sample_incident(&pt.position, rng)?;
+                let point = pt.position + sample.direction * sample.distance;
+                let beta = sample.radiance / (sample.pdf * pmf);
+                Vertex::light(emitter, point, Three::zeros(), beta)
+            }
+        };
+        vertex.pdf_fwd = vertex.pdf_light_origin(scene, self.time);
+        Some(vertex)
+    }
+
+    fn geometry_term(&self, a: &Vertex<F>, b: &Vertex<F>) -> Option<F> {
+        if !visible(self.scene, &a.position, &b.position, self.time) {
+            return None;
+        }
+        let d = b.position - a.position;
+        let inv_distance_squared = d.length_squared().recip();
+        let d = d * inv_distance_squared.sqrt();
+        let mut g = inv_distance_squared;
+        if a.is_on_surface() {
+            g *= a.normal.dot(&d).abs();
+        }
+        if b.is_on_surface() {
+            g *= b.normal.dot(&d).abs();
+        }
+        Some(g)
+    }
+
+    /// Power heuristic weight of this strategy against every other way of sampling the same path,
+    /// where `sampled` replaces the end of the subpath with only one vertex.
+    fn mis_weight(&self, sampled: Option<Vertex<F>>) -> F {
+        let scene = self.scene;
+        let (s, t) = (self.light_path.len(), self.camera_path.len());
+        if s + t == 2 {
+            return F::one();
+        }
+        let mut light_path = self.light_path.to_vec();
+        let mut camera_path = self.camera_path.to_vec();
+        match (sampled, s, t) {
+            (Some(vertex), 1, _) => light_path[0] = vertex,
+            (Some(vertex), _, 1) => camera_path[0] = vertex,
+            _ => {}
+        }
+        // only this strategy could have found lights no light path can start from
+        if s == 0 && camera_path[t - 1].pdf_light_origin(scene, self.time) == F::zero() {
+            return F::one();
+        }
+
+        // reverse densities of the vertices around the connection, for this particular path
+        let pt = &camera_path[t - 1];
+        let pt_minus = if t > 1 {
+            Some(&camera_path[t - 2])
+        } else {
+            None
+        };
+        let qs = if s > 0 {
+            Some(&light_path[s - 1])
+        } else {
+            None
+        };
+        let qs_minus = if s > 1 {
+            Some(&light_path[s - 2])
+        } else {
+            None
+        };
+        let pt_rev = match qs {
+            Some(qs) => qs.pdf(scene, self.camera, self.time, qs_minus, pt),
+            None => pt.pdf_light_origin(scene, self.time),
+        };
+        let pt_minus_rev = pt_minus.map(|pt_minus| match qs {
+            Some(qs) => pt.pdf(scene, self.camera, self.time, Some(qs), pt_minus),
+            None => pt.pdf_light(scene, self.time, pt_minus),
+        });
+        let qs_rev = qs.map(|qs| pt.pdf(scene, self.camera, self.time, pt_minus, qs));
+        let qs_minus_rev = qs_minus.map(|qs_minus| {
+            light_path[s - 1].pdf(scene, self.camera, self.time, Some(pt), qs_minus)
+        });
+
+        camera_path[t - 1].pdf_rev = pt_rev;
+        camera_path[t - 1].delta = false;
+        if let Some(pdf) = pt_minus_rev {
+            camera_path[t - 2].pdf_rev = pdf;
+        }
+        if let Some(pdf) = qs_rev {
+            light_path[s - 1].pdf_rev = pdf;
+            light_path[s - 1].delta = false;
+        }
+        if let Some(pdf) = qs_minus_rev {
+            light_path[s - 2].pdf_rev = pdf;
+        }
+
+        // delta vertices have zero densities, which cancel out in the ratios
+        let remap0 = |pdf: F| if pdf != F::zero() { pdf } else { F::one() };
+        let mut sum_ri = F::zero();
+        let mut ri = F::one();
+        for i in (1..t).rev() {
+            ri = ri * remap0(camera_path[i].pdf_rev) / remap0(camera_path[i].pdf_fwd);
+            // connecting to the camera is only possible when there's a camera to splat onto
+            let possible = i > 1 || self.camera.is_some();
+            if possible && !camera_path[i].delta && !camera_path[i - 1].delta {
+                sum_ri += ri * ri;
+            }
+        }
+        ri = F::one();
+        for i in (0..s).rev() {
+            ri = ri * remap0(light_path[i].pdf_rev) / remap0(light_path[i].pdf_fwd);
+            let delta_light_vertex = if i > 0 {
+                light_path[i - 1].delta
+            } else {
+                light_path[0].is_delta_light()
+            };
+            if !light_path[i].delta && !delta_light_vertex {
+                sum_ri += ri * ri;
+            }
+        }
+        (F::one() + sum_ri).recip()
+    }
+}
@@ -0,0 +1,349 @@
+use crate::data::{Aabb, Hit, Ray, Three};
+use crate::debug::count_intersection_test;
+use num_traits::Float;
+
+/// Bounding volume hierarchy over anything with bounds, so rays only test the primitives whose
+/// boxes they pass through instead of all of them.
+#[derive(Debug, Clone)]
+pub(crate) struct Bvh<F> {
+    nodes: Vec<BvhNode<F>>,
+    /// Primitive indices, ordered so every leaf covers a contiguous range.
+    indices: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct BvhNode<F> {
+    bounds: Aabb<F>,
+    kind: BvhNodeKind,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum BvhNodeKind {
+    Leaf {
+        first: usize,
+        count: usize,
+    },
+    /// The left child directly follows its parent.
+    Interior {
+        right: usize,
+        axis: usize,
+    },
+}
+
+const MAX_LEAF_SIZE: usize = 4;
+const NUM_BINS: usize = 12;
+
+impl<F> Bvh<F>
+where
+    F: Float,
+{
+    /// Builds a hierarchy over primitives with the given bounds, split by the surface area
+    /// heuristic.
+    pub(crate) fn new(bounds: &[Aabb<F>]) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(2 * bounds.len()),
+            indices: (0..bounds.len()).collect(),
+        };
+        if !bounds.is_empty() {
+            let centers: Vec<Three<F>> = bounds.iter().map(|b| b.center()).collect();
+            bvh.build(bounds, &centers, 0, bounds.len());
+        }
+        bvh
+    }
+
+    /// Bounds of everything in the hierarchy.
+    pub(crate) fn bounds(&self) -> Aabb<F> {
+        self.nodes.first().map_or(Aabb::empty(), |node| node.bounds)
+    }
+
+    fn build(&mut self, bounds: &[Aabb<F>], centers: &[Three<F>], first: usize, end: usize) {
+        let node_idx = self.nodes.len();
+        let node_bounds = self.indices[first..end]
+            .iter()
+            .fold(Aabb::empty(), |aabb, &i| aabb.union(&bounds[i]));
+        self.nodes.push(BvhNode {
+            bounds: node_bounds,
+            kind: BvhNodeKind::Leaf {
+                first,
+                count: end - first,
+            },
+        });
+        if end - first <= MAX_LEAF_SIZE {
+            return;
+        }
+
+        let center_bounds = self.indices[first..end]
+            .iter()
+            .fold(Aabb::empty(), |aabb, &i| aabb.including(&centers[i]));
+        let axis = center_bounds.longest_axis();
+        let (lo, hi) = (center_bounds.min.axis(axis), center_bounds.max.axis(axis));
+        if hi <= lo {
+            // every center is in the same place, so no split separates anything
+            return;
+        }
+        let num_bins = F::from(NUM_BINS).unwrap();
+        let bin_of = |i: usize| {
+            let t = (centers[i].axis(axis) - lo) / (hi - lo) * num_bins;
+            t.to_usize().unwrap_or(0).min(NUM_BINS - 1)
+        };
+
+        let mut bin_bounds = [Aabb::empty(); NUM_BINS];
+        let mut bin_counts = [0usize; NUM_BINS];
+        for &i in &self.indices[first..end] {
+            let bin = bin_of(i);
+            bin_bounds[bin] = bin_bounds[bin].union(&bounds[i]);
+            bin_counts[bin] += 1;
+        }
+
+        // cost of splitting after each bin, up to a constant factor: primitives on each side
+        // times the chance of a ray hitting that side's box
+        let mut best: Option<(usize, F)> = None;
+        for split in 1..NUM_BINS {
+            let (left, left_count) = merge_bins(&bin_bounds[..split], &bin_counts[..split]);
+            let (right, right_count) = merge_bins(&bin_bounds[split..], &bin_counts[split..]);
+            if left_count == 0 || right_count == 0 {
+                continue;
+            }
+            let cost = surface_area(&left) * F::from(left_count).unwrap()
+                + surface_area(&right) * F::from(right_count).unwrap();
+            if best.is_none_or(|(_, best_cost)| cost < best_cost) {
+                best = Some((split, cost));
+            }
+        }
+        let split = match best {
+            Some((split, cost)) => {
+                let leaf_cost = surface_area(&node_bounds) * F::from(end - first).unwrap();
+                if cost >= leaf_cost && end - first <= 4 * MAX_LEAF_SIZE {
+                    return;
+                }
+                split
+            }
+            None => return,
+        };
+
+        let mid = first + partition(&mut self.indices[first..end], |&i| bin_of(i) < split);
+        self.build(bounds, centers, first, mid);
+        let right = self.nodes.len();
+        self.build(bounds, centers, mid, end);
+        self.nodes[node_idx].kind = BvhNodeKind::Interior { right, axis };
+    }
+
+    /// Updates the boxes of the hierarchy for primitives that moved, keeping its structure. This
+    /// is much faster than building it again, but rays slow down as the primitives move further
+    /// from where they were when it was built.
+    pub(crate) fn refit(&mut self, bounds: &[Aabb<F>]) {
+        // children come after their parents, so going backwards updates them first
+        for node_idx in (0..self.nodes.len()).rev() {
+            self.nodes[node_idx].bounds = match self.nodes[node_idx].kind {
+                BvhNodeKind::Leaf { first, count } => self.indices[first..first + count]
+                    .iter()
+                    .fold(Aabb::empty(), |aabb, &i| aabb.union(&bounds[i])),
+                BvhNodeKind::Interior { right, .. } => self.nodes[node_idx + 1]
+                    .bounds
+                    .union(&self.nodes[right].bounds),
+            };
+        }
+    }
+
+    /// Finds the closest hit along `ray`, calling `hit_primitive(primitive_idx, t_max)` for
+    /// every primitive whose box the ray passes through closer than the closest hit so far.
+    pub(crate) fn shoot<H>(
+        &self,
+        ray: &Ray<F>,
+        t_min: F,
+        mut t_max: F,
+        mut hit_primitive: H,
+    ) -> Option<Hit<F>>
+    where
+        H: FnMut(usize, F) -> Option<Hit<F>>,
+    {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let inv_direction = Three::new(
+            ray.direction.x.recip(),
+            ray.direction.y.recip(),
+            ray.direction.z.recip(),
+        );
+        let mut closest = None;
+        let mut stack = vec![0];
+        while let Some(node_idx) = stack.pop() {
+            let node = &self.nodes[node_idx];
+            count_intersection_test();
+            if !hits_box(&node.bounds, ray, &inv_direction, t_min, t_max) {
+                continue;
+            }
+            match node.kind {
+                BvhNodeKind::Leaf { first, count } => {
+                    for &i in &self.indices[first..first + count] {
+                        count_intersection_test();
+                        if let Some(hit) = hit_primitive(i, t_max) {
+                            t_max = hit.distance;
+                            closest = Some(hit);
+                        }
+                    }
+                }
+                BvhNodeKind::Interior { right, axis } => {
+                    // visit the near child first, so hits there can cull the far one
+                    if inv_direction.axis(axis) < F::zero() {
+                        stack.push(node_idx + 1);
+                        stack.push(right);
+                    } else {
+                        stack.push(right);
+                        stack.push(node_idx + 1);
+                    }
+                }
+            }
+        }
+        closest
+    }
+
+    /// Calls `visit` with every primitive whose box is within `epsilon` of `point`.
+    pub(crate) fn visit_near<V: FnMut(usize)>(&self, point: &Three<F>, epsilon: F, mut visit: V) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut stack = vec![0];
+        while let Some(node_idx) = stack.pop() {
+            let node = &self.nodes[node_idx];
+            let near = (0..3).all(|axis| {
+                point.axis(axis) >= node.bounds.min.axis(axis) - epsilon
+                    && point.axis(axis) <= node.bounds.max.axis(axis) + epsilon
+            });
+            if !near {
+                continue;
+            }
+            match node.kind {
+                BvhNodeKind::Leaf { first, count } => self.indices[first..first + count]
+                    .iter()
+                    .for_each(|&i| visit(i)),
+                BvhNodeKind::Interior { right, .. } => {
+                    stack.push(node_idx + 1);
+                    stack.push(right);
+                }
+            }
+        }
+    }
+}
+
+fn merge_bins<F: Float>(bounds: &[Aabb<F>], counts: &[usize]) -> (Aabb<F>, usize) {
+    bounds
+        .iter()
+        .zip(counts)
+        .fold((Aabb::empty(), 0), |(aabb, total), (b, &count)| {
+            (aabb.union(b), total + count)
+        })
+}
+
+fn surface_area<F: Float>(aabb: &Aabb<F>) -> F {
+    let d = aabb.diagonal();
+    (d.x * d.y + d.y * d.z + d.z * d.x) * F::from(2.0f64).unwrap()
+}
+
+/// Reorders `items` so the ones matching `pred` come first, and returns how many there are.
+fn partition<T, P: Fn(&T) -> bool>(items: &mut [T], pred: P) -> usize {
+    let mut first_false = 0;
+    for i in 0..items.len() {
+        if pred(&items[i]) {
+            items.swap(i, first_false);
+            first_false += 1;
+        }
+    }
+    first_false
+}
+
+/// Slab test of whether `ray` passes through `aabb` between `t_min` and `t_max`.
+fn hits_box<F: Float>(
+    aabb: &Aabb<F>,
+    ray: &Ray<F>,
+    inv_direction: &Three<F>,
+    mut t_min: F,
+    mut t_max: F,
+) -> bool {
+    for axis in 0..3 {
+        let inv = inv_direction.axis(axis);
+        let origin = ray.origin.axis(axis);
+        let mut t0 = (aabb.min.axis(axis) - origin) * inv;
+        let mut t1 = (aabb.max.axis(axis) - origin) * inv;
+        if inv < F::zero() {
+            std::mem::swap(&mut t0, &mut t1);
+        }
+        // written so NaNs, from rays in the plane of a face, keep the current range
+        if t0 > t_min {
+            t_min = t0;
+        }
+        if t1 < t_max {
+            t_max = t1;
+        }
+        if t_max < t_min {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Bounded, CanHit};
+    use crate::shapes::Sphere;
+    use rand::{Rng, SeedableRng};
+    use rand_xorshift::XorShiftRng;
+
+    #[test]
+    fn finds_the_same_nearest_hit_as_testing_everything() {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let mut random_point = |scale: f64| {
+            Three::new(
+                rng.gen_range(-scale..scale),
+                rng.gen_range(-scale..scale),
+                rng.gen_range(-scale..scale),
+            )
+        };
+        let centers: Vec<Three<f64>> = (0..200).map(|_| random_point(10.0)).collect();
+        let spheres: Vec<Sphere<f64>> = centers
+            .iter()
+            .enumerate()
+            .map(|(i, &center)| Sphere::new(center, 0.1 + (i % 7) as f64 * 0.1))
+            .collect();
+        let bounds: Vec<Aabb<f64>> = spheres.iter().map(|s| s.bounds()).collect();
+        let bvh = Bvh::new(&bounds);
+
+        let mut hits = 0;
+        for k in 0..1000 {
+            // aim at the spheres, but not always close enough to hit them
+            let origin = random_point(15.0);
+            let target = centers[k % centers.len()] + random_point(1.0);
+            let ray = Ray::new(origin, (target - origin).normalized());
+            let hit_sphere = |i: usize, t_max: f64| {
+                ray.shoot_at(&spheres[i], 1e-9, t_max).map(|hit| Hit {
+                    object_index: i,
+                    ..hit
+                })
+            };
+            let nearest = (0..spheres.len())
+                .filter_map(|i| hit_sphere(i, f64::INFINITY))
+                .min_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap());
+            let found = bvh.shoot(&ray, 1e-9, f64::INFINITY, hit_sphere);
+            match (nearest, found) {
+                (Some(nearest), Some(found)) => {
+                    assert_eq!(nearest.object_index, found.object_index);
+                    assert!((nearest.distance - found.distance).abs() < 1e-9);
+                    hits += 1;
+                }
+                (None, None) => {}
+                (nearest, found) => panic!("expected {:?}, found {:?}", nearest, found),
+            }
+        }
+        assert!(hits > 300, "only {} rays hit anything", hits);
+    }
+
+    #[test]
+    fn an_empty_hierarchy_is_never_hit() {
+        let bvh = Bvh::<f64>::new(&[]);
+        let ray = Ray::new(Three::zeros(), Three::new(0.0, 0.0, 1.0));
+        assert!(bvh
+            .shoot(&ray, 0.0, f64::INFINITY, |_, _| unreachable!())
+            .is_none());
+    }
+}
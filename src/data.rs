@@ -1,3 +1,4 @@
+use crate::texture::Texture;
 use rand::Rng;
 use std::fmt::Debug;
 
@@ -8,9 +9,12 @@ pub struct Three<T> {
     pub z: T,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct Diffuse<F> {
     pub rgb: Three<F>,
+    /// Multiplies `rgb` by a color looked up at the hit point, e.g. for painted or printed
+    /// surfaces.
+    pub texture: Option<Texture<F>>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -24,13 +28,33 @@ pub struct Dielectric<F> {
     pub ior: F,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// An emissive material. It's `Clone` but not `Copy`, since it can hold a `Texture`, whose
+/// image is shared between clones.
+#[derive(Debug, Clone)]
 pub struct Light<F> {
     pub rgb: Three<F>,
     pub power: F,
+    pub units: EmissionUnits,
+    /// Whether light leaves both sides of the surface, or only the side its normal points to.
+    pub two_sided: bool,
+    /// Multiplies `rgb` by a color looked up at the emitting point, e.g. for screens and signs.
+    pub texture: Option<Texture<F>>,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// How the `power` of a `Light` is interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmissionUnits {
+    /// Emitted radiance is `rgb * power`.
+    Radiance,
+    /// `power` is the total radiant flux in watts leaving the object, spread evenly over its
+    /// surface. Bigger objects are dimmer per unit area.
+    Watts,
+    /// `power` is the luminance of the surface in nits (candela per square meter).
+    Nits,
+}
+
+/// Not `Copy`, because a `Light` isn't; clone it or borrow it from the `Scene` instead.
+#[derive(Debug, Clone)]
 pub enum Material<F> {
     Diffuse(Diffuse<F>),
     Mirror(Mirror<F>),
@@ -42,6 +66,9 @@ pub enum Material<F> {
 pub struct Ray<F> {
     pub origin: Three<F>,
     pub direction: Three<F>,
+    /// When the ray is traced, which is where moving shapes are found. Rays bouncing off a
+    /// surface keep the time of the ray that hit it.
+    pub time: F,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -49,6 +76,7 @@ pub struct Hit<F> {
     pub position: Three<F>,
     pub distance: F,
     pub normal: Three<F>,
+    pub uv: (F, F),
     pub object_index: usize,
 }
 
@@ -67,8 +95,23 @@ pub enum LightInteraction<F> {
 }
 
 pub trait Surface<F> {
+    /// Samples a point uniformly with respect to surface area.
     fn sample_point_on_surface<R: Rng>(&self, rng: &mut R) -> Three<F>;
     fn normal_at_point(&self, point: &Three<F>) -> Three<F>;
+    /// Texture coordinates of a point on the surface, matching the `uv` of hits there.
+    fn uv_at_point(&self, point: &Three<F>) -> (F, F);
+    fn area(&self) -> F;
+}
+
+/// Axis aligned bounding box.
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb<F> {
+    pub min: Three<F>,
+    pub max: Three<F>,
+}
+
+pub trait Bounded<F> {
+    fn bounds(&self) -> Aabb<F>;
 }
 
 pub enum FieldOfView<F> {
@@ -90,8 +133,14 @@ pub struct LinearTransform<F> {
 #[derive(Copy, Clone, Debug)]
 pub struct Camera<F> {
     pub(crate) position: Three<F>,
+    /// Directions of the image's x and y axes, and of where the camera looks.
+    pub(crate) right: Three<F>,
+    pub(crate) up: Three<F>,
+    pub(crate) forward: Three<F>,
     pub(crate) x_transform: LinearTransform<F>,
     pub(crate) y_transform: LinearTransform<F>,
     pub(crate) width: usize,
     pub(crate) height: usize,
+    /// Times the shutter opens and closes, which camera rays are spread between.
+    pub(crate) shutter: (F, F),
 }
@@ -1,5 +1,8 @@
 use super::data::*;
-use num_traits::{cast, Float};
+use crate::texture::Texture;
+use num_traits::{cast, Float, FloatConst};
+use rand::Rng;
+use rand_distr::{Distribution, Standard};
 use std::{
     fmt::Debug,
     ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign},
@@ -287,7 +290,7 @@ where
     }
 
     pub fn length_squared(&self) -> T {
-        self.dot(&self)
+        self.dot(self)
     }
 }
 
@@ -354,6 +357,18 @@ where
         }
     }
 
+    /// Returns two unit vectors that together with `self` (assumed normalized) form an orthonormal basis.
+    pub fn orthonormal_basis(&self) -> (Self, Self) {
+        let a = if self.x.abs() > F::from(0.9f64).unwrap() {
+            Three::new(F::zero(), F::one(), F::zero())
+        } else {
+            Three::new(F::one(), F::zero(), F::zero())
+        };
+        let u = self.cross(&a).normalized();
+        let v = self.cross(&u).normalized();
+        (u, v)
+    }
+
     pub fn rotate(&self, axis: &Self, angle: F) -> Self {
         let theta = angle.to_radians();
         let sin_theta = theta.sin();
@@ -363,10 +378,132 @@ where
     }
 }
 
+impl<F> Aabb<F>
+where
+    F: Float,
+{
+    /// A box containing nothing, which is the identity for `union`.
+    pub fn empty() -> Self {
+        Self {
+            min: Three::from(F::infinity()),
+            max: Three::from(F::neg_infinity()),
+        }
+    }
+
+    /// A box containing all of space, e.g. for infinite planes.
+    pub fn infinite() -> Self {
+        Self {
+            min: Three::from(F::neg_infinity()),
+            max: Three::from(F::infinity()),
+        }
+    }
+
+    pub fn around(points: &[Three<F>]) -> Self {
+        points
+            .iter()
+            .fold(Self::empty(), |aabb, point| aabb.including(point))
+    }
+
+    pub fn including(&self, point: &Three<F>) -> Self {
+        Self {
+            min: Three::new(
+                self.min.x.min(point.x),
+                self.min.y.min(point.y),
+                self.min.z.min(point.z),
+            ),
+            max: Three::new(
+                self.max.x.max(point.x),
+                self.max.y.max(point.y),
+                self.max.z.max(point.z),
+            ),
+        }
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        self.including(&other.min).including(&other.max)
+    }
+
+    pub fn center(&self) -> Three<F> {
+        (self.min + self.max) * F::from(0.5f64).unwrap()
+    }
+
+    pub fn diagonal(&self) -> Three<F> {
+        self.max - self.min
+    }
+
+    pub fn is_finite(&self) -> bool {
+        let d = self.diagonal();
+        d.x.is_finite() && d.y.is_finite() && d.z.is_finite()
+    }
+
+    /// Index of the axis (0 = x, 1 = y, 2 = z) along which the box is longest.
+    pub fn longest_axis(&self) -> usize {
+        let d = self.diagonal();
+        if d.x >= d.y && d.x >= d.z {
+            0
+        } else if d.y >= d.z {
+            1
+        } else {
+            2
+        }
+    }
+}
+
+impl<F> Three<F>
+where
+    F: Copy,
+{
+    /// Component by axis index (0 = x, 1 = y, 2 = z).
+    pub fn axis(&self, axis: usize) -> F {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
+    }
+}
+
+impl<F> Three<F>
+where
+    F: Float,
+{
+    /// Perceived brightness of an rgb color.
+    pub fn luminance(&self) -> F {
+        self.x * F::from(0.2126f64).unwrap()
+            + self.y * F::from(0.7152f64).unwrap()
+            + self.z * F::from(0.0722f64).unwrap()
+    }
+
+    pub fn max_component(&self) -> F {
+        self.x.max(self.y).max(self.z)
+    }
+}
+
 impl<F> Diffuse<F> {
     pub fn rgb(r: F, g: F, b: F) -> Self {
         Self {
             rgb: Three::new(r, g, b),
+            texture: None,
+        }
+    }
+
+    pub fn textured<T: Into<Texture<F>>>(self, texture: T) -> Self {
+        Self {
+            texture: Some(texture.into()),
+            ..self
+        }
+    }
+}
+
+impl<F> Diffuse<F>
+where
+    F: Float,
+{
+    /// Reflectance at texture coordinates `uv`.
+    pub fn albedo(&self, uv: (F, F)) -> Three<F> {
+        match &self.texture {
+            Some(texture) => self.rgb * texture.lookup(uv),
+            None => self.rgb,
         }
     }
 }
@@ -405,36 +542,157 @@ where
     }
 }
 
+impl<F> Light<F>
+where
+    F: Float + FloatConst,
+{
+    /// A two sided light emitting `rgb * power` radiance.
+    pub fn new(rgb: Three<F>, power: F) -> Self {
+        Self {
+            rgb,
+            power,
+            units: EmissionUnits::Radiance,
+            two_sided: true,
+            texture: None,
+        }
+    }
+
+    pub fn one_sided(self) -> Self {
+        Self {
+            two_sided: false,
+            ..self
+        }
+    }
+
+    pub fn in_units(self, units: EmissionUnits) -> Self {
+        Self { units, ..self }
+    }
+
+    pub fn textured<T: Into<Texture<F>>>(self, texture: T) -> Self {
+        Self {
+            texture: Some(texture.into()),
+            ..self
+        }
+    }
+
+    /// Whether a ray travelling along `direction` that hits the light where its surface normal is
+    /// `normal` receives any light.
+    pub fn emits_towards(&self, direction: &Three<F>, normal: &Three<F>) -> bool {
+        self.two_sided || direction.dot(normal) < F::zero()
+    }
+
+    /// Radiance leaving the point with texture coordinates `uv` on an object with total surface
+    /// `area`.
+    pub fn radiance(&self, area: F, uv: (F, F)) -> Three<F> {
+        let rgb = match &self.texture {
+            Some(texture) => self.rgb * texture.lookup(uv),
+            None => self.rgb,
+        };
+        rgb * self.radiance_scale(area)
+    }
+
+    /// Radiance averaged over the whole surface of an object with total surface `area`.
+    pub fn average_radiance(&self, area: F) -> Three<F> {
+        let rgb = match &self.texture {
+            Some(texture) => self.rgb * texture.average(),
+            None => self.rgb,
+        };
+        rgb * self.radiance_scale(area)
+    }
+
+    /// Total power leaving an object with surface `area`, weighted by perceived brightness.
+    pub fn emitted_power(&self, area: F) -> F {
+        let sides = if self.two_sided {
+            F::from(2.0f64).unwrap()
+        } else {
+            F::one()
+        };
+        self.average_radiance(area).luminance() * F::PI() * area * sides
+    }
+
+    fn radiance_scale(&self, area: F) -> F {
+        // in physical units rgb only picks the color, so it's normalized to unit luminance
+        let luminance = self.rgb.luminance();
+        if self.units != EmissionUnits::Radiance && luminance <= F::zero() {
+            // black has no luminance to normalize, and emits nothing in any units
+            return F::zero();
+        }
+        match self.units {
+            EmissionUnits::Radiance => self.power,
+            EmissionUnits::Watts => {
+                let sides = if self.two_sided {
+                    F::from(2.0f64).unwrap()
+                } else {
+                    F::one()
+                };
+                // flux leaving a lambertian emitter is pi * area * radiance per side
+                self.power / (luminance * F::PI() * area * sides)
+            }
+            // 683 lumens per watt converts from photometric to radiometric units
+            EmissionUnits::Nits => self.power / (luminance * F::from(683.0f64).unwrap()),
+        }
+    }
+}
+
 impl<F> Material<F> {
     pub fn is_emissive(&self) -> bool {
+        matches!(self, Material::Light(_))
+    }
+}
+
+impl<F> Material<F>
+where
+    F: Float,
+{
+    /// Base color of the material at texture coordinates `uv`.
+    pub fn albedo(&self, uv: (F, F)) -> Three<F> {
         match self {
-            Material::Light(_) => true,
-            _ => false,
+            Material::Diffuse(m) => m.albedo(uv),
+            Material::Mirror(m) => m.rgb,
+            Material::Dielectric(m) => m.rgb,
+            Material::Light(m) => match &m.texture {
+                Some(texture) => m.rgb * texture.lookup(uv),
+                None => m.rgb,
+            },
         }
     }
 }
 
-impl<F> Into<Material<F>> for Diffuse<F> {
-    fn into(self) -> Material<F> {
-        Material::Diffuse(self)
+impl<F> From<Diffuse<F>> for Material<F> {
+    fn from(m: Diffuse<F>) -> Self {
+        Material::Diffuse(m)
     }
 }
 
-impl<F> Into<Material<F>> for Mirror<F> {
-    fn into(self) -> Material<F> {
-        Material::Mirror(self)
+impl<F> From<Mirror<F>> for Material<F> {
+    fn from(m: Mirror<F>) -> Self {
+        Material::Mirror(m)
     }
 }
 
-impl<F> Into<Material<F>> for Dielectric<F> {
-    fn into(self) -> Material<F> {
-        Material::Dielectric(self)
+impl<F> From<Dielectric<F>> for Material<F> {
+    fn from(m: Dielectric<F>) -> Self {
+        Material::Dielectric(m)
     }
 }
 
-impl<F> Into<Material<F>> for Light<F> {
-    fn into(self) -> Material<F> {
-        Material::Light(self)
+impl<F> From<Light<F>> for Material<F> {
+    fn from(m: Light<F>) -> Self {
+        Material::Light(m)
+    }
+}
+
+impl<F> Ray<F>
+where
+    F: Float,
+{
+    /// A ray traced at time 0, for scenes where nothing moves.
+    pub fn new(origin: Three<F>, direction: Three<F>) -> Self {
+        Self {
+            origin,
+            direction,
+            time: F::zero(),
+        }
     }
 }
 
@@ -471,6 +729,9 @@ where
         let aspect_ratio = w / h;
         Self {
             position: Three::new(F::zero(), F::zero(), F::zero()),
+            right: Three::new(F::one(), F::zero(), F::zero()),
+            up: Three::new(F::zero(), F::one(), F::zero()),
+            forward: Three::new(F::zero(), F::zero(), -F::one()),
             x_transform: LinearTransform {
                 // (2.0 * x / width - 1.0) * aspect_ratio & tan_half_fov
                 scale: two * aspect_ratio * tan_half_fov / w,
@@ -483,26 +744,185 @@ where
             },
             width: image_shape.width,
             height: image_shape.height,
+            shutter: (F::zero(), F::zero()),
         }
     }
 
     pub fn at(&self, x: F, y: F, z: F) -> Self {
-        let mut p = self.clone();
+        let mut p = *self;
         p.position = Three::new(x, y, z);
         p
     }
 
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The same camera rendering an image of a different size, keeping the vertical field of
+    /// view.
+    pub fn with_image_shape(&self, image_shape: ImageShape) -> Self {
+        // y_transform's offset is tan(fov / 2)
+        let fov = self.y_transform.offset.atan() * F::from(2.0f64).unwrap();
+        Self {
+            position: self.position,
+            right: self.right,
+            up: self.up,
+            forward: self.forward,
+            shutter: self.shutter,
+            ..Self::new(FieldOfView::Radians(fov), image_shape)
+        }
+    }
+
+    /// Turns the camera to look at `target`, keeping `up` pointing up in the image as much as
+    /// possible. By default cameras look along the negative z axis with y up.
+    ///
+    /// When looking straight along `up`, which way is up in the image is picked arbitrarily. A
+    /// `target` at the camera's own position leaves it looking where it did.
+    pub fn looking_at(&self, target: Three<F>, up: Three<F>) -> Self {
+        let mut p = *self;
+        let forward = target - self.position;
+        if forward.length_squared() <= F::min_positive_value() {
+            return p;
+        }
+        p.forward = forward.normalized();
+        let right = p.forward.cross(&up);
+        p.right = if right.length_squared() > F::epsilon() * up.length_squared() {
+            right.normalized()
+        } else {
+            p.forward.orthonormal_basis().0
+        };
+        p.up = p.right.cross(&p.forward);
+        p
+    }
+
+    /// Keeps the shutter open from time `open` to `close`, so shapes moving in that time are
+    /// blurred. By default it opens and closes at time 0, freezing everything.
+    pub fn with_shutter(&self, open: F, close: F) -> Self {
+        let mut p = *self;
+        p.shutter = (open, close);
+        p
+    }
+
+    /// A random time while the shutter is open.
+    pub(crate) fn sample_time<R: Rng>(&self, rng: &mut R) -> F
+    where
+        Standard: Distribution<F>,
+    {
+        let (open, close) = self.shutter;
+        if open == close {
+            // saves a random number, so still images come out the same as without a shutter
+            return open;
+        }
+        let u: F = Standard.sample(rng);
+        open + (close - open) * u
+    }
+
     pub(crate) fn empty_image(&self) -> Vec<Three<F>> {
         vec![Three::new(F::zero(), F::zero(), F::zero()); self.width * self.height]
     }
 
-    pub(crate) fn ray_through(&self, x_screen: F, y_screen: F) -> Ray<F> {
+    /// Direction the camera looks, i.e. the normal of its image plane.
+    pub(crate) fn forward(&self) -> Three<F> {
+        self.forward
+    }
+
+    /// Area of the image plane at unit distance in front of the camera.
+    fn image_plane_area(&self) -> F {
+        let w: F = cast(self.width).unwrap();
+        let h: F = cast(self.height).unwrap();
+        (self.x_transform.scale * w * self.y_transform.scale * h).abs()
+    }
+
+    /// Screen coordinates of where `point` appears in the image, if it is in view.
+    pub(crate) fn raster_position(&self, point: &Three<F>) -> Option<(F, F)> {
+        let d = point - &self.position;
+        let depth = d.dot(&self.forward);
+        if depth <= F::zero() {
+            return None;
+        }
+        let x_world = d.dot(&self.right) / depth;
+        let y_world = d.dot(&self.up) / depth;
+        let x_screen = (x_world - self.x_transform.offset) / self.x_transform.scale;
+        let y_screen = (y_world - self.y_transform.offset) / self.y_transform.scale;
+        let w: F = cast(self.width).unwrap();
+        let h: F = cast(self.height).unwrap();
+        if x_screen < F::zero() || x_screen >= w || y_screen < F::zero() || y_screen >= h {
+            return None;
+        }
+        Some((x_screen, y_screen))
+    }
+
+    pub(crate) fn pixel_index(&self, x_screen: F, y_screen: F) -> usize {
+        let x = x_screen.to_usize().unwrap().min(self.width - 1);
+        let y = y_screen.to_usize().unwrap().min(self.height - 1);
+        y * self.width + x
+    }
+
+    /// Solid angle density of the camera generating a ray along `direction` for a uniformly
+    /// random point on the image.
+    pub(crate) fn direction_pdf(&self, direction: &Three<F>) -> F {
+        let cos_theta = direction.dot(&self.forward());
+        if cos_theta <= F::zero() {
+            return F::zero();
+        }
+        (self.image_plane_area() * cos_theta.powi(3)).recip()
+    }
+
+    /// How much a ray leaving the camera along `direction` contributes to the image, such that
+    /// `importance * cos_theta / direction_pdf` is 1.
+    pub(crate) fn importance(&self, direction: &Three<F>) -> F {
+        let cos_theta = direction.dot(&self.forward());
+        if cos_theta <= F::zero() {
+            return F::zero();
+        }
+        (self.image_plane_area() * cos_theta.powi(4)).recip()
+    }
+
+    pub(crate) fn ray_through(&self, x_screen: F, y_screen: F, time: F) -> Ray<F> {
         let x_world = self.x_transform.apply(x_screen);
         let y_world = self.y_transform.apply(y_screen);
-        let direction = Three::new(x_world, y_world, -F::one()).normalized();
+        let direction = (self.right * x_world + self.up * y_world + self.forward).normalized();
         Ray {
             origin: self.position,
             direction,
+            time,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera() -> Camera<f64> {
+        Camera::new(
+            FieldOfView::Degrees(60.0),
+            ImageShape {
+                width: 4,
+                height: 4,
+            },
+        )
+        .at(0.0, 5.0, 0.0)
+    }
+
+    #[test]
+    fn looking_along_up_picks_another_up() {
+        let camera = camera().looking_at(Three::zeros(), Three::new(0.0, 1.0, 0.0));
+        let ray = camera.ray_through(1.0, 3.0, 0.0);
+        assert!(ray.direction.y < 0.0);
+        for v in [camera.forward, camera.right, camera.up] {
+            assert!((v.length() - 1.0).abs() < 1e-9);
         }
+        assert!(camera.forward.dot(&camera.right).abs() < 1e-9);
+    }
+
+    #[test]
+    fn looking_at_own_position_keeps_looking_ahead() {
+        let camera = camera().looking_at(Three::new(0.0, 5.0, 0.0), Three::new(0.0, 1.0, 0.0));
+        assert_eq!(camera.forward, Three::new(0.0, 0.0, -1.0));
     }
 }
@@ -0,0 +1,320 @@
+//! Tracers for inspecting scenes quickly, instead of waiting for a full render.
+
+use crate::data::{CanHit, Hit, Ray, Three};
+use crate::pdf::{CosineHemisphereDistribution, HemisphereDistribution};
+use crate::scene::{Scene, SceneTracer};
+use crate::tracer::facing_normal;
+use num_traits::{Float, FloatConst};
+use rand::Rng;
+use rand_distr::{Distribution, Standard};
+#[cfg(feature = "traversal-cost")]
+use std::cell::Cell;
+
+/// Surface normals mapped from [-1, 1] to [0, 1].
+#[derive(Default, Debug, Clone, Copy)]
+pub struct NormalTracer;
+
+/// A different color for every object.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct ObjectIdTracer;
+
+/// A different color for every material.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct MaterialIdTracer;
+
+/// Distance from the camera, from white up close to black at `far`.
+#[derive(Debug, Clone, Copy)]
+pub struct DepthTracer<F> {
+    pub far: F,
+}
+
+/// Texture coordinates as red and green, repeating outside of [0, 1].
+#[derive(Default, Debug, Clone, Copy)]
+pub struct UvTracer;
+
+/// White where nothing is within `distance` of a surface in a random direction, black where
+/// something is, which averages out to how occluded each point is. Rays that miss everything
+/// show the environment.
+#[derive(Debug, Clone, Copy)]
+pub struct AmbientOcclusionTracer<F> {
+    pub distance: F,
+}
+
+/// Heat map of how many intersection tests finding the first hit took, from blue for none to
+/// red for `max_tests` or more.
+///
+/// Only built with the `traversal-cost` feature, which counts the tests in every render.
+#[cfg(feature = "traversal-cost")]
+#[derive(Debug, Clone, Copy)]
+pub struct TraversalCostTracer {
+    pub max_tests: usize,
+}
+
+#[cfg(feature = "traversal-cost")]
+thread_local! {
+    static INTERSECTION_TESTS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Counts an intersection test against an object or bounding box, for `TraversalCostTracer`.
+/// Does nothing without the `traversal-cost` feature.
+#[inline(always)]
+pub(crate) fn count_intersection_test() {
+    #[cfg(feature = "traversal-cost")]
+    INTERSECTION_TESTS.with(|tests| tests.set(tests.get() + 1));
+}
+
+fn first_hit<F>(ray: &Ray<F>, scene: &Scene<F>) -> Option<Hit<F>>
+where
+    F: Float + FloatConst,
+{
+    ray.shoot_at(scene, F::from(1e-3f64).unwrap(), F::infinity())
+}
+
+/// A bright color for `idx`, with hues spread out so neighboring indices are easy to tell apart.
+fn false_color<F: Float>(idx: usize) -> Three<F> {
+    // golden ratio steps around the color wheel never repeat a hue
+    hue_color((idx as f64 * 0.618_033_988_749_895).fract())
+}
+
+/// Hue `t` in [0, 1] around the color wheel, starting at red.
+fn hue_color<F: Float>(t: f64) -> Three<F> {
+    let channel = |offset: f64| {
+        let k = (t * 6.0 + offset) % 6.0;
+        let value = 1.0 - (k.min(4.0 - k).clamp(0.0, 1.0));
+        F::from(value).unwrap()
+    };
+    Three::new(channel(5.0), channel(3.0), channel(1.0))
+}
+
+impl<F> SceneTracer<F> for NormalTracer
+where
+    F: Float + FloatConst,
+{
+    fn trace<R: Rng>(&self, ray: Ray<F>, scene: &Scene<F>, _rng: &mut R) -> Option<Three<F>> {
+        let half = F::from(0.5f64).unwrap();
+        first_hit(&ray, scene).map(|hit| hit.normal * half + Three::new(half, half, half))
+    }
+}
+
+impl<F> SceneTracer<F> for ObjectIdTracer
+where
+    F: Float + FloatConst,
+{
+    fn trace<R: Rng>(&self, ray: Ray<F>, scene: &Scene<F>, _rng: &mut R) -> Option<Three<F>> {
+        first_hit(&ray, scene).map(|hit| false_color(hit.object_index))
+    }
+}
+
+impl<F> SceneTracer<F> for MaterialIdTracer
+where
+    F: Float + FloatConst,
+{
+    fn trace<R: Rng>(&self, ray: Ray<F>, scene: &Scene<F>, _rng: &mut R) -> Option<Three<F>> {
+        first_hit(&ray, scene).map(|hit| false_color(scene.material_idx(hit.object_index).index()))
+    }
+}
+
+impl<F> Default for DepthTracer<F>
+where
+    F: Float,
+{
+    fn default() -> Self {
+        Self {
+            far: F::from(10.0f64).unwrap(),
+        }
+    }
+}
+
+impl<F> SceneTracer<F> for DepthTracer<F>
+where
+    F: Float + FloatConst,
+{
+    fn trace<R: Rng>(&self, ray: Ray<F>, scene: &Scene<F>, _rng: &mut R) -> Option<Three<F>> {
+        first_hit(&ray, scene).map(|hit| {
+            let brightness = (F::one() - hit.distance / self.far).max(F::zero());
+            Three::new(brightness, brightness, brightness)
+        })
+    }
+}
+
+impl<F> SceneTracer<F> for UvTracer
+where
+    F: Float + FloatConst,
+{
+    fn trace<R: Rng>(&self, ray: Ray<F>, scene: &Scene<F>, _rng: &mut R) -> Option<Three<F>> {
+        first_hit(&ray, scene).map(|hit| {
+            let (u, v) = hit.uv;
+            Three::new(u - u.floor(), v - v.floor(), F::zero())
+        })
+    }
+}
+
+impl<F> Default for AmbientOcclusionTracer<F>
+where
+    F: Float,
+{
+    fn default() -> Self {
+        Self { distance: F::one() }
+    }
+}
+
+impl<F> SceneTracer<F> for AmbientOcclusionTracer<F>
+where
+    F: Float + FloatConst,
+    Standard: Distribution<F>,
+{
+    fn trace<R: Rng>(&self, ray: Ray<F>, scene: &Scene<F>, rng: &mut R) -> Option<Three<F>> {
+        let hit = match first_hit(&ray, scene) {
+            Some(hit) => hit,
+            None => {
+                return scene
+                    .environment()
+                    .map(|environment| environment.radiance(&ray.direction))
+            }
+        };
+        let normal = facing_normal(&ray.direction, &hit.normal);
+        let direction = CosineHemisphereDistribution::oriented_towards(normal).sample(rng);
+        let occlusion_ray = Ray {
+            origin: hit.position,
+            direction,
+            time: ray.time,
+        };
+        let occluded = occlusion_ray
+            .shoot_at(scene, F::from(1e-3f64).unwrap(), self.distance)
+            .is_some();
+        Some(if occluded {
+            Three::zeros()
+        } else {
+            Three::ones()
+        })
+    }
+}
+
+#[cfg(feature = "traversal-cost")]
+impl Default for TraversalCostTracer {
+    fn default() -> Self {
+        Self { max_tests: 64 }
+    }
+}
+
+#[cfg(feature = "traversal-cost")]
+impl<F> SceneTracer<F> for TraversalCostTracer
+where
+    F: Float + FloatConst,
+{
+    fn trace<R: Rng>(&self, ray: Ray<F>, scene: &Scene<F>, _rng: &mut R) -> Option<Three<F>> {
+        INTERSECTION_TESTS.with(|tests| tests.set(0));
+        first_hit(&ray, scene);
+        let tests = INTERSECTION_TESTS.with(|tests| tests.get());
+        let t = tests.min(self.max_tests) as f64 / self.max_tests.max(1) as f64;
+        // blue for cheap rays through to red for expensive ones
+        Some(hue_color((1.0 - t) * 2.0 / 3.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Diffuse;
+    use crate::lights::Environment;
+    use crate::shapes::Sphere;
+    use crate::texture::Texture;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    /// A unit sphere 3 in front of the origin, of the scene's second material.
+    fn ball() -> Scene<f64> {
+        let mut scene = Scene::new();
+        scene.add_material(Diffuse::rgb(0.2, 0.2, 0.2));
+        let gray = scene.add_material(Diffuse::rgb(0.5, 0.5, 0.5));
+        scene.add_object(Sphere::new(Three::new(0.0, 0.0, -3.0), 1.0), gray);
+        scene
+    }
+
+    /// The ray through the middle of the image, straight at the ball.
+    fn middle() -> Ray<f64> {
+        Ray::new(Three::zeros(), Three::new(0.0, 0.0, -1.0))
+    }
+
+    fn trace<T: SceneTracer<f64>>(tracer: T, ray: Ray<f64>) -> Option<Three<f64>> {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        tracer.trace(ray, &ball(), &mut rng)
+    }
+
+    #[test]
+    fn normals_are_mapped_to_colors() {
+        // the ball faces the camera along z
+        assert_eq!(
+            trace(NormalTracer, middle()),
+            Some(Three::new(0.5, 0.5, 1.0))
+        );
+        let past = Ray::new(Three::zeros(), Three::new(0.0, 1.0, 0.0));
+        assert_eq!(trace(NormalTracer, past), None);
+    }
+
+    #[test]
+    fn depth_fades_with_distance() {
+        // the ball is 2 away, a fifth of the way to `far`
+        let depth = trace(DepthTracer::default(), middle()).unwrap();
+        assert!((depth - Three::from(0.8)).length() < 1e-9, "{:?}", depth);
+        let near = DepthTracer { far: 1.0 };
+        assert_eq!(trace(near, middle()), Some(Three::zeros()));
+    }
+
+    #[test]
+    fn ids_color_the_object_and_its_material() {
+        assert_eq!(trace(ObjectIdTracer, middle()), Some(false_color(0)));
+        assert_eq!(trace(MaterialIdTracer, middle()), Some(false_color(1)));
+        assert_ne!(false_color::<f64>(0), false_color(1));
+    }
+
+    #[test]
+    fn uvs_are_the_hits_texture_coordinates() {
+        let ray = Ray::new(Three::zeros(), Three::new(0.15, 0.1, -1.0).normalized());
+        let (u, v) = ray.shoot_at(&ball(), 1e-3, f64::INFINITY).unwrap().uv;
+        assert_eq!(trace(UvTracer, ray), Some(Three::new(u, v, 0.0)));
+    }
+
+    #[test]
+    fn unoccluded_surfaces_are_white() {
+        let scene = ball();
+        let tracer = AmbientOcclusionTracer::default();
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        for _ in 0..100 {
+            assert_eq!(
+                tracer.trace(middle(), &scene, &mut rng),
+                Some(Three::ones())
+            );
+        }
+    }
+
+    #[cfg(feature = "traversal-cost")]
+    #[test]
+    fn traversal_cost_counts_intersection_tests() {
+        let mut scene = ball();
+        let tracer = TraversalCostTracer::default();
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        // no tests at all would be pure blue
+        let free = hue_color::<f64>(2.0 / 3.0);
+        for _ in 0..2 {
+            INTERSECTION_TESTS.with(|tests| tests.set(0));
+            first_hit(&middle(), &scene);
+            assert!(INTERSECTION_TESTS.with(|tests| tests.get()) > 0);
+            assert_ne!(tracer.trace(middle(), &scene, &mut rng), Some(free));
+            // and through the hierarchy too
+            scene.build_bvh();
+        }
+    }
+
+    #[test]
+    fn ambient_occlusion_shows_the_environment_behind_everything() {
+        let mut scene = Scene::<f64>::new();
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let ray = Ray::new(Three::zeros(), Three::new(0.0, 0.0, -1.0));
+        let tracer = AmbientOcclusionTracer::default();
+        assert!(tracer.trace(ray, &scene, &mut rng).is_none());
+        let sky = Three::new(0.2, 0.4, 0.8);
+        scene.set_environment(Environment::new(Texture::Constant(sky)));
+        assert_eq!(tracer.trace(ray, &scene, &mut rng), Some(sky));
+    }
+}
@@ -0,0 +1,232 @@
+use crate::data::Three;
+use crate::rendering::{to_image, RenderBuffers};
+use image::{Rgb, RgbImage};
+use num_traits::Float;
+use rayon::prelude::*;
+
+/// Edge-avoiding à-trous wavelet filter (Dammertz et al. 2010), with the variance guided color
+/// weights of SVGF (Schied et al. 2017).
+///
+/// Every iteration blurs each pixel with a 5x5 kernel whose taps are twice as far apart as the
+/// last iteration's, so large areas are smoothed in a few cheap passes. Neighbors only count if
+/// their normal and albedo match, so edges and textures stay sharp, and if their color is
+/// within what the pixel's noise can explain, so lighting details do too. Renders by tracers
+/// that splat have no variance, and are blurred by normal and albedo alone.
+#[derive(Debug, Clone, Copy)]
+pub struct Denoiser<F> {
+    pub iterations: usize,
+    /// How many standard deviations of noise colors may differ by and still be blurred together.
+    pub sigma_color: F,
+    /// Exponent on the cosine between normals. Higher values keep more geometric detail.
+    pub sigma_normal: F,
+    pub sigma_albedo: F,
+}
+
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+impl<F> Default for Denoiser<F>
+where
+    F: Float,
+{
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            sigma_color: F::from(4.0f64).unwrap(),
+            sigma_normal: F::from(128.0f64).unwrap(),
+            sigma_albedo: F::from(0.1f64).unwrap(),
+        }
+    }
+}
+
+impl<F> Denoiser<F>
+where
+    F: Float + Send + Sync,
+{
+    /// Denoised colors of `buffers`.
+    pub fn denoise(&self, buffers: &RenderBuffers<F>) -> Vec<Three<F>> {
+        let mut color = buffers.color.clone();
+        let mut variance = buffers.variance.clone();
+        for iteration in 0..self.iterations {
+            let step = 1 << iteration;
+            let filtered: Vec<(Three<F>, F)> = (0..color.len())
+                .into_par_iter()
+                .map(|pixel_idx| self.filter(buffers, &color, variance.as_deref(), pixel_idx, step))
+                .collect();
+            for (pixel_idx, (c, v)) in filtered.into_iter().enumerate() {
+                color[pixel_idx] = c;
+                if let Some(variance) = &mut variance {
+                    variance[pixel_idx] = v;
+                }
+            }
+        }
+        color
+    }
+
+    /// Filtered color and variance of one pixel, for taps `step` pixels apart.
+    fn filter(
+        &self,
+        buffers: &RenderBuffers<F>,
+        color: &[Three<F>],
+        variance: Option<&[F]>,
+        pixel_idx: usize,
+        step: isize,
+    ) -> (Three<F>, F) {
+        let aovs = &buffers.aovs;
+        let (width, height) = (buffers.width as isize, buffers.height as isize);
+        let (x, y) = (pixel_idx as isize % width, pixel_idx as isize / width);
+        let color_p = color[pixel_idx];
+        let luminance_p = color_p.luminance();
+        let normal_p = aovs.normal[pixel_idx];
+        let albedo_p = aovs.albedo[pixel_idx];
+        // a small floor keeps converged pixels from refusing every neighbor
+        let epsilon = F::from(1e-4f64).unwrap();
+        // without a variance to tell noise from detail, only normals and albedo keep edges
+        let color_scale = variance.map(|variance| {
+            self.sigma_color * (self.blurred_variance(buffers, variance, x, y)).sqrt() + epsilon
+        });
+
+        let mut color_sum = Three::zeros();
+        let mut variance_sum = F::zero();
+        let mut weight_sum = F::zero();
+        for (j, kernel_y) in KERNEL.iter().enumerate() {
+            for (i, kernel_x) in KERNEL.iter().enumerate() {
+                let qx = x + (i as isize - 2) * step;
+                let qy = y + (j as isize - 2) * step;
+                if qx < 0 || qy < 0 || qx >= width || qy >= height {
+                    continue;
+                }
+                let q = (qy * width + qx) as usize;
+                let color_q = color[q];
+
+                let normal_weight = normal_p
+                    .dot(&aovs.normal[q])
+                    .max(F::zero())
+                    .powf(self.sigma_normal);
+                let albedo_distance = (albedo_p - aovs.albedo[q]).length_squared();
+                let albedo_weight =
+                    (-albedo_distance / (self.sigma_albedo * self.sigma_albedo)).exp();
+                let color_weight = color_scale.map_or(F::one(), |scale| {
+                    (-(luminance_p - color_q.luminance()).abs() / scale).exp()
+                });
+                let kernel = F::from(kernel_x * kernel_y).unwrap();
+                let weight = if q == pixel_idx {
+                    kernel
+                } else {
+                    kernel * normal_weight * albedo_weight * color_weight
+                };
+
+                color_sum = color_sum + color_q * weight;
+                if let Some(variance) = variance {
+                    variance_sum = variance_sum + variance[q] * weight * weight;
+                }
+                weight_sum = weight_sum + weight;
+            }
+        }
+        (
+            color_sum / weight_sum,
+            variance_sum / (weight_sum * weight_sum),
+        )
+    }
+
+    /// Variance around a pixel blurred with a 3x3 gaussian, which is less noisy than the
+    /// pixel's own estimate.
+    fn blurred_variance(
+        &self,
+        buffers: &RenderBuffers<F>,
+        variance: &[F],
+        x: isize,
+        y: isize,
+    ) -> F {
+        let (width, height) = (buffers.width as isize, buffers.height as isize);
+        let kernel = [0.25f64, 0.5, 0.25];
+        let mut sum = F::zero();
+        let mut weight_sum = F::zero();
+        for (j, ky) in kernel.iter().enumerate() {
+            for (i, kx) in kernel.iter().enumerate() {
+                let qx = x + i as isize - 1;
+                let qy = y + j as isize - 1;
+                if qx < 0 || qy < 0 || qx >= width || qy >= height {
+                    continue;
+                }
+                let weight = F::from(kx * ky).unwrap();
+                sum = sum + variance[(qy * width + qx) as usize] * weight;
+                weight_sum = weight_sum + weight;
+            }
+        }
+        sum / weight_sum
+    }
+}
+
+impl<F> RenderBuffers<F>
+where
+    F: Float + Send + Sync,
+    Three<F>: Into<Rgb<u8>>,
+{
+    pub fn denoised_image(&self, denoiser: &Denoiser<F>) -> RgbImage {
+        to_image(self.width, self.height, &denoiser.denoise(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aov::Aovs;
+
+    const WIDTH: usize = 8;
+    const HEIGHT: usize = 4;
+
+    /// Buffers with a gray, up facing picture, for tests to paint over.
+    fn buffers(variance: Option<f64>) -> RenderBuffers<f64> {
+        let num_pixels = WIDTH * HEIGHT;
+        let mut aovs = Aovs::new(WIDTH, HEIGHT);
+        aovs.albedo = vec![Three::from(0.5); num_pixels];
+        aovs.normal = vec![Three::new(0.0, 1.0, 0.0); num_pixels];
+        RenderBuffers {
+            width: WIDTH,
+            height: HEIGHT,
+            color: vec![Three::from(0.3); num_pixels],
+            variance: variance.map(|v| vec![v; num_pixels]),
+            aovs,
+        }
+    }
+
+    fn assert_colors_near(a: &[Three<f64>], b: &[Three<f64>]) {
+        for (a, b) in a.iter().zip(b) {
+            assert!((*a - *b).length() < 1e-9, "{:?} is not {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn constant_images_stay_the_same() {
+        for variance in [None, Some(0.0), Some(0.1)] {
+            let buffers = buffers(variance);
+            assert_colors_near(&Denoiser::default().denoise(&buffers), &buffers.color);
+        }
+    }
+
+    #[test]
+    fn edges_in_the_normals_arent_blurred_across() {
+        // the left half faces up and is dark, the right half faces sideways and is bright, and
+        // noisy enough that only the normals tell the two apart
+        for variance in [None, Some(1.0)] {
+            let mut buffers = buffers(variance);
+            for pixel_idx in 0..WIDTH * HEIGHT {
+                if pixel_idx % WIDTH >= WIDTH / 2 {
+                    buffers.aovs.normal[pixel_idx] = Three::new(1.0, 0.0, 0.0);
+                    buffers.color[pixel_idx] = Three::from(0.9);
+                }
+            }
+            let denoised = Denoiser::default().denoise(&buffers);
+            assert_colors_near(&denoised, &buffers.color);
+
+            // without the edge, the halves run together
+            buffers.aovs.normal = vec![Three::new(0.0, 1.0, 0.0); WIDTH * HEIGHT];
+            let blurred = Denoiser::default().denoise(&buffers);
+            assert!(
+                blurred[0].x > 0.4 && blurred[WIDTH - 1].x < 0.8,
+                "{:?}",
+                blurred
+            );
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use super::float;
+use crate::data::Three;
+use crate::shapes::Triangle;
+use crate::texture::ImageTexture;
+use num_traits::Float;
+
+/// Bakes colors given at the corners of each triangle into an image, and points the texture
+/// coordinates of the triangles into it so that looking them up interpolates the colors across
+/// each triangle like its uvs.
+///
+/// Every triangle gets its own 2x2 texels: its three corner colors, and a fourth one that makes
+/// bilinear filtering between them exactly linear.
+pub(super) fn color_atlas<F: Float>(
+    triangles: Vec<Triangle<F>>,
+    colors: &[[Three<F>; 3]],
+) -> (Vec<Triangle<F>>, ImageTexture<F>) {
+    let columns = (triangles.len() as f64).sqrt().ceil().max(1.0) as usize;
+    let rows = triangles.len().div_ceil(columns).max(1);
+    let (width, height) = (2 * columns, 2 * rows);
+    let mut texels = vec![Three::zeros(); width * height];
+    let uv = |x: usize, y: usize| {
+        // texel centers, with v going up the image
+        (
+            float((x as f64 + 0.5) / width as f64),
+            float(1.0 - (y as f64 + 0.5) / height as f64),
+        )
+    };
+    let triangles = triangles
+        .into_iter()
+        .zip(colors)
+        .enumerate()
+        .map(|(i, (triangle, &[c0, c1, c2]))| {
+            let (x, y) = (2 * (i % columns), 2 * (i / columns));
+            texels[y * width + x] = c0;
+            texels[y * width + x + 1] = c1;
+            texels[(y + 1) * width + x] = c2;
+            texels[(y + 1) * width + x + 1] = c1 + c2 - c0;
+            triangle.with_uvs(uv(x, y), uv(x + 1, y), uv(x, y + 1))
+        })
+        .collect();
+    (triangles, ImageTexture::from_texels(width, height, texels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looking_up_the_atlas_interpolates_corner_colors() {
+        let triangle = Triangle::new(
+            Three::zeros(),
+            Three::new(1.0, 0.0, 0.0),
+            Three::new(0.0, 1.0, 0.0),
+        );
+        let colors = [
+            [
+                Three::new(1.0, 0.0, 0.0),
+                Three::new(0.0, 1.0, 0.0),
+                Three::new(0.0, 0.0, 1.0),
+            ],
+            [
+                Three::new(0.2, 0.4, 0.6),
+                Three::new(0.8, 0.8, 0.8),
+                Three::new(0.0, 0.5, 1.0),
+            ],
+            [Three::new(0.5, 0.5, 0.5); 3],
+        ];
+        let (triangles, atlas) = color_atlas(vec![triangle; 3], &colors);
+        for (triangle, &[c0, c1, c2]) in triangles.iter().zip(&colors) {
+            let [uv0, uv1, uv2] = triangle.uvs();
+            for (b1, b2) in [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (0.25, 0.5), (0.3, 0.3)] {
+                let b0 = 1.0 - b1 - b2;
+                let uv = (
+                    uv0.0 * b0 + uv1.0 * b1 + uv2.0 * b2,
+                    uv0.1 * b0 + uv1.1 * b1 + uv2.1 * b2,
+                );
+                let expected = c0 * b0 + c1 * b1 + c2 * b2;
+                let color = atlas.lookup(uv);
+                assert!(
+                    (color - expected).length() < 1e-9,
+                    "{:?} is not {:?}",
+                    color,
+                    expected
+                );
+            }
+        }
+    }
+}
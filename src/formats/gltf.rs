@@ -0,0 +1,565 @@
+use super::{float, three, LoadError};
+use crate::data::{
+    Camera, Dielectric, Diffuse, FieldOfView, ImageShape, Light, Material, Mirror, Three,
+};
+use crate::lights::{DirectionalLight, PointLight, SpotLight};
+use crate::scene::{MaterialIdx, Scene};
+use crate::shapes::{Mesh, Triangle};
+use crate::texture::{ImageTexture, Texture};
+use crate::transform::Transform;
+use ::gltf::khr_lights_punctual::Kind;
+use ::gltf::mesh::Mode;
+use ::gltf::{buffer, camera::Projection, image, Gltf, Node};
+use data_url::DataUrl;
+use num_traits::{Float, FloatConst};
+use percent_encoding::percent_decode_str;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Width of the images of glTF cameras, whose heights follow from their aspect ratios.
+const CAMERA_WIDTH: usize = 1280;
+
+/// Everything a glTF file describes.
+pub struct GltfScene<F> {
+    pub scene: Scene<F>,
+    /// The first camera in the file, if it has one.
+    pub camera: Option<Camera<F>>,
+    /// Parts of the file that were skipped or approximated.
+    pub warnings: Vec<String>,
+}
+
+/// Loads the default scene of a glTF 2.0 file, either `.gltf` JSON or binary `.glb`. Buffers and
+/// images can be embedded, in data URIs or the binary chunk, or in files next to it.
+///
+/// Mesh primitives become triangle meshes placed by their node transforms. Metallic-roughness
+/// materials become the closest teeracer material: emissive ones lights, transmissive ones
+/// dielectrics, smooth metals mirrors, and everything else diffuse, with its base color
+/// texture. Cameras and `KHR_lights_punctual` lights are loaded too, with photometric light
+/// intensities divided by 683 lm/W to get the watts teeracer's lights use. glTF doesn't say how
+/// big images should be, so cameras are 1280 pixels wide with their aspect ratio, until
+/// `Camera::with_image_shape` sets another resolution.
+///
+/// Materials keep their names, and objects are named by the path of node names down to them, for
+/// `Scene::material_named` and `Scene::object_named`.
+pub fn load_gltf<F, P>(path: P) -> Result<GltfScene<F>, LoadError>
+where
+    F: Float + FloatConst,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let bytes = std::fs::read(path).map_err(|error| LoadError::io(path, error))?;
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    parse_gltf(&bytes, base_dir).map_err(|error| error.in_file(path))
+}
+
+/// Parses the contents of a glTF file, with external files it refers to relative to `base_dir`.
+pub fn parse_gltf<F>(bytes: &[u8], base_dir: &Path) -> Result<GltfScene<F>, LoadError>
+where
+    F: Float + FloatConst,
+{
+    let Gltf { document, blob } =
+        Gltf::from_slice(bytes).map_err(|error| LoadError::invalid(None, error.to_string()))?;
+
+    let mut buffers = Vec::new();
+    for buffer in document.buffers() {
+        let data = match buffer.source() {
+            buffer::Source::Bin => blob
+                .clone()
+                .ok_or_else(|| LoadError::invalid(None, "glb has no binary chunk"))?,
+            buffer::Source::Uri(uri) => read_uri(uri, base_dir)?,
+        };
+        if data.len() < buffer.length() {
+            return Err(LoadError::invalid(
+                None,
+                format!("buffer {} is shorter than it says", buffer.index()),
+            ));
+        }
+        buffers.push(data);
+    }
+
+    let mut importer = Importer {
+        scene: Scene::new(),
+        camera: None,
+        warnings: Vec::new(),
+        materials: HashMap::new(),
+        textures: HashMap::new(),
+        buffers,
+        base_dir: base_dir.to_path_buf(),
+    };
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next());
+    match scene {
+        Some(scene) => {
+            for node in scene.nodes() {
+                importer.node(&node, &Transform::identity(), "")?;
+            }
+        }
+        None => importer.warnings.push("file has no scenes".to_string()),
+    }
+    Ok(GltfScene {
+        scene: importer.scene,
+        camera: importer.camera,
+        warnings: importer.warnings,
+    })
+}
+
+struct Importer<F> {
+    scene: Scene<F>,
+    camera: Option<Camera<F>>,
+    warnings: Vec<String>,
+    /// Scene materials of glTF materials, by their index, or `None` for the default material.
+    materials: HashMap<Option<usize>, MaterialIdx>,
+    /// Decoded images, by their index.
+    textures: HashMap<usize, ImageTexture<F>>,
+    buffers: Vec<Vec<u8>>,
+    base_dir: PathBuf,
+}
+
+impl<F> Importer<F>
+where
+    F: Float + FloatConst,
+{
+    /// Adds the contents of a node and its children. Objects are named by their paths of node
+    /// names, like `"house/door"`.
+    fn node(
+        &mut self,
+        node: &Node,
+        parent: &Transform<f64>,
+        parent_path: &str,
+    ) -> Result<(), LoadError> {
+        let name = node.name().map_or_else(
+            || format!("node {}", node.index()),
+            |name| format!("node \"{}\"", name),
+        );
+        // glTF matrices are column major
+        let local = node.transform().matrix();
+        let mut m = [[0.0; 4]; 4];
+        for (col, column) in local.iter().enumerate() {
+            for (row, value) in column.iter().enumerate() {
+                m[row][col] = *value as f64;
+            }
+        }
+        let local = match Transform::from_matrix(m) {
+            Some(local) => local,
+            None => {
+                // e.g. scaled to zero to hide it, which hides its children too
+                self.warnings
+                    .push(format!("{}: flattened by its transform and skipped", name));
+                return Ok(());
+            }
+        };
+        let world = *parent * local;
+        let path = match (parent_path, node.name()) {
+            ("", Some(name)) => name.to_string(),
+            ("", None) => format!("node {}", node.index()),
+            (parent, Some(name)) => format!("{}/{}", parent, name),
+            (parent, None) => format!("{}/node {}", parent, node.index()),
+        };
+
+        if let Some(mesh) = node.mesh() {
+            let count = mesh.primitives().len();
+            for (i, primitive) in mesh.primitives().enumerate() {
+                let object_name = match count {
+                    1 => path.clone(),
+                    _ => format!("{}/{}", path, i),
+                };
+                self.primitive(&primitive, &world, &name, object_name)?;
+            }
+        }
+        if let Some(camera) = node.camera() {
+            self.add_camera(&camera, &world, &name);
+        }
+        if let Some(light) = node.light() {
+            self.add_light(&light, &world);
+        }
+        for child in node.children() {
+            self.node(&child, &world, &path)?;
+        }
+        Ok(())
+    }
+
+    fn primitive(
+        &mut self,
+        primitive: &::gltf::Primitive,
+        world: &Transform<f64>,
+        name: &str,
+        object_name: String,
+    ) -> Result<(), LoadError> {
+        let buffers = &self.buffers;
+        let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+        let positions: Vec<Three<F>> = match reader.read_positions() {
+            Some(positions) => positions
+                .map(|[x, y, z]| three(world.point(&Three::new(x as f64, y as f64, z as f64))))
+                .collect(),
+            None => {
+                self.warnings
+                    .push(format!("{}: primitive without positions skipped", name));
+                return Ok(());
+            }
+        };
+        let material = primitive.material();
+        let tex_coord = material
+            .pbr_metallic_roughness()
+            .base_color_texture()
+            .map_or(0, |info| info.tex_coord());
+        // glTF's v goes down the image, ours up it
+        let uvs: Option<Vec<(F, F)>> = reader.read_tex_coords(tex_coord).map(|uvs| {
+            uvs.into_f32()
+                .map(|[u, v]| (float(u as f64), float(1.0 - v as f64)))
+                .collect()
+        });
+        let uvs = uvs.filter(|uvs| uvs.len() >= positions.len());
+        let indices: Vec<usize> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().map(|i| i as usize).collect(),
+            None => (0..positions.len()).collect(),
+        };
+        if indices.iter().any(|&i| i >= positions.len()) {
+            return Err(LoadError::invalid(
+                None,
+                format!("{}: indices refer to missing vertices", name),
+            ));
+        }
+
+        let corners: Vec<[usize; 3]> = match primitive.mode() {
+            Mode::Triangles => indices
+                .chunks_exact(3)
+                .map(|c| [c[0], c[1], c[2]])
+                .collect(),
+            Mode::TriangleStrip => (2..indices.len())
+                .map(|i| {
+                    // every other triangle of a strip is wound the other way
+                    if i % 2 == 0 {
+                        [indices[i - 2], indices[i - 1], indices[i]]
+                    } else {
+                        [indices[i - 1], indices[i - 2], indices[i]]
+                    }
+                })
+                .collect(),
+            Mode::TriangleFan => (2..indices.len())
+                .map(|i| [indices[0], indices[i - 1], indices[i]])
+                .collect(),
+            mode => {
+                self.warnings
+                    .push(format!("{}: {:?} primitives are not supported", name, mode));
+                return Ok(());
+            }
+        };
+        // mirroring transforms turn counter-clockwise front faces clockwise
+        let flip = world.determinant() < 0.0;
+        let triangles: Vec<Triangle<F>> = corners
+            .into_iter()
+            .map(|[i0, i1, i2]| {
+                let [i0, i1, i2] = if flip { [i0, i2, i1] } else { [i0, i1, i2] };
+                let triangle = Triangle::new(positions[i0], positions[i1], positions[i2]);
+                match &uvs {
+                    Some(uvs) => triangle.with_uvs(uvs[i0], uvs[i1], uvs[i2]),
+                    None => triangle,
+                }
+            })
+            .collect();
+        if triangles.is_empty() {
+            return Ok(());
+        }
+        let material = self.material(&material)?;
+        self.scene
+            .add_named_object(object_name, Mesh::new(triangles), material);
+        Ok(())
+    }
+
+    fn material(&mut self, material: &::gltf::Material) -> Result<MaterialIdx, LoadError> {
+        if let Some(&idx) = self.materials.get(&material.index()) {
+            return Ok(idx);
+        }
+        let name = material.name().map_or_else(
+            || format!("material {}", material.index().unwrap_or(0)),
+            |name| format!("material \"{}\"", name),
+        );
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, _] = pbr.base_color_factor();
+        let base_color = Three::new(float(r as f64), float(g as f64), float(b as f64));
+        let base_texture = match pbr.base_color_texture() {
+            Some(info) => Some(self.texture(&info.texture().source())?),
+            None => None,
+        };
+        let strength = material.emissive_strength().unwrap_or(1.0);
+        let [er, eg, eb] = material
+            .emissive_factor()
+            .map(|e| float::<F>((e * strength) as f64));
+        let emission = Three::new(er, eg, eb);
+        let transmission = material
+            .transmission()
+            .map_or(0.0, |transmission| transmission.transmission_factor());
+
+        let converted: Material<F> = if emission.max_component() > F::zero() {
+            let mut light = Light::new(emission, F::one());
+            if !material.double_sided() {
+                light = light.one_sided();
+            }
+            if let Some(info) = material.emissive_texture() {
+                light = light.textured(self.texture(&info.texture().source())?);
+            }
+            light.into()
+        } else if transmission >= 0.5 {
+            Dielectric {
+                rgb: base_color,
+                ior: float(material.ior().unwrap_or(1.5) as f64),
+            }
+            .into()
+        } else if pbr.metallic_factor() >= 0.5 && pbr.roughness_factor() < 0.5 {
+            if pbr.roughness_factor() > 0.1 {
+                self.warnings
+                    .push(format!("{}: rough metal is rendered as a mirror", name));
+            }
+            Mirror { rgb: base_color }.into()
+        } else {
+            Diffuse {
+                rgb: base_color,
+                texture: base_texture.map(Texture::from),
+            }
+            .into()
+        };
+        let idx = match material.name() {
+            Some(name) => self.scene.add_named_material(name, converted),
+            None => self.scene.add_material(converted),
+        };
+        self.materials.insert(material.index(), idx);
+        Ok(idx)
+    }
+
+    fn texture(&mut self, image: &::gltf::Image) -> Result<ImageTexture<F>, LoadError> {
+        if let Some(texture) = self.textures.get(&image.index()) {
+            return Ok(texture.clone());
+        }
+        let bytes = match image.source() {
+            image::Source::View { view, .. } => {
+                let buffer = &self.buffers[view.buffer().index()];
+                buffer
+                    .get(view.offset()..view.offset() + view.length())
+                    .ok_or_else(|| {
+                        LoadError::invalid(
+                            None,
+                            format!("image {} is out of bounds", image.index()),
+                        )
+                    })?
+                    .to_vec()
+            }
+            image::Source::Uri { uri, .. } => read_uri(uri, &self.base_dir)?,
+        };
+        let decoded = ::image::load_from_memory(&bytes).map_err(|error| {
+            LoadError::invalid(None, format!("image {}: {}", image.index(), error))
+        })?;
+        let texture = ImageTexture::from_image(&decoded);
+        self.textures.insert(image.index(), texture.clone());
+        Ok(texture)
+    }
+
+    fn add_camera(&mut self, camera: &::gltf::Camera, world: &Transform<f64>, name: &str) {
+        if self.camera.is_some() {
+            self.warnings
+                .push(format!("{}: only the first camera is used", name));
+            return;
+        }
+        let perspective = match camera.projection() {
+            Projection::Perspective(perspective) => perspective,
+            Projection::Orthographic(_) => {
+                self.warnings
+                    .push(format!("{}: orthographic cameras are not supported", name));
+                return;
+            }
+        };
+        // glTF leaves the resolution to the viewer, so only the shape of the image is known
+        let aspect_ratio = perspective.aspect_ratio().unwrap_or(16.0 / 9.0) as f64;
+        let width = CAMERA_WIDTH;
+        let height = (width as f64 / aspect_ratio).round().max(1.0) as usize;
+        let mut teeracer_camera = Camera::new(
+            FieldOfView::Degrees(float((perspective.yfov() as f64).to_degrees())),
+            ImageShape { width, height },
+        );
+        let position = world.point(&Three::zeros());
+        teeracer_camera =
+            teeracer_camera.at(float(position.x), float(position.y), float(position.z));
+        // glTF cameras look along -z, with y up and x to the right
+        let axis = |x, y, z| three(world.vector(&Three::new(x, y, z)).normalized());
+        teeracer_camera.right = axis(1.0, 0.0, 0.0);
+        teeracer_camera.up = axis(0.0, 1.0, 0.0);
+        teeracer_camera.forward = axis(0.0, 0.0, -1.0);
+        self.camera = Some(teeracer_camera);
+    }
+
+    fn add_light(&mut self, light: &::gltf::khr_lights_punctual::Light, world: &Transform<f64>) {
+        let [r, g, b] = light.color();
+        let rgb = Three::new(float(r as f64), float(g as f64), float(b as f64));
+        // candela and lux to watts per steradian and square meter
+        let power = float(light.intensity() as f64 / 683.0);
+        let position = three(world.point(&Three::zeros()));
+        let direction = three(world.vector(&Three::new(0.0, 0.0, -1.0)).normalized());
+        match light.kind() {
+            Kind::Point => self.scene.add_light(PointLight {
+                position,
+                rgb,
+                power,
+            }),
+            Kind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => self.scene.add_light(SpotLight {
+                position,
+                direction,
+                rgb,
+                power,
+                cone_angle: float((outer_cone_angle as f64).to_degrees()),
+                falloff_start: float((inner_cone_angle as f64).to_degrees()),
+                profile: None,
+            }),
+            Kind::Directional => self.scene.add_light(DirectionalLight {
+                direction,
+                rgb,
+                power,
+            }),
+        }
+    }
+}
+
+/// Contents of a `data:` URI, or of a file relative to `base_dir`.
+fn read_uri(uri: &str, base_dir: &Path) -> Result<Vec<u8>, LoadError> {
+    if uri.starts_with("data:") {
+        let url = DataUrl::process(uri)
+            .map_err(|error| LoadError::invalid(None, format!("invalid data URI: {:?}", error)))?;
+        let (data, _) = url
+            .decode_to_vec()
+            .map_err(|_| LoadError::invalid(None, "invalid base64 data"))?;
+        Ok(data)
+    } else {
+        let path = base_dir.join(&*percent_decode_str(uri).decode_utf8_lossy());
+        std::fs::read(&path).map_err(|error| LoadError::io(&path, error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Bounded;
+    use crate::lights::DeltaLight;
+
+    /// A floor, a textured crate, a steel cube, a glowing panel, a point light and a camera, all
+    /// in one binary file.
+    fn load_crate() -> GltfScene<f64> {
+        parse_gltf(include_bytes!("../../scenes/crate.glb"), Path::new("")).unwrap()
+    }
+
+    fn assert_near(a: Three<f64>, b: Three<f64>) {
+        assert!((a - b).length() < 1e-6, "{:?} is not {:?}", a, b);
+    }
+
+    #[test]
+    fn nodes_place_their_meshes() {
+        let gltf = load_crate();
+        let bounds = |name| {
+            let scene = &gltf.scene;
+            scene.object(scene.object_named(name).unwrap()).bounds()
+        };
+        let floor = bounds("floor");
+        assert_near(floor.min, Three::new(-4.0, 0.0, -4.0));
+        assert_near(floor.max, Three::new(4.0, 0.0, 4.0));
+        let steel = bounds("steel");
+        assert_near(steel.min, Three::new(0.5, 0.0, -0.7));
+        assert_near(steel.max, Three::new(1.3, 0.8, 0.1));
+        // turned 30 degrees around y, so its corners stick out further along x and z
+        let crate_ = bounds("crate");
+        let reach = 0.5 * (30f64.to_radians().cos() + 30f64.to_radians().sin());
+        assert_near(crate_.min, Three::new(-0.6 - reach, 0.0, -reach));
+        assert_near(crate_.max, Three::new(-0.6 + reach, 1.0, reach));
+        // flipped upside down, to shine down onto the floor
+        let panel = bounds("panel");
+        assert_near(panel.min, Three::new(-0.6, 3.0, -0.6));
+        assert_near(panel.max, Three::new(0.6, 3.0, 0.6));
+        assert!(gltf.warnings.is_empty(), "{:?}", gltf.warnings);
+    }
+
+    #[test]
+    fn materials_map_to_the_closest_ones() {
+        let gltf = load_crate();
+        let material = |name| {
+            gltf.scene
+                .material(gltf.scene.material_named(name).unwrap())
+        };
+        match material("floor") {
+            Material::Diffuse(diffuse) => {
+                assert_near(diffuse.rgb, Three::new(0.7, 0.7, 0.7));
+                assert!(diffuse.texture.is_none());
+            }
+            other => panic!("{:?}", other),
+        }
+        match material("crate") {
+            Material::Diffuse(diffuse) => match &diffuse.texture {
+                Some(Texture::Image(image)) => assert!(image.size().0 > 0),
+                other => panic!("{:?}", other),
+            },
+            other => panic!("{:?}", other),
+        }
+        match material("steel") {
+            Material::Mirror(mirror) => assert_near(mirror.rgb, Three::new(0.9, 0.9, 0.9)),
+            other => panic!("{:?}", other),
+        }
+        match material("panel") {
+            Material::Light(light) => {
+                assert_near(light.rgb, Three::new(8.0, 7.6, 6.8));
+                assert!(!light.two_sided);
+            }
+            other => panic!("{:?}", other),
+        }
+        let panel = gltf.scene.object_named("panel").unwrap();
+        assert_eq!(
+            gltf.scene.material_idx(panel),
+            gltf.scene.material_named("panel").unwrap()
+        );
+    }
+
+    #[test]
+    fn camera_keeps_its_place_and_aspect_ratio() {
+        let camera = load_crate().camera.unwrap();
+        assert_near(camera.position, Three::new(0.0, 1.6, 4.5));
+        // tilted 15 degrees down
+        let tilt = 15f64.to_radians();
+        assert_near(camera.forward, Three::new(0.0, -tilt.sin(), -tilt.cos()));
+        assert_near(camera.right, Three::new(1.0, 0.0, 0.0));
+        assert_eq!((camera.width(), camera.height()), (CAMERA_WIDTH, 853));
+        // y_transform's offset is tan(yfov / 2)
+        assert!((camera.y_transform.offset - 0.35f64.tan()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn punctual_lights_are_in_watts() {
+        let gltf = load_crate();
+        match gltf.scene.lights() {
+            [DeltaLight::Point(light)] => {
+                assert_near(light.position, Three::new(2.0, 2.5, 2.0));
+                assert_near(light.rgb, Three::new(1.0, 0.9, 0.8));
+                assert!((light.power - 20000.0 / 683.0).abs() < 1e-6);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn uris_are_decoded() {
+        assert_eq!(
+            read_uri(
+                "data:application/octet-stream;base64,AAEC/w==",
+                Path::new("")
+            )
+            .unwrap(),
+            [0, 1, 2, 255]
+        );
+        assert_eq!(
+            read_uri("data:text/plain,a%20b", Path::new("")).unwrap(),
+            b"a b"
+        );
+        let dir = std::env::temp_dir().join("teeracer_gltf_uri_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("two words.bin"), [7u8, 8]).unwrap();
+        assert_eq!(read_uri("two%20words.bin", &dir).unwrap(), [7, 8]);
+        assert!(read_uri("missing.bin", &dir).is_err());
+    }
+}
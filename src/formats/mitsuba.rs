@@ -0,0 +1,916 @@
+use super::{
+    float, line_of, load_obj, load_ply, three, transformed_disk, transformed_mesh, LoadError,
+    RenderSettings, SceneDescription, TracerKind,
+};
+use crate::data::{
+    Camera, Dielectric, Diffuse, FieldOfView, ImageShape, Light, Material, Mirror, Three,
+};
+use crate::lights::{DirectionalLight, Environment, PointLight, SpotLight};
+use crate::scene::{MaterialIdx, Scene};
+use crate::shapes::{Mesh, Sphere, Triangle};
+use crate::texture::{ImageTexture, Texture};
+use crate::transform::Transform;
+use num_traits::{Float, FloatConst};
+use roxmltree::{Document, Node};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Loads a Mitsuba 2 or 3 XML scene. Paths in it, like meshes and environment maps, are
+/// relative to the file.
+///
+/// Supported are `perspective` sensors with their film and sampler, `obj`, `ply`, `sphere`,
+/// `disk`, `rectangle` and `cube` shapes, `diffuse`, `conductor`, `roughconductor` and `dielectric`
+/// bsdfs, and `area`, `envmap`, `constant`, `point`, `spot` and `directional` emitters, along
+/// with `<default>` parameters and `to_world` transforms. Anything else is skipped or
+/// approximated with a warning in `SceneDescription::warnings`.
+pub fn load_mitsuba<F, P>(path: P) -> Result<SceneDescription<F>, LoadError>
+where
+    F: Float + FloatConst,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let source = std::fs::read_to_string(path).map_err(|error| LoadError::io(path, error))?;
+    let document = Document::parse(&source).map_err(|error| {
+        LoadError::invalid(Some(error.pos().row as usize), error.to_string()).in_file(path)
+    })?;
+    let mut importer = Importer::new(&source, path);
+    importer
+        .scene_element(document.root_element())
+        .map_err(|error| error.in_file(path))?;
+    Ok(importer.finish())
+}
+
+/// Index of refraction of the materials Mitsuba knows by name.
+const NAMED_IORS: [(&str, f64); 23] = [
+    ("vacuum", 1.0),
+    ("helium", 1.000036),
+    ("hydrogen", 1.000132),
+    ("air", 1.000277),
+    ("carbon dioxide", 1.00045),
+    ("water", 1.333),
+    ("acetone", 1.36),
+    ("ethanol", 1.361),
+    ("carbon tetrachloride", 1.461),
+    ("glycerol", 1.4729),
+    ("benzene", 1.501),
+    ("silicone oil", 1.52045),
+    ("bromine", 1.661),
+    ("water ice", 1.31),
+    ("fused quartz", 1.458),
+    ("pyrex", 1.47),
+    ("acrylic glass", 1.49),
+    ("polypropylene", 1.49),
+    ("bk7", 1.5046),
+    ("sodium chloride", 1.544),
+    ("amber", 1.55),
+    ("pet", 1.575),
+    ("diamond", 2.419),
+];
+
+struct Importer<'a, F> {
+    source: &'a str,
+    base_dir: PathBuf,
+    scene: Scene<F>,
+    /// Values of `<default>` parameters, substituted for `$name` in attributes.
+    defaults: HashMap<String, String>,
+    default_material: Option<MaterialIdx>,
+    /// Camera to world transform and vertical field of view in degrees.
+    camera: Option<(Transform<f64>, f64)>,
+    resolution: (usize, usize),
+    settings: RenderSettings,
+    warnings: Vec<String>,
+    file: PathBuf,
+}
+
+impl<'a, F> Importer<'a, F>
+where
+    F: Float + FloatConst,
+{
+    fn new(source: &'a str, file: &Path) -> Self {
+        Self {
+            source,
+            base_dir: file.parent().unwrap_or(Path::new("")).to_path_buf(),
+            scene: Scene::new(),
+            defaults: HashMap::new(),
+            default_material: None,
+            camera: None,
+            // Mitsuba's defaults
+            resolution: (768, 576),
+            settings: RenderSettings {
+                tracer: TracerKind::Path,
+                samples: 4,
+                depth: 64,
+            },
+            warnings: Vec::new(),
+            file: file.to_path_buf(),
+        }
+    }
+
+    fn line(&self, node: Node) -> usize {
+        line_of(self.source, node.range().start)
+    }
+
+    fn warn<M: AsRef<str>>(&mut self, node: Node, message: M) {
+        self.warnings.push(format!(
+            "{}:{}: {}",
+            self.file.display(),
+            self.line(node),
+            message.as_ref()
+        ));
+    }
+
+    fn error<M: Into<String>>(&self, node: Node, message: M) -> LoadError {
+        LoadError::invalid(Some(self.line(node)), message)
+    }
+
+    fn finish(self) -> SceneDescription<F> {
+        let (width, height) = self.resolution;
+        let (world_from_camera, fov) = self.camera.unwrap_or((Transform::identity(), 45.0));
+        let mut camera = Camera::new(
+            FieldOfView::Degrees(float(fov)),
+            ImageShape { width, height },
+        );
+        let position = world_from_camera.point(&Three::zeros());
+        camera = camera.at(float(position.x), float(position.y), float(position.z));
+        // Mitsuba's cameras look along z with x pointing to the left of the image
+        let axis = |x, y, z| three(world_from_camera.vector(&Three::new(x, y, z)).normalized());
+        camera.right = axis(-1.0, 0.0, 0.0);
+        camera.up = axis(0.0, 1.0, 0.0);
+        camera.forward = axis(0.0, 0.0, 1.0);
+        SceneDescription {
+            scene: self.scene,
+            camera,
+            settings: self.settings,
+            warnings: self.warnings,
+        }
+    }
+
+    /// An attribute, with `$name`s of `<default>` parameters replaced by their values.
+    fn attribute(&self, node: Node, name: &str) -> Option<String> {
+        let mut value = node.attribute(name)?.to_string();
+        if value.contains('$') {
+            // longest first, so `$spp` doesn't replace the start of `$spp_max`
+            let mut defaults: Vec<_> = self.defaults.iter().collect();
+            defaults.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+            for (name, default) in defaults {
+                value = value.replace(&format!("${}", name), default);
+            }
+        }
+        Some(value)
+    }
+
+    /// The child element with the attribute `name="..."`.
+    fn param<'n>(&self, node: Node<'n, 'n>, name: &str) -> Option<Node<'n, 'n>> {
+        node.children()
+            .filter(Node::is_element)
+            .find(|child| child.attribute("name") == Some(name))
+    }
+
+    fn numbers(&self, node: Node, attribute: &str) -> Result<Vec<f64>, LoadError> {
+        let value = self.attribute(node, attribute).unwrap_or_default();
+        value
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|word| !word.is_empty())
+            .map(|word| word.parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| {
+                self.error(
+                    node,
+                    format!("expected numbers in {}=\"{}\"", attribute, value),
+                )
+            })
+    }
+
+    /// A `<float>` or `<integer>` parameter.
+    fn float(&self, node: Node, name: &str) -> Result<Option<f64>, LoadError> {
+        match self.param(node, name) {
+            Some(param) => match self.numbers(param, "value")?[..] {
+                [value] => Ok(Some(value)),
+                _ => Err(self.error(param, format!("{} should be one number", name))),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn string(&self, node: Node, name: &str) -> Option<String> {
+        self.attribute(self.param(node, name)?, "value")
+    }
+
+    fn boolean(&self, node: Node, name: &str) -> Option<bool> {
+        Some(self.string(node, name)? == "true")
+    }
+
+    /// A `<point>` or `<vector>` parameter, given by `x`, `y` and `z` or a `value`.
+    fn point(&self, node: Node, name: &str) -> Result<Option<Three<f64>>, LoadError> {
+        let param = match self.param(node, name) {
+            Some(param) => param,
+            None => return Ok(None),
+        };
+        if param.has_attribute("value") {
+            return match self.numbers(param, "value")?[..] {
+                [x, y, z] => Ok(Some(Three::new(x, y, z))),
+                [v] => Ok(Some(Three::new(v, v, v))),
+                _ => Err(self.error(param, format!("{} should be three numbers", name))),
+            };
+        }
+        let mut xyz = [0.0; 3];
+        for (value, axis) in xyz.iter_mut().zip(["x", "y", "z"]) {
+            if param.has_attribute(axis) {
+                *value = self.numbers(param, axis)?.first().copied().unwrap_or(0.0);
+            }
+        }
+        Ok(Some(Three::new(xyz[0], xyz[1], xyz[2])))
+    }
+
+    /// A color parameter, or `default` if it's missing. Spectra are averaged, and textures
+    /// replaced by `default`, with a warning.
+    fn color(
+        &mut self,
+        node: Node,
+        name: &str,
+        default: Three<f64>,
+    ) -> Result<Three<f64>, LoadError> {
+        let param = match self.param(node, name) {
+            Some(param) => param,
+            None => return Ok(default),
+        };
+        match param.tag_name().name() {
+            "rgb" | "srgb" | "color" => match self.numbers(param, "value")?[..] {
+                [r, g, b] => Ok(Three::new(r, g, b)),
+                [v] => Ok(Three::new(v, v, v)),
+                _ => Err(self.error(param, format!("{} should be three numbers", name))),
+            },
+            "float" => {
+                let v = self
+                    .numbers(param, "value")?
+                    .first()
+                    .copied()
+                    .unwrap_or(0.0);
+                Ok(Three::new(v, v, v))
+            }
+            "spectrum" => {
+                let value = self.attribute(param, "value").unwrap_or_default();
+                if !value.contains(':') {
+                    let v = self
+                        .numbers(param, "value")?
+                        .first()
+                        .copied()
+                        .unwrap_or(0.0);
+                    return Ok(Three::new(v, v, v));
+                }
+                // wavelength:value pairs
+                let values: Vec<f64> = value
+                    .split(',')
+                    .filter_map(|pair| pair.split(':').nth(1)?.trim().parse().ok())
+                    .collect();
+                let mean = values.iter().sum::<f64>() / values.len().max(1) as f64;
+                self.warn(param, format!("spectrum {} is rendered as grey", name));
+                Ok(Three::new(mean, mean, mean))
+            }
+            other => {
+                self.warn(
+                    param,
+                    format!("{} {} is not supported, using {:?}", other, name, default),
+                );
+                Ok(default)
+            }
+        }
+    }
+
+    /// The `to_world` transform of an element, or the identity if it has none.
+    fn to_world(&self, node: Node) -> Result<Transform<f64>, LoadError> {
+        let transform = match node
+            .children()
+            .find(|child| child.has_tag_name("transform"))
+        {
+            Some(transform) => transform,
+            None => return Ok(Transform::identity()),
+        };
+        let mut matrix = Transform::identity();
+        for op in transform.children().filter(Node::is_element) {
+            let xyz = |default: f64| -> Result<Three<f64>, LoadError> {
+                if op.has_attribute("value") {
+                    return match self.numbers(op, "value")?[..] {
+                        [x, y, z] => Ok(Three::new(x, y, z)),
+                        [v] => Ok(Three::new(v, v, v)),
+                        _ => Err(self.error(op, "expected one or three numbers")),
+                    };
+                }
+                let mut xyz = [default; 3];
+                for (value, axis) in xyz.iter_mut().zip(["x", "y", "z"]) {
+                    if op.has_attribute(axis) {
+                        *value = self.numbers(op, axis)?.first().copied().unwrap_or(default);
+                    }
+                }
+                Ok(Three::new(xyz[0], xyz[1], xyz[2]))
+            };
+            let vector = |name: &str| -> Result<Three<f64>, LoadError> {
+                match self.numbers(op, name)?[..] {
+                    [x, y, z] => Ok(Three::new(x, y, z)),
+                    _ => Err(self.error(op, format!("{} should be three numbers", name))),
+                }
+            };
+            let step = match op.tag_name().name() {
+                "translate" => Transform::translation(xyz(0.0)?),
+                "scale" => {
+                    let factors = xyz(1.0)?;
+                    if factors.x == 0.0 || factors.y == 0.0 || factors.z == 0.0 {
+                        return Err(self.error(op, "scale can't be zero"));
+                    }
+                    Transform::scaling(factors)
+                }
+                "rotate" => {
+                    let angle = self.numbers(op, "angle")?.first().copied().unwrap_or(0.0);
+                    let axis = if op.has_attribute("axis") {
+                        vector("axis")?
+                    } else {
+                        xyz(0.0)?
+                    };
+                    // counter-clockwise looking down the axis, the other way from ours
+                    Transform::rotation(&axis, -angle)
+                }
+                "matrix" => {
+                    let values = self.numbers(op, "value")?;
+                    let mut m = [[0.0; 4]; 4];
+                    m[3][3] = 1.0;
+                    match values.len() {
+                        16 => (0..16).for_each(|i| m[i / 4][i % 4] = values[i]),
+                        9 => (0..9).for_each(|i| m[i / 3][i % 3] = values[i]),
+                        _ => return Err(self.error(op, "matrix should be 9 or 16 numbers")),
+                    }
+                    Transform::from_matrix(m)
+                        .ok_or_else(|| self.error(op, "matrix can't be inverted"))?
+                }
+                "lookat" | "look_at" => {
+                    let origin = vector("origin")?;
+                    let target = vector("target")?;
+                    let up = if op.has_attribute("up") {
+                        vector("up")?
+                    } else {
+                        Three::new(0.0, 1.0, 0.0)
+                    };
+                    Transform::looking_at(origin, target, up)
+                        .ok_or_else(|| self.error(op, "lookat's up is parallel to its direction"))?
+                }
+                other => {
+                    return Err(self.error(op, format!("unknown transform <{}>", other)));
+                }
+            };
+            // each step applies after the ones before it
+            matrix = step * matrix;
+        }
+        Ok(matrix)
+    }
+
+    fn scene_element(&mut self, root: Node) -> Result<(), LoadError> {
+        if !root.has_tag_name("scene") {
+            return Err(self.error(root, "expected a <scene>"));
+        }
+        for node in root.children().filter(Node::is_element) {
+            match node.tag_name().name() {
+                "default" => {
+                    let name = self.attribute(node, "name").unwrap_or_default();
+                    let value = self.attribute(node, "value").unwrap_or_default();
+                    self.defaults.insert(name, value);
+                }
+                "integrator" => self.integrator(node)?,
+                "sensor" => self.sensor(node)?,
+                "bsdf" => {
+                    self.bsdf(node)?;
+                }
+                "shape" => self.shape(node)?,
+                "emitter" => self.emitter(node)?,
+                other => self.warn(node, format!("<{}> is not supported", other)),
+            }
+        }
+        Ok(())
+    }
+
+    fn integrator(&mut self, node: Node) -> Result<(), LoadError> {
+        let kind = self.attribute(node, "type").unwrap_or_default();
+        self.settings.tracer = match kind.as_str() {
+            "path" | "volpath" | "volpathmis" | "direct" => TracerKind::Path,
+            "bdpt" => TracerKind::Bidirectional,
+            "sppm" | "ppm" => TracerKind::ProgressivePhotonMapping,
+            "photonmapper" => TracerKind::PhotonMapping,
+            "pssmlt" | "mlt" => TracerKind::Metropolis,
+            "aov" => TracerKind::Normals,
+            "moment" | "stokes" => {
+                // these wrap the integrator that does the work
+                if let Some(inner) = node.children().find(|c| c.has_tag_name("integrator")) {
+                    return self.integrator(inner);
+                }
+                TracerKind::Path
+            }
+            other => {
+                self.warn(
+                    node,
+                    format!("\"{}\" integrator is rendered as \"path\"", other),
+                );
+                TracerKind::Path
+            }
+        };
+        // -1 is unlimited
+        match self.float(node, "max_depth")? {
+            Some(depth) if depth >= 0.0 => self.settings.depth = depth as usize,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn sensor(&mut self, node: Node) -> Result<(), LoadError> {
+        let kind = self.attribute(node, "type").unwrap_or_default();
+        match kind.as_str() {
+            "perspective" => {}
+            "thinlens" => self.warn(node, "depth of field is ignored"),
+            other => self.warn(
+                node,
+                format!("\"{}\" sensors are rendered as perspective", other),
+            ),
+        }
+        if let Some(film) = node.children().find(|c| c.has_tag_name("film")) {
+            let width = self.float(film, "width")?.unwrap_or(768.0);
+            let height = self.float(film, "height")?.unwrap_or(576.0);
+            self.resolution = (width as usize, height as usize);
+        }
+        if let Some(sampler) = node.children().find(|c| c.has_tag_name("sampler")) {
+            if let Some(samples) = self.float(sampler, "sample_count")? {
+                self.settings.samples = samples as usize;
+            }
+        }
+        let (width, height) = (self.resolution.0 as f64, self.resolution.1 as f64);
+        let diagonal = width.hypot(height);
+        let (tan_half, axis) = match (self.float(node, "fov")?, self.string(node, "focal_length")) {
+            (Some(fov), _) => {
+                let axis = self
+                    .string(node, "fov_axis")
+                    .unwrap_or_else(|| "x".to_string());
+                ((fov.to_radians() / 2.0).tan(), axis)
+            }
+            (None, focal_length) => {
+                // focal lengths are for a 35mm film, whose diagonal is 43.27mm
+                let focal_length = focal_length.unwrap_or_else(|| "50mm".to_string());
+                let millimeters: f64 = focal_length
+                    .trim_end_matches("mm")
+                    .parse()
+                    .map_err(|_| self.error(node, "focal_length should be like 50mm"))?;
+                (43.266615 / (2.0 * millimeters), "diagonal".to_string())
+            }
+        };
+        let tan_half_y = match axis.as_str() {
+            "x" => tan_half * height / width,
+            "y" => tan_half,
+            "diagonal" => tan_half * height / diagonal,
+            "smaller" if width < height => tan_half * height / width,
+            "larger" if width > height => tan_half * height / width,
+            "smaller" | "larger" => tan_half,
+            other => return Err(self.error(node, format!("unknown fov_axis \"{}\"", other))),
+        };
+        let fov = 2.0 * tan_half_y.atan().to_degrees();
+        self.camera = Some((self.to_world(node)?, fov));
+        Ok(())
+    }
+
+    /// Adds the material of a `<bsdf>`, and names it if it has an `id`.
+    fn bsdf(&mut self, node: Node) -> Result<MaterialIdx, LoadError> {
+        let material = self.material(node)?;
+        Ok(match self.attribute(node, "id") {
+            Some(id) => self.scene.add_named_material(id, material),
+            None => self.scene.add_material(material),
+        })
+    }
+
+    fn material(&mut self, node: Node) -> Result<Material<F>, LoadError> {
+        let grey = Three::new(0.5, 0.5, 0.5);
+        let white = Three::new(1.0, 1.0, 1.0);
+        let kind = self.attribute(node, "type").unwrap_or_default();
+        let inner = node.children().find(|c| c.has_tag_name("bsdf"));
+        Ok(match (kind.as_str(), inner) {
+            // our materials are two sided already
+            ("twosided", Some(inner)) => self.material(inner)?,
+            ("mask" | "bumpmap" | "normalmap" | "blendbsdf", Some(inner)) => {
+                self.warn(node, format!("\"{}\" bsdf is ignored", kind));
+                self.material(inner)?
+            }
+            ("diffuse", _) => Diffuse {
+                rgb: three(self.color(node, "reflectance", grey)?),
+                texture: None,
+            }
+            .into(),
+            ("conductor" | "roughconductor", _) => {
+                if kind == "roughconductor" {
+                    self.warn(node, "rough conductors are rendered as perfect mirrors");
+                }
+                Mirror {
+                    rgb: three(self.color(node, "specular_reflectance", white)?),
+                }
+                .into()
+            }
+            ("dielectric" | "roughdielectric" | "thindielectric", _) => {
+                if kind != "dielectric" {
+                    self.warn(node, format!("\"{}\" is rendered as \"dielectric\"", kind));
+                }
+                let int_ior = self.ior(node, "int_ior", 1.5046)?;
+                let ext_ior = self.ior(node, "ext_ior", 1.000277)?;
+                Dielectric {
+                    rgb: three(self.color(node, "specular_transmittance", white)?),
+                    ior: float(int_ior / ext_ior),
+                }
+                .into()
+            }
+            (other, _) => {
+                let rgb = ["diffuse_reflectance", "base_color", "reflectance"]
+                    .iter()
+                    .find_map(|name| self.param(node, name))
+                    .and_then(|param| self.attribute(param, "name"));
+                let rgb = match rgb {
+                    Some(name) => self.color(node, &name, grey)?,
+                    None => grey,
+                };
+                self.warn(node, format!("\"{}\" bsdf is rendered as diffuse", other));
+                Diffuse {
+                    rgb: three(rgb),
+                    texture: None,
+                }
+                .into()
+            }
+        })
+    }
+
+    fn ior(&mut self, node: Node, name: &str, default: f64) -> Result<f64, LoadError> {
+        let param = match self.param(node, name) {
+            Some(param) => param,
+            None => return Ok(default),
+        };
+        if param.has_tag_name("float") {
+            return Ok(self.float(node, name)?.unwrap_or(default));
+        }
+        let value = self.attribute(param, "value").unwrap_or_default();
+        match NAMED_IORS.iter().find(|(material, _)| *material == value) {
+            Some((_, ior)) => Ok(*ior),
+            None => {
+                self.warn(
+                    param,
+                    format!("unknown {} \"{}\", using {}", name, value, default),
+                );
+                Ok(default)
+            }
+        }
+    }
+
+    fn shape_material(&mut self, node: Node) -> Result<MaterialIdx, LoadError> {
+        for child in node.children().filter(Node::is_element) {
+            match child.tag_name().name() {
+                "emitter" if self.attribute(child, "type").as_deref() == Some("area") => {
+                    let rgb = self.color(child, "radiance", Three::new(1.0, 1.0, 1.0))?;
+                    return Ok(self
+                        .scene
+                        .add_material(Light::new(three(rgb), F::one()).one_sided()));
+                }
+                "emitter" => {
+                    let kind = self.attribute(child, "type").unwrap_or_default();
+                    self.warn(
+                        child,
+                        format!("\"{}\" emitters on shapes are not supported", kind),
+                    )
+                }
+                _ => {}
+            }
+        }
+        for child in node.children().filter(Node::is_element) {
+            match child.tag_name().name() {
+                "bsdf" => return self.bsdf(child),
+                "ref" => {
+                    let id = self.attribute(child, "id").unwrap_or_default();
+                    return self
+                        .scene
+                        .material_named(&id)
+                        .ok_or_else(|| self.error(child, format!("no bsdf with id \"{}\"", id)));
+                }
+                _ => {}
+            }
+        }
+        Ok(match self.default_material {
+            Some(material) => material,
+            None => {
+                let material = self.scene.add_material(Diffuse {
+                    rgb: three(Three::new(0.5, 0.5, 0.5)),
+                    texture: None,
+                });
+                self.default_material = Some(material);
+                material
+            }
+        })
+    }
+
+    fn shape(&mut self, node: Node) -> Result<(), LoadError> {
+        let kind = self.attribute(node, "type").unwrap_or_default();
+        let to_world = self.to_world(node)?;
+        let flip = self.boolean(node, "flip_normals").unwrap_or(false);
+        // the normals of built in shapes follow to_world, even when it mirrors them
+        let flip_built_in = flip ^ (to_world.determinant() < 0.0);
+        let file = |importer: &Self| -> Result<PathBuf, LoadError> {
+            importer
+                .string(node, "filename")
+                .map(|file| importer.base_dir.join(file))
+                .ok_or_else(|| importer.error(node, format!("{} shape needs a filename", kind)))
+        };
+        let mesh: Mesh<F> = match kind.as_str() {
+            "obj" => load_obj(file(self)?)?,
+            "ply" => load_ply(file(self)?)?,
+            "sphere" => {
+                let center = to_world.point(&self.point(node, "center")?.unwrap_or_default());
+                let scale = [
+                    Three::new(1.0, 0.0, 0.0),
+                    Three::new(0.0, 1.0, 0.0),
+                    Three::new(0.0, 0.0, 1.0),
+                ]
+                .map(|axis| to_world.vector(&axis).length());
+                if (scale[0] - scale[1]).abs() > 1e-6 || (scale[1] - scale[2]).abs() > 1e-6 {
+                    self.warn(node, "non-uniformly scaled spheres are rendered as round");
+                }
+                let radius = self.float(node, "radius")?.unwrap_or(1.0)
+                    * (scale[0] + scale[1] + scale[2])
+                    / 3.0;
+                let material = self.shape_material(node)?;
+                let sphere = Sphere::new(three(center), float(radius));
+                self.scene.add_object(sphere, material);
+                return Ok(());
+            }
+            "disk" => {
+                // radius 1 around the origin in the xy plane, facing z
+                let (disk, round) = transformed_disk(&to_world, 0.0, 1.0, flip_built_in);
+                if !round {
+                    self.warn(node, "non-uniformly scaled disks are rendered as round");
+                }
+                let material = self.shape_material(node)?;
+                self.scene.add_object(disk, material);
+                return Ok(());
+            }
+            "rectangle" => {
+                // [-1, 1]² in the xy plane, facing z
+                let corner = |x: f64, y: f64| Three::new(x, y, 0.0);
+                let uv = |x: f64, y: f64| (float((x + 1.0) / 2.0), float((y + 1.0) / 2.0));
+                let (p0, p1, p2, p3) = (
+                    corner(-1.0, -1.0),
+                    corner(1.0, -1.0),
+                    corner(1.0, 1.0),
+                    corner(-1.0, 1.0),
+                );
+                Mesh::new(vec![
+                    Triangle::new(three(p0), three(p1), three(p2)).with_uvs(
+                        uv(-1.0, -1.0),
+                        uv(1.0, -1.0),
+                        uv(1.0, 1.0),
+                    ),
+                    Triangle::new(three(p0), three(p2), three(p3)).with_uvs(
+                        uv(-1.0, -1.0),
+                        uv(1.0, 1.0),
+                        uv(-1.0, 1.0),
+                    ),
+                ])
+            }
+            "cube" => {
+                // [-1, 1]³, facing outwards
+                let vertices: Vec<Three<F>> = (0..8)
+                    .map(|i| {
+                        let coordinate =
+                            |bit: usize| if i & bit == 0 { -F::one() } else { F::one() };
+                        Three::new(coordinate(1), coordinate(2), coordinate(4))
+                    })
+                    .collect();
+                let faces = [
+                    [0, 2, 3, 1],
+                    [4, 5, 7, 6],
+                    [0, 1, 5, 4],
+                    [2, 6, 7, 3],
+                    [0, 4, 6, 2],
+                    [1, 3, 7, 5],
+                ];
+                let indices: Vec<[usize; 3]> = faces
+                    .iter()
+                    .flat_map(|[a, b, c, d]| [[*a, *b, *c], [*a, *c, *d]])
+                    .collect();
+                Mesh::indexed(&vertices, &indices)
+            }
+            other => {
+                self.warn(node, format!("\"{}\" shapes are not supported", other));
+                return Ok(());
+            }
+        };
+        let flip = match kind.as_str() {
+            "obj" | "ply" => flip,
+            _ => flip_built_in,
+        };
+        let mesh = transformed_mesh(&mesh, &to_world, flip);
+        if mesh.triangles().is_empty() {
+            self.warn(node, "shape has no triangles");
+            return Ok(());
+        }
+        let material = self.shape_material(node)?;
+        self.scene.add_object(mesh, material);
+        Ok(())
+    }
+
+    fn emitter(&mut self, node: Node) -> Result<(), LoadError> {
+        let kind = self.attribute(node, "type").unwrap_or_default();
+        let to_world = self.to_world(node)?;
+        let white = Three::new(1.0, 1.0, 1.0);
+        let scale = self.float(node, "scale")?.unwrap_or(1.0);
+        match kind.as_str() {
+            "envmap" => {
+                let file = self
+                    .string(node, "filename")
+                    .map(|file| self.base_dir.join(file))
+                    .ok_or_else(|| self.error(node, "envmap needs a filename"))?;
+                let texture = ImageTexture::open(&file).map_err(|error| {
+                    self.error(
+                        node,
+                        format!("can't load envmap {}: {}", file.display(), error),
+                    )
+                })?;
+                let mut environment = Environment::new(texture);
+                environment.scale = float(scale);
+                environment.frame = [
+                    Three::new(1.0, 0.0, 0.0),
+                    Three::new(0.0, 1.0, 0.0),
+                    Three::new(0.0, 0.0, 1.0),
+                ]
+                .map(|axis| three(to_world.vector(&axis).normalized()));
+                self.scene.set_environment(environment);
+            }
+            "constant" => {
+                let rgb = self.color(node, "radiance", white)?;
+                let mut environment = Environment::new(Texture::Constant(three(rgb)));
+                environment.scale = float(scale);
+                self.scene.set_environment(environment);
+            }
+            "point" => {
+                let position = match self.point(node, "position")? {
+                    Some(position) => position,
+                    None => to_world.point(&Three::zeros()),
+                };
+                let rgb = self.color(node, "intensity", white)?;
+                self.scene.add_light(PointLight {
+                    position: three(position),
+                    rgb: three(rgb),
+                    power: float(scale),
+                });
+            }
+            "spot" => {
+                let cutoff_angle = self.float(node, "cutoff_angle")?.unwrap_or(20.0);
+                let beam_width = self
+                    .float(node, "beam_width")?
+                    .unwrap_or(cutoff_angle * 3.0 / 4.0);
+                let rgb = self.color(node, "intensity", white)?;
+                self.scene.add_light(SpotLight {
+                    position: three(to_world.point(&Three::zeros())),
+                    direction: three(to_world.vector(&Three::new(0.0, 0.0, 1.0)).normalized()),
+                    rgb: three(rgb),
+                    power: float(scale),
+                    cone_angle: float(cutoff_angle),
+                    falloff_start: float(beam_width),
+                    profile: None,
+                });
+            }
+            "directional" => {
+                let direction = match self.point(node, "direction")? {
+                    Some(direction) => direction,
+                    None => to_world.vector(&Three::new(0.0, 0.0, 1.0)),
+                };
+                let rgb = self.color(node, "irradiance", white)?;
+                self.scene.add_light(DirectionalLight {
+                    direction: three(direction.normalized()),
+                    rgb: three(rgb),
+                    power: float(scale),
+                });
+            }
+            "area" => self.warn(node, "area emitters need to be inside a <shape>"),
+            other => self.warn(node, format!("\"{}\" emitters are not supported", other)),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str) -> Importer<'_, f64> {
+        let document = Document::parse(source).unwrap();
+        let mut importer = Importer::new(source, Path::new("test.xml"));
+        importer.scene_element(document.root_element()).unwrap();
+        importer
+    }
+
+    /// Vertical field of view in degrees of a 200 by 100 sensor.
+    fn vertical_fov(sensor: &str) -> f64 {
+        let source = format!(
+            "<scene version=\"3.0.0\"><sensor type=\"perspective\">{}\
+             <film type=\"hdrfilm\"><integer name=\"width\" value=\"200\"/>\
+             <integer name=\"height\" value=\"100\"/></film></sensor></scene>",
+            sensor
+        );
+        run(&source).camera.unwrap().1
+    }
+
+    fn assert_near(a: Three<f64>, b: Three<f64>) {
+        assert!((a - b).length() < 1e-9, "{:?} is not {:?}", a, b);
+    }
+
+    #[test]
+    fn fov_axis_converts_to_a_vertical_field_of_view() {
+        let fov = |axis: &str| {
+            vertical_fov(&format!(
+                "<float name=\"fov\" value=\"90\"/><string name=\"fov_axis\" value=\"{}\"/>",
+                axis
+            ))
+        };
+        let from_tan_half = |tan_half: f64| 2.0 * tan_half.atan().to_degrees();
+        assert!((fov("y") - 90.0).abs() < 1e-9);
+        assert!((fov("x") - from_tan_half(0.5)).abs() < 1e-9);
+        assert!((fov("larger") - fov("x")).abs() < 1e-9);
+        assert!((fov("smaller") - 90.0).abs() < 1e-9);
+        let diagonal = 2.0 * (100.0 / 200f64.hypot(100.0)).atan().to_degrees();
+        assert!((fov("diagonal") - diagonal).abs() < 1e-9);
+        // x is the default
+        assert!((vertical_fov("<float name=\"fov\" value=\"90\"/>") - fov("x")).abs() < 1e-9);
+
+        let source = "<scene version=\"3.0.0\"><sensor type=\"perspective\">\
+                      <float name=\"fov\" value=\"90\"/><string name=\"fov_axis\" value=\"z\"/>\
+                      </sensor></scene>";
+        let document = Document::parse(source).unwrap();
+        let mut importer = Importer::<f64>::new(source, Path::new("test.xml"));
+        assert!(importer.scene_element(document.root_element()).is_err());
+    }
+
+    #[test]
+    fn lookat_places_the_camera() {
+        let scene = run("<scene version=\"3.0.0\"><sensor type=\"perspective\">\
+             <transform name=\"to_world\">\
+             <lookat origin=\"0, 0, 5\" target=\"0, 0, 0\" up=\"0, 1, 0\"/>\
+             </transform></sensor></scene>")
+        .finish();
+        assert_near(scene.camera.position, Three::new(0.0, 0.0, 5.0));
+        assert_near(scene.camera.forward, Three::new(0.0, 0.0, -1.0));
+        assert_near(scene.camera.up, Three::new(0.0, 1.0, 0.0));
+        assert_near(scene.camera.right, Three::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn transforms_apply_in_order_and_rotate_counter_clockwise() {
+        let scene = run("<scene version=\"3.0.0\"><sensor type=\"perspective\">\
+             <transform name=\"to_world\">\
+             <translate x=\"1\"/><rotate z=\"1\" angle=\"90\"/><scale value=\"2\"/>\
+             </transform></sensor></scene>")
+        .finish();
+        // (0, 0, 0) moves to (1, 0, 0), turns to (0, 1, 0) and doubles to (0, 2, 0)
+        assert_near(scene.camera.position, Three::new(0.0, 2.0, 0.0));
+        // the camera's left, +x, turns to +y
+        assert_near(scene.camera.right, Three::new(0.0, -1.0, 0.0));
+    }
+
+    #[test]
+    fn bsdfs_map_to_materials() {
+        let importer = run("<scene version=\"3.0.0\">\
+             <bsdf type=\"twosided\" id=\"red\"><bsdf type=\"diffuse\">\
+             <rgb name=\"reflectance\" value=\"0.8, 0.1, 0.1\"/></bsdf></bsdf>\
+             <bsdf type=\"conductor\" id=\"metal\"/>\
+             <bsdf type=\"dielectric\" id=\"water\">\
+             <string name=\"int_ior\" value=\"water\"/><string name=\"ext_ior\" value=\"air\"/>\
+             </bsdf>\
+             <bsdf type=\"dielectric\" id=\"glass\"><float name=\"int_ior\" value=\"1.5\"/>\
+             <float name=\"ext_ior\" value=\"1\"/></bsdf>\
+             <bsdf type=\"dielectric\" id=\"unknown\">\
+             <string name=\"int_ior\" value=\"unobtainium\"/></bsdf>\
+             </scene>");
+        let material = |name| {
+            importer
+                .scene
+                .material(importer.scene.material_named(name).unwrap())
+        };
+        match material("red") {
+            Material::Diffuse(diffuse) => assert_near(diffuse.rgb, Three::new(0.8, 0.1, 0.1)),
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(material("metal"), Material::Mirror(_)));
+        let ior = |name| match material(name) {
+            Material::Dielectric(dielectric) => dielectric.ior,
+            other => panic!("{:?}", other),
+        };
+        assert!((ior("water") - 1.333 / 1.000277).abs() < 1e-9);
+        assert!((ior("glass") - 1.5).abs() < 1e-9);
+        // unknown names fall back to bk7 with a warning
+        assert!((ior("unknown") - 1.5046 / 1.000277).abs() < 1e-9);
+        assert_eq!(importer.warnings.len(), 1);
+    }
+
+    #[test]
+    fn missing_envmaps_are_errors() {
+        let source = "<scene version=\"3.0.0\"><emitter type=\"envmap\">\
+                      <string name=\"filename\" value=\"missing.exr\"/></emitter></scene>";
+        let document = Document::parse(source).unwrap();
+        let mut importer = Importer::<f64>::new(source, Path::new("test.xml"));
+        assert!(importer.scene_element(document.root_element()).is_err());
+    }
+}
@@ -0,0 +1,217 @@
+//! Reading scenes and models from files.
+
+mod colors;
+mod gltf;
+mod mitsuba;
+mod obj;
+mod pbrt;
+mod ply;
+mod scene_file;
+mod stl;
+
+pub use self::gltf::{load_gltf, parse_gltf, GltfScene};
+pub use mitsuba::load_mitsuba;
+pub use obj::load_obj;
+pub use pbrt::load_pbrt;
+pub use ply::load_ply;
+pub use scene_file::{load_scene, parse_scene, RenderSettings, SceneDescription, TracerKind};
+pub use stl::load_stl;
+
+use crate::data::Three;
+use crate::shapes::{Disk, Mesh, Triangle};
+use crate::texture::Texture;
+use crate::transform::Transform;
+use num_traits::Float;
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Why a file couldn't be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    /// The file was read but its contents are wrong.
+    Invalid {
+        path: Option<PathBuf>,
+        /// Line of the problem, counting from 1, if it's known.
+        line: Option<usize>,
+        message: String,
+    },
+}
+
+impl LoadError {
+    pub(crate) fn io(path: &Path, error: io::Error) -> Self {
+        Self::Io {
+            path: path.to_path_buf(),
+            error,
+        }
+    }
+
+    pub(crate) fn invalid<M: Into<String>>(line: Option<usize>, message: M) -> Self {
+        Self::Invalid {
+            path: None,
+            line,
+            message: message.into(),
+        }
+    }
+
+    /// Sets the file the error is about, unless it's already known.
+    pub(crate) fn in_file(self, file: &Path) -> Self {
+        match self {
+            Self::Invalid {
+                path: None,
+                line,
+                message,
+            } => Self::Invalid {
+                path: Some(file.to_path_buf()),
+                line,
+                message,
+            },
+            error => error,
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            Self::Invalid {
+                path,
+                line,
+                message,
+            } => match (path, line) {
+                (Some(path), Some(line)) => write!(f, "{}:{}: {}", path.display(), line, message),
+                (Some(path), None) => write!(f, "{}: {}", path.display(), message),
+                (None, Some(line)) => write!(f, "line {}: {}", line, message),
+                (None, None) => write!(f, "{}", message),
+            },
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { error, .. } => Some(error),
+            Self::Invalid { .. } => None,
+        }
+    }
+}
+
+/// Loads an OBJ, PLY or STL mesh, picking the format by the file's extension.
+pub fn load_mesh<F, P>(path: P) -> Result<Mesh<F>, LoadError>
+where
+    F: Float,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    match extension(path).as_deref() {
+        Some("ply") => load_ply(path),
+        Some("stl") => load_stl(path),
+        _ => load_obj(path),
+    }
+}
+
+/// Loads the vertex colors of a PLY file or the facet colors of an STL file as a texture, for
+/// the mesh `load_ply`, `load_stl` or `load_mesh` loads from it. Its texture coordinates are
+/// made to interpolate the colors across each triangle.
+pub fn load_mesh_colors<F, P>(path: P) -> Result<Texture<F>, LoadError>
+where
+    F: Float,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let bytes = std::fs::read(path).map_err(|error| LoadError::io(path, error))?;
+    let colors = match extension(path).as_deref() {
+        Some("ply") => ply::parse_ply::<F>(&bytes)?.1,
+        Some("stl") => stl::parse_stl::<F>(&bytes)?.1,
+        _ => {
+            return Err(
+                LoadError::invalid(None, "only PLY and STL files have colors").in_file(path),
+            )
+        }
+    };
+    colors
+        .map(Texture::from)
+        .ok_or_else(|| LoadError::invalid(None, "mesh has no colors").in_file(path))
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase)
+}
+
+/// `x` in the float type a scene is loaded with.
+fn float<F: Float>(x: f64) -> F {
+    F::from(x).unwrap()
+}
+
+fn three<F: Float>(v: Three<f64>) -> Three<F> {
+    Three::new(float(v.x), float(v.y), float(v.z))
+}
+
+/// A transform from a 4x4 matrix listed column by column, like pbrt's, or `None` if it can't be
+/// inverted.
+fn from_columns(values: &[f64]) -> Option<Transform<f64>> {
+    let mut matrix = [[0.0; 4]; 4];
+    for (i, value) in values.iter().enumerate().take(16) {
+        matrix[i % 4][i / 4] = *value;
+    }
+    Transform::from_matrix(matrix)
+}
+
+/// `mesh` moved by `transform`, swapping the winding of each triangle if `flip`.
+fn transformed_mesh<F: Float>(mesh: &Mesh<F>, transform: &Transform<f64>, flip: bool) -> Mesh<F> {
+    let transform = transform.cast();
+    let triangles = mesh
+        .triangles()
+        .iter()
+        .map(|triangle| {
+            let triangle = triangle.transformed(&transform);
+            if !flip {
+                return triangle;
+            }
+            let (v0, v1, v2) = triangle.vertices();
+            let [uv0, uv1, uv2] = triangle.uvs();
+            let flipped = Triangle::new(v0, v2, v1).with_uvs(uv0, uv2, uv1);
+            match triangle.normals() {
+                Some([n0, n1, n2]) => flipped.with_normals(n0, n2, n1),
+                None => flipped,
+            }
+        })
+        .collect();
+    Mesh::new(triangles)
+}
+
+/// A disk of `radius` in the xy plane at `height` along z, facing z or the other way if `flip`,
+/// moved by `transform`. Disks stay round, so it's also told whether `transform` scales x and y
+/// alike.
+fn transformed_disk<F: Float>(
+    transform: &Transform<f64>,
+    height: f64,
+    radius: f64,
+    flip: bool,
+) -> (Disk<F>, bool) {
+    let x = transform.vector(&Three::new(1.0, 0.0, 0.0));
+    let y = transform.vector(&Three::new(0.0, 1.0, 0.0));
+    let normal = if flip { y.cross(&x) } else { x.cross(&y) };
+    let center = transform.point(&Three::new(0.0, 0.0, height));
+    let disk = Disk::new(
+        three(center),
+        three(normal),
+        float(radius * (x.length() + y.length()) / 2.0),
+    );
+    let round = (x.length() - y.length()).abs() <= 1e-6 && x.dot(&y).abs() <= 1e-6;
+    (disk, round)
+}
+
+/// Line, counting from 1, of the byte at `offset` in `source`.
+pub(crate) fn line_of(source: &str, offset: usize) -> usize {
+    source[..offset.min(source.len())].matches('\n').count() + 1
+}
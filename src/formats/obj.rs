@@ -0,0 +1,102 @@
+use super::LoadError;
+use crate::data::Three;
+use crate::shapes::{Mesh, Triangle};
+use num_traits::Float;
+use std::path::Path;
+
+/// Loads the triangles of a Wavefront OBJ file as one mesh, with texture coordinates if it has
+/// them. Polygons with more than three vertices are split into triangles. Normals, groups and
+/// materials are ignored.
+pub fn load_obj<F, P>(path: P) -> Result<Mesh<F>, LoadError>
+where
+    F: Float,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let source = std::fs::read_to_string(path).map_err(|error| LoadError::io(path, error))?;
+    parse_obj(&source).map_err(|error| error.in_file(path))
+}
+
+pub(crate) fn parse_obj<F: Float>(source: &str) -> Result<Mesh<F>, LoadError> {
+    let mut positions: Vec<Three<F>> = Vec::new();
+    let mut uvs: Vec<(F, F)> = Vec::new();
+    let mut triangles = Vec::new();
+
+    for (line_idx, line) in source.lines().enumerate() {
+        let line_number = Some(line_idx + 1);
+        let mut words = line.split_whitespace();
+        let numbers = |words: std::str::SplitWhitespace, count: usize| {
+            let numbers: Vec<F> = words
+                .take(count)
+                .map(|word| word.parse::<f64>().ok().and_then(F::from))
+                .collect::<Option<_>>()
+                .ok_or_else(|| LoadError::invalid(line_number, "expected a number"))?;
+            if numbers.len() < count {
+                return Err(LoadError::invalid(
+                    line_number,
+                    format!("expected {} numbers", count),
+                ));
+            }
+            Ok(numbers)
+        };
+        match words.next() {
+            Some("v") => {
+                let v = numbers(words, 3)?;
+                positions.push(Three::new(v[0], v[1], v[2]));
+            }
+            Some("vt") => {
+                let vt = numbers(words, 2)?;
+                uvs.push((vt[0], vt[1]));
+            }
+            Some("f") => {
+                let corners = words
+                    .map(|word| face_corner(word, positions.len(), uvs.len()))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| {
+                        LoadError::invalid(line_number, "face refers to a missing vertex")
+                    })?;
+                if corners.len() < 3 {
+                    return Err(LoadError::invalid(
+                        line_number,
+                        "faces need at least 3 vertices",
+                    ));
+                }
+                // fan out from the first corner
+                for i in 1..corners.len() - 1 {
+                    let [c0, c1, c2] = [corners[0], corners[i], corners[i + 1]];
+                    let mut triangle =
+                        Triangle::new(positions[c0.0], positions[c1.0], positions[c2.0]);
+                    if let (Some(uv0), Some(uv1), Some(uv2)) = (c0.1, c1.1, c2.1) {
+                        triangle = triangle.with_uvs(uvs[uv0], uvs[uv1], uvs[uv2]);
+                    }
+                    triangles.push(triangle);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(Mesh::new(triangles))
+}
+
+/// Position and texture coordinate indices of a face corner like `3`, `3/1`, `3//2` or `3/1/2`.
+/// Indices count from 1, or back from the last vertex if negative.
+fn face_corner(word: &str, num_positions: usize, num_uvs: usize) -> Option<(usize, Option<usize>)> {
+    let resolve = |index: &str, count: usize| -> Option<usize> {
+        let index: isize = index.parse().ok()?;
+        let resolved = if index < 0 {
+            count as isize + index
+        } else {
+            index - 1
+        };
+        (0..count as isize)
+            .contains(&resolved)
+            .then_some(resolved as usize)
+    };
+    let mut parts = word.split('/');
+    let position = resolve(parts.next()?, num_positions)?;
+    let uv = match parts.next() {
+        Some("") | None => None,
+        Some(uv) => Some(resolve(uv, num_uvs)?),
+    };
+    Some((position, uv))
+}
@@ -0,0 +1,906 @@
+use super::{
+    float, from_columns, load_ply, three, transformed_disk, transformed_mesh, LoadError,
+    RenderSettings, SceneDescription, TracerKind,
+};
+use crate::data::{
+    Camera, Dielectric, Diffuse, FieldOfView, ImageShape, Light, Material, Mirror, Three,
+};
+use crate::lights::{DirectionalLight, Environment, PointLight, SpotLight};
+use crate::scene::{MaterialIdx, Scene};
+use crate::shapes::{Mesh, Sphere, Triangle};
+use crate::texture::Texture;
+use crate::transform::Transform;
+use num_traits::{Float, FloatConst};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Loads a scene in the pbrt-v3 or pbrt-v4 format, e.g. to compare renders with pbrt's.
+///
+/// Supported are cameras (`LookAt`, `Camera "perspective"`, `Film`, `Sampler`, `Integrator`),
+/// transforms and `AttributeBegin`/`AttributeEnd`, `"trianglemesh"`, `"plymesh"`, `"sphere"`
+/// and `"disk"` shapes, diffuse, conductor and dielectric materials (or their pbrt-v3 names),
+/// diffuse area lights, point, spot, distant and uniform infinite lights, and `Include`.
+/// Anything else is skipped with a warning in `SceneDescription::warnings`.
+pub fn load_pbrt<F, P>(path: P) -> Result<SceneDescription<F>, LoadError>
+where
+    F: Float + FloatConst,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let mut importer = Importer::new();
+    importer.include(path)?;
+    Ok(importer.finish())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Name(String),
+    Str(String),
+    Num(f64),
+    Open,
+    Close,
+}
+
+/// `Token`s of a file, with the lines they're on.
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, LoadError> {
+    let mut tokens = Vec::new();
+    for (line_idx, line) in source.lines().enumerate() {
+        let line_number = line_idx + 1;
+        let mut chars = line.char_indices().peekable();
+        while let Some(&(start, c)) = chars.peek() {
+            match c {
+                '#' => break,
+                c if c.is_whitespace() => {
+                    chars.next();
+                }
+                '[' | ']' => {
+                    chars.next();
+                    let token = if c == '[' { Token::Open } else { Token::Close };
+                    tokens.push((token, line_number));
+                }
+                '"' => {
+                    chars.next();
+                    let end = line[start + 1..].find('"').ok_or_else(|| {
+                        LoadError::invalid(Some(line_number), "string is missing its closing \"")
+                    })?;
+                    let text = &line[start + 1..start + 1 + end];
+                    tokens.push((Token::Str(text.to_string()), line_number));
+                    while chars.next_if(|&(i, _)| i <= start + 1 + end).is_some() {}
+                }
+                _ => {
+                    let mut end = line.len();
+                    while let Some(&(i, c)) = chars.peek() {
+                        if c.is_whitespace() || matches!(c, '[' | ']' | '"' | '#') {
+                            end = i;
+                            break;
+                        }
+                        chars.next();
+                    }
+                    let word = &line[start..end];
+                    let token = match word.parse::<f64>() {
+                        Ok(number) => Token::Num(number),
+                        Err(_) => Token::Name(word.to_string()),
+                    };
+                    tokens.push((token, line_number));
+                }
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// A parameter like `"rgb reflectance" [0.5 0.5 0.5]`.
+#[derive(Debug, Clone)]
+struct Param {
+    kind: String,
+    name: String,
+    values: Vec<Token>,
+}
+
+#[derive(Debug, Default)]
+struct Params(Vec<Param>);
+
+impl Params {
+    fn get(&self, name: &str) -> Option<&Param> {
+        self.0.iter().find(|param| param.name == name)
+    }
+
+    fn numbers(&self, name: &str) -> Option<Vec<f64>> {
+        let param = self.get(name)?;
+        param
+            .values
+            .iter()
+            .map(|value| match value {
+                Token::Num(number) => Some(*number),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn float(&self, name: &str) -> Option<f64> {
+        self.numbers(name)?.first().copied()
+    }
+
+    fn string(&self, name: &str) -> Option<&str> {
+        match self.get(name)?.values.first()? {
+            Token::Str(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    fn boolean(&self, name: &str) -> Option<bool> {
+        // pbrt-v3 writes bools as strings, pbrt-v4 as bare words
+        match self.get(name)?.values.first()? {
+            Token::Str(word) | Token::Name(word) => Some(word == "true"),
+            _ => None,
+        }
+    }
+
+    fn point(&self, name: &str) -> Option<Three<f64>> {
+        match self.numbers(name)?[..] {
+            [x, y, z] => Some(Three::new(x, y, z)),
+            _ => None,
+        }
+    }
+
+    /// An rgb color, or `None` with the parameter's type if it's a color of another kind, like a
+    /// spectrum or texture.
+    fn rgb(&self, name: &str) -> Result<Option<Three<f64>>, String> {
+        let param = match self.get(name) {
+            Some(param) => param,
+            None => return Ok(None),
+        };
+        if param.kind != "rgb" && param.kind != "color" {
+            return Err(param.kind.clone());
+        }
+        match self.numbers(name).as_deref() {
+            Some(&[r, g, b]) => Ok(Some(Three::new(r, g, b))),
+            _ => Err(param.kind.clone()),
+        }
+    }
+}
+
+/// State saved by `AttributeBegin` and restored by `AttributeEnd`.
+#[derive(Debug, Clone)]
+struct GraphicsState {
+    ctm: Transform<f64>,
+    material: Option<MaterialIdx>,
+    area_light: Option<MaterialIdx>,
+    reverse_orientation: bool,
+}
+
+struct Importer<F> {
+    scene: Scene<F>,
+    state: GraphicsState,
+    stack: Vec<GraphicsState>,
+    named_coordinate_systems: HashMap<String, Transform<f64>>,
+    default_material: Option<MaterialIdx>,
+    /// Camera to world transform and vertical field of view in degrees, from `Camera`.
+    camera: Option<(Transform<f64>, f64)>,
+    resolution: (usize, usize),
+    settings: RenderSettings,
+    warnings: Vec<String>,
+    /// File being read, and the line of the directive being run.
+    file: PathBuf,
+    line: usize,
+    /// Files being read, from the outermost one in, to catch files that include themselves.
+    including: Vec<PathBuf>,
+}
+
+impl<F> Importer<F>
+where
+    F: Float + FloatConst,
+{
+    fn new() -> Self {
+        Self {
+            scene: Scene::new(),
+            state: GraphicsState {
+                ctm: Transform::identity(),
+                material: None,
+                area_light: None,
+                reverse_orientation: false,
+            },
+            stack: Vec::new(),
+            named_coordinate_systems: HashMap::new(),
+            default_material: None,
+            camera: None,
+            // pbrt's defaults
+            resolution: (1280, 720),
+            settings: RenderSettings {
+                tracer: TracerKind::Path,
+                samples: 16,
+                depth: 5,
+            },
+            warnings: Vec::new(),
+            file: PathBuf::new(),
+            line: 0,
+            including: Vec::new(),
+        }
+    }
+
+    fn warn<M: AsRef<str>>(&mut self, message: M) {
+        self.warnings.push(format!(
+            "{}:{}: {}",
+            self.file.display(),
+            self.line,
+            message.as_ref()
+        ));
+    }
+
+    fn error<M: Into<String>>(&self, message: M) -> LoadError {
+        LoadError::invalid(Some(self.line), message).in_file(&self.file)
+    }
+
+    fn finish(self) -> SceneDescription<F> {
+        let (width, height) = self.resolution;
+        let (world_from_camera, fov) = self.camera.unwrap_or((Transform::identity(), 90.0));
+        // pbrt's field of view is along the shorter side of the image
+        let fov = if width < height {
+            let tan_half = (fov.to_radians() / 2.0).tan() * height as f64 / width as f64;
+            2.0 * tan_half.atan().to_degrees()
+        } else {
+            fov
+        };
+        let mut camera = Camera::new(
+            FieldOfView::Degrees(float(fov)),
+            ImageShape { width, height },
+        );
+        let position = world_from_camera.point(&Three::zeros());
+        camera = camera.at(float(position.x), float(position.y), float(position.z));
+        // pbrt's camera space can be left handed, so the axes are copied instead of letting
+        // `looking_at` work out which way is right
+        camera.right = three(
+            world_from_camera
+                .vector(&Three::new(1.0, 0.0, 0.0))
+                .normalized(),
+        );
+        camera.up = three(
+            world_from_camera
+                .vector(&Three::new(0.0, 1.0, 0.0))
+                .normalized(),
+        );
+        camera.forward = three(
+            world_from_camera
+                .vector(&Three::new(0.0, 0.0, 1.0))
+                .normalized(),
+        );
+        SceneDescription {
+            scene: self.scene,
+            camera,
+            settings: self.settings,
+            warnings: self.warnings,
+        }
+    }
+
+    /// Reads and runs every directive of the file at `path`.
+    fn include(&mut self, path: &Path) -> Result<(), LoadError> {
+        let source = std::fs::read_to_string(path).map_err(|error| LoadError::io(path, error))?;
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if self.including.contains(&canonical) {
+            return Err(self.error(format!("{} includes itself", path.display())));
+        }
+        self.including.push(canonical);
+        let outer = (
+            std::mem::replace(&mut self.file, path.to_path_buf()),
+            self.line,
+        );
+        self.run(&source)?;
+        (self.file, self.line) = outer;
+        self.including.pop();
+        Ok(())
+    }
+
+    /// Runs every directive of `source`, the contents of `self.file`.
+    fn run(&mut self, source: &str) -> Result<(), LoadError> {
+        let tokens = tokenize(source).map_err(|error| error.in_file(&self.file))?;
+        let mut tokens = tokens.into_iter().peekable();
+        while let Some((token, line)) = tokens.next() {
+            self.line = line;
+            let directive = match token {
+                Token::Name(name) => name,
+                token => return Err(self.error(format!("expected a directive, not {:?}", token))),
+            };
+            let mut args = Vec::new();
+            let mut params = Params::default();
+            // positional arguments, then "type name" value parameters
+            while let Some((token, _)) = tokens.peek() {
+                match token {
+                    Token::Name(_) => break,
+                    Token::Str(text) if text.contains(' ') => {
+                        let mut words = text.split_whitespace();
+                        let kind = words.next().unwrap_or_default().to_string();
+                        let name = words.next().unwrap_or_default().to_string();
+                        tokens.next();
+                        let values = match tokens.next() {
+                            Some((Token::Open, _)) => {
+                                let mut values = Vec::new();
+                                loop {
+                                    match tokens.next() {
+                                        Some((Token::Close, _)) => break,
+                                        Some((value, _)) => values.push(value),
+                                        None => {
+                                            return Err(self.error(format!(
+                                                "parameter \"{} {}\" is missing its closing ]",
+                                                kind, name
+                                            )))
+                                        }
+                                    }
+                                }
+                                values
+                            }
+                            Some((value, _)) => vec![value],
+                            None => {
+                                return Err(self.error(format!(
+                                    "parameter \"{} {}\" has no value",
+                                    kind, name
+                                )))
+                            }
+                        };
+                        params.0.push(Param { kind, name, values });
+                    }
+                    Token::Open => {
+                        tokens.next();
+                        for (token, _) in tokens.by_ref() {
+                            match token {
+                                Token::Close => break,
+                                token => args.push(token),
+                            }
+                        }
+                    }
+                    _ => args.push(tokens.next().unwrap().0),
+                }
+            }
+            self.directive(&directive, &args, &params)?;
+        }
+        Ok(())
+    }
+
+    fn directive(
+        &mut self,
+        directive: &str,
+        args: &[Token],
+        params: &Params,
+    ) -> Result<(), LoadError> {
+        let numbers = || -> Vec<f64> {
+            args.iter()
+                .filter_map(|arg| match arg {
+                    Token::Num(number) => Some(*number),
+                    _ => None,
+                })
+                .collect()
+        };
+        let expect_numbers = |count: usize| -> Result<Vec<f64>, LoadError> {
+            let values = numbers();
+            if values.len() == count {
+                Ok(values)
+            } else {
+                Err(self.error(format!("{} takes {} numbers", directive, count)))
+            }
+        };
+        let name = || -> Result<&str, LoadError> {
+            match args.first() {
+                Some(Token::Str(name)) => Ok(name),
+                _ => Err(self.error(format!("{} needs a name", directive))),
+            }
+        };
+
+        match directive {
+            "Identity" => self.state.ctm = Transform::identity(),
+            "Translate" => {
+                let v = expect_numbers(3)?;
+                self.concat(Transform::translation(Three::new(v[0], v[1], v[2])));
+            }
+            "Scale" => {
+                let v = expect_numbers(3)?;
+                if v.contains(&0.0) {
+                    return Err(self.error("Scale can't be zero"));
+                }
+                self.concat(Transform::scaling(Three::new(v[0], v[1], v[2])));
+            }
+            "Rotate" => {
+                let v = expect_numbers(4)?;
+                // pbrt turns counter-clockwise looking down the axis, the other way from ours
+                self.concat(Transform::rotation(&Three::new(v[1], v[2], v[3]), -v[0]));
+            }
+            "LookAt" => {
+                let v = expect_numbers(9)?;
+                let eye = Three::new(v[0], v[1], v[2]);
+                let target = Three::new(v[3], v[4], v[5]);
+                let up = Three::new(v[6], v[7], v[8]);
+                let world_from_camera = Transform::looking_at(eye, target, up)
+                    .ok_or_else(|| self.error("LookAt's up vector is parallel to its direction"))?;
+                self.concat(world_from_camera.inverse());
+            }
+            "Transform" | "ConcatTransform" => {
+                let transform = from_columns(&expect_numbers(16)?)
+                    .ok_or_else(|| self.error(format!("{} can't be inverted", directive)))?;
+                if directive == "Transform" {
+                    self.state.ctm = transform;
+                } else {
+                    self.concat(transform);
+                }
+            }
+            "CoordinateSystem" => {
+                let name = name()?.to_string();
+                self.named_coordinate_systems.insert(name, self.state.ctm);
+            }
+            "CoordSysTransform" => {
+                let name = name()?;
+                match self.named_coordinate_systems.get(name) {
+                    Some(ctm) => self.state.ctm = *ctm,
+                    None => self.warn(format!("no coordinate system named \"{}\"", name)),
+                }
+            }
+            "ReverseOrientation" => {
+                self.state.reverse_orientation = !self.state.reverse_orientation
+            }
+            "AttributeBegin" | "TransformBegin" => self.stack.push(self.state.clone()),
+            "AttributeEnd" | "TransformEnd" => {
+                let saved = self
+                    .stack
+                    .pop()
+                    .ok_or_else(|| self.error(format!("{} without a matching begin", directive)))?;
+                if directive == "TransformEnd" {
+                    self.state.ctm = saved.ctm;
+                } else {
+                    self.state = saved;
+                }
+            }
+            "Camera" => {
+                let kind = name()?;
+                if kind != "perspective" {
+                    self.warn(format!("\"{}\" cameras are rendered as perspective", kind));
+                }
+                if params.float("lensradius").unwrap_or(0.0) > 0.0 {
+                    self.warn("depth of field is ignored");
+                }
+                let world_from_camera = self.state.ctm.inverse();
+                self.named_coordinate_systems
+                    .insert("camera".to_string(), world_from_camera);
+                self.camera = Some((world_from_camera, params.float("fov").unwrap_or(90.0)));
+            }
+            "Film" => {
+                let width = params.float("xresolution").unwrap_or(1280.0);
+                let height = params.float("yresolution").unwrap_or(720.0);
+                self.resolution = (width as usize, height as usize);
+            }
+            "Sampler" => {
+                if let Some(samples) = params.float("pixelsamples") {
+                    self.settings.samples = samples as usize;
+                }
+            }
+            "Integrator" => {
+                self.settings.tracer = match name()? {
+                    "path" | "volpath" => TracerKind::Path,
+                    "bdpt" => TracerKind::Bidirectional,
+                    "mlt" => TracerKind::Metropolis,
+                    "sppm" => TracerKind::ProgressivePhotonMapping,
+                    "ambientocclusion" => TracerKind::AmbientOcclusion,
+                    other => {
+                        self.warn(format!("\"{}\" integrator is rendered as \"path\"", other));
+                        TracerKind::Path
+                    }
+                };
+                if let Some(depth) = params.float("maxdepth") {
+                    self.settings.depth = depth as usize;
+                }
+            }
+            "WorldBegin" => {
+                self.state.ctm = Transform::identity();
+                self.named_coordinate_systems
+                    .insert("world".to_string(), Transform::identity());
+            }
+            "WorldEnd" | "PixelFilter" | "ColorSpace" | "Option" => {}
+            "Include" | "Import" => {
+                let path = self.file.parent().unwrap_or(Path::new("")).join(name()?);
+                self.include(&path)?;
+            }
+            "Material" => {
+                let material = self.material(name()?, params);
+                self.state.material = Some(self.scene.add_material(material));
+            }
+            "MakeNamedMaterial" => {
+                let name = name()?.to_string();
+                let kind = params.string("type").unwrap_or("diffuse").to_string();
+                let material = self.material(&kind, params);
+                self.scene.add_named_material(name, material);
+            }
+            "NamedMaterial" => {
+                let name = name()?;
+                match self.scene.material_named(name) {
+                    Some(material) => self.state.material = Some(material),
+                    None => return Err(self.error(format!("no material named \"{}\"", name))),
+                }
+            }
+            "AreaLightSource" => {
+                let kind = name()?;
+                if kind != "diffuse" {
+                    self.warn(format!("\"{}\" area lights are not supported", kind));
+                    return Ok(());
+                }
+                let rgb = self.color(params, "L", Three::new(1.0, 1.0, 1.0));
+                let scale = params.float("scale").unwrap_or(1.0);
+                let mut light = Light::new(three(rgb), float(scale));
+                if !params.boolean("twosided").unwrap_or(false) {
+                    light = light.one_sided();
+                }
+                self.state.area_light = Some(self.scene.add_material(light));
+            }
+            "LightSource" => self.light_source(name()?, params),
+            "Shape" => self.shape(name()?, params)?,
+            other => self.warn(format!("{} is not supported", other)),
+        }
+        Ok(())
+    }
+
+    fn concat(&mut self, transform: Transform<f64>) {
+        self.state.ctm = self.state.ctm * transform;
+    }
+
+    /// An rgb parameter, or `default` with a warning if it's given some other way.
+    fn color(&mut self, params: &Params, name: &str, default: Three<f64>) -> Three<f64> {
+        match params.rgb(name) {
+            Ok(rgb) => rgb.unwrap_or(default),
+            Err(kind) => {
+                self.warn(format!(
+                    "\"{} {}\" is not supported, using {:?}",
+                    kind, name, default
+                ));
+                default
+            }
+        }
+    }
+
+    fn material(&mut self, kind: &str, params: &Params) -> Material<F> {
+        let grey = Three::new(0.5, 0.5, 0.5);
+        let white = Three::new(1.0, 1.0, 1.0);
+        match kind {
+            "diffuse" => Diffuse {
+                rgb: three(self.color(params, "reflectance", grey)),
+                texture: None,
+            }
+            .into(),
+            "matte" => Diffuse {
+                rgb: three(self.color(params, "Kd", grey)),
+                texture: None,
+            }
+            .into(),
+            "conductor" | "metal" | "mirror" => {
+                let rgb = if kind == "mirror" {
+                    self.color(params, "Kr", Three::new(0.9, 0.9, 0.9))
+                } else {
+                    self.color(params, "reflectance", white)
+                };
+                let roughness = params
+                    .float("roughness")
+                    .or_else(|| params.float("uroughness"))
+                    .unwrap_or(0.0);
+                if roughness > 0.0 {
+                    self.warn("rough conductors are rendered as perfect mirrors");
+                }
+                Mirror { rgb: three(rgb) }.into()
+            }
+            "dielectric" | "glass" | "thindielectric" => {
+                let ior = params
+                    .float("eta")
+                    .or_else(|| params.float("index"))
+                    .unwrap_or(1.5);
+                Dielectric {
+                    rgb: three(white),
+                    ior: float(ior),
+                }
+                .into()
+            }
+            "" | "none" | "interface" => {
+                self.warn(format!("\"{}\" material is rendered as diffuse", kind));
+                Diffuse {
+                    rgb: three(grey),
+                    texture: None,
+                }
+                .into()
+            }
+            other => {
+                let rgb = match (params.rgb("reflectance"), params.rgb("Kd")) {
+                    (Ok(Some(rgb)), _) | (_, Ok(Some(rgb))) => rgb,
+                    _ => grey,
+                };
+                self.warn(format!("\"{}\" material is rendered as diffuse", other));
+                Diffuse {
+                    rgb: three(rgb),
+                    texture: None,
+                }
+                .into()
+            }
+        }
+    }
+
+    fn light_source(&mut self, kind: &str, params: &Params) {
+        let scale = params.float("scale").unwrap_or(1.0);
+        let ctm = self.state.ctm;
+        let from = ctm.point(&params.point("from").unwrap_or(Three::zeros()));
+        match kind {
+            "point" => {
+                let rgb = self.color(params, "I", Three::new(1.0, 1.0, 1.0));
+                self.scene.add_light(PointLight {
+                    position: three(from),
+                    rgb: three(rgb),
+                    power: float(scale),
+                });
+            }
+            "spot" => {
+                let rgb = self.color(params, "I", Three::new(1.0, 1.0, 1.0));
+                let to = ctm.point(&params.point("to").unwrap_or(Three::new(0.0, 0.0, 1.0)));
+                let cone_angle = params.float("coneangle").unwrap_or(30.0);
+                let cone_delta = params.float("conedelta").unwrap_or(5.0);
+                self.scene.add_light(SpotLight {
+                    position: three(from),
+                    direction: three((to - from).normalized()),
+                    rgb: three(rgb),
+                    power: float(scale),
+                    cone_angle: float(cone_angle),
+                    falloff_start: float((cone_angle - cone_delta).max(0.0)),
+                    profile: None,
+                });
+            }
+            "distant" => {
+                let rgb = self.color(params, "L", Three::new(1.0, 1.0, 1.0));
+                let to = ctm.point(&params.point("to").unwrap_or(Three::new(0.0, 0.0, 1.0)));
+                self.scene.add_light(DirectionalLight {
+                    direction: three((to - from).normalized()),
+                    rgb: three(rgb),
+                    power: float(scale),
+                });
+            }
+            "infinite" if params.get("filename").is_none() => {
+                let rgb = self.color(params, "L", Three::new(1.0, 1.0, 1.0));
+                let mut environment = Environment::new(Texture::Constant(three(rgb)));
+                environment.scale = float(scale);
+                self.scene.set_environment(environment);
+            }
+            "infinite" => self.warn("image based \"infinite\" lights are not supported"),
+            other => self.warn(format!("\"{}\" lights are not supported", other)),
+        }
+    }
+
+    fn shape_material(&mut self) -> MaterialIdx {
+        if let Some(light) = self.state.area_light {
+            return light;
+        }
+        if let Some(material) = self.state.material {
+            return material;
+        }
+        match self.default_material {
+            Some(material) => material,
+            None => {
+                let material = self.scene.add_material(Diffuse {
+                    rgb: three(Three::new(0.5, 0.5, 0.5)),
+                    texture: None,
+                });
+                self.default_material = Some(material);
+                material
+            }
+        }
+    }
+
+    fn shape(&mut self, kind: &str, params: &Params) -> Result<(), LoadError> {
+        let ctm = self.state.ctm;
+        match kind {
+            "sphere" => {
+                let radius = params.float("radius").unwrap_or(1.0);
+                let center = ctm.point(&Three::zeros());
+                // spheres only scale uniformly, so use the average of the axes' scales
+                let scale = [
+                    Three::new(1.0, 0.0, 0.0),
+                    Three::new(0.0, 1.0, 0.0),
+                    Three::new(0.0, 0.0, 1.0),
+                ]
+                .map(|axis| ctm.vector(&axis).length());
+                if (scale[0] - scale[1]).abs() > 1e-6 || (scale[1] - scale[2]).abs() > 1e-6 {
+                    self.warn("non-uniformly scaled spheres are rendered as round");
+                }
+                let radius = radius * (scale[0] + scale[1] + scale[2]) / 3.0;
+                let material = self.shape_material();
+                self.scene
+                    .add_object(Sphere::new(three(center), float(radius)), material);
+            }
+            "disk" => {
+                if params.float("innerradius").unwrap_or(0.0) > 0.0
+                    || params.float("phimax").unwrap_or(360.0) < 360.0
+                {
+                    self.warn("disks are rendered without their hole and as full circles");
+                }
+                let flip = self.state.reverse_orientation ^ (ctm.determinant() < 0.0);
+                let (disk, round) = transformed_disk(
+                    &ctm,
+                    params.float("height").unwrap_or(0.0),
+                    params.float("radius").unwrap_or(1.0),
+                    flip,
+                );
+                if !round {
+                    self.warn("non-uniformly scaled disks are rendered as round");
+                }
+                let material = self.shape_material();
+                self.scene.add_object(disk, material);
+            }
+            "trianglemesh" => {
+                let positions = params
+                    .numbers("P")
+                    .ok_or_else(|| self.error("trianglemesh needs \"point3 P\""))?;
+                let positions: Vec<Three<f64>> = positions
+                    .chunks_exact(3)
+                    .map(|p| ctm.point(&Three::new(p[0], p[1], p[2])))
+                    .collect();
+                let indices: Vec<usize> = match params.numbers("indices") {
+                    Some(indices) => indices.into_iter().map(|i| i as usize).collect(),
+                    // three vertices need no indices
+                    None if positions.len() == 3 => vec![0, 1, 2],
+                    None => return Err(self.error("trianglemesh needs \"integer indices\"")),
+                };
+                if !indices.len().is_multiple_of(3) || indices.iter().any(|&i| i >= positions.len())
+                {
+                    return Err(self.error("trianglemesh indices don't match its vertices"));
+                }
+                let uvs = params
+                    .numbers("uv")
+                    .or_else(|| params.numbers("st"))
+                    .filter(|uvs| uvs.len() == 2 * positions.len());
+                // pbrt flips normals of mirrored shapes, which swapping two vertices does too
+                let flip = self.state.reverse_orientation ^ (ctm.determinant() < 0.0);
+                let triangles = indices
+                    .chunks_exact(3)
+                    .map(|corners| {
+                        let (i0, i1, i2) = if flip {
+                            (corners[0], corners[2], corners[1])
+                        } else {
+                            (corners[0], corners[1], corners[2])
+                        };
+                        let triangle = Triangle::new(
+                            three(positions[i0]),
+                            three(positions[i1]),
+                            three(positions[i2]),
+                        );
+                        match &uvs {
+                            Some(uvs) => {
+                                let uv = |i: usize| (float(uvs[2 * i]), float(uvs[2 * i + 1]));
+                                triangle.with_uvs(uv(i0), uv(i1), uv(i2))
+                            }
+                            None => triangle,
+                        }
+                    })
+                    .collect();
+                let material = self.shape_material();
+                self.scene.add_object(Mesh::new(triangles), material);
+            }
+            "plymesh" => {
+                let file = params
+                    .string("filename")
+                    .ok_or_else(|| self.error("plymesh needs \"string filename\""))?;
+                let path = self.file.parent().unwrap_or(Path::new("")).join(file);
+                let flip = self.state.reverse_orientation ^ (ctm.determinant() < 0.0);
+                let mesh = transformed_mesh(&load_ply(path)?, &ctm, flip);
+                let material = self.shape_material();
+                self.scene.add_object(mesh, material);
+            }
+            other => self.warn(format!("\"{}\" shapes are not supported", other)),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str) -> Importer<f64> {
+        let mut importer = Importer::new();
+        importer.run(source).unwrap();
+        importer
+    }
+
+    fn assert_near(a: Three<f64>, b: Three<f64>) {
+        assert!((a - b).length() < 1e-9, "{:?} is not {:?}", a, b);
+    }
+
+    #[test]
+    fn tokenizes_names_strings_numbers_and_brackets() {
+        let tokens = tokenize("Shape \"sphere\" \"float radius\" [2.5] # a comment\n  -1e2")
+            .unwrap()
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            [
+                Token::Name("Shape".to_string()),
+                Token::Str("sphere".to_string()),
+                Token::Str("float radius".to_string()),
+                Token::Open,
+                Token::Num(2.5),
+                Token::Close,
+                Token::Num(-100.0),
+            ]
+        );
+        assert!(tokenize("Shape \"sphere").is_err());
+    }
+
+    #[test]
+    fn look_at_places_the_camera() {
+        let scene = run("LookAt 0 0 5  0 0 0  0 1 0\nCamera \"perspective\"").finish();
+        assert_near(scene.camera.position, Three::new(0.0, 0.0, 5.0));
+        assert_near(scene.camera.forward, Three::new(0.0, 0.0, -1.0));
+        assert_near(scene.camera.up, Three::new(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn transform_matrices_are_given_column_by_column() {
+        let importer = run("Transform [1 0 0 0  0 1 0 0  0 0 1 0  1 2 3 1]");
+        assert_near(
+            importer.state.ctm.point(&Three::zeros()),
+            Three::new(1.0, 2.0, 3.0),
+        );
+        // a rotation by 90 degrees around z, with its columns the images of x and y
+        let importer = run("ConcatTransform [0 1 0 0  -1 0 0 0  0 0 1 0  0 0 0 1]");
+        assert_near(
+            importer.state.ctm.point(&Three::new(1.0, 0.0, 0.0)),
+            Three::new(0.0, 1.0, 0.0),
+        );
+    }
+
+    #[test]
+    fn rotations_turn_counter_clockwise() {
+        let importer = run("Rotate 90 0 0 2");
+        assert_near(
+            importer.state.ctm.point(&Three::new(1.0, 0.0, 0.0)),
+            Three::new(0.0, 1.0, 0.0),
+        );
+        assert!(Importer::<f64>::new()
+            .run("Transform [0 0 0 0  0 0 0 0  0 0 0 0  0 0 0 1]")
+            .is_err());
+    }
+
+    #[test]
+    fn attribute_end_restores_the_state() {
+        let importer = run("Translate 1 0 0
+            AttributeBegin
+              Translate 0 1 0
+              Material \"diffuse\"
+              ReverseOrientation
+            AttributeEnd");
+        assert_near(
+            importer.state.ctm.point(&Three::zeros()),
+            Three::new(1.0, 0.0, 0.0),
+        );
+        assert_eq!(importer.state.material, None);
+        assert!(!importer.state.reverse_orientation);
+        assert!(Importer::<f64>::new().run("AttributeEnd").is_err());
+    }
+
+    #[test]
+    fn named_materials_are_looked_up_by_name() {
+        let importer = run("MakeNamedMaterial \"red\" \"string type\" \"diffuse\"
+                \"rgb reflectance\" [1 0 0]
+            NamedMaterial \"red\"");
+        let red = importer.scene.material_named("red").unwrap();
+        assert_eq!(importer.state.material, Some(red));
+        match importer.scene.material(red) {
+            Material::Diffuse(diffuse) => assert_eq!(diffuse.rgb, Three::new(1.0, 0.0, 0.0)),
+            other => panic!("red is {:?}", other),
+        }
+        assert!(Importer::<f64>::new()
+            .run("NamedMaterial \"blue\"")
+            .is_err());
+    }
+
+    #[test]
+    fn files_including_themselves_are_an_error() {
+        let dir = std::env::temp_dir().join("teeracer_pbrt_include_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.pbrt"), "Include \"b.pbrt\"").unwrap();
+        std::fs::write(dir.join("b.pbrt"), "Include \"a.pbrt\"").unwrap();
+        let error = match load_pbrt::<f64, _>(dir.join("a.pbrt")) {
+            Ok(_) => panic!("loaded a file that includes itself"),
+            Err(error) => error,
+        };
+        assert!(error.to_string().contains("includes itself"), "{}", error);
+    }
+}
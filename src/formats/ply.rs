@@ -0,0 +1,473 @@
+use super::colors::color_atlas;
+use super::{float, LoadError};
+use crate::data::Three;
+use crate::shapes::{Mesh, Triangle};
+use crate::texture::ImageTexture;
+use num_traits::Float;
+use std::path::Path;
+
+/// Loads the faces of a PLY file, in its ascii or binary formats, as one mesh. Texture
+/// coordinates are read from `u`/`v`, `s`/`t` or `texture_u`/`texture_v` vertex properties if it
+/// has them, and vertex normals from `nx`/`ny`/`nz` properties smooth the shading. Polygons with
+/// more than three vertices are split into triangles.
+///
+/// If the vertices or faces have `red`, `green` and `blue` properties, the texture coordinates
+/// instead point into the texture of those colors that `load_mesh_colors` loads.
+pub fn load_ply<F, P>(path: P) -> Result<Mesh<F>, LoadError>
+where
+    F: Float,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let bytes = std::fs::read(path).map_err(|error| LoadError::io(path, error))?;
+    parse_ply(&bytes)
+        .map(|(mesh, _)| mesh)
+        .map_err(|error| error.in_file(path))
+}
+
+/// The mesh in a PLY file, and a texture of its vertex or face colors if it has them.
+pub(super) fn parse_ply<F: Float>(
+    bytes: &[u8],
+) -> Result<(Mesh<F>, Option<ImageTexture<F>>), LoadError> {
+    let (header, body) = parse_header(bytes)?;
+    let mut reader = match header.format {
+        Format::Ascii => {
+            let text = std::str::from_utf8(body)
+                .map_err(|_| LoadError::invalid(None, "ascii data isn't valid text"))?;
+            Reader::Ascii(text.split_ascii_whitespace())
+        }
+        Format::Binary { big_endian } => Reader::Binary {
+            bytes: body,
+            big_endian,
+        },
+    };
+
+    let mut positions: Vec<Three<F>> = Vec::new();
+    let mut uvs: Vec<(F, F)> = Vec::new();
+    let mut normals: Vec<Three<F>> = Vec::new();
+    let mut vertex_colors: Vec<Three<F>> = Vec::new();
+    let mut triangles = Vec::new();
+    // colors of the corners of each triangle, if the vertices or faces have any
+    let mut corner_colors: Vec<[Three<F>; 3]> = Vec::new();
+    for element in &header.elements {
+        let truncated = || {
+            LoadError::invalid(
+                None,
+                format!("file ends inside its {} elements", element.name),
+            )
+        };
+        let find = |names: &[&str]| {
+            element
+                .properties
+                .iter()
+                .position(|property| names.contains(&property.name.as_str()))
+        };
+        let xyz = [find(&["x"]), find(&["y"]), find(&["z"])];
+        let uv = match (
+            find(&["u", "s", "texture_u", "texture_s"]),
+            find(&["v", "t", "texture_v", "texture_t"]),
+        ) {
+            (Some(u), Some(v)) => Some((u, v)),
+            _ => None,
+        };
+        let normal = match (find(&["nx"]), find(&["ny"]), find(&["nz"])) {
+            (Some(x), Some(y), Some(z)) => Some([x, y, z]),
+            _ => None,
+        };
+        let rgb = match (
+            find(&["red", "diffuse_red", "r"]),
+            find(&["green", "diffuse_green", "g"]),
+            find(&["blue", "diffuse_blue", "b"]),
+        ) {
+            (Some(r), Some(g), Some(b)) => Some([r, g, b]),
+            _ => None,
+        };
+        let indices = find(&["vertex_indices", "vertex_index"]);
+
+        let mut values = Vec::new();
+        for _ in 0..element.count {
+            values.clear();
+            let mut list = Vec::new();
+            for (i, property) in element.properties.iter().enumerate() {
+                match property.kind {
+                    PropertyKind::Scalar(scalar) => {
+                        values.push(reader.read(scalar).ok_or_else(truncated)?)
+                    }
+                    PropertyKind::List { count, item } => {
+                        let len = reader.read(count).ok_or_else(truncated)? as usize;
+                        values.push(0.0);
+                        for _ in 0..len {
+                            let value = reader.read(item).ok_or_else(truncated)?;
+                            if Some(i) == indices {
+                                list.push(value as usize);
+                            }
+                        }
+                    }
+                }
+            }
+            let color = rgb.map(|rgb| {
+                let [r, g, b] = rgb.map(|i| {
+                    let value = values[i] / element.properties[i].kind.color_range();
+                    float::<F>(value)
+                });
+                Three::new(r, g, b)
+            });
+            match element.name.as_str() {
+                "vertex" => {
+                    let [x, y, z] = xyz.map(|i| i.map_or(0.0, |i| values[i]));
+                    positions.push(Three::new(float(x), float(y), float(z)));
+                    if let Some((u, v)) = uv {
+                        uvs.push((float(values[u]), float(values[v])));
+                    }
+                    if let Some([x, y, z]) = normal {
+                        normals.push(Three::new(
+                            float(values[x]),
+                            float(values[y]),
+                            float(values[z]),
+                        ));
+                    }
+                    if let Some(color) = color {
+                        vertex_colors.push(color);
+                    }
+                }
+                "face" => {
+                    if list.iter().any(|&i| i >= positions.len()) {
+                        return Err(LoadError::invalid(None, "face refers to a missing vertex"));
+                    }
+                    // fan out from the first corner
+                    for i in 1..list.len().saturating_sub(1) {
+                        let [c0, c1, c2] = [list[0], list[i], list[i + 1]];
+                        let mut triangle =
+                            Triangle::new(positions[c0], positions[c1], positions[c2]);
+                        if !uvs.is_empty() {
+                            triangle = triangle.with_uvs(uvs[c0], uvs[c1], uvs[c2]);
+                        }
+                        if !normals.is_empty() {
+                            triangle = triangle.with_normals(normals[c0], normals[c1], normals[c2]);
+                        }
+                        triangles.push(triangle);
+                        match color {
+                            Some(color) => corner_colors.push([color; 3]),
+                            None if !vertex_colors.is_empty() => {
+                                corner_colors.push([c0, c1, c2].map(|corner| vertex_colors[corner]))
+                            }
+                            None => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    if !corner_colors.is_empty() && corner_colors.len() == triangles.len() {
+        let (triangles, colors) = color_atlas(triangles, &corner_colors);
+        Ok((Mesh::new(triangles), Some(colors)))
+    } else {
+        Ok((Mesh::new(triangles), None))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Ascii,
+    Binary { big_endian: bool },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PropertyKind {
+    Scalar(Scalar),
+    List { count: Scalar, item: Scalar },
+}
+
+impl PropertyKind {
+    /// The value of a color channel at full intensity.
+    fn color_range(&self) -> f64 {
+        match self {
+            PropertyKind::Scalar(Scalar::I8) => 127.0,
+            PropertyKind::Scalar(Scalar::U8) => 255.0,
+            PropertyKind::Scalar(Scalar::I16) => 32767.0,
+            PropertyKind::Scalar(Scalar::U16) => 65535.0,
+            PropertyKind::Scalar(Scalar::I32) => 2147483647.0,
+            PropertyKind::Scalar(Scalar::U32) => 4294967295.0,
+            _ => 1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Property {
+    name: String,
+    kind: PropertyKind,
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+#[derive(Debug)]
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+}
+
+/// The header, and the data after it.
+fn parse_header(bytes: &[u8]) -> Result<(Header, &[u8]), LoadError> {
+    if !bytes.starts_with(b"ply") {
+        return Err(LoadError::invalid(Some(1), "not a PLY file"));
+    }
+    let end = bytes
+        .windows(b"end_header".len())
+        .position(|window| window == b"end_header")
+        .ok_or_else(|| LoadError::invalid(None, "header has no end_header"))?;
+    let body_start = bytes[end..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(bytes.len(), |newline| end + newline + 1);
+    let text = String::from_utf8_lossy(&bytes[..end]);
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for (line_idx, line) in text.lines().enumerate() {
+        let line_number = Some(line_idx + 1);
+        let invalid = |message: &str| LoadError::invalid(line_number, message);
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", kind, _version] => {
+                format = Some(match *kind {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::Binary { big_endian: false },
+                    "binary_big_endian" => Format::Binary { big_endian: true },
+                    _ => return Err(invalid("unknown format")),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| invalid("expected an element count"))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let kind = PropertyKind::List {
+                    count: scalar(count).ok_or_else(|| invalid("unknown property type"))?,
+                    item: scalar(item).ok_or_else(|| invalid("unknown property type"))?,
+                };
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| invalid("property before any element"))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    kind,
+                });
+            }
+            ["property", kind, name] => {
+                let kind = scalar(kind).ok_or_else(|| invalid("unknown property type"))?;
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| invalid("property before any element"))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    kind: PropertyKind::Scalar(kind),
+                });
+            }
+            ["ply"] | [] => {}
+            ["comment", ..] | ["obj_info", ..] => {}
+            _ => return Err(invalid("unexpected line in header")),
+        }
+    }
+    let format = format.ok_or_else(|| LoadError::invalid(None, "header has no format"))?;
+    Ok((Header { format, elements }, &bytes[body_start..]))
+}
+
+fn scalar(name: &str) -> Option<Scalar> {
+    Some(match name {
+        "char" | "int8" => Scalar::I8,
+        "uchar" | "uint8" => Scalar::U8,
+        "short" | "int16" => Scalar::I16,
+        "ushort" | "uint16" => Scalar::U16,
+        "int" | "int32" => Scalar::I32,
+        "uint" | "uint32" => Scalar::U32,
+        "float" | "float32" => Scalar::F32,
+        "double" | "float64" => Scalar::F64,
+        _ => return None,
+    })
+}
+
+enum Reader<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary { bytes: &'a [u8], big_endian: bool },
+}
+
+impl Reader<'_> {
+    /// The next value, or `None` at the end of the data.
+    fn read(&mut self, scalar: Scalar) -> Option<f64> {
+        match self {
+            Reader::Ascii(words) => words.next()?.parse().ok(),
+            Reader::Binary { bytes, big_endian } => {
+                let size = match scalar {
+                    Scalar::I8 | Scalar::U8 => 1,
+                    Scalar::I16 | Scalar::U16 => 2,
+                    Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+                    Scalar::F64 => 8,
+                };
+                if bytes.len() < size {
+                    return None;
+                }
+                let mut buffer = [0u8; 8];
+                buffer[..size].copy_from_slice(&bytes[..size]);
+                *bytes = &bytes[size..];
+                if *big_endian {
+                    buffer[..size].reverse();
+                }
+                let [b0, b1, b2, b3, ..] = buffer;
+                Some(match scalar {
+                    Scalar::I8 => b0 as i8 as f64,
+                    Scalar::U8 => b0 as f64,
+                    Scalar::I16 => i16::from_le_bytes([b0, b1]) as f64,
+                    Scalar::U16 => u16::from_le_bytes([b0, b1]) as f64,
+                    Scalar::I32 => i32::from_le_bytes([b0, b1, b2, b3]) as f64,
+                    Scalar::U32 => u32::from_le_bytes([b0, b1, b2, b3]) as f64,
+                    Scalar::F32 => f32::from_le_bytes([b0, b1, b2, b3]) as f64,
+                    Scalar::F64 => f64::from_le_bytes(buffer),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_ascii_polygons() {
+        let (mesh, colors) = parse_ply::<f64>(include_bytes!("../../scenes/pyramid.ply")).unwrap();
+        assert!(colors.is_none());
+        // the square base is split in two
+        assert_eq!(mesh.triangles().len(), 6);
+        let (v0, v1, v2) = mesh.triangles()[2].vertices();
+        assert_eq!(
+            (v0, v1, v2),
+            (
+                Three::new(-1.0, 0.0, -1.0),
+                Three::new(0.0, 1.4, 0.0),
+                Three::new(1.0, 0.0, -1.0)
+            )
+        );
+        assert!(mesh.triangles().iter().all(|t| t.normals().is_none()));
+    }
+
+    #[test]
+    fn reads_uvs_and_vertex_normals() {
+        let source = "ply\nformat ascii 1.0\n\
+                      element vertex 3\n\
+                      property float x\nproperty float y\nproperty float z\n\
+                      property float nx\nproperty float ny\nproperty float nz\n\
+                      property float s\nproperty float t\n\
+                      element face 1\nproperty list uchar int vertex_indices\n\
+                      end_header\n\
+                      0 0 0  0 0 2  0 0\n\
+                      1 0 0  1 0 1  1 0\n\
+                      0 1 0  0 1 1  0 1\n\
+                      3 0 1 2\n";
+        let (mesh, _) = parse_ply::<f64>(source.as_bytes()).unwrap();
+        let triangle = &mesh.triangles()[0];
+        assert_eq!(triangle.uvs(), [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]);
+        let [n0, n1, n2] = triangle.normals().unwrap();
+        assert_eq!(n0, Three::new(0.0, 0.0, 1.0));
+        assert!((n1 - Three::new(1.0, 0.0, 1.0).normalized()).length() < 1e-9);
+        assert!((n2 - Three::new(0.0, 1.0, 1.0).normalized()).length() < 1e-9);
+    }
+
+    #[test]
+    fn reads_binary_vertices_and_their_colors() {
+        let (mesh, colors) =
+            parse_ply::<f64>(include_bytes!("../../scenes/icosphere_colors.ply")).unwrap();
+        assert_eq!(mesh.triangles().len(), 320);
+        for triangle in mesh.triangles() {
+            let (v0, v1, v2) = triangle.vertices();
+            for v in [v0, v1, v2] {
+                assert!((v.length() - 1.0).abs() < 1e-3, "{:?}", v);
+            }
+        }
+        // every corner gets its vertex's color
+        let colors = colors.unwrap();
+        let corner_color =
+            |triangle: &Triangle<f64>, corner: usize| colors.lookup(triangle.uvs()[corner]);
+        let first = &mesh.triangles()[0];
+        let (v0, _, _) = first.vertices();
+        for triangle in mesh.triangles() {
+            let (w0, w1, w2) = triangle.vertices();
+            for (corner, w) in [w0, w1, w2].into_iter().enumerate() {
+                if w == v0 {
+                    let difference = corner_color(triangle, corner) - corner_color(first, 0);
+                    assert!(difference.length() < 1e-9);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reads_big_endian_data() {
+        let mut bytes = b"ply\nformat binary_big_endian 1.0\n\
+                          element vertex 3\n\
+                          property double x\nproperty double y\nproperty double z\n\
+                          element face 1\nproperty list uchar ushort vertex_indices\n\
+                          end_header\n"
+            .to_vec();
+        for value in [0.0f64, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 0.0, -3.0] {
+            bytes.extend(value.to_be_bytes());
+        }
+        bytes.push(3);
+        for index in [0u16, 1, 2] {
+            bytes.extend(index.to_be_bytes());
+        }
+        let (mesh, _) = parse_ply::<f64>(&bytes).unwrap();
+        let (v0, v1, v2) = mesh.triangles()[0].vertices();
+        assert_eq!(
+            (v0, v1, v2),
+            (
+                Three::zeros(),
+                Three::new(2.0, 0.0, 0.0),
+                Three::new(0.0, 0.0, -3.0)
+            )
+        );
+
+        bytes.truncate(bytes.len() - 1);
+        assert!(parse_ply::<f64>(&bytes).is_err());
+    }
+
+    #[test]
+    fn face_colors_fill_their_triangles() {
+        let source = "ply\nformat ascii 1.0\n\
+                      element vertex 4\nproperty float x\nproperty float y\nproperty float z\n\
+                      element face 1\nproperty list uchar int vertex_indices\n\
+                      property uchar red\nproperty uchar green\nproperty uchar blue\n\
+                      end_header\n\
+                      0 0 0\n1 0 0\n1 1 0\n0 1 0\n\
+                      4 0 1 2 3 255 0 51\n";
+        let (mesh, colors) = parse_ply::<f64>(source.as_bytes()).unwrap();
+        let colors = colors.unwrap();
+        assert_eq!(mesh.triangles().len(), 2);
+        for triangle in mesh.triangles() {
+            for uv in triangle.uvs() {
+                assert!((colors.lookup(uv) - Three::new(1.0, 0.0, 0.2)).length() < 1e-9);
+            }
+        }
+    }
+}
@@ -0,0 +1,1469 @@
+use super::{float, line_of, load_mesh, load_mesh_colors, LoadError};
+use crate::data::{
+    Camera, Dielectric, Diffuse, EmissionUnits, FieldOfView, ImageShape, Light, Material, Mirror,
+    Three,
+};
+use crate::light_sampling::LightSelection;
+use crate::lights::{DirectionalLight, IesProfile, PointLight, SpotLight};
+use crate::scene::Scene;
+use crate::shapes::{
+    Cone, Csg, Cuboid, Cylinder, Disk, Heightfield, Instance, Mesh, MovingInstance, Object, Plane,
+    Prism, Quad, Sphere, Torus, Triangle,
+};
+use crate::texture::{ImageTexture, Texture};
+use crate::transform::{Keyframe, Motion, Transform};
+use num_traits::{Float, FloatConst};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use toml::Spanned;
+
+/// Everything a scene file describes.
+pub struct SceneDescription<F> {
+    pub scene: Scene<F>,
+    pub camera: Camera<F>,
+    pub settings: RenderSettings,
+    /// Parts of the file that were skipped or approximated, for importers of other renderers'
+    /// formats.
+    pub warnings: Vec<String>,
+}
+
+/// How a scene file asks to be rendered.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderSettings {
+    pub tracer: TracerKind,
+    /// Samples per pixel.
+    pub samples: usize,
+    /// Maximum number of bounces in a path.
+    pub depth: usize,
+}
+
+/// The tracers a scene file can choose from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TracerKind {
+    #[default]
+    Path,
+    Bidirectional,
+    PhotonMapping,
+    ProgressivePhotonMapping,
+    Metropolis,
+    Normals,
+    ObjectId,
+    MaterialId,
+    Depth,
+    Uv,
+    AmbientOcclusion,
+    TraversalCost,
+}
+
+impl FromStr for TracerKind {
+    type Err = String;
+
+    /// Parses the names tracers have in scene files, like `path` or `photon-mapping`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::deserialize(serde::de::value::StrDeserializer::<serde::de::value::Error>::new(s))
+            .map_err(|error| error.to_string())
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            tracer: TracerKind::default(),
+            samples: 100,
+            depth: 10,
+        }
+    }
+}
+
+/// Loads a TOML scene file. Paths in it, like meshes and textures, are relative to the file.
+///
+/// ```toml
+/// [camera]
+/// position = [0, 1, 4]
+/// look_at = [0, 0, 0]
+/// fov = 60
+/// width = 640
+/// height = 480
+///
+/// [render]
+/// tracer = "path"
+/// samples = 256
+/// depth = 10
+///
+/// [materials.white]
+/// type = "diffuse"
+/// rgb = [0.8, 0.8, 0.8]
+///
+/// [materials.poster]
+/// type = "diffuse"
+/// texture = "poster.png"
+///
+/// [materials.scan]
+/// type = "diffuse"
+/// texture = "statue.ply" # the vertex colors of the statue mesh
+///
+/// [materials.lamp]
+/// type = "light"
+/// power = 20
+///
+/// [[objects]]
+/// type = "plane"
+/// normal = [0, 1, 0]
+/// point = [0, -1, 0]
+/// material = "white"
+///
+/// [[objects]]
+/// type = "mesh"
+/// file = "bunny.obj"
+/// material = "white"
+/// transform = { scale = 2, rotate = { axis = [0, 1, 0], degrees = 30 } }
+///
+/// [[objects]]
+/// type = "sphere"
+/// center = [0, 3, 0]
+/// radius = 0.5
+/// material = "lamp"
+///
+/// [[lights]]
+/// type = "point"
+/// position = [1, 2, 1]
+/// power = 5
+/// ```
+pub fn load_scene<F, P>(path: P) -> Result<SceneDescription<F>, LoadError>
+where
+    F: Float + FloatConst,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let source = std::fs::read_to_string(path).map_err(|error| LoadError::io(path, error))?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    parse_scene(&source, base_dir).map_err(|error| error.in_file(path))
+}
+
+/// Parses the contents of a scene file, with paths in it relative to `base_dir`.
+pub fn parse_scene<F>(source: &str, base_dir: &Path) -> Result<SceneDescription<F>, LoadError>
+where
+    F: Float + FloatConst,
+{
+    let file: SceneFile = toml::from_str(source).map_err(|error| {
+        let line = error.span().map(|span| line_of(source, span.start));
+        // toml puts what it expected instead on separate lines
+        let message: Vec<&str> = error.message().lines().map(str::trim).collect();
+        LoadError::invalid(line, message.join(", "))
+    })?;
+
+    let mut scene = Scene::new();
+    scene.set_light_selection(file.light_selection.into());
+    // sorted, so materials are added in the same order every time
+    let mut material_defs: Vec<_> = file.materials.into_iter().collect();
+    material_defs.sort_by(|a, b| a.0.cmp(&b.0));
+    for (name, def) in material_defs {
+        let line = Some(line_of(source, def.span().start));
+        let field = format!("materials.{}", name);
+        let material = material(definition(source, &field, def)?, base_dir)
+            .map_err(|message| LoadError::invalid(line, format!("{}: {}", field, message)))?;
+        scene.add_named_material(name, material);
+    }
+
+    let mut meshes = HashMap::new();
+    for (i, def) in file.objects.into_iter().enumerate() {
+        let line = Some(line_of(source, def.span().start));
+        let field = format!("objects[{}]", i);
+        let def: ObjectDef = definition(source, &field, def)?;
+        let name = def.material().ok_or_else(|| {
+            LoadError::invalid(line, format!("{}: missing field `material`", field))
+        })?;
+        let material = scene.material_named(name).ok_or_else(|| {
+            LoadError::invalid(
+                line,
+                format!("{}.material: no material named `{}`", field, name),
+            )
+        })?;
+        let object = def
+            .into_object(base_dir, &mut meshes)
+            .map_err(|error| match error {
+                ObjectError::Load(error) => error,
+                ObjectError::Invalid(message) => {
+                    LoadError::invalid(line, format!("{}: {}", field, message))
+                }
+            })?;
+        if scene.material(material).is_emissive() {
+            if let Err(reason) = object.check_light_sampling() {
+                return Err(LoadError::invalid(
+                    line,
+                    format!("{}: can't be a light: {}", field, reason),
+                ));
+            }
+        }
+        scene.add_object(object, material);
+    }
+
+    for (i, def) in file.lights.into_iter().enumerate() {
+        let line = Some(line_of(source, def.span().start));
+        let field = format!("lights[{}]", i);
+        add_light(&mut scene, definition(source, &field, def)?, base_dir)
+            .map_err(|message| LoadError::invalid(line, format!("{}: {}", field, message)))?;
+    }
+
+    let camera_def = &file.camera;
+    let mut camera = Camera::new(
+        FieldOfView::Degrees(float(camera_def.fov)),
+        ImageShape {
+            width: camera_def.width,
+            height: camera_def.height,
+        },
+    )
+    .at(
+        float(camera_def.position[0]),
+        float(camera_def.position[1]),
+        float(camera_def.position[2]),
+    );
+    if let Some(target) = camera_def.look_at {
+        camera = camera.looking_at(three(target), three(camera_def.up));
+    }
+    if let Some([open, close]) = camera_def.shutter {
+        camera = camera.with_shutter(float(open), float(close));
+    }
+
+    Ok(SceneDescription {
+        scene,
+        camera,
+        settings: file.render,
+        warnings: Vec::new(),
+    })
+}
+
+/// Reads the definition at `field` of a scene file from its `value`. Errors point at the line
+/// of the key they're about, if they name one, or at the start of the definition otherwise.
+fn definition<T>(source: &str, field: &str, value: Spanned<toml::Value>) -> Result<T, LoadError>
+where
+    T: DeserializeOwned,
+{
+    let span = value.span();
+    value.into_inner().try_into().map_err(|error| {
+        let message = error.message().trim().to_string();
+        let key = message
+            .strip_prefix("unknown field `")
+            .and_then(|rest| rest.split('`').next());
+        let offset = key
+            .and_then(|key| key_offset(&source[span.clone()], key))
+            .unwrap_or(0);
+        LoadError::invalid(
+            Some(line_of(source, span.start + offset)),
+            format!("{}: {}", field, message),
+        )
+    })
+}
+
+/// Where `key` is first set in a piece of TOML, including inside inline tables.
+fn key_offset(toml: &str, key: &str) -> Option<usize> {
+    toml.match_indices(key)
+        .map(|(offset, _)| offset)
+        .find(|&offset| {
+            let before = toml[..offset].chars().next_back();
+            let after = toml[offset + key.len()..].trim_start();
+            matches!(before, None | Some(' ' | '\t' | '\n' | '{' | ',')) && after.starts_with('=')
+        })
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    camera: CameraDef,
+    #[serde(default)]
+    render: RenderSettings,
+    #[serde(default)]
+    light_selection: LightSelectionDef,
+    // kept as plain values, so errors in each can say which it is, see `definition`
+    #[serde(default)]
+    materials: HashMap<String, Spanned<toml::Value>>,
+    #[serde(default)]
+    objects: Vec<Spanned<toml::Value>>,
+    #[serde(default)]
+    lights: Vec<Spanned<toml::Value>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDef {
+    #[serde(default)]
+    position: [f64; 3],
+    look_at: Option<[f64; 3]>,
+    #[serde(default = "y_axis")]
+    up: [f64; 3],
+    /// Vertical field of view, in degrees.
+    #[serde(default = "default_fov")]
+    fov: f64,
+    width: usize,
+    height: usize,
+    /// Times the shutter opens and closes, which blurs `moving` objects.
+    shutter: Option<[f64; 2]>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum LightSelectionDef {
+    Uniform,
+    #[default]
+    Power,
+    Tree,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum MaterialDef {
+    Diffuse {
+        #[serde(default = "white")]
+        rgb: [f64; 3],
+        /// Image multiplying the color, or a PLY or STL file whose mesh gets its colors.
+        texture: Option<PathBuf>,
+    },
+    Mirror {
+        #[serde(default = "white")]
+        rgb: [f64; 3],
+    },
+    Dielectric {
+        #[serde(default = "white")]
+        rgb: [f64; 3],
+        ior: f64,
+    },
+    Light {
+        #[serde(default = "white")]
+        rgb: [f64; 3],
+        power: f64,
+        #[serde(default)]
+        units: UnitsDef,
+        #[serde(default = "yes")]
+        two_sided: bool,
+        /// Image multiplying the emitted color.
+        texture: Option<PathBuf>,
+    },
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum UnitsDef {
+    #[default]
+    Radiance,
+    Watts,
+    Nits,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum ObjectDef {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        material: Option<String>,
+        #[serde(default)]
+        transform: TransformDef,
+    },
+    /// Infinite plane through `point`, facing `normal`.
+    Plane {
+        normal: [f64; 3],
+        #[serde(default)]
+        point: [f64; 3],
+        material: Option<String>,
+        #[serde(default)]
+        transform: TransformDef,
+    },
+    Triangle {
+        vertices: [[f64; 3]; 3],
+        uvs: Option<[[f64; 2]; 3]>,
+        material: Option<String>,
+        #[serde(default)]
+        transform: TransformDef,
+    },
+    /// `triangle` extended `length` along its negative normal.
+    Prism {
+        triangle: [[f64; 3]; 3],
+        length: f64,
+        material: Option<String>,
+        #[serde(default)]
+        transform: TransformDef,
+    },
+    /// Box between the opposite corners `min` and `max`.
+    #[serde(rename = "box")]
+    Cuboid {
+        min: [f64; 3],
+        max: [f64; 3],
+        material: Option<String>,
+        #[serde(default)]
+        transform: TransformDef,
+    },
+    /// Parallelogram spanned by the two `edges` from `corner`, facing their cross product.
+    Quad {
+        corner: [f64; 3],
+        edges: [[f64; 3]; 2],
+        material: Option<String>,
+        #[serde(default)]
+        transform: TransformDef,
+    },
+    Disk {
+        center: [f64; 3],
+        normal: [f64; 3],
+        radius: f64,
+        material: Option<String>,
+        #[serde(default)]
+        transform: TransformDef,
+    },
+    /// Tube from the center of its `base` to the center of its `top`, with disks closing it if
+    /// `capped`, going `sweep` degrees around.
+    Cylinder {
+        base: [f64; 3],
+        top: [f64; 3],
+        radius: f64,
+        #[serde(default)]
+        capped: bool,
+        #[serde(default = "full_circle")]
+        sweep: f64,
+        material: Option<String>,
+        #[serde(default)]
+        transform: TransformDef,
+    },
+    Cone {
+        base: [f64; 3],
+        apex: [f64; 3],
+        radius: f64,
+        #[serde(default)]
+        capped: bool,
+        #[serde(default = "full_circle")]
+        sweep: f64,
+        material: Option<String>,
+        #[serde(default)]
+        transform: TransformDef,
+    },
+    /// Ring around `center` and `axis`, with disks closing its ends if it's `capped` and doesn't
+    /// go all the way around.
+    Torus {
+        center: [f64; 3],
+        #[serde(default = "y_axis")]
+        axis: [f64; 3],
+        major_radius: f64,
+        minor_radius: f64,
+        #[serde(default)]
+        capped: bool,
+        #[serde(default = "full_circle")]
+        sweep: f64,
+        material: Option<String>,
+        #[serde(default)]
+        transform: TransformDef,
+    },
+    /// Solid made of the closed `objects`: everything inside any of them.
+    ///
+    /// Objects inside solids don't need materials, and any they have are ignored.
+    Union {
+        objects: Vec<ObjectDef>,
+        material: Option<String>,
+    },
+    /// Solid made of what's inside all of the closed `objects`.
+    Intersection {
+        objects: Vec<ObjectDef>,
+        material: Option<String>,
+    },
+    /// Solid made of the first of the closed `objects` with the others cut out of it.
+    Difference {
+        objects: Vec<ObjectDef>,
+        material: Option<String>,
+    },
+    /// Terrain over the xz plane from `corner` to `corner + size`, with heights from 0 to 1
+    /// scaled by the y of `size`. They're the brightness of a grayscale image `file`, or given as
+    /// `heights`, a list of rows along x from the lowest z up.
+    Heightfield {
+        file: Option<PathBuf>,
+        #[serde(default)]
+        heights: Vec<Vec<f64>>,
+        corner: [f64; 3],
+        size: [f64; 3],
+        material: Option<String>,
+    },
+    /// `object` moving between `keyframes`, which each place it like a `transform` at their
+    /// `time`. It's blurred while the camera's shutter is open.
+    Moving {
+        object: Box<ObjectDef>,
+        keyframes: Vec<KeyframeDef>,
+        material: Option<String>,
+    },
+    /// Triangles from an OBJ, PLY or STL `file`, or given as `vertices` and `indices` into them.
+    /// Objects using the same file share one copy of its mesh.
+    Mesh {
+        file: Option<PathBuf>,
+        #[serde(default)]
+        vertices: Vec<[f64; 3]>,
+        #[serde(default)]
+        indices: Vec<[usize; 3]>,
+        material: Option<String>,
+        #[serde(default)]
+        transform: TransformDef,
+    },
+}
+
+/// Scales, then rotates, then translates, all around the origin.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransformDef {
+    #[serde(default = "one")]
+    scale: f64,
+    rotate: Option<RotationDef>,
+    #[serde(default)]
+    translate: [f64; 3],
+}
+
+/// Where a moving object is at `time`, like a `TransformDef`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyframeDef {
+    time: f64,
+    #[serde(default = "one")]
+    scale: f64,
+    rotate: Option<RotationDef>,
+    #[serde(default)]
+    translate: [f64; 3],
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RotationDef {
+    axis: [f64; 3],
+    degrees: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum LightDef {
+    Point {
+        position: [f64; 3],
+        #[serde(default = "white")]
+        rgb: [f64; 3],
+        power: f64,
+    },
+    Spot {
+        position: [f64; 3],
+        direction: [f64; 3],
+        #[serde(default = "white")]
+        rgb: [f64; 3],
+        power: f64,
+        cone_angle: f64,
+        /// Defaults to `cone_angle`, i.e. a hard edge.
+        falloff_start: Option<f64>,
+        /// IES file replacing the smooth falloff.
+        profile: Option<PathBuf>,
+    },
+    Directional {
+        direction: [f64; 3],
+        #[serde(default = "white")]
+        rgb: [f64; 3],
+        power: f64,
+    },
+}
+
+enum ObjectError {
+    /// A file the object refers to couldn't be loaded.
+    Load(LoadError),
+    Invalid(String),
+}
+
+fn white() -> [f64; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn y_axis() -> [f64; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+fn full_circle() -> f64 {
+    360.0
+}
+
+fn default_fov() -> f64 {
+    60.0
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+fn yes() -> bool {
+    true
+}
+
+fn three<F: Float>(v: [f64; 3]) -> Three<F> {
+    Three::new(float(v[0]), float(v[1]), float(v[2]))
+}
+
+impl Default for TransformDef {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            rotate: None,
+            translate: [0.0; 3],
+        }
+    }
+}
+
+impl From<LightSelectionDef> for LightSelection {
+    fn from(def: LightSelectionDef) -> Self {
+        match def {
+            LightSelectionDef::Uniform => LightSelection::Uniform,
+            LightSelectionDef::Power => LightSelection::Power,
+            LightSelectionDef::Tree => LightSelection::Tree,
+        }
+    }
+}
+
+impl From<UnitsDef> for EmissionUnits {
+    fn from(def: UnitsDef) -> Self {
+        match def {
+            UnitsDef::Radiance => EmissionUnits::Radiance,
+            UnitsDef::Watts => EmissionUnits::Watts,
+            UnitsDef::Nits => EmissionUnits::Nits,
+        }
+    }
+}
+
+fn material<F>(def: MaterialDef, base_dir: &Path) -> Result<Material<F>, String>
+where
+    F: Float + FloatConst,
+{
+    Ok(match def {
+        MaterialDef::Diffuse { rgb, texture } => {
+            let texture = match texture {
+                Some(texture) => Some(open_texture(&base_dir.join(texture))?),
+                None => None,
+            };
+            Diffuse {
+                rgb: three(rgb),
+                texture,
+            }
+            .into()
+        }
+        MaterialDef::Mirror { rgb } => Mirror { rgb: three(rgb) }.into(),
+        MaterialDef::Dielectric { rgb, ior } => Dielectric {
+            rgb: three(rgb),
+            ior: float(ior),
+        }
+        .into(),
+        MaterialDef::Light {
+            rgb,
+            power,
+            units,
+            two_sided,
+            texture,
+        } => {
+            let mut light = Light::new(three(rgb), float(power)).in_units(units.into());
+            if !two_sided {
+                light = light.one_sided();
+            }
+            if let Some(texture) = texture {
+                light = light.textured(open_texture(&base_dir.join(texture))?);
+            }
+            light.into()
+        }
+    })
+}
+
+/// An image, or the colors of a PLY or STL mesh.
+fn open_texture<F: Float>(path: &Path) -> Result<Texture<F>, String> {
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    if let Some("ply" | "stl" | "PLY" | "STL") = extension {
+        return load_mesh_colors(path).map_err(|error| error.to_string());
+    }
+    ImageTexture::open(path)
+        .map(Texture::from)
+        .map_err(|error| format!("{}: {}", path.display(), error))
+}
+
+fn add_light<F>(scene: &mut Scene<F>, def: LightDef, base_dir: &Path) -> Result<(), String>
+where
+    F: Float,
+{
+    match def {
+        LightDef::Point {
+            position,
+            rgb,
+            power,
+        } => scene.add_light(PointLight {
+            position: three(position),
+            rgb: three(rgb),
+            power: float(power),
+        }),
+        LightDef::Spot {
+            position,
+            direction,
+            rgb,
+            power,
+            cone_angle,
+            falloff_start,
+            profile,
+        } => {
+            let mut light = SpotLight {
+                position: three(position),
+                direction: three(direction),
+                rgb: three(rgb),
+                power: float(power),
+                cone_angle: float(cone_angle),
+                falloff_start: float(falloff_start.unwrap_or(cone_angle)),
+                profile: None,
+            };
+            if let Some(profile) = profile {
+                let path = base_dir.join(profile);
+                let profile = IesProfile::open(&path)
+                    .map_err(|error| format!("{}: {}", path.display(), error))?;
+                light = light.with_profile(profile);
+            }
+            scene.add_light(light)
+        }
+        LightDef::Directional {
+            direction,
+            rgb,
+            power,
+        } => scene.add_light(DirectionalLight {
+            direction: three(direction),
+            rgb: three(rgb),
+            power: float(power),
+        }),
+    }
+    Ok(())
+}
+
+impl ObjectDef {
+    fn material(&self) -> Option<&str> {
+        match self {
+            Self::Sphere { material, .. }
+            | Self::Plane { material, .. }
+            | Self::Triangle { material, .. }
+            | Self::Prism { material, .. }
+            | Self::Cuboid { material, .. }
+            | Self::Quad { material, .. }
+            | Self::Disk { material, .. }
+            | Self::Cylinder { material, .. }
+            | Self::Cone { material, .. }
+            | Self::Torus { material, .. }
+            | Self::Union { material, .. }
+            | Self::Intersection { material, .. }
+            | Self::Difference { material, .. }
+            | Self::Heightfield { material, .. }
+            | Self::Moving { material, .. }
+            | Self::Mesh { material, .. } => material.as_deref(),
+        }
+    }
+
+    fn into_object<F: Float + FloatConst>(
+        self,
+        base_dir: &Path,
+        meshes: &mut HashMap<PathBuf, Arc<Object<F>>>,
+    ) -> Result<Object<F>, ObjectError> {
+        let invalid = |message: &str| Err(ObjectError::Invalid(message.to_string()));
+        Ok(match self {
+            Self::Sphere {
+                center,
+                radius,
+                transform,
+                ..
+            } => {
+                if radius <= 0.0 || transform.scale <= 0.0 {
+                    return invalid("radius and scale must be positive");
+                }
+                let center = transform.point(three(center));
+                Sphere::new(center, float(radius * transform.scale)).into()
+            }
+            Self::Plane {
+                normal,
+                point,
+                transform,
+                ..
+            } => {
+                if three::<F>(normal).length_squared() == F::zero() {
+                    return invalid("normal can't be zero");
+                }
+                let normal: Three<F> = transform.direction(three(normal)).normalized();
+                let point = transform.point(three(point));
+                Plane::new(normal)
+                    .transformed(&Transform::translation(point))
+                    .into()
+            }
+            Self::Triangle {
+                vertices,
+                uvs,
+                transform,
+                ..
+            } => {
+                let triangle = transform.triangle(vertices);
+                match uvs {
+                    Some([uv0, uv1, uv2]) => triangle
+                        .with_uvs(
+                            (float(uv0[0]), float(uv0[1])),
+                            (float(uv1[0]), float(uv1[1])),
+                            (float(uv2[0]), float(uv2[1])),
+                        )
+                        .into(),
+                    None => triangle.into(),
+                }
+            }
+            Self::Prism {
+                triangle,
+                length,
+                transform,
+                ..
+            } => {
+                if length <= 0.0 {
+                    return invalid("length must be positive");
+                }
+                Prism::new(
+                    transform.triangle(triangle),
+                    float(length * transform.scale),
+                )
+                .into()
+            }
+            Self::Cuboid {
+                min,
+                max,
+                transform,
+                ..
+            } => {
+                if transform.scale <= 0.0 {
+                    return invalid("scale must be positive");
+                }
+                Cuboid::new(three(min), three(max))
+                    .transformed(&transform.transform())
+                    .into()
+            }
+            Self::Quad {
+                corner,
+                edges: [u, v],
+                transform,
+                ..
+            } => {
+                let scale = float::<F>(transform.scale);
+                let u = transform.direction(three::<F>(u)) * scale;
+                let v = transform.direction(three::<F>(v)) * scale;
+                if u.cross(&v).length_squared() == F::zero() {
+                    return invalid("edges can't be parallel");
+                }
+                Quad::new(transform.point(three(corner)), u, v).into()
+            }
+            Self::Disk {
+                center,
+                normal,
+                radius,
+                transform,
+                ..
+            } => {
+                if radius <= 0.0 || transform.scale <= 0.0 {
+                    return invalid("radius and scale must be positive");
+                }
+                if three::<F>(normal).length_squared() == F::zero() {
+                    return invalid("normal can't be zero");
+                }
+                Disk::new(
+                    transform.point(three(center)),
+                    transform.direction(three(normal)),
+                    float(radius * transform.scale),
+                )
+                .into()
+            }
+            Self::Cylinder {
+                base,
+                top,
+                radius,
+                capped,
+                sweep,
+                transform,
+                ..
+            } => {
+                if radius <= 0.0 || transform.scale <= 0.0 || sweep <= 0.0 || base == top {
+                    return invalid("radius, scale, sweep and height must be positive");
+                }
+                let cylinder = Cylinder::new(
+                    transform.point(three(base)),
+                    transform.point(three(top)),
+                    float(radius * transform.scale),
+                )
+                .swept(float(sweep));
+                if capped {
+                    cylinder.capped().into()
+                } else {
+                    cylinder.into()
+                }
+            }
+            Self::Cone {
+                base,
+                apex,
+                radius,
+                capped,
+                sweep,
+                transform,
+                ..
+            } => {
+                if radius <= 0.0 || transform.scale <= 0.0 || sweep <= 0.0 || base == apex {
+                    return invalid("radius, scale, sweep and height must be positive");
+                }
+                let cone = Cone::new(
+                    transform.point(three(base)),
+                    transform.point(three(apex)),
+                    float(radius * transform.scale),
+                )
+                .swept(float(sweep));
+                if capped {
+                    cone.capped().into()
+                } else {
+                    cone.into()
+                }
+            }
+            Self::Torus {
+                center,
+                axis,
+                major_radius,
+                minor_radius,
+                capped,
+                sweep,
+                transform,
+                ..
+            } => {
+                if major_radius <= 0.0
+                    || minor_radius <= 0.0
+                    || transform.scale <= 0.0
+                    || sweep <= 0.0
+                {
+                    return invalid("radii, scale and sweep must be positive");
+                }
+                if three::<F>(axis).length_squared() == F::zero() {
+                    return invalid("axis can't be zero");
+                }
+                let torus = Torus::new(
+                    transform.point(three(center)),
+                    transform.direction(three(axis)),
+                    float(major_radius * transform.scale),
+                    float(minor_radius * transform.scale),
+                )
+                .swept(float(sweep));
+                if capped {
+                    torus.capped().into()
+                } else {
+                    torus.into()
+                }
+            }
+            Self::Union { objects, .. } => solid(objects, base_dir, meshes, Csg::union)?,
+            Self::Intersection { objects, .. } => {
+                solid(objects, base_dir, meshes, Csg::intersection)?
+            }
+            Self::Difference { objects, .. } => solid(objects, base_dir, meshes, Csg::difference)?,
+            Self::Heightfield {
+                file,
+                heights,
+                corner,
+                size,
+                ..
+            } => {
+                let (corner, size) = (three(corner), three(size));
+                if size.x <= F::zero() || size.z <= F::zero() {
+                    return invalid("size must be positive along x and z");
+                }
+                match file {
+                    Some(_) if !heights.is_empty() => {
+                        return invalid("give either a file or heights, not both")
+                    }
+                    Some(file) => {
+                        let path = base_dir.join(file);
+                        let image = image::open(&path).map_err(|error| {
+                            ObjectError::Invalid(format!("{}: {}", path.display(), error))
+                        })?;
+                        let (width, height) = image::GenericImageView::dimensions(&image);
+                        if width < 2 || height < 2 {
+                            return invalid("image must be at least 2x2 pixels");
+                        }
+                        Heightfield::from_image(&image, corner, size).into()
+                    }
+                    None => {
+                        let columns = heights.first().map_or(0, Vec::len);
+                        if heights.len() < 2 || columns < 2 {
+                            return invalid("heights must be at least 2x2");
+                        }
+                        if heights.iter().any(|row| row.len() != columns) {
+                            return invalid("rows of heights must be the same length");
+                        }
+                        let rows = heights.len();
+                        let heights = heights.into_iter().flatten().map(float).collect();
+                        Heightfield::new(columns, rows, heights, corner, size).into()
+                    }
+                }
+            }
+            Self::Moving {
+                object, keyframes, ..
+            } => {
+                if keyframes.is_empty() {
+                    return invalid("keyframes can't be empty");
+                }
+                if keyframes.iter().any(|key| key.scale <= 0.0) {
+                    return invalid("scale must be positive");
+                }
+                let object = object.into_object(base_dir, meshes)?;
+                let keyframes = keyframes.iter().map(KeyframeDef::keyframe).collect();
+                MovingInstance::new(Arc::new(object), Motion::new(keyframes)).into()
+            }
+            Self::Mesh {
+                file,
+                vertices,
+                indices,
+                transform,
+                ..
+            } => {
+                let mesh = match file {
+                    Some(_) if !vertices.is_empty() || !indices.is_empty() => {
+                        return invalid("give either a file or vertices and indices, not both")
+                    }
+                    Some(file) => {
+                        // every object using a file shares one copy of its mesh
+                        let path = base_dir.join(file);
+                        let mesh = match meshes.get(&path) {
+                            Some(mesh) => mesh.clone(),
+                            None => {
+                                let mesh = load_mesh(&path).map_err(ObjectError::Load)?;
+                                if mesh.triangles().is_empty() {
+                                    return invalid("mesh has no triangles");
+                                }
+                                let mesh = Arc::new(Object::from(mesh));
+                                meshes.insert(path, mesh.clone());
+                                mesh
+                            }
+                        };
+                        return Ok(Instance::new(mesh, transform.transform()).into());
+                    }
+                    None => {
+                        if indices.iter().flatten().any(|&i| i >= vertices.len()) {
+                            return invalid("indices refer to missing vertices");
+                        }
+                        let vertices: Vec<Three<F>> = vertices.into_iter().map(three).collect();
+                        Mesh::indexed(&vertices, &indices)
+                    }
+                };
+                if mesh.triangles().is_empty() {
+                    return invalid("mesh has no triangles");
+                }
+                mesh.transformed(&transform.transform()).into()
+            }
+        })
+    }
+}
+
+/// Combines the first of `objects` with each of the others in turn.
+fn solid<F, C>(
+    objects: Vec<ObjectDef>,
+    base_dir: &Path,
+    meshes: &mut HashMap<PathBuf, Arc<Object<F>>>,
+    combine: C,
+) -> Result<Object<F>, ObjectError>
+where
+    F: Float + FloatConst,
+    C: Fn(Object<F>, Object<F>) -> Csg<F>,
+{
+    let mut objects = objects.into_iter();
+    let first = match objects.next() {
+        Some(def) => def.into_object(base_dir, meshes)?,
+        None => return Err(ObjectError::Invalid("objects can't be empty".to_string())),
+    };
+    objects.try_fold(first, |solid, def| {
+        Ok(combine(solid, def.into_object(base_dir, meshes)?).into())
+    })
+}
+
+impl KeyframeDef {
+    fn keyframe<F: Float>(&self) -> Keyframe<F> {
+        let keyframe = Keyframe::new(float(self.time))
+            .with_scale(Three::from(float::<F>(self.scale)))
+            .with_translation(three(self.translate));
+        match &self.rotate {
+            Some(rotation) => {
+                keyframe.with_rotation(&three(rotation.axis), float(rotation.degrees))
+            }
+            None => keyframe,
+        }
+    }
+}
+
+impl TransformDef {
+    fn direction<F: Float>(&self, v: Three<F>) -> Three<F> {
+        match &self.rotate {
+            Some(rotation) => v.rotate(
+                &three::<F>(rotation.axis).normalized(),
+                float(rotation.degrees),
+            ),
+            None => v,
+        }
+    }
+
+    fn point<F: Float>(&self, p: Three<F>) -> Three<F> {
+        self.direction(p * float::<F>(self.scale)) + three(self.translate)
+    }
+
+    fn triangle<F: Float>(&self, vertices: [[f64; 3]; 3]) -> Triangle<F> {
+        let [v0, v1, v2] = vertices.map(|v| self.point(three(v)));
+        Triangle::new(v0, v1, v2)
+    }
+
+    fn transform<F: Float>(&self) -> Transform<F> {
+        let rotation = match &self.rotate {
+            Some(rotation) => Transform::rotation(
+                &three::<F>(rotation.axis).normalized(),
+                float(rotation.degrees),
+            ),
+            None => Transform::identity(),
+        };
+        Transform::translation(three(self.translate))
+            * rotation
+            * Transform::scaling(Three::from(float::<F>(self.scale)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Bounded, CanHit, Ray};
+
+    const CAMERA: &str = "[camera]\nwidth = 4\nheight = 3\n";
+
+    fn parse(source: &str) -> SceneDescription<f64> {
+        parse_scene(source, Path::new("")).unwrap()
+    }
+
+    /// Line and message of the error loading `source`.
+    fn error(source: &str) -> (Option<usize>, String) {
+        match parse_scene::<f64>(source, Path::new("")) {
+            Err(LoadError::Invalid { line, message, .. }) => (line, message),
+            Err(error) => panic!("{}", error),
+            Ok(_) => panic!("loaded"),
+        }
+    }
+
+    fn assert_near(a: Three<f64>, b: Three<f64>) {
+        assert!((a - b).length() < 1e-9, "{:?} is not {:?}", a, b);
+    }
+
+    #[test]
+    fn loads_the_camera_materials_objects_lights_and_settings() {
+        let description = parse(
+            r#"
+light_selection = "tree"
+
+[camera]
+position = [0, 1, 4]
+look_at = [0, 1, 0]
+fov = 45
+width = 64
+height = 32
+shutter = [0, 0.5]
+
+[render]
+tracer = "bidirectional"
+samples = 8
+depth = 3
+
+[materials.white]
+type = "diffuse"
+rgb = [0.8, 0.8, 0.8]
+
+[materials.lamp]
+type = "light"
+power = 20
+two_sided = false
+
+[[objects]]
+type = "sphere"
+center = [1, 0, 0]
+radius = 0.5
+material = "lamp"
+transform = { scale = 2, translate = [0, 3, 0] }
+
+[[objects]]
+type = "plane"
+normal = [0, 1, 0]
+point = [0, -1, 0]
+material = "white"
+
+[[objects]]
+type = "triangle"
+vertices = [[0, 0, 0], [1, 0, 0], [0, 1, 0]]
+uvs = [[0, 0], [1, 0], [0, 1]]
+material = "white"
+
+[[objects]]
+type = "prism"
+triangle = [[0, 0, 0], [1, 0, 0], [0, 1, 0]]
+length = 1
+material = "white"
+
+[[objects]]
+type = "box"
+min = [-1, -1, -1]
+max = [1, 1, 1]
+material = "white"
+transform = { rotate = { axis = [0, 1, 0], degrees = 90 }, translate = [5, 0, 0] }
+
+[[objects]]
+type = "quad"
+corner = [0, 2, 0]
+edges = [[1, 0, 0], [0, 0, 1]]
+material = "lamp"
+
+[[objects]]
+type = "disk"
+center = [0, 0, 0]
+normal = [0, 0, 1]
+radius = 1
+material = "white"
+
+[[objects]]
+type = "cylinder"
+base = [0, 0, 0]
+top = [0, 1, 0]
+radius = 0.5
+capped = true
+material = "white"
+
+[[objects]]
+type = "cone"
+base = [0, 0, 0]
+apex = [0, 1, 0]
+radius = 0.5
+sweep = 180
+material = "white"
+
+[[objects]]
+type = "torus"
+center = [0, 0, 0]
+major_radius = 1
+minor_radius = 0.25
+material = "white"
+
+[[objects]]
+type = "difference"
+material = "white"
+objects = [
+    { type = "box", min = [-1, -1, -1], max = [1, 1, 1] },
+    { type = "sphere", center = [0, 0, 0], radius = 1.2 },
+]
+
+[[objects]]
+type = "union"
+material = "white"
+objects = [
+    { type = "sphere", center = [0, 0, 0], radius = 1 },
+    { type = "sphere", center = [1, 0, 0], radius = 1 },
+]
+
+[[objects]]
+type = "intersection"
+material = "white"
+objects = [
+    { type = "sphere", center = [0, 0, 0], radius = 1 },
+    { type = "sphere", center = [1, 0, 0], radius = 1 },
+]
+
+[[objects]]
+type = "heightfield"
+heights = [[0, 0.5], [1, 0]]
+corner = [0, 0, 0]
+size = [2, 1, 2]
+material = "white"
+
+[[objects]]
+type = "moving"
+material = "white"
+object = { type = "sphere", center = [0, 0, 0], radius = 1 }
+keyframes = [{ time = 0 }, { time = 1, translate = [1, 0, 0] }]
+
+[[objects]]
+type = "mesh"
+vertices = [[0, 0, 0], [1, 0, 0], [0, 1, 0], [1, 1, 0]]
+indices = [[0, 1, 2], [2, 1, 3]]
+material = "white"
+
+[[lights]]
+type = "point"
+position = [1, 2, 1]
+power = 5
+
+[[lights]]
+type = "spot"
+position = [0, 3, 0]
+direction = [0, -1, 0]
+power = 5
+cone_angle = 30
+falloff_start = 20
+
+[[lights]]
+type = "directional"
+direction = [0, -1, 0]
+power = 1
+"#,
+        );
+        let SceneDescription {
+            mut scene,
+            camera,
+            settings,
+            warnings,
+        } = description;
+        assert!(warnings.is_empty());
+
+        assert_eq!(settings.tracer, TracerKind::Bidirectional);
+        assert_eq!((settings.samples, settings.depth), (8, 3));
+        assert_eq!((camera.width, camera.height), (64, 32));
+        assert_near(camera.position, Three::new(0.0, 1.0, 4.0));
+        assert_near(camera.forward, Three::new(0.0, 0.0, -1.0));
+        assert_eq!(camera.shutter, (0.0, 0.5));
+
+        let lamp = scene.material_named("lamp").unwrap();
+        assert!(scene.material(lamp).is_emissive());
+        assert!(matches!(scene.material_for(1), Material::Diffuse(_)));
+        let kinds: Vec<fn(&Object<f64>) -> bool> = vec![
+            |o| matches!(o, Object::Sphere(_)),
+            |o| matches!(o, Object::Plane(_)),
+            |o| matches!(o, Object::Triangle(_)),
+            |o| matches!(o, Object::Prism(_)),
+            |o| matches!(o, Object::Cuboid(_)),
+            |o| matches!(o, Object::Quad(_)),
+            |o| matches!(o, Object::Disk(_)),
+            |o| matches!(o, Object::Cylinder(_)),
+            |o| matches!(o, Object::Cone(_)),
+            |o| matches!(o, Object::Torus(_)),
+            |o| matches!(o, Object::Csg(_)),
+            |o| matches!(o, Object::Csg(_)),
+            |o| matches!(o, Object::Csg(_)),
+            |o| matches!(o, Object::Heightfield(_)),
+            |o| matches!(o, Object::MovingInstance(_)),
+            |o| matches!(o, Object::Mesh(_)),
+        ];
+        for (i, is_kind) in kinds.iter().enumerate() {
+            assert!(is_kind(scene.object(i)), "{}: {:?}", i, scene.object(i));
+        }
+        assert_eq!(scene.emissive_objects().len(), 2);
+        assert_eq!(scene.lights().len(), 3);
+
+        // scaled, then moved
+        let sphere = scene.object(0).bounds();
+        assert_near(sphere.min, Three::new(1.0, 2.0, -1.0));
+        assert_near(sphere.max, Three::new(3.0, 4.0, 1.0));
+        // turning a box that's the same size along x and z doesn't change its bounds
+        let cuboid = scene.object(4).bounds();
+        assert_near(cuboid.min, Three::new(4.0, -1.0, -1.0));
+        assert_near(cuboid.max, Three::new(6.0, 1.0, 1.0));
+        let plane_hit = Ray::new(Three::new(20.0, 5.0, 20.0), Three::new(0.0, -1.0, 0.0))
+            .shoot_at(scene.object(1), 1e-6, f64::INFINITY)
+            .unwrap();
+        assert!((plane_hit.distance - 6.0).abs() < 1e-9);
+        scene.build_light_sampler();
+        assert!(scene.light_sampler().is_some());
+    }
+
+    #[test]
+    fn unknown_fields_point_at_their_line() {
+        let source = format!(
+            "{}\n[materials.white]\ntype = \"diffuse\"\n\n[[objects]]\ntype = \"sphere\"\n\
+             center = [0, 0, 0]\nradius = 1\ncolour = [1, 0, 0]\nmaterial = \"white\"\n",
+            CAMERA
+        );
+        let (line, message) = error(&source);
+        assert_eq!(line, Some(12));
+        assert!(
+            message.starts_with("objects[0]: unknown field `colour`"),
+            "{}",
+            message
+        );
+
+        let source = format!(
+            "{}\n[materials.white]\ntype = \"diffuse\"\nshiny = true\n",
+            CAMERA
+        );
+        let (line, message) = error(&source);
+        assert_eq!(line, Some(7));
+        assert!(
+            message.starts_with("materials.white: unknown field `shiny`"),
+            "{}",
+            message
+        );
+
+        // inside an inline table too
+        let source = format!(
+            "{}\n[materials.white]\ntype = \"diffuse\"\n\n[[objects]]\ntype = \"box\"\n\
+             min = [0, 0, 0]\nmax = [1, 1, 1]\nmaterial = \"white\"\n\
+             transform = {{ scale = 2, spin = 3 }}\n",
+            CAMERA
+        );
+        let (line, message) = error(&source);
+        assert_eq!(line, Some(13));
+        assert!(
+            message.starts_with("objects[0]: unknown field `spin`"),
+            "{}",
+            message
+        );
+
+        let (line, message) = error(&format!("{}focus = 2\n", CAMERA));
+        assert_eq!(line, Some(4));
+        assert!(message.starts_with("unknown field `focus`"), "{}", message);
+    }
+
+    #[test]
+    fn objects_without_a_material_point_at_the_object() {
+        let source = format!(
+            "{}\n[materials.white]\ntype = \"diffuse\"\n\n[[objects]]\ntype = \"sphere\"\n\
+             center = [0, 0, 0]\nradius = 1\nmaterial = \"white\"\n\n[[objects]]\n\
+             type = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\n",
+            CAMERA
+        );
+        assert_eq!(
+            error(&source),
+            (Some(14), "objects[1]: missing field `material`".to_string())
+        );
+        let source = format!(
+            "{}\n[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\n\
+             material = \"gold\"\n",
+            CAMERA
+        );
+        assert_eq!(
+            error(&source),
+            (
+                Some(5),
+                "objects[0].material: no material named `gold`".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn emissive_planes_are_refused() {
+        let source = format!(
+            "{}\n[materials.lamp]\ntype = \"light\"\npower = 1\n\n[[objects]]\n\
+             type = \"plane\"\nnormal = [0, 1, 0]\nmaterial = \"lamp\"\n",
+            CAMERA
+        );
+        let (line, message) = error(&source);
+        assert_eq!(line, Some(9));
+        assert!(
+            message.starts_with("objects[0]: can't be a light: planes"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn invalid_shapes_name_their_field() {
+        let source = format!(
+            "{}\n[materials.white]\ntype = \"diffuse\"\n\n[[objects]]\ntype = \"sphere\"\n\
+             center = [0, 0, 0]\nradius = -1\nmaterial = \"white\"\n",
+            CAMERA
+        );
+        assert_eq!(
+            error(&source),
+            (
+                Some(8),
+                "objects[0]: radius and scale must be positive".to_string()
+            )
+        );
+    }
+}
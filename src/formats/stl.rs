@@ -0,0 +1,207 @@
+use super::colors::color_atlas;
+use super::{float, LoadError};
+use crate::data::Three;
+use crate::shapes::{Mesh, Triangle};
+use crate::texture::ImageTexture;
+use num_traits::Float;
+use std::path::Path;
+
+/// Loads the facets of an STL file, in its ascii or binary format, as one mesh. The normals
+/// stored with each facet are ignored in favor of the ones given by its winding.
+///
+/// Binary files can color their facets, in the VisCAM/SolidView or Materialise Magics way. If
+/// any facet is colored the texture coordinates point into the texture of those colors that
+/// `load_mesh_colors` loads.
+pub fn load_stl<F, P>(path: P) -> Result<Mesh<F>, LoadError>
+where
+    F: Float,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let bytes = std::fs::read(path).map_err(|error| LoadError::io(path, error))?;
+    parse_stl(&bytes)
+        .map(|(mesh, _)| mesh)
+        .map_err(|error| error.in_file(path))
+}
+
+/// The mesh in an STL file, and a texture of its facet colors if it has them.
+pub(super) fn parse_stl<F: Float>(
+    bytes: &[u8],
+) -> Result<(Mesh<F>, Option<ImageTexture<F>>), LoadError> {
+    // ascii files start with "solid", but so do some binary ones, whose size gives them away
+    let binary_size = bytes
+        .get(80..84)
+        .map(|count| 84 + 50 * u32::from_le_bytes(count.try_into().unwrap()) as usize);
+    if bytes.starts_with(b"solid") && binary_size != Some(bytes.len()) {
+        let text = std::str::from_utf8(bytes)
+            .map_err(|_| LoadError::invalid(None, "ascii STL isn't valid text"))?;
+        parse_ascii(text).map(|mesh| (mesh, None))
+    } else {
+        parse_binary(bytes)
+    }
+}
+
+fn parse_ascii<F: Float>(source: &str) -> Result<Mesh<F>, LoadError> {
+    let mut triangles = Vec::new();
+    let mut corners = Vec::new();
+    for (line_idx, line) in source.lines().enumerate() {
+        let line_number = Some(line_idx + 1);
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["vertex", x, y, z] => {
+                let [x, y, z] = [x, y, z].map(|word| word.parse::<f64>().ok());
+                match (x, y, z) {
+                    (Some(x), Some(y), Some(z)) => {
+                        corners.push(Three::new(float(x), float(y), float(z)))
+                    }
+                    _ => return Err(LoadError::invalid(line_number, "expected three numbers")),
+                }
+            }
+            ["endloop", ..] => {
+                // facets are triangles, but fan out bigger loops just in case
+                if corners.len() < 3 {
+                    return Err(LoadError::invalid(
+                        line_number,
+                        "loop has fewer than three vertices",
+                    ));
+                }
+                for i in 1..corners.len() - 1 {
+                    triangles.push(Triangle::new(corners[0], corners[i], corners[i + 1]));
+                }
+                corners.clear();
+            }
+            ["vertex", ..] => {
+                return Err(LoadError::invalid(line_number, "expected three numbers"))
+            }
+            ["solid", ..]
+            | ["endsolid", ..]
+            | ["facet", ..]
+            | ["endfacet", ..]
+            | ["outer", "loop"]
+            | [] => {}
+            _ => return Err(LoadError::invalid(line_number, "unexpected line")),
+        }
+    }
+    Ok(Mesh::new(triangles))
+}
+
+fn parse_binary<F: Float>(bytes: &[u8]) -> Result<(Mesh<F>, Option<ImageTexture<F>>), LoadError> {
+    let (header, body) = match (bytes.get(..80), bytes.get(84..)) {
+        (Some(header), Some(body)) => (header, body),
+        _ => return Err(LoadError::invalid(None, "file is too short to be an STL")),
+    };
+    let count = u32::from_le_bytes(bytes[80..84].try_into().unwrap()) as usize;
+    if body.len() < 50 * count {
+        return Err(LoadError::invalid(
+            None,
+            format!("file ends before its {} facets", count),
+        ));
+    }
+    // Magics files say so in their header, along with a color for uncolored facets
+    let magics = header.windows(6).position(|window| window == b"COLOR=");
+    let default_color = match magics.and_then(|at| header.get(at + 6..at + 9)) {
+        Some(&[r, g, b]) => {
+            let [r, g, b] = [r, g, b].map(|c| float(c as f64 / 255.0));
+            Three::new(r, g, b)
+        }
+        _ => Three::ones(),
+    };
+
+    let mut triangles = Vec::with_capacity(count);
+    let mut colors = Vec::with_capacity(count);
+    let mut any_colored = false;
+    for facet in body.chunks_exact(50).take(count) {
+        let number = |i: usize| {
+            let at = 12 + 4 * i;
+            float::<F>(f32::from_le_bytes(facet[at..at + 4].try_into().unwrap()) as f64)
+        };
+        let vertex = |v: usize| Three::new(number(3 * v), number(3 * v + 1), number(3 * v + 2));
+        triangles.push(Triangle::new(vertex(0), vertex(1), vertex(2)));
+
+        let attribute = u16::from_le_bytes([facet[48], facet[49]]);
+        let channel = |shift: u16| float::<F>(((attribute >> shift) & 31) as f64 / 31.0);
+        let color = match (magics, attribute & 0x8000 != 0) {
+            // Magics colors are valid with the top bit clear, and red in the lowest bits
+            (Some(_), false) => Some(Three::new(channel(0), channel(5), channel(10))),
+            // VisCAM colors are valid with the top bit set, and blue in the lowest bits
+            (None, true) => Some(Three::new(channel(10), channel(5), channel(0))),
+            _ => None,
+        };
+        any_colored |= color.is_some();
+        colors.push([color.unwrap_or(default_color); 3]);
+    }
+    if any_colored {
+        let (triangles, colors) = color_atlas(triangles, &colors);
+        Ok((Mesh::new(triangles), Some(colors)))
+    } else {
+        Ok((Mesh::new(triangles), None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Bounded;
+
+    #[test]
+    fn reads_ascii_facets() {
+        let source = "solid two\n\
+                      facet normal 0 0 1\n outer loop\n\
+                      vertex 0 0 0\n vertex 1 0 0\n vertex 0 1 0\n\
+                      endloop\nendfacet\n\
+                      facet normal 0 0 1\n outer loop\n\
+                      vertex 0 0 1\n vertex 1 0 1\n vertex 1 1 1\n vertex 0 1 1\n\
+                      endloop\nendfacet\n\
+                      endsolid two\n";
+        let (mesh, colors) = parse_stl::<f64>(source.as_bytes()).unwrap();
+        assert!(colors.is_none());
+        // the four sided loop is split in two
+        assert_eq!(mesh.triangles().len(), 3);
+        let (v0, v1, v2) = mesh.triangles()[0].vertices();
+        assert_eq!(
+            (v0, v1, v2),
+            (
+                Three::zeros(),
+                Three::new(1.0, 0.0, 0.0),
+                Three::new(0.0, 1.0, 0.0)
+            )
+        );
+
+        match parse_stl::<f64>(b"solid bad\nvertex 1 2\n") {
+            Err(LoadError::Invalid { line, .. }) => assert_eq!(line, Some(2)),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn reads_binary_facets_and_their_colors() {
+        let (mesh, colors) = parse_stl::<f64>(include_bytes!("../../scenes/block.stl")).unwrap();
+        assert_eq!(mesh.triangles().len(), 24);
+        let bounds = mesh.bounds();
+        assert_eq!(bounds.min, Three::new(-1.0, 0.0, -0.5));
+        assert_eq!(bounds.max, Three::new(1.0, 1.0, 0.5));
+
+        // VisCAM colors, 5 bits a channel with red in the highest bits
+        let colors = colors.unwrap();
+        let orange = Three::new(1.0, 15.0 / 31.0, 2.0 / 31.0);
+        let blue = Three::new(4.0 / 31.0, 10.0 / 31.0, 28.0 / 31.0);
+        for triangle in mesh.triangles() {
+            let [uv0, uv1, uv2] = triangle.uvs();
+            let center = ((uv0.0 + uv1.0 + uv2.0) / 3.0, (uv0.1 + uv1.1 + uv2.1) / 3.0);
+            let color = colors.lookup(center);
+            assert!(
+                (color - orange).length() < 1e-9 || (color - blue).length() < 1e-9,
+                "{:?}",
+                color
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_binary_files() {
+        let mut bytes = vec![0u8; 84];
+        bytes[80] = 2;
+        bytes.extend([0u8; 50]);
+        assert!(parse_stl::<f64>(&bytes).is_err());
+    }
+}
@@ -1,16 +1,56 @@
+pub mod aov;
+pub mod bidirectional;
+mod bvh;
 pub mod data;
 mod data_impls;
+pub mod debug;
+pub mod denoise;
+pub mod formats;
+pub mod light_sampling;
+pub mod lights;
+pub mod metropolis;
 pub mod pdf;
+pub mod photon_mapping;
 mod rendering;
 pub mod scene;
+pub mod scene_graph;
+pub mod sdf;
 pub mod shapes;
+pub mod texture;
 pub mod tracer;
+pub mod transform;
 
+pub use aov::Aovs;
+pub use bidirectional::BidirectionalPathTracer;
 pub use data::{
-    Camera, CanHit, Dielectric, Diffuse, FieldOfView, Hit, ImageShape, Light, Material, Mirror,
-    Ray, Three,
+    Aabb, Camera, CanHit, Dielectric, Diffuse, EmissionUnits, FieldOfView, Hit, ImageShape, Light,
+    Material, Mirror, Ray, Three,
 };
-pub use rendering::render;
+#[cfg(feature = "traversal-cost")]
+pub use debug::TraversalCostTracer;
+pub use debug::{
+    AmbientOcclusionTracer, DepthTracer, MaterialIdTracer, NormalTracer, ObjectIdTracer, UvTracer,
+};
+pub use denoise::Denoiser;
+pub use formats::{
+    load_gltf, load_mesh, load_mesh_colors, load_mitsuba, load_obj, load_pbrt, load_ply,
+    load_scene, load_stl, parse_gltf, parse_scene, GltfScene, LoadError, RenderSettings,
+    SceneDescription, TracerKind,
+};
+pub use light_sampling::LightSelection;
+pub use lights::{
+    DeltaLight, DirectionalLight, Environment, EnvironmentSampler, IesProfile, PointLight,
+    SpotLight,
+};
+pub use metropolis::MetropolisLightTransport;
+pub use photon_mapping::{PhotonMapper, StochasticProgressivePhotonMapper};
+pub use rendering::{render, render_buffers, render_seeded, render_with_aovs, RenderBuffers};
 pub use scene::{Scene, SceneTracer};
-pub use shapes::{Plane, Prism, Sphere, Triangle};
+pub use scene_graph::Node;
+pub use shapes::{
+    Cone, Csg, Cuboid, Cylinder, Disk, Heightfield, Instance, Mesh, MovingInstance, Plane, Prism,
+    Quad, SdfShape, Sphere, Torus, Triangle,
+};
+pub use texture::{ImageTexture, Texture};
 pub use tracer::PathTracer;
+pub use transform::{Keyframe, Motion, Transform};
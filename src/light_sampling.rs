@@ -0,0 +1,446 @@
+use crate::data::{Aabb, Bounded, Light, Surface, Three};
+use crate::shapes::Object;
+use num_traits::{Float, FloatConst};
+use rand_distr::uniform::SampleUniform;
+use rand_distr::{Distribution, Standard};
+
+/// Picks which of the scene's emissive objects to sample for direct lighting.
+pub trait LightSampler<F> {
+    /// Chooses a light for `point` using the uniform random number `u`, returning its index into
+    /// `Scene::emissive_objects` and the probability it had of being chosen.
+    fn sample(&self, point: &Three<F>, u: F) -> Option<(usize, F)>;
+
+    /// Probability that `sample` chooses `light_idx` for `point`.
+    fn pmf(&self, point: &Three<F>, light_idx: usize) -> F;
+}
+
+/// Which `LightSampler` a scene builds for its emissive objects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LightSelection {
+    /// Every light is equally likely.
+    Uniform,
+    /// Lights are chosen proportional to their emitted power.
+    #[default]
+    Power,
+    /// Lights are chosen by a `LightTree`, favoring bright lights near the shaded point.
+    Tree,
+}
+
+/// Chooses lights proportional to fixed weights, regardless of the shaded point.
+#[derive(Debug, Clone)]
+pub struct PowerLightSampler<F> {
+    pmf: Vec<F>,
+    cdf: Vec<F>,
+}
+
+/// A bounding volume hierarchy over lights, for scenes with many of them.
+///
+/// Each node stores the total power and bounds of the lights below it. Sampling walks down from
+/// the root, choosing children proportional to their power over the squared distance to the
+/// shaded point, so nearby bright lights are chosen far more often than distant dim ones.
+#[derive(Debug, Clone)]
+pub struct LightTree<F> {
+    nodes: Vec<LightNode<F>>,
+    light_to_leaf: Vec<Option<usize>>,
+}
+
+#[derive(Debug, Clone)]
+struct LightNode<F> {
+    bounds: Aabb<F>,
+    power: F,
+    parent: Option<usize>,
+    kind: LightNodeKind,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum LightNodeKind {
+    Leaf { light_idx: usize },
+    Interior { left: usize, right: usize },
+}
+
+/// Any light in a scene that light paths can start from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emitter {
+    /// Index into `Scene::emissive_objects`.
+    Area(usize),
+    /// Index into `Scene::lights`.
+    Delta(usize),
+}
+
+#[derive(Debug, Clone)]
+pub enum LightSamplers<F> {
+    Power(PowerLightSampler<F>),
+    Tree(LightTree<F>),
+}
+
+/// The power an emissive object radiates, used to weight how often it is sampled.
+///
+/// Objects with infinite area (e.g. planes) can't be sampled, so they get no weight and are only
+/// ever found by tracing rays.
+pub(crate) fn emitted_power<F>(obj: &Object<F>, light: &Light<F>) -> F
+where
+    F: Float + FloatConst + SampleUniform,
+    Standard: Distribution<F>,
+{
+    let area = obj.area();
+    if area.is_finite() {
+        light.emitted_power(area)
+    } else {
+        F::zero()
+    }
+}
+
+impl<F> PowerLightSampler<F>
+where
+    F: Float,
+{
+    pub fn new(weights: &[F]) -> Self {
+        let total = weights.iter().fold(F::zero(), |a, &b| a + b);
+        let pmf: Vec<F> = weights
+            .iter()
+            .map(|&w| {
+                if total > F::zero() {
+                    w / total
+                } else {
+                    F::zero()
+                }
+            })
+            .collect();
+        let mut running = F::zero();
+        let cdf = pmf
+            .iter()
+            .map(|&p| {
+                running = running + p;
+                running
+            })
+            .collect();
+        Self { pmf, cdf }
+    }
+
+    /// Every light with a non zero `weight` is equally likely.
+    pub fn uniform(weights: &[F]) -> Self {
+        let unit_weights: Vec<F> = weights
+            .iter()
+            .map(|&w| if w > F::zero() { F::one() } else { F::zero() })
+            .collect();
+        Self::new(&unit_weights)
+    }
+}
+
+impl<F> LightSampler<F> for PowerLightSampler<F>
+where
+    F: Float,
+{
+    fn sample(&self, _point: &Three<F>, u: F) -> Option<(usize, F)> {
+        let idx = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .min(self.cdf.len().checked_sub(1)?);
+        // skip over zero probability lights that u landed on due to rounding
+        let idx = (0..=idx).rev().find(|&i| self.pmf[i] > F::zero())?;
+        Some((idx, self.pmf[idx]))
+    }
+
+    fn pmf(&self, _point: &Three<F>, light_idx: usize) -> F {
+        self.pmf[light_idx]
+    }
+}
+
+impl<F> LightTree<F>
+where
+    F: Float,
+{
+    /// Builds a tree over lights with the given bounds and power. Lights with zero power are left
+    /// out of the tree and are never sampled.
+    pub fn new(lights: &[(Aabb<F>, F)]) -> Self {
+        let mut tree = Self {
+            nodes: Vec::new(),
+            light_to_leaf: vec![None; lights.len()],
+        };
+        let mut light_indices: Vec<usize> = (0..lights.len())
+            .filter(|&i| lights[i].1 > F::zero())
+            .collect();
+        if !light_indices.is_empty() {
+            tree.build(lights, &mut light_indices, None);
+        }
+        tree
+    }
+
+    fn build(
+        &mut self,
+        lights: &[(Aabb<F>, F)],
+        light_indices: &mut [usize],
+        parent: Option<usize>,
+    ) -> usize {
+        let node_idx = self.nodes.len();
+        let bounds = light_indices
+            .iter()
+            .fold(Aabb::empty(), |aabb, &i| aabb.union(&lights[i].0));
+        let power = light_indices
+            .iter()
+            .fold(F::zero(), |total, &i| total + lights[i].1);
+        self.nodes.push(LightNode {
+            bounds,
+            power,
+            parent,
+            kind: LightNodeKind::Leaf {
+                light_idx: light_indices[0],
+            },
+        });
+
+        if light_indices.len() == 1 {
+            self.light_to_leaf[light_indices[0]] = Some(node_idx);
+            return node_idx;
+        }
+
+        // median split along the longest axis of the light centers
+        let centers = light_indices.iter().fold(Aabb::empty(), |aabb, &i| {
+            aabb.including(&lights[i].0.center())
+        });
+        let axis = centers.longest_axis();
+        light_indices.sort_by(|&a, &b| {
+            let a = lights[a].0.center().axis(axis);
+            let b = lights[b].0.center().axis(axis);
+            a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+        });
+        let (left_lights, right_lights) = light_indices.split_at_mut(light_indices.len() / 2);
+        let left = self.build(lights, left_lights, Some(node_idx));
+        let right = self.build(lights, right_lights, Some(node_idx));
+        self.nodes[node_idx].kind = LightNodeKind::Interior { left, right };
+        node_idx
+    }
+
+    /// How much light from the node is expected to reach `point`.
+    fn importance(&self, point: &Three<F>, node_idx: usize) -> F {
+        let node = &self.nodes[node_idx];
+        let distance_squared = (node.bounds.center() - point).length_squared();
+        // don't let importance blow up for points inside or very near the node
+        let half_diagonal_squared =
+            node.bounds.diagonal().length_squared() * F::from(0.25f64).unwrap();
+        node.power / distance_squared.max(half_diagonal_squared)
+    }
+
+    /// Probability of walking to `left` when at a node with children `left` and `right`.
+    fn left_probability(&self, point: &Three<F>, left: usize, right: usize) -> F {
+        let left_importance = self.importance(point, left);
+        let right_importance = self.importance(point, right);
+        let total = left_importance + right_importance;
+        if total > F::zero() && total.is_finite() {
+            left_importance / total
+        } else {
+            // fall back to power alone
+            self.nodes[left].power / (self.nodes[left].power + self.nodes[right].power)
+        }
+    }
+}
+
+impl<F> LightSampler<F> for LightTree<F>
+where
+    F: Float,
+{
+    fn sample(&self, point: &Three<F>, mut u: F) -> Option<(usize, F)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let mut node_idx = 0;
+        let mut pmf = F::one();
+        loop {
+            match self.nodes[node_idx].kind {
+                LightNodeKind::Leaf { light_idx } => return Some((light_idx, pmf)),
+                LightNodeKind::Interior { left, right } => {
+                    let p_left = self.left_probability(point, left, right);
+                    // reuse u for the next decision by remapping it back to [0, 1)
+                    if u < p_left {
+                        u = (u / p_left).min(F::one() - F::epsilon());
+                        pmf = pmf * p_left;
+                        node_idx = left;
+                    } else {
+                        u = ((u - p_left) / (F::one() - p_left)).min(F::one() - F::epsilon());
+                        pmf = pmf * (F::one() - p_left);
+                        node_idx = right;
+                    }
+                }
+            }
+        }
+    }
+
+    fn pmf(&self, point: &Three<F>, light_idx: usize) -> F {
+        let mut node_idx = match self.light_to_leaf[light_idx] {
+            Some(leaf) => leaf,
+            None => return F::zero(),
+        };
+        let mut pmf = F::one();
+        while let Some(parent) = self.nodes[node_idx].parent {
+            if let LightNodeKind::Interior { left, right } = self.nodes[parent].kind {
+                let p_left = self.left_probability(point, left, right);
+                pmf = pmf
+                    * if node_idx == left {
+                        p_left
+                    } else {
+                        F::one() - p_left
+                    };
+            }
+            node_idx = parent;
+        }
+        pmf
+    }
+}
+
+impl<F> LightSamplers<F>
+where
+    F: Float + FloatConst + SampleUniform,
+    Standard: Distribution<F>,
+{
+    /// Builds a sampler for `emitters`, given as objects and their light material.
+    pub fn build(selection: LightSelection, emitters: &[(&Object<F>, &Light<F>)]) -> Self {
+        let powers: Vec<F> = emitters
+            .iter()
+            .map(|(obj, light)| emitted_power(obj, light))
+            .collect();
+        match selection {
+            LightSelection::Uniform => Self::Power(PowerLightSampler::uniform(&powers)),
+            LightSelection::Power => Self::Power(PowerLightSampler::new(&powers)),
+            LightSelection::Tree => {
+                let lights: Vec<(Aabb<F>, F)> = emitters
+                    .iter()
+                    .zip(powers.iter())
+                    .map(|((obj, _), &power)| (obj.bounds(), power))
+                    .collect();
+                Self::Tree(LightTree::new(&lights))
+            }
+        }
+    }
+}
+
+impl<F> LightSampler<F> for LightSamplers<F>
+where
+    F: Float,
+{
+    fn sample(&self, point: &Three<F>, u: F) -> Option<(usize, F)> {
+        match self {
+            LightSamplers::Power(sampler) => sampler.sample(point, u),
+            LightSamplers::Tree(sampler) => sampler.sample(point, u),
+        }
+    }
+
+    fn pmf(&self, point: &Three<F>, light_idx: usize) -> F {
+        match self {
+            LightSamplers::Power(sampler) => sampler.pmf(point, light_idx),
+            LightSamplers::Tree(sampler) => sampler.pmf(point, light_idx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_xorshift::XorShiftRng;
+
+    const DRAWS: usize = 100_000;
+
+    /// Checks that `sampler` returns the pmf of what it samples, that the pmfs add up to 1, and
+    /// that each light is sampled as often as its pmf says.
+    fn assert_pmf_matches_samples<S: LightSampler<f64>>(
+        sampler: &S,
+        num_lights: usize,
+        point: &Three<f64>,
+    ) {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let mut counts = vec![0usize; num_lights];
+        for _ in 0..DRAWS {
+            let (idx, pmf) = sampler.sample(point, rng.gen()).unwrap();
+            assert!((pmf - sampler.pmf(point, idx)).abs() < 1e-12);
+            counts[idx] += 1;
+        }
+        let total: f64 = (0..num_lights).map(|i| sampler.pmf(point, i)).sum();
+        assert!((total - 1.0).abs() < 1e-12, "pmfs add up to {}", total);
+        for (i, &count) in counts.iter().enumerate() {
+            let pmf = sampler.pmf(point, i);
+            let frequency = count as f64 / DRAWS as f64;
+            // a few standard deviations of the frequency
+            let tolerance = 4.0 * (pmf * (1.0 - pmf) / DRAWS as f64).sqrt() + 1e-9;
+            assert!(
+                (frequency - pmf).abs() < tolerance,
+                "light {} was sampled {} of the time instead of {}",
+                i,
+                frequency,
+                pmf
+            );
+        }
+    }
+
+    /// Unit cubes along the x axis.
+    fn lights(powers: &[f64]) -> Vec<(Aabb<f64>, f64)> {
+        powers
+            .iter()
+            .enumerate()
+            .map(|(i, &power)| {
+                let min = Three::new(3.0 * i as f64, 0.0, 0.0);
+                (
+                    Aabb {
+                        min,
+                        max: min + Three::from(1.0),
+                    },
+                    power,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn power_sampler_pmf_matches_its_samples() {
+        let powers = [1.0, 0.0, 3.0, 0.5, 2.5];
+        let sampler = PowerLightSampler::new(&powers);
+        assert_pmf_matches_samples(&sampler, powers.len(), &Three::zeros());
+        assert_eq!(sampler.pmf(&Three::zeros(), 1), 0.0);
+        assert!((sampler.pmf(&Three::zeros(), 2) - 3.0 / 7.0).abs() < 1e-12);
+
+        let uniform = PowerLightSampler::uniform(&powers);
+        assert_pmf_matches_samples(&uniform, powers.len(), &Three::zeros());
+        assert_eq!(uniform.pmf(&Three::zeros(), 1), 0.0);
+        assert!((uniform.pmf(&Three::zeros(), 2) - 0.25).abs() < 1e-12);
+    }
+
+    #[test]
+    fn light_tree_pmf_matches_its_samples() {
+        let lights = lights(&[1.0, 2.0, 0.0, 4.0, 1.0, 3.0, 0.5]);
+        let tree = LightTree::new(&lights);
+        for point in [
+            Three::new(-2.0, 0.5, 0.5),
+            Three::new(9.5, 2.0, 0.0),
+            Three::new(30.0, -5.0, 4.0),
+        ] {
+            assert_pmf_matches_samples(&tree, lights.len(), &point);
+            assert_eq!(tree.pmf(&point, 2), 0.0);
+        }
+    }
+
+    #[test]
+    fn light_tree_favors_nearby_lights() {
+        let lights = lights(&[1.0; 8]);
+        let tree = LightTree::new(&lights);
+        // beside the first light, and far from the last
+        let point = Three::new(0.5, 2.0, 0.5);
+        let near = tree.pmf(&point, 0);
+        let far = tree.pmf(&point, 7);
+        assert!(near > 4.0 * far, "{} isn't much more than {}", near, far);
+        // and more often than by power alone, which can't tell them apart
+        let power = PowerLightSampler::new(&[1.0; 8]);
+        assert!(near > power.pmf(&point, 0));
+    }
+
+    #[test]
+    fn samplers_without_lights_sample_nothing() {
+        let point = Three::zeros();
+        assert!(PowerLightSampler::<f64>::new(&[])
+            .sample(&point, 0.5)
+            .is_none());
+        assert!(PowerLightSampler::new(&[0.0, 0.0])
+            .sample(&point, 0.5)
+            .is_none());
+        assert!(LightTree::new(&lights(&[0.0]))
+            .sample(&point, 0.5)
+            .is_none());
+    }
+}
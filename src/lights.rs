@@ -0,0 +1,737 @@
+use crate::data::Three;
+use crate::light_sampling::{LightSampler, PowerLightSampler};
+use crate::texture::Texture;
+use num_traits::{Float, FloatConst};
+use rand::Rng;
+use rand_distr::{uniform::SampleUniform, Distribution, Standard, UnitSphere};
+use std::{fs, io, path::Path, sync::Arc};
+
+/// An infinitesimally small light that radiates `rgb * power` watts per steradian in every direction.
+#[derive(Debug, Clone, Copy)]
+pub struct PointLight<F> {
+    pub position: Three<F>,
+    pub rgb: Three<F>,
+    pub power: F,
+}
+
+/// A point light restricted to a cone around `direction`.
+///
+/// Intensity is constant up to `falloff_start` degrees away from `direction`, and smoothly
+/// falls to zero at `cone_angle` degrees. If a `profile` is given, it replaces the smooth
+/// falloff.
+#[derive(Debug, Clone)]
+pub struct SpotLight<F> {
+    pub position: Three<F>,
+    pub direction: Three<F>,
+    pub rgb: Three<F>,
+    pub power: F,
+    pub cone_angle: F,
+    pub falloff_start: F,
+    pub profile: Option<Arc<IesProfile>>,
+}
+
+/// A light infinitely far away, so all of its rays travel along `direction`.
+#[derive(Debug, Clone, Copy)]
+pub struct DirectionalLight<F> {
+    pub direction: Three<F>,
+    pub rgb: Three<F>,
+    pub power: F,
+}
+
+/// Lights that can't be hit by rays, and so must be sampled explicitly by tracers.
+#[derive(Debug, Clone)]
+pub enum DeltaLight<F> {
+    Point(PointLight<F>),
+    Spot(SpotLight<F>),
+    Directional(DirectionalLight<F>),
+}
+
+/// Light arriving from infinitely far away, like the sky, from a texture wrapped around the
+/// scene in latitude-longitude format.
+///
+/// The middle of the texture is seen looking along `z`, its top looking along `y`, and `frame`
+/// holds the world directions of those `x`, `y` and `z` axes, as in Mitsuba. The path tracer adds
+/// it to paths that escape the scene, and samples it directly with an `EnvironmentSampler`.
+#[derive(Debug, Clone)]
+pub struct Environment<F> {
+    pub texture: Texture<F>,
+    pub scale: F,
+    pub frame: [Three<F>; 3],
+}
+
+/// Picks directions towards an `Environment` in proportion to how bright its texture is there,
+/// so small bright areas like the sun are found by direct lighting instead of only by chance.
+///
+/// The texture is split into cells, one per texel for images, which are chosen by their
+/// luminance times the solid angle they cover and then sampled uniformly.
+#[derive(Debug, Clone)]
+pub struct EnvironmentSampler<F> {
+    width: usize,
+    height: usize,
+    /// Chooses a row of cells, from the bottom of the texture.
+    rows: PowerLightSampler<F>,
+    /// Chooses a cell within each row.
+    columns: Vec<PowerLightSampler<F>>,
+}
+
+/// Light arriving at a point from a single sampled direction.
+#[derive(Debug, Clone, Copy)]
+pub struct LightSample<F> {
+    /// Unit vector pointing from the shaded point towards the light.
+    pub direction: Three<F>,
+    /// Distance to the light along `direction`. Infinite for directional lights.
+    pub distance: F,
+    pub radiance: Three<F>,
+    /// Solid angle density of `direction`. Delta lights always have a pdf of 1.
+    pub pdf: F,
+}
+
+/// A ray of light leaving a light, for tracers that follow light from lights into the scene.
+#[derive(Debug, Clone, Copy)]
+pub struct EmissionSample<F> {
+    pub origin: Three<F>,
+    /// Surface normal at `origin`, or `None` for lights that are a single point.
+    pub normal: Option<Three<F>>,
+    pub direction: Three<F>,
+    /// Radiance for surfaces, or intensity for points, along `direction`.
+    pub radiance: Three<F>,
+    /// Area density of `origin`. Lights that are a single point have a pdf of 1.
+    pub pdf_position: F,
+    /// Solid angle density of `direction`.
+    pub pdf_direction: F,
+}
+
+pub trait LightSource<F> {
+    fn sample_incident<R: Rng>(&self, point: &Three<F>, rng: &mut R) -> Option<LightSample<F>>;
+}
+
+impl<F> SpotLight<F>
+where
+    F: Float,
+{
+    pub fn with_profile(self, profile: IesProfile) -> Self {
+        Self {
+            profile: Some(Arc::new(profile)),
+            ..self
+        }
+    }
+
+    /// Fraction of `power` emitted towards `w`, a unit vector pointing away from the light.
+    fn falloff(&self, w: &Three<F>) -> F {
+        let axis = self.direction.normalized();
+        let cos_theta = w.dot(&axis);
+        let cos_total_width = self.cone_angle.to_radians().cos();
+        if cos_theta < cos_total_width {
+            return F::zero();
+        }
+
+        if let Some(profile) = &self.profile {
+            let (u, v) = axis.orthonormal_basis();
+            let theta = cos_theta.min(F::one()).acos().to_degrees();
+            let phi = w.dot(&v).atan2(w.dot(&u)).to_degrees();
+            return F::from(
+                profile.relative_intensity(theta.to_f64().unwrap(), phi.to_f64().unwrap()),
+            )
+            .unwrap();
+        }
+
+        let cos_falloff_start = self.falloff_start.to_radians().cos();
+        if cos_theta >= cos_falloff_start {
+            return F::one();
+        }
+        let t = (cos_theta - cos_total_width) / (cos_falloff_start - cos_total_width);
+        t * t * (F::from(3.0f64).unwrap() - F::from(2.0f64).unwrap() * t)
+    }
+}
+
+impl<F> DeltaLight<F>
+where
+    F: Float + FloatConst + SampleUniform,
+    Standard: Distribution<F>,
+{
+    /// Samples a ray leaving the light. Directional lights have no origin, so they can't be
+    /// sampled this way.
+    pub fn sample_emission<R: Rng>(&self, rng: &mut R) -> Option<EmissionSample<F>> {
+        let (origin, direction, radiance) = match self {
+            DeltaLight::Point(light) => {
+                let direction = Three::from(UnitSphere.sample(rng));
+                (light.position, direction, light.rgb * light.power)
+            }
+            DeltaLight::Spot(light) => {
+                // uniformly sample directions in the cone
+                let cos_total_width = light.cone_angle.to_radians().cos();
+                let u1: F = Standard.sample(rng);
+                let u2: F = Standard.sample(rng);
+                let cos_theta = F::one() - u1 * (F::one() - cos_total_width);
+                let sin_theta = (F::one() - cos_theta * cos_theta).max(F::zero()).sqrt();
+                let phi = F::from(2.0f64).unwrap() * F::PI() * u2;
+                let axis = light.direction.normalized();
+                let (u, v) = axis.orthonormal_basis();
+                let direction =
+                    u * (sin_theta * phi.cos()) + v * (sin_theta * phi.sin()) + axis * cos_theta;
+                let radiance = light.rgb * (light.power * light.falloff(&direction));
+                (light.position, direction, radiance)
+            }
+            DeltaLight::Directional(_) => return None,
+        };
+        Some(EmissionSample {
+            origin,
+            normal: None,
+            direction,
+            radiance,
+            pdf_position: F::one(),
+            pdf_direction: self.emission_pdf(&direction),
+        })
+    }
+
+    /// Solid angle density of `sample_emission` choosing `direction`.
+    pub fn emission_pdf(&self, direction: &Three<F>) -> F {
+        match self {
+            DeltaLight::Point(_) => (F::from(4.0f64).unwrap() * F::PI()).recip(),
+            DeltaLight::Spot(light) => {
+                let cos_total_width = light.cone_angle.to_radians().cos();
+                if direction.dot(&light.direction.normalized()) < cos_total_width {
+                    F::zero()
+                } else {
+                    (F::from(2.0f64).unwrap() * F::PI() * (F::one() - cos_total_width)).recip()
+                }
+            }
+            DeltaLight::Directional(_) => F::zero(),
+        }
+    }
+
+    /// Total power leaving the light, weighted by perceived brightness. Directional lights have
+    /// no power, because they light the whole scene and can't be sampled by `sample_emission`.
+    pub fn emitted_power(&self) -> F {
+        match self {
+            DeltaLight::Point(light) => {
+                light.rgb.luminance() * light.power * F::from(4.0f64).unwrap() * F::PI()
+            }
+            DeltaLight::Spot(light) => {
+                let solid_angle = match &light.profile {
+                    Some(profile) => {
+                        F::from(profile.solid_angle(light.cone_angle.to_f64().unwrap())).unwrap()
+                    }
+                    None => {
+                        let cos_total_width = light.cone_angle.to_radians().cos();
+                        let cos_falloff_start = light.falloff_start.to_radians().cos();
+                        F::from(2.0f64).unwrap()
+                            * F::PI()
+                            * (F::one()
+                                - (cos_total_width + cos_falloff_start) * F::from(0.5).unwrap())
+                    }
+                };
+                light.rgb.luminance() * light.power * solid_angle
+            }
+            DeltaLight::Directional(_) => F::zero(),
+        }
+    }
+}
+
+impl<F> LightSource<F> for DeltaLight<F>
+where
+    F: Float,
+{
+    fn sample_incident<R: Rng>(&self, point: &Three<F>, _rng: &mut R) -> Option<LightSample<F>> {
+        let (direction, distance, radiance) = match self {
+            DeltaLight::Point(light) => {
+                let to_light = light.position - point;
+                let distance_squared = to_light.length_squared();
+                let distance = distance_squared.sqrt();
+                let radiance = light.rgb * (light.power / distance_squared);
+                (to_light / distance, distance, radiance)
+            }
+            DeltaLight::Spot(light) => {
+                let to_light = light.position - point;
+                let distance_squared = to_light.length_squared();
+                let distance = distance_squared.sqrt();
+                let direction = to_light / distance;
+                let falloff = light.falloff(&-direction);
+                if falloff <= F::zero() {
+                    return None;
+                }
+                let radiance = light.rgb * (light.power * falloff / distance_squared);
+                (direction, distance, radiance)
+            }
+            DeltaLight::Directional(light) => (
+                -light.direction.normalized(),
+                F::infinity(),
+                light.rgb * light.power,
+            ),
+        };
+        Some(LightSample {
+            direction,
+            distance,
+            radiance,
+            pdf: F::one(),
+        })
+    }
+}
+
+impl<F> Environment<F>
+where
+    F: Float + FloatConst,
+{
+    pub fn new<T: Into<Texture<F>>>(texture: T) -> Self {
+        Self {
+            texture: texture.into(),
+            scale: F::one(),
+            frame: [
+                Three::new(F::one(), F::zero(), F::zero()),
+                Three::new(F::zero(), F::one(), F::zero()),
+                Three::new(F::zero(), F::zero(), F::one()),
+            ],
+        }
+    }
+
+    /// Radiance arriving from the direction `-direction`, i.e. seen by a ray going `direction`.
+    pub fn radiance(&self, direction: &Three<F>) -> Three<F> {
+        self.texture.lookup(self.uv_of(direction)) * self.scale
+    }
+
+    /// Texture coordinates seen looking along `direction`.
+    fn uv_of(&self, direction: &Three<F>) -> (F, F) {
+        let d = direction.normalized();
+        let [x, y, z] = self.frame.map(|axis| d.dot(&axis));
+        let u = x.atan2(-z) / F::TAU();
+        let u = u - u.floor();
+        let v = F::one() - y.max(-F::one()).min(F::one()).acos() / F::PI();
+        (u, v)
+    }
+
+    /// The direction looking at texture coordinates `uv`, the inverse of `uv_of`.
+    fn direction_at(&self, (u, v): (F, F)) -> Three<F> {
+        let theta = (F::one() - v) * F::PI();
+        let phi = u * F::TAU();
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let [x_axis, y_axis, z_axis] = self.frame;
+        x_axis * (sin_theta * phi.sin()) + y_axis * cos_theta - z_axis * (sin_theta * phi.cos())
+    }
+}
+
+impl<F> EnvironmentSampler<F>
+where
+    F: Float + FloatConst + SampleUniform,
+    Standard: Distribution<F>,
+{
+    /// Cells of constant textures, which are only weighted by the solid angle they cover.
+    const CONSTANT_SIZE: (usize, usize) = (32, 16);
+
+    pub fn new(environment: &Environment<F>) -> Self {
+        let (width, height) = match &environment.texture {
+            Texture::Constant(_) => Self::CONSTANT_SIZE,
+            Texture::Image(image) => image.size(),
+        };
+        let half = F::from(0.5f64).unwrap();
+        let (w, h) = (F::from(width).unwrap(), F::from(height).unwrap());
+        let mut row_weights = Vec::with_capacity(height);
+        let columns = (0..height)
+            .map(|row| {
+                let v = (F::from(row).unwrap() + half) / h;
+                let sin_theta = ((F::one() - v) * F::PI()).sin();
+                let weights: Vec<F> = (0..width)
+                    .map(|column| {
+                        let u = (F::from(column).unwrap() + half) / w;
+                        environment
+                            .texture
+                            .lookup((u, v))
+                            .luminance()
+                            .max(F::zero())
+                            * sin_theta
+                    })
+                    .collect();
+                row_weights.push(weights.iter().fold(F::zero(), |a, &b| a + b));
+                PowerLightSampler::new(&weights)
+            })
+            .collect();
+        Self {
+            width,
+            height,
+            rows: PowerLightSampler::new(&row_weights),
+            columns,
+        }
+    }
+
+    /// Samples a direction towards `environment`, which must be the one the sampler was built
+    /// from. Returns `None` if the environment is black everywhere.
+    pub fn sample<R: Rng>(
+        &self,
+        environment: &Environment<F>,
+        rng: &mut R,
+    ) -> Option<LightSample<F>> {
+        let origin = Three::zeros();
+        let (row, row_pmf) = self.rows.sample(&origin, Standard.sample(rng))?;
+        let (column, column_pmf) = self.columns[row].sample(&origin, Standard.sample(rng))?;
+        let (w, h) = (F::from(self.width).unwrap(), F::from(self.height).unwrap());
+        let u = (F::from(column).unwrap() + Standard.sample(rng)) / w;
+        let v = (F::from(row).unwrap() + Standard.sample(rng)) / h;
+        let direction = environment.direction_at((u, v));
+        let pdf = self.uv_pdf(row_pmf * column_pmf, v);
+        if pdf <= F::zero() {
+            return None;
+        }
+        Some(LightSample {
+            direction,
+            distance: F::infinity(),
+            radiance: environment.texture.lookup((u, v)) * environment.scale,
+            pdf,
+        })
+    }
+
+    /// Solid angle density of `sample` choosing `direction`.
+    pub fn pdf(&self, environment: &Environment<F>, direction: &Three<F>) -> F {
+        let (u, v) = environment.uv_of(direction);
+        let cell = |t: F, size: usize| {
+            (t * F::from(size).unwrap())
+                .to_usize()
+                .unwrap_or(0)
+                .min(size - 1)
+        };
+        let (row, column) = (cell(v, self.height), cell(u, self.width));
+        let origin = Three::zeros();
+        let pmf = self.rows.pmf(&origin, row) * self.columns[row].pmf(&origin, column);
+        self.uv_pdf(pmf, v)
+    }
+
+    /// Solid angle density of a direction at height `v` in a cell chosen with probability `pmf`.
+    fn uv_pdf(&self, pmf: F, v: F) -> F {
+        let sin_theta = ((F::one() - v) * F::PI()).sin();
+        if sin_theta <= F::zero() {
+            return F::zero();
+        }
+        // the cell covers 1 / (width * height) of the texture, which maps onto the sphere with
+        // a jacobian of 2 pi^2 sin(theta)
+        let cells = F::from(self.width * self.height).unwrap();
+        pmf * cells / (F::from(2.0f64).unwrap() * F::PI() * F::PI() * sin_theta)
+    }
+}
+
+impl<F> From<PointLight<F>> for DeltaLight<F> {
+    fn from(light: PointLight<F>) -> Self {
+        DeltaLight::Point(light)
+    }
+}
+
+impl<F> From<SpotLight<F>> for DeltaLight<F> {
+    fn from(light: SpotLight<F>) -> Self {
+        DeltaLight::Spot(light)
+    }
+}
+
+impl<F> From<DirectionalLight<F>> for DeltaLight<F> {
+    fn from(light: DirectionalLight<F>) -> Self {
+        DeltaLight::Directional(light)
+    }
+}
+
+/// Angular intensity distribution of a luminaire, read from an IESNA LM-63 photometric file.
+///
+/// Only type C photometry is supported, where vertical angles are measured from the
+/// luminaire's axis (the spot light's `direction`) and horizontal angles around it.
+#[derive(Debug, Clone)]
+pub struct IesProfile {
+    vertical_angles: Vec<f64>,
+    horizontal_angles: Vec<f64>,
+    /// Candela values relative to the brightest direction, indexed by `[horizontal][vertical]`.
+    candela: Vec<Vec<f64>>,
+}
+
+impl IesProfile {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(contents: &str) -> io::Result<Self> {
+        let mut lines = contents.lines();
+        let tilt = loop {
+            match lines.next() {
+                Some(line) if line.trim_start().starts_with("TILT=") => {
+                    break line.trim_start()["TILT=".len()..].trim();
+                }
+                Some(_) => continue,
+                None => return Err(invalid_ies("missing TILT line")),
+            }
+        };
+
+        let mut numbers = lines
+            .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|token| !token.is_empty())
+            .map(|token| {
+                token
+                    .parse::<f64>()
+                    .map_err(|_| invalid_ies(&format!("expected a number, found `{}`", token)))
+            });
+        let mut next = || {
+            numbers
+                .next()
+                .unwrap_or_else(|| Err(invalid_ies("unexpected end of file")))
+        };
+
+        match tilt {
+            "NONE" => {}
+            "INCLUDE" => {
+                let _lamp_geometry = next()?;
+                let num_tilt_angles = next()? as usize;
+                for _ in 0..2 * num_tilt_angles {
+                    next()?;
+                }
+            }
+            _ => return Err(invalid_ies("TILT files are not supported")),
+        }
+
+        let _num_lamps = next()?;
+        let _lumens_per_lamp = next()?;
+        let multiplier = next()?;
+        let num_vertical = next()? as usize;
+        let num_horizontal = next()? as usize;
+        let photometric_type = next()?;
+        let _units = next()?;
+        let _dimensions = (next()?, next()?, next()?);
+        let ballast_factor = next()?;
+        let _future_use = next()?;
+        let _input_watts = next()?;
+
+        if photometric_type != 1.0 {
+            return Err(invalid_ies("only type C photometry is supported"));
+        }
+        if num_vertical == 0 || num_horizontal == 0 {
+            return Err(invalid_ies("profile has no angles"));
+        }
+
+        let vertical_angles = (0..num_vertical)
+            .map(|_| next())
+            .collect::<io::Result<Vec<f64>>>()?;
+        let horizontal_angles = (0..num_horizontal)
+            .map(|_| next())
+            .collect::<io::Result<Vec<f64>>>()?;
+        let mut candela = (0..num_horizontal)
+            .map(|_| {
+                (0..num_vertical)
+                    .map(|_| Ok(next()? * multiplier * ballast_factor))
+                    .collect::<io::Result<Vec<f64>>>()
+            })
+            .collect::<io::Result<Vec<Vec<f64>>>>()?;
+
+        let max_candela = candela.iter().flatten().fold(0.0f64, |a, &b| a.max(b));
+        if max_candela <= 0.0 {
+            return Err(invalid_ies("profile emits no light"));
+        }
+        for value in candela.iter_mut().flatten() {
+            *value /= max_candela;
+        }
+
+        Ok(Self {
+            vertical_angles,
+            horizontal_angles,
+            candela,
+        })
+    }
+
+    /// Intensity in the direction `theta` degrees from the axis and `phi` degrees around it,
+    /// relative to the brightest direction of the profile.
+    pub fn relative_intensity(&self, theta: f64, phi: f64) -> f64 {
+        let (v, v_t) = match interpolation_point(&self.vertical_angles, theta) {
+            Some(p) => p,
+            None => return 0.0,
+        };
+
+        // fold phi into the range covered by the profile's symmetry
+        let last_horizontal = *self.horizontal_angles.last().unwrap();
+        let mut phi = phi.rem_euclid(360.0);
+        if last_horizontal <= 180.0 && phi > 180.0 {
+            phi = 360.0 - phi;
+        }
+        if last_horizontal <= 90.0 && phi > 90.0 {
+            phi = 180.0 - phi;
+        }
+        let (h, h_t) = interpolation_point(&self.horizontal_angles, phi).unwrap_or((0, 0.0));
+
+        let at_h = |h: usize| {
+            let column = &self.candela[h];
+            let v_next = (v + 1).min(column.len() - 1);
+            column[v] * (1.0 - v_t) + column[v_next] * v_t
+        };
+        let h_next = (h + 1).min(self.horizontal_angles.len() - 1);
+        at_h(h) * (1.0 - h_t) + at_h(h_next) * h_t
+    }
+
+    /// Integral of `relative_intensity` over the directions up to `max_theta` degrees from the
+    /// axis, i.e. the solid angle a light of the profile's brightest intensity would have to
+    /// fill to emit as much.
+    pub fn solid_angle(&self, max_theta: f64) -> f64 {
+        const THETA_STEPS: usize = 256;
+        const PHI_STEPS: usize = 64;
+        let max_theta = max_theta.clamp(0.0, 180.0);
+        let d_theta = max_theta / THETA_STEPS as f64;
+        let d_phi = 360.0 / PHI_STEPS as f64;
+        let mut sum = 0.0;
+        for i in 0..THETA_STEPS {
+            let theta = (i as f64 + 0.5) * d_theta;
+            let ring: f64 = (0..PHI_STEPS)
+                .map(|j| self.relative_intensity(theta, (j as f64 + 0.5) * d_phi))
+                .sum();
+            sum += ring * theta.to_radians().sin();
+        }
+        sum * d_theta.to_radians() * d_phi.to_radians()
+    }
+}
+
+/// Finds the segment of the sorted `angles` containing `x`, and how far along it `x` is.
+fn interpolation_point(angles: &[f64], x: f64) -> Option<(usize, f64)> {
+    if angles.len() == 1 {
+        return Some((0, 0.0));
+    }
+    if x < angles[0] || x > angles[angles.len() - 1] {
+        return None;
+    }
+    let i = angles
+        .partition_point(|&a| a <= x)
+        .clamp(1, angles.len() - 1)
+        - 1;
+    let width = angles[i + 1] - angles[i];
+    let t = if width > 0.0 {
+        (x - angles[i]) / width
+    } else {
+        0.0
+    };
+    Some((i, t))
+}
+
+fn invalid_ies(msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid IES profile: {}", msg),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::ImageTexture;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+    use std::f64::consts::PI;
+
+    /// Brightest along the axis and dark sideways, and a little dimmer at 90 degrees around it
+    /// than at 0, mirrored into the other quadrants.
+    const PROFILE: &str = "IESNA:LM-63-2002
+[TEST] small
+TILT=NONE
+1 1000 1 3 2 1 2 0 0 0
+1 1 100
+0 45 90
+0 90
+100 50 0
+80 40 0
+";
+
+    /// Lights evenly up to 90 degrees from the axis.
+    const FLAT_PROFILE: &str = "TILT=NONE
+1 -1 1 2 1 1 2 0 0 0
+1 1 100
+0 90
+0
+250 250
+";
+
+    fn spot(profile: &str, cone_angle: f64) -> DeltaLight<f64> {
+        DeltaLight::Spot(
+            SpotLight {
+                position: Three::zeros(),
+                direction: Three::new(0.0, -1.0, 0.0),
+                rgb: Three::ones(),
+                power: 2.0,
+                cone_angle,
+                falloff_start: cone_angle,
+                profile: None,
+            }
+            .with_profile(IesProfile::parse(profile).unwrap()),
+        )
+    }
+
+    #[test]
+    fn ies_intensities_are_interpolated_between_the_table_angles() {
+        let profile = IesProfile::parse(PROFILE).unwrap();
+        let assert_intensity = |theta: f64, phi: f64, expected: f64| {
+            let intensity = profile.relative_intensity(theta, phi);
+            assert!(
+                (intensity - expected).abs() < 1e-12,
+                "{} at ({}, {}) instead of {}",
+                intensity,
+                theta,
+                phi,
+                expected
+            );
+        };
+        // at the angles of the table
+        assert_intensity(0.0, 0.0, 1.0);
+        assert_intensity(45.0, 0.0, 0.5);
+        assert_intensity(45.0, 90.0, 0.4);
+        assert_intensity(90.0, 90.0, 0.0);
+        // between them
+        assert_intensity(22.5, 0.0, 0.75);
+        assert_intensity(45.0, 45.0, 0.45);
+        assert_intensity(22.5, 45.0, 0.675);
+        // mirrored around
+        assert_intensity(45.0, 180.0, 0.5);
+        assert_intensity(45.0, 270.0, 0.4);
+        assert_intensity(45.0, 315.0, 0.45);
+        // and nothing past the last vertical angle
+        assert_intensity(120.0, 0.0, 0.0);
+    }
+
+    #[test]
+    fn profiled_spot_lights_emit_the_power_of_their_profile() {
+        // the flat profile fills the hemisphere, or as much of it as the cone lets through
+        let power = spot(FLAT_PROFILE, 90.0).emitted_power();
+        assert!((power - 2.0 * 2.0 * PI).abs() < 1e-3, "{}", power);
+        let power = spot(FLAT_PROFILE, 60.0).emitted_power();
+        assert!((power - 2.0 * PI).abs() < 1e-3, "{}", power);
+        // the other falls off linearly from the axis, and is 0.9 as bright around it on average
+        let falling = spot(PROFILE, 90.0).emitted_power();
+        let expected = 2.0 * 0.9 * 2.0 * PI * (1.0 - 2.0 / PI);
+        assert!((falling - expected).abs() < 1e-3, "{}", falling);
+    }
+
+    /// A dim sky with one bright texel.
+    fn sky() -> Environment<f64> {
+        let mut texels = vec![Three::new(0.1, 0.1, 0.1); 8 * 4];
+        texels[8 + 5] = Three::new(50.0, 40.0, 30.0);
+        Environment::new(ImageTexture::from_texels(8, 4, texels))
+    }
+
+    #[test]
+    fn environment_sampler_pdf_matches_its_samples() {
+        let environment = sky();
+        let sampler = EnvironmentSampler::new(&environment);
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let sample = sampler.sample(&environment, &mut rng).unwrap();
+            let pdf = sampler.pdf(&environment, &sample.direction);
+            assert!(
+                (pdf - sample.pdf).abs() < 1e-6 * pdf,
+                "{} != {}",
+                pdf,
+                sample.pdf
+            );
+            assert!((sample.direction.length() - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn environment_sampler_covers_the_sphere() {
+        let environment = sky();
+        let sampler = EnvironmentSampler::new(&environment);
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let n = 100_000;
+        let solid_angle: f64 = (0..n)
+            .map(|_| sampler.sample(&environment, &mut rng).unwrap().pdf.recip())
+            .sum::<f64>()
+            / n as f64;
+        assert!(
+            (solid_angle - 4.0 * std::f64::consts::PI).abs() < 0.2,
+            "{}",
+            solid_angle
+        );
+    }
+}
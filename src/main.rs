@@ -0,0 +1,319 @@
+use clap::{Parser, ValueEnum};
+use image::{ImageFormat, Rgb, RgbImage};
+use num_traits::{Float, FloatConst};
+use rand_distr::{uniform::SampleUniform, Distribution, Standard};
+use rand_xorshift::XorShiftRng;
+use std::ops::{AddAssign, MulAssign};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Instant;
+use teeracer::*;
+
+// exit codes, from BSD's sysexits.h
+const EX_USAGE: u8 = 64;
+const EX_DATAERR: u8 = 65;
+const EX_NOINPUT: u8 = 66;
+const EX_SOFTWARE: u8 = 70;
+const EX_CANTCREAT: u8 = 73;
+
+/// Renders a TOML, pbrt, Mitsuba or glTF scene file to an image.
+///
+/// Options given here override the scene's own [camera] and [render] settings.
+#[derive(Parser, Debug)]
+#[command(
+    name = "teeracer",
+    version,
+    after_help = "Exit codes: 0 on success, 64 for invalid options, 65 for invalid scene files, \
+                  66 for scene files that can't be read, 70 for internal errors, and 73 when \
+                  the image can't be saved."
+)]
+struct Cli {
+    /// Scene file to render.
+    scene: PathBuf,
+    /// Where to save the image. Defaults to the scene's name with a .png extension, in the
+    /// current directory.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Image format, e.g. png, jpg, bmp or tiff. Defaults to the output's extension.
+    #[arg(long, value_parser = parse_format)]
+    format: Option<ImageFormat>,
+    /// Image size, like 800x600.
+    #[arg(short, long, value_parser = parse_resolution)]
+    resolution: Option<(usize, usize)>,
+    /// Samples per pixel.
+    #[arg(short, long)]
+    spp: Option<usize>,
+    /// Maximum number of bounces in a path.
+    #[arg(short, long)]
+    depth: Option<usize>,
+    /// One of path, bidirectional, photon-mapping, progressive-photon-mapping, metropolis,
+    /// normals, object-id, material-id, depth, uv, ambient-occlusion or traversal-cost.
+    #[arg(short, long)]
+    tracer: Option<TracerKind>,
+    /// Float precision to render with.
+    #[arg(short, long, value_enum, default_value_t = Precision::F32)]
+    precision: Precision,
+    /// Different seeds render with different random numbers.
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Number of threads to render with. Defaults to one per CPU.
+    #[arg(short = 'j', long)]
+    threads: Option<usize>,
+    /// Total number of photons the photon mapping tracers trace.
+    #[arg(long, default_value_t = 1_000_000)]
+    photons: usize,
+    /// Radius photons are gathered within by the photon mapping tracers, in scene units.
+    #[arg(long, default_value_t = 0.05)]
+    photon_radius: f64,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Precision {
+    F32,
+    F64,
+}
+
+/// An error message, and the code to exit with because of it.
+struct Failure {
+    code: u8,
+    message: String,
+}
+
+fn parse_resolution(s: &str) -> Result<(usize, usize), String> {
+    let parsed = s
+        .split_once('x')
+        .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)));
+    match parsed {
+        Some((width, height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(format!("expected WIDTHxHEIGHT, like 800x600, not `{}`", s)),
+    }
+}
+
+fn parse_format(s: &str) -> Result<ImageFormat, String> {
+    match ImageFormat::from_extension(s) {
+        Some(format) if format.can_write() => Ok(format),
+        Some(_) => Err(format!("images can't be saved as {}", s)),
+        None => Err(format!("unknown image format `{}`", s)),
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(error) => {
+            // --help and --version also end up here, but aren't failures
+            let _ = error.print();
+            return if error.use_stderr() {
+                ExitCode::from(EX_USAGE)
+            } else {
+                ExitCode::SUCCESS
+            };
+        }
+    };
+    let result = match cli.precision {
+        Precision::F32 => run::<f32>(&cli),
+        Precision::F64 => run::<f64>(&cli),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            eprintln!("error: {}", failure.message);
+            ExitCode::from(failure.code)
+        }
+    }
+}
+
+fn run<F>(cli: &Cli) -> Result<(), Failure>
+where
+    F: Float + FloatConst + SampleUniform + MulAssign + AddAssign + Send + Sync + 'static,
+    Standard: Distribution<F>,
+    Three<F>: Into<Rgb<u8>>,
+{
+    let output = cli.output.clone().unwrap_or_else(|| {
+        let name = cli.scene.file_stem().unwrap_or_default();
+        PathBuf::from(name).with_extension("png")
+    });
+    // check the format before rendering, instead of finding out after
+    let format = match cli.format {
+        Some(format) => format,
+        None => ImageFormat::from_path(&output).map_err(|_| Failure {
+            code: EX_USAGE,
+            message: format!(
+                "can't tell the image format of {}, use --format",
+                output.display()
+            ),
+        })?,
+    };
+
+    if let Some(threads) = cli.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .map_err(|error| Failure {
+                code: EX_SOFTWARE,
+                message: error.to_string(),
+            })?;
+    }
+
+    let extension = cli.scene.extension().and_then(|ext| ext.to_str());
+    let loaded = match extension {
+        Some("pbrt") => load_pbrt::<F, _>(&cli.scene),
+        Some("xml") => load_mitsuba::<F, _>(&cli.scene),
+        Some("gltf") | Some("glb") => load_gltf::<F, _>(&cli.scene).and_then(|gltf| {
+            let camera = gltf.camera.ok_or_else(|| LoadError::Invalid {
+                path: Some(cli.scene.clone()),
+                line: None,
+                message: "the scene has no camera".to_string(),
+            })?;
+            Ok(SceneDescription {
+                scene: gltf.scene,
+                camera,
+                settings: RenderSettings::default(),
+                warnings: gltf.warnings,
+            })
+        }),
+        _ => load_scene::<F, _>(&cli.scene),
+    };
+    let SceneDescription {
+        scene,
+        mut camera,
+        mut settings,
+        warnings,
+    } = loaded.map_err(|error| Failure {
+        code: match error {
+            LoadError::Io { .. } => EX_NOINPUT,
+            LoadError::Invalid { .. } => EX_DATAERR,
+        },
+        message: error.to_string(),
+    })?;
+    for warning in &warnings {
+        eprintln!("warning: {}", warning);
+    }
+    if let Some((width, height)) = cli.resolution {
+        camera = camera.with_image_shape(ImageShape { width, height });
+    }
+    settings.samples = cli.spp.unwrap_or(settings.samples);
+    settings.depth = cli.depth.unwrap_or(settings.depth);
+    settings.tracer = cli.tracer.unwrap_or(settings.tracer);
+    if cfg!(not(feature = "traversal-cost")) && settings.tracer == TracerKind::TraversalCost {
+        return Err(Failure {
+            code: EX_USAGE,
+            message:
+                "the traversal-cost tracer needs teeracer built with --features traversal-cost"
+                    .to_string(),
+        });
+    }
+
+    let photon_mapping = matches!(
+        settings.tracer,
+        TracerKind::PhotonMapping | TracerKind::ProgressivePhotonMapping
+    );
+    if photon_mapping && scene.environment().is_some() {
+        eprintln!(
+            "warning: photons don't leave the environment, so only its direct light is rendered"
+        );
+    }
+
+    eprintln!(
+        "rendering {} at {}x{}, {} samples per pixel, with the {:?} tracer",
+        cli.scene.display(),
+        camera.width(),
+        camera.height(),
+        settings.samples,
+        settings.tracer,
+    );
+    let start = Instant::now();
+    let image = render_with(cli, scene, camera, &settings);
+    image
+        .save_with_format(&output, format)
+        .map_err(|error| Failure {
+            code: EX_CANTCREAT,
+            message: format!("{}: {}", output.display(), error),
+        })?;
+    eprintln!("saved {} after {:.1?}", output.display(), start.elapsed());
+    Ok(())
+}
+
+fn render_with<F>(
+    cli: &Cli,
+    mut scene: Scene<F>,
+    camera: Camera<F>,
+    settings: &RenderSettings,
+) -> RgbImage
+where
+    F: Float + FloatConst + SampleUniform + MulAssign + AddAssign + Send + Sync + 'static,
+    Standard: Distribution<F>,
+    Three<F>: Into<Rgb<u8>>,
+{
+    let (spp, depth, seed) = (settings.samples, settings.depth, cli.seed);
+    let path_tracer = PathTracer {
+        depth,
+        ..Default::default()
+    };
+    let radius = F::from(cli.photon_radius).unwrap();
+    match settings.tracer {
+        TracerKind::Path => {
+            render_seeded::<_, F, XorShiftRng>(path_tracer, scene, camera, spp, seed)
+        }
+        TracerKind::Bidirectional => render_seeded::<_, F, XorShiftRng>(
+            BidirectionalPathTracer { depth },
+            scene,
+            camera,
+            spp,
+            seed,
+        ),
+        TracerKind::PhotonMapping => {
+            let tracer = PhotonMapper::build_seeded::<XorShiftRng>(
+                &mut scene,
+                &camera,
+                depth,
+                cli.photons,
+                radius,
+                seed,
+            );
+            render_seeded::<_, F, XorShiftRng>(tracer, scene, camera, spp, seed)
+        }
+        TracerKind::ProgressivePhotonMapping => {
+            let photons_per_pass = (cli.photons / spp.max(1)).max(1);
+            let mut tracer =
+                StochasticProgressivePhotonMapper::new(depth, spp, photons_per_pass, radius);
+            tracer.seed = seed;
+            tracer.render::<XorShiftRng>(scene, camera)
+        }
+        TracerKind::Metropolis => {
+            let mut mlt = MetropolisLightTransport::new(path_tracer, spp);
+            mlt.seed = seed;
+            mlt.render::<F, XorShiftRng>(scene, camera)
+        }
+        TracerKind::Normals => {
+            render_seeded::<_, F, XorShiftRng>(NormalTracer, scene, camera, spp, seed)
+        }
+        TracerKind::ObjectId => {
+            render_seeded::<_, F, XorShiftRng>(ObjectIdTracer, scene, camera, spp, seed)
+        }
+        TracerKind::MaterialId => {
+            render_seeded::<_, F, XorShiftRng>(MaterialIdTracer, scene, camera, spp, seed)
+        }
+        TracerKind::Depth => {
+            render_seeded::<_, F, XorShiftRng>(DepthTracer::default(), scene, camera, spp, seed)
+        }
+        TracerKind::Uv => render_seeded::<_, F, XorShiftRng>(UvTracer, scene, camera, spp, seed),
+        TracerKind::AmbientOcclusion => render_seeded::<_, F, XorShiftRng>(
+            AmbientOcclusionTracer::default(),
+            scene,
+            camera,
+            spp,
+            seed,
+        ),
+        #[cfg(feature = "traversal-cost")]
+        TracerKind::TraversalCost => render_seeded::<_, F, XorShiftRng>(
+            TraversalCostTracer::default(),
+            scene,
+            camera,
+            spp,
+            seed,
+        ),
+        #[cfg(not(feature = "traversal-cost"))]
+        TracerKind::TraversalCost => unreachable!("checked before rendering"),
+    }
+}
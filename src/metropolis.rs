@@ -0,0 +1,392 @@
+use crate::data::{Camera, Three};
+use crate::rendering::seeded_rng;
+use crate::scene::{Scene, SceneTracer};
+use crossbeam::channel;
+use image::{Rgb, RgbImage};
+use indicatif::{ProgressBar, ProgressStyle};
+use num_traits::{cast, Float, FloatConst};
+use rand::{Error, Rng, RngCore, SeedableRng};
+use rand_distr::{uniform::SampleUniform, Distribution, Standard, StandardNormal};
+use rayon::prelude::*;
+use std::ops::AddAssign;
+
+/// Primary sample space Metropolis light transport (Kelemen et al. 2002).
+///
+/// Every random number a `SceneTracer` uses is drawn from a vector of "primary samples", which
+/// are mutated slightly (small steps) or replaced entirely (large steps) to explore paths near
+/// the ones that carried the most light. Once a bright path through a narrow opening is found,
+/// nearby paths through the same opening are found too, where independent sampling would
+/// rarely find any.
+///
+/// Chains start from paths picked out of a bootstrap phase of independent samples, which also
+/// finds the average brightness of the image that the chains' splats are scaled to.
+#[derive(Debug, Clone, Copy)]
+pub struct MetropolisLightTransport<T> {
+    pub tracer: T,
+    /// Average number of mutations per pixel of the image.
+    pub mutations_per_pixel: usize,
+    pub num_bootstrap: usize,
+    /// Number of independent Markov chains. More chains parallelize better, but each spends
+    /// less time exploring.
+    pub num_chains: usize,
+    /// Standard deviation of small step mutations.
+    pub sigma: f64,
+    pub large_step_probability: f64,
+    /// Different seeds use different random numbers.
+    pub seed: u64,
+}
+
+/// A random number generator that replays a mutable vector of uniform samples, so the paths
+/// traced with it can be mutated by `MetropolisLightTransport`.
+///
+/// Samples are mutated lazily when they're used, so tracers can use as many random numbers as
+/// they like.
+#[derive(Debug, Clone)]
+pub struct PrimarySampleRng<R> {
+    rng: R,
+    sigma: f64,
+    large_step_probability: f64,
+    samples: Vec<PrimarySample>,
+    sample_idx: usize,
+    iteration: u64,
+    large_step: bool,
+    last_large_step_iteration: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PrimarySample {
+    value: f64,
+    last_modified: u64,
+    backup_value: f64,
+    backup_last_modified: u64,
+}
+
+impl<R> PrimarySampleRng<R>
+where
+    R: Rng,
+{
+    /// `rng` drives the mutations. The first iteration is always a large step.
+    pub fn new(rng: R, sigma: f64, large_step_probability: f64) -> Self {
+        Self {
+            rng,
+            sigma,
+            large_step_probability,
+            samples: Vec::new(),
+            sample_idx: 0,
+            iteration: 0,
+            large_step: true,
+            last_large_step_iteration: 0,
+        }
+    }
+
+    /// Starts proposing a mutation of the samples, to be kept with `accept` or undone with
+    /// `reject`.
+    pub fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.gen::<f64>() < self.large_step_probability;
+        self.sample_idx = 0;
+    }
+
+    /// Drives further mutations with `rng`, keeping the current samples. Chains that start
+    /// from the same path need this to explore differently from there.
+    pub fn reseed(&mut self, rng: R) {
+        self.rng = rng;
+    }
+
+    pub fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step_iteration = self.iteration;
+        }
+    }
+
+    pub fn reject(&mut self) {
+        for sample in self.samples.iter_mut() {
+            if sample.last_modified == self.iteration {
+                sample.value = sample.backup_value;
+                sample.last_modified = sample.backup_last_modified;
+            }
+        }
+        self.iteration -= 1;
+        self.sample_idx = 0;
+    }
+
+    fn next_sample(&mut self) -> f64 {
+        if self.sample_idx >= self.samples.len() {
+            // samples used for the first time start out uniformly random, instead of as small
+            // steps from zero
+            let value = self.rng.gen();
+            self.samples.push(PrimarySample {
+                value,
+                last_modified: self.iteration,
+                backup_value: value,
+                backup_last_modified: self.iteration,
+            });
+        }
+        let sample = &mut self.samples[self.sample_idx];
+        self.sample_idx += 1;
+
+        // catch up on the last large step if the sample wasn't used since
+        if sample.last_modified < self.last_large_step_iteration {
+            sample.value = self.rng.gen();
+            sample.last_modified = self.last_large_step_iteration;
+        }
+
+        sample.backup_value = sample.value;
+        sample.backup_last_modified = sample.last_modified;
+        if self.large_step {
+            sample.value = self.rng.gen();
+        } else if sample.last_modified < self.iteration {
+            // apply every small step the sample missed at once
+            let missed_steps = (self.iteration - sample.last_modified) as f64;
+            let normal: f64 = self.rng.sample(StandardNormal);
+            sample.value += normal * self.sigma * missed_steps.sqrt();
+            sample.value -= sample.value.floor();
+        }
+        sample.last_modified = self.iteration;
+        sample.value
+    }
+}
+
+impl<R> RngCore for PrimarySampleRng<R>
+where
+    R: Rng,
+{
+    fn next_u32(&mut self) -> u32 {
+        (self.next_sample() * 4294967296.0) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        (self.next_sample() * 18446744073709551616.0) as u64
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl<T> MetropolisLightTransport<T> {
+    pub fn new(tracer: T, mutations_per_pixel: usize) -> Self {
+        Self {
+            tracer,
+            mutations_per_pixel,
+            num_bootstrap: 100_000,
+            num_chains: 1000,
+            sigma: 0.01,
+            large_step_probability: 0.3,
+            seed: 0,
+        }
+    }
+
+    /// Renders `scene`, like `render` does with the tracer on its own.
+    pub fn render<F, R>(&self, mut scene: Scene<F>, camera: Camera<F>) -> RgbImage
+    where
+        T: SceneTracer<F> + Sync,
+        F: Float + FloatConst + SampleUniform + Send + Sync + AddAssign,
+        R: Rng + SeedableRng + Send,
+        Standard: Distribution<F>,
+        Three<F>: Into<Rgb<u8>>,
+    {
+        scene.build_light_sampler();
+        scene.build_bvh();
+        let scene = &scene;
+        let camera = &camera;
+
+        // bootstrap: find how bright paths are on average, and which to start chains from
+        let weights: Vec<F> = (0..self.num_bootstrap)
+            .into_par_iter()
+            .map(|bootstrap_idx| {
+                let mut sampler = self.sampler::<R>(bootstrap_idx);
+                brightness(&self.contributions(scene, camera, &mut sampler))
+            })
+            .collect();
+        let total = weights.iter().fold(F::zero(), |total, &w| total + w);
+        let mut image = camera.empty_image();
+        if total <= F::zero() {
+            return to_image(camera, &image, F::zero());
+        }
+        let mean_brightness = total / cast(self.num_bootstrap).unwrap();
+        let mut running = F::zero();
+        let cdf: Vec<F> = weights
+            .iter()
+            .map(|&w| {
+                running += w;
+                running / total
+            })
+            .collect();
+
+        let num_pixels = camera.width * camera.height;
+        let num_mutations = num_pixels * self.mutations_per_pixel;
+        // at least one mutation per chain, or small images would come out black
+        let mutations_per_chain = (num_mutations / self.num_chains.max(1)).max(1);
+        let num_mutations = mutations_per_chain * self.num_chains;
+
+        let pb = ProgressBar::new(num_mutations as u64).with_style(
+            ProgressStyle::default_bar().template("{bar:40} {elapsed_precise}<{eta} {per_sec}"),
+        );
+        pb.set_draw_rate(1);
+
+        let (sender, receiver) = channel::unbounded();
+        crossbeam::scope(|s| {
+            s.spawn(|_| {
+                (0..self.num_chains)
+                    .into_par_iter()
+                    .for_each_with(sender, |sender, chain_idx| {
+                        self.run_chain::<F, R>(
+                            scene,
+                            camera,
+                            &cdf,
+                            chain_idx,
+                            mutations_per_chain,
+                            sender,
+                        );
+                    });
+            });
+
+            for splats in receiver.iter() {
+                for (pixel_idx, color) in splats {
+                    image[pixel_idx] += color;
+                }
+                pb.inc(1);
+            }
+        })
+        .unwrap();
+
+        // each pixel gets the mean brightness of the image per mutation it would get on average
+        let mutations_per_pixel: F =
+            cast::<usize, F>(num_mutations).unwrap() / cast(num_pixels).unwrap();
+        let scale = mean_brightness / mutations_per_pixel;
+        to_image(camera, &image, scale)
+    }
+
+    /// Sampler that reproduces the bootstrap path with index `bootstrap_idx`.
+    fn sampler<R>(&self, bootstrap_idx: usize) -> PrimarySampleRng<R>
+    where
+        R: Rng + SeedableRng,
+    {
+        PrimarySampleRng::new(
+            seeded_rng(self.seed, bootstrap_idx as u64),
+            self.sigma,
+            self.large_step_probability,
+        )
+    }
+
+    fn run_chain<F, R>(
+        &self,
+        scene: &Scene<F>,
+        camera: &Camera<F>,
+        cdf: &[F],
+        chain_idx: usize,
+        num_mutations: usize,
+        sender: &channel::Sender<Vec<(usize, Three<F>)>>,
+    ) where
+        T: SceneTracer<F>,
+        F: Float + FloatConst + SampleUniform,
+        R: Rng + SeedableRng,
+        Standard: Distribution<F>,
+    {
+        // start from a bootstrap path picked by how bright it is
+        let chain_seed = (self.num_bootstrap + chain_idx) as u64;
+        let mut rng = seeded_rng::<R>(self.seed, chain_seed);
+        let u: F = Standard.sample(&mut rng);
+        let bootstrap_idx = cdf.partition_point(|&c| c <= u).min(self.num_bootstrap - 1);
+        let mut sampler = self.sampler::<R>(bootstrap_idx);
+        let mut current = self.contributions(scene, camera, &mut sampler);
+        let mut current_brightness = brightness(&current);
+        // chains starting from the same path would otherwise propose the same mutations
+        let mutation_seed = self.num_bootstrap + self.num_chains + chain_idx;
+        sampler.reseed(seeded_rng(self.seed, mutation_seed as u64));
+
+        for _ in 0..num_mutations {
+            sampler.start_iteration();
+            let proposed = self.contributions(scene, camera, &mut sampler);
+            let proposed_brightness = brightness(&proposed);
+            let accept = if current_brightness > F::zero() {
+                (proposed_brightness / current_brightness).min(F::one())
+            } else {
+                F::one()
+            };
+
+            // splat both paths weighted by how likely each is to be kept, which has less variance
+            // than only splatting the one that is
+            let mut splats = Vec::with_capacity(current.len() + proposed.len());
+            if accept > F::zero() {
+                let weight = accept / proposed_brightness;
+                splats.extend(proposed.iter().map(|&(idx, c)| (idx, c * weight)));
+            }
+            if accept < F::one() {
+                let weight = (F::one() - accept) / current_brightness;
+                splats.extend(current.iter().map(|&(idx, c)| (idx, c * weight)));
+            }
+            sender.send(splats).unwrap();
+
+            if Standard.sample(&mut rng) < accept {
+                current = proposed;
+                current_brightness = proposed_brightness;
+                sampler.accept();
+            } else {
+                sampler.reject();
+            }
+        }
+    }
+
+    /// Traces a path through a point on the image picked by the first two primary samples,
+    /// returning the light it carries to each pixel.
+    fn contributions<F, R>(
+        &self,
+        scene: &Scene<F>,
+        camera: &Camera<F>,
+        sampler: &mut PrimarySampleRng<R>,
+    ) -> Vec<(usize, Three<F>)>
+    where
+        T: SceneTracer<F>,
+        F: Float,
+        R: Rng,
+        Standard: Distribution<F>,
+    {
+        let x_screen = Standard.sample(sampler) * cast(camera.width).unwrap();
+        let y_screen = Standard.sample(sampler) * cast(camera.height).unwrap();
+        let ray = camera.ray_through(x_screen, y_screen, camera.sample_time(sampler));
+        let mut splats = Vec::new();
+        let color = self
+            .tracer
+            .trace_with_splats(ray, scene, camera, sampler, &mut splats)
+            .unwrap_or_else(Three::zeros);
+        splats.push((camera.pixel_index(x_screen, y_screen), color));
+        splats
+    }
+}
+
+/// How much a path is worth exploring, i.e. the total perceived brightness it carries.
+fn brightness<F: Float>(contributions: &[(usize, Three<F>)]) -> F {
+    let total = contributions.iter().fold(F::zero(), |total, (_, c)| {
+        total + c.luminance().max(F::zero())
+    });
+    if total.is_finite() {
+        total
+    } else {
+        F::zero()
+    }
+}
+
+fn to_image<F>(camera: &Camera<F>, colors: &[Three<F>], scale: F) -> RgbImage
+where
+    F: Float,
+    Three<F>: Into<Rgb<u8>>,
+{
+    let mut img = RgbImage::new(camera.width as u32, camera.height as u32);
+    for x in 0..camera.width {
+        for y in 0..camera.height {
+            let color = colors[y * camera.width + x] * scale;
+            img.put_pixel(x as u32, y as u32, color.into());
+        }
+    }
+    img
+}
@@ -3,6 +3,20 @@ use num_traits::{Float, FloatConst};
 use rand::Rng;
 use rand_distr::{Distribution, Standard};
 
+/// Multiple importance sampling weight for a sample drawn from a distribution with density
+/// `f_pdf`, when it could also have been drawn from one with density `g_pdf`.
+pub fn power_heuristic<F: Float>(f_pdf: F, g_pdf: F) -> F {
+    let f2 = f_pdf * f_pdf;
+    let g2 = g_pdf * g_pdf;
+    if f2.is_infinite() {
+        F::one()
+    } else if f2 + g2 > F::zero() {
+        f2 / (f2 + g2)
+    } else {
+        F::zero()
+    }
+}
+
 pub trait HemisphereDistribution<F> {
     fn sample<R: Rng>(&self, rng: &mut R) -> Three<F>;
     fn pdf(&self, v: &Three<F>) -> F;
@@ -19,15 +33,7 @@ where
     F: Float,
 {
     pub fn oriented_towards(normal: Three<F>) -> Self {
-        let a = if normal.x.abs() > F::from(0.9f64).unwrap() {
-            Three::new(F::zero(), F::one(), F::zero())
-        } else {
-            Three::new(F::one(), F::zero(), F::zero())
-        };
-
-        let u = normal.cross(&a).normalized();
-        let v = normal.cross(&u).normalized();
-
+        let (u, v) = normal.orthonormal_basis();
         Self { normal, u, v }
     }
 }
@@ -46,7 +52,7 @@ where
         let x = phi.cos() * r.sqrt();
 
         // transform to world coordinates using u/v/normal basis
-        &self.u * x + &self.v * y + &self.normal * z
+        self.u * x + self.v * y + self.normal * z
     }
 
     fn pdf(&self, v: &Three<F>) -> F {
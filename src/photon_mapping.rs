@@ -0,0 +1,629 @@
+use crate::data::{
+    Aabb, Camera, CanHit, Diffuse, Hit, LightInteraction, Material, Ray, Surface, Three,
+};
+use crate::pdf::{power_heuristic, CosineHemisphereDistribution, HemisphereDistribution};
+use crate::rendering::seeded_rng;
+use crate::scene::{Scene, SceneTracer};
+use crate::tracer::{
+    area_light_contribution, area_light_pdf, delta_light_contribution, dielectric_interaction,
+    environment_contribution, facing_normal, light_interaction, mirror_interaction,
+};
+use image::{Rgb, RgbImage};
+use indicatif::{ProgressBar, ProgressStyle};
+use num_traits::{cast, Float, FloatConst};
+use rand::{prelude::Rng, SeedableRng};
+use rand_distr::{uniform::SampleUniform, Distribution, Standard};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::ops::{AddAssign, MulAssign};
+use std::sync::Arc;
+
+/// Light carried by a photon to a diffuse surface.
+#[derive(Debug, Clone, Copy)]
+pub struct Photon<F> {
+    pub position: Three<F>,
+    /// Unit vector the photon was travelling along when it landed.
+    pub direction: Three<F>,
+    pub power: Three<F>,
+}
+
+/// Buckets items into a uniform grid of cubes, hashed by their integer coordinates, so
+/// everything near a point can be found without looking at every item.
+#[derive(Debug, Clone)]
+pub struct HashGrid<F> {
+    cell_size: F,
+    cells: HashMap<(i64, i64, i64), Vec<usize>>,
+}
+
+/// Photons stored by where they landed, for estimating the light reflected by diffuse surfaces
+/// from the photons within `radius` of a point.
+#[derive(Debug, Clone)]
+pub struct PhotonMap<F> {
+    photons: Vec<Photon<F>>,
+    grid: HashGrid<F>,
+    radius: F,
+}
+
+/// Finds light from the camera until the first diffuse surface, then estimates the indirect
+/// light there from a photon map built before rendering. Much faster than `PathTracer` at
+/// caustics, but blurs lighting by the photon map's radius.
+///
+/// Photons only leave lights and emissive objects, so the scene's environment lights what the
+/// camera sees directly or through mirrors and glass, but nothing it lights reflects on.
+///
+/// A default `PhotonMapper` has no photons, so use `PhotonMapper::build`.
+#[derive(Debug, Clone)]
+pub struct PhotonMapper<F> {
+    pub depth: usize,
+    photon_map: Arc<PhotonMap<F>>,
+}
+
+/// Stochastic progressive photon mapping.
+///
+/// Every pass finds one visible point per pixel, traces `photons_per_pass` photons, and shrinks
+/// the radius photons are gathered within at each pixel that received some. Unlike
+/// `PhotonMapper`, the blur shrinks away as passes accumulate, so the image converges to the
+/// right answer, caustics included. Like `PhotonMapper`, it only finds direct light from the
+/// environment.
+#[derive(Debug, Clone, Copy)]
+pub struct StochasticProgressivePhotonMapper<F> {
+    pub depth: usize,
+    pub passes: usize,
+    pub photons_per_pass: usize,
+    /// Radius photons are gathered within on the first pass.
+    pub initial_radius: F,
+    /// Fraction of each pass's photons kept when shrinking the radius, between 0 and 1. Smaller
+    /// values shrink the radius faster.
+    pub alpha: F,
+    /// Different seeds use different random numbers.
+    pub seed: u64,
+}
+
+/// Where a camera path reached a diffuse surface.
+#[derive(Debug, Clone, Copy)]
+struct VisiblePoint<F> {
+    position: Three<F>,
+    /// Surface normal on the side the camera path arrived from.
+    normal: Three<F>,
+    rgb: Three<F>,
+    /// Throughput of the camera path.
+    beta: Three<F>,
+}
+
+/// Progressive photon mapping state of one pixel.
+#[derive(Debug, Clone, Copy)]
+struct SppmPixel<F> {
+    direct: Three<F>,
+    radius: F,
+    num_photons: F,
+    tau: Three<F>,
+}
+
+impl<F> HashGrid<F>
+where
+    F: Float,
+{
+    /// A grid of cubes with sides of `cell_size`. Lookups are fastest when it's about the size
+    /// of the items.
+    pub fn new(cell_size: F) -> Self {
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+        }
+    }
+
+    fn cell(&self, point: &Three<F>) -> (i64, i64, i64) {
+        let coord = |x: F| (x / self.cell_size).floor().to_i64().unwrap_or(0);
+        (coord(point.x), coord(point.y), coord(point.z))
+    }
+
+    /// Adds `idx` to every cell overlapping `bounds`.
+    pub fn insert(&mut self, bounds: &Aabb<F>, idx: usize) {
+        let (x0, y0, z0) = self.cell(&bounds.min);
+        let (x1, y1, z1) = self.cell(&bounds.max);
+        for x in x0..=x1 {
+            for y in y0..=y1 {
+                for z in z0..=z1 {
+                    self.cells.entry((x, y, z)).or_default().push(idx);
+                }
+            }
+        }
+    }
+
+    /// Indices of the items whose bounds overlap the cell containing `point`.
+    pub fn get(&self, point: &Three<F>) -> &[usize] {
+        self.cells
+            .get(&self.cell(point))
+            .map_or(&[], |indices| indices.as_slice())
+    }
+}
+
+fn bounds_around<F: Float>(point: &Three<F>, radius: F) -> Aabb<F> {
+    let r = Three::new(radius, radius, radius);
+    Aabb {
+        min: *point - r,
+        max: *point + r,
+    }
+}
+
+impl<F> PhotonMap<F>
+where
+    F: Float + FloatConst + AddAssign,
+{
+    pub fn new(photons: Vec<Photon<F>>, radius: F) -> Self {
+        let mut grid = HashGrid::new(radius * F::from(2.0f64).unwrap());
+        for (idx, photon) in photons.iter().enumerate() {
+            grid.insert(&bounds_around(&photon.position, radius), idx);
+        }
+        Self {
+            photons,
+            grid,
+            radius,
+        }
+    }
+
+    pub fn photons(&self) -> &[Photon<F>] {
+        &self.photons
+    }
+
+    /// Radiance reflected towards the camera at `point`.
+    fn estimate(&self, point: &VisiblePoint<F>) -> Three<F> {
+        let radius_squared = self.radius * self.radius;
+        let mut flux = Three::zeros();
+        for &idx in self.grid.get(&point.position) {
+            flux += gathered_power(point, &self.photons[idx], radius_squared);
+        }
+        flux / (F::PI() * radius_squared)
+    }
+}
+
+impl<F> Default for PhotonMap<F>
+where
+    F: Float + FloatConst + AddAssign,
+{
+    fn default() -> Self {
+        Self::new(Vec::new(), F::one())
+    }
+}
+
+/// Power of `photon` reflected by `point` towards the camera, if it landed within the radius.
+fn gathered_power<F: Float + FloatConst>(
+    point: &VisiblePoint<F>,
+    photon: &Photon<F>,
+    radius_squared: F,
+) -> Three<F> {
+    let arrives_in_front = photon.direction.dot(&point.normal) < F::zero();
+    if arrives_in_front && (photon.position - point.position).length_squared() < radius_squared {
+        point.rgb * F::FRAC_1_PI() * photon.power
+    } else {
+        Three::zeros()
+    }
+}
+
+impl<F> PhotonMapper<F>
+where
+    F: Float + FloatConst + SampleUniform + MulAssign + AddAssign + Send + Sync,
+    Standard: Distribution<F>,
+{
+    /// Traces `num_photons` photons through `scene` to build the photon map, which estimates
+    /// light from the photons within `radius` of each shaded point. Each photon leaves at a
+    /// random time while `camera`'s shutter is open, so moving shapes are lit where they go.
+    pub fn build<R>(
+        scene: &mut Scene<F>,
+        camera: &Camera<F>,
+        depth: usize,
+        num_photons: usize,
+        radius: F,
+    ) -> Self
+    where
+        R: Rng + SeedableRng,
+    {
+        Self::build_seeded::<R>(scene, camera, depth, num_photons, radius, 0)
+    }
+
+    /// Like `build`, but with different photons for every `seed`. `build` uses seed 0.
+    pub fn build_seeded<R>(
+        scene: &mut Scene<F>,
+        camera: &Camera<F>,
+        depth: usize,
+        num_photons: usize,
+        radius: F,
+        seed: u64,
+    ) -> Self
+    where
+        R: Rng + SeedableRng,
+    {
+        scene.build_light_sampler();
+        scene.build_bvh();
+        let scale = cast::<usize, F>(num_photons).unwrap().recip();
+        let scene = &*scene;
+        let photons: Vec<Photon<F>> = (0..num_photons)
+            .into_par_iter()
+            .flat_map_iter(|photon_idx| {
+                let mut rng = photon_rng::<R>(seed, photon_idx);
+                let mut photons = Vec::new();
+                let time = camera.sample_time(&mut rng);
+                trace_photon(scene, depth, time, &mut rng, |photon| {
+                    photons.push(Photon {
+                        power: photon.power * scale,
+                        ..photon
+                    })
+                });
+                photons
+            })
+            .collect();
+        Self {
+            depth,
+            photon_map: Arc::new(PhotonMap::new(photons, radius)),
+        }
+    }
+}
+
+impl<F> Default for PhotonMapper<F>
+where
+    F: Float + FloatConst + AddAssign,
+{
+    fn default() -> Self {
+        Self {
+            depth: 0,
+            photon_map: Arc::new(PhotonMap::default()),
+        }
+    }
+}
+
+impl<F> SceneTracer<F> for PhotonMapper<F>
+where
+    F: Float + FloatConst + SampleUniform + MulAssign + AddAssign,
+    Standard: Distribution<F>,
+{
+    fn trace<R>(&self, ray: Ray<F>, scene: &Scene<F>, rng: &mut R) -> Option<Three<F>>
+    where
+        R: Rng,
+    {
+        let (mut radiance, point) = visible_point(ray, scene, self.depth, rng);
+        if let Some(point) = point {
+            radiance += point.beta * self.photon_map.estimate(&point);
+        }
+        Some(radiance)
+    }
+}
+
+impl<F> StochasticProgressivePhotonMapper<F>
+where
+    F: Float + FloatConst + SampleUniform + MulAssign + AddAssign + Send + Sync + 'static,
+    Standard: Distribution<F>,
+{
+    pub fn new(depth: usize, passes: usize, photons_per_pass: usize, initial_radius: F) -> Self {
+        Self {
+            depth,
+            passes,
+            photons_per_pass,
+            initial_radius,
+            alpha: F::from(2.0f64 / 3.0).unwrap(),
+            seed: 0,
+        }
+    }
+
+    /// Renders `scene`, like `render` does for `SceneTracer`s.
+    pub fn render<R>(&self, mut scene: Scene<F>, camera: Camera<F>) -> RgbImage
+    where
+        R: Rng + SeedableRng,
+        Three<F>: Into<Rgb<u8>>,
+    {
+        scene.build_light_sampler();
+        scene.build_bvh();
+
+        let num_pixels = camera.width * camera.height;
+        let mut pixels = vec![
+            SppmPixel {
+                direct: Three::zeros(),
+                radius: self.initial_radius,
+                num_photons: F::zero(),
+                tau: Three::zeros(),
+            };
+            num_pixels
+        ];
+
+        let pb = ProgressBar::new(self.passes as u64).with_style(
+            ProgressStyle::default_bar().template("{bar:40} {elapsed_precise}<{eta} {per_sec}"),
+        );
+
+        for pass in 0..self.passes {
+            // find a visible point through every pixel
+            let points: Vec<(Three<F>, Option<VisiblePoint<F>>)> = (0..num_pixels)
+                .into_par_iter()
+                .map(|pixel_idx| {
+                    let mut rng =
+                        seeded_rng::<R>(self.seed, (pass * num_pixels + pixel_idx) as u64);
+                    let y: F = cast(pixel_idx / camera.width).unwrap();
+                    let x: F = cast(pixel_idx % camera.width).unwrap();
+                    let jx = x + Standard.sample(&mut rng);
+                    let jy = y + Standard.sample(&mut rng);
+                    let ray = camera.ray_through(jx, jy, camera.sample_time(&mut rng));
+                    visible_point(ray, &scene, self.depth, &mut rng)
+                })
+                .collect();
+
+            let max_radius = pixels
+                .iter()
+                .fold(F::zero(), |max, pixel| max.max(pixel.radius));
+            let mut grid = HashGrid::new(max_radius * F::from(2.0f64).unwrap());
+            for (pixel_idx, (_, point)) in points.iter().enumerate() {
+                if let Some(point) = point {
+                    grid.insert(
+                        &bounds_around(&point.position, pixels[pixel_idx].radius),
+                        pixel_idx,
+                    );
+                }
+            }
+
+            // gather photons at the visible points they land near
+            let pixels_ref = &pixels;
+            let gathered: Vec<(usize, Three<F>)> = (0..self.photons_per_pass)
+                .into_par_iter()
+                .flat_map_iter(|photon_idx| {
+                    let mut rng =
+                        photon_rng::<R>(self.seed, pass * self.photons_per_pass + photon_idx);
+                    let mut gathered = Vec::new();
+                    let time = camera.sample_time(&mut rng);
+                    trace_photon(&scene, self.depth, time, &mut rng, |photon| {
+                        for &pixel_idx in grid.get(&photon.position) {
+                            let point = points[pixel_idx].1.as_ref().unwrap();
+                            let radius = pixels_ref[pixel_idx].radius;
+                            let power = gathered_power(point, &photon, radius * radius);
+                            if power != Three::zeros() {
+                                gathered.push((pixel_idx, power));
+                            }
+                        }
+                    });
+                    gathered
+                })
+                .collect();
+
+            let mut flux = vec![(Three::zeros(), 0usize); num_pixels];
+            for (pixel_idx, power) in gathered {
+                flux[pixel_idx].0 += power;
+                flux[pixel_idx].1 += 1;
+            }
+
+            for (pixel_idx, pixel) in pixels.iter_mut().enumerate() {
+                let (direct, point) = &points[pixel_idx];
+                pixel.direct += *direct;
+                let (phi, count) = flux[pixel_idx];
+                if let (Some(point), true) = (point, count > 0) {
+                    // keep only a fraction of the new photons, and shrink the radius to match
+                    let count: F = cast(count).unwrap();
+                    let num_photons = pixel.num_photons + self.alpha * count;
+                    let radius = pixel.radius * (num_photons / (pixel.num_photons + count)).sqrt();
+                    let shrink = (radius * radius) / (pixel.radius * pixel.radius);
+                    pixel.tau = (pixel.tau + point.beta * phi) * shrink;
+                    pixel.num_photons = num_photons;
+                    pixel.radius = radius;
+                }
+            }
+            pb.inc(1);
+        }
+
+        let passes: F = cast(self.passes).unwrap();
+        let total_photons: F = cast(self.passes * self.photons_per_pass).unwrap();
+        let mut img = RgbImage::new(camera.width as u32, camera.height as u32);
+        for x in 0..camera.width {
+            for y in 0..camera.height {
+                let pixel = &pixels[y * camera.width + x];
+                let indirect = pixel.tau / (total_photons * F::PI() * pixel.radius * pixel.radius);
+                let color = pixel.direct / passes + indirect;
+                img.put_pixel(x as u32, y as u32, color.into());
+            }
+        }
+        img
+    }
+}
+
+/// Random number generator for the photon with index `photon_idx`. The bits are flipped so
+/// photons don't reuse the random numbers of camera rays, which are seeded with small indices.
+fn photon_rng<R: SeedableRng>(seed: u64, photon_idx: usize) -> R {
+    seeded_rng(seed, !(photon_idx as u64))
+}
+
+/// Follows a photon from a light chosen proportional to its power, calling `deposit` at every
+/// diffuse surface it lands on after its first bounce. Light that arrives without bouncing is
+/// left to light sampling at the visible points instead.
+///
+/// Directional lights don't emit photons, so they only ever light scenes directly.
+fn trace_photon<F, R, D>(scene: &Scene<F>, depth: usize, time: F, rng: &mut R, mut deposit: D)
+where
+    R: Rng,
+    F: Float + FloatConst + SampleUniform + MulAssign,
+    Standard: Distribution<F>,
+    D: FnMut(Photon<F>),
+{
+    let (emitter, pmf) = match scene.sample_emitter(Standard.sample(rng)) {
+        Some(choice) => choice,
+        None => return,
+    };
+    let sample = match scene.sample_emission(emitter, time, rng) {
+        Some(sample) => sample,
+        None => return,
+    };
+    if sample.pdf_position <= F::zero() || sample.pdf_direction <= F::zero() {
+        return;
+    }
+    let cos_theta = sample
+        .normal
+        .map_or(F::one(), |normal| normal.dot(&sample.direction).abs());
+    let mut power =
+        sample.radiance * (cos_theta / (pmf * sample.pdf_position * sample.pdf_direction));
+    let mut ray = Ray {
+        origin: sample.origin,
+        direction: sample.direction,
+        time,
+    };
+
+    let t_min = F::from(1e-3f64).unwrap();
+    for bounce in 0..depth {
+        let hit = match ray.shoot_at(scene, t_min, F::infinity()) {
+            Some(hit) => hit,
+            None => break,
+        };
+        let interaction = match scene.material_for(hit.object_index) {
+            Material::Diffuse(diffuse) => {
+                if bounce > 0 {
+                    deposit(Photon {
+                        position: hit.position,
+                        direction: ray.direction,
+                        power,
+                    });
+                }
+                let normal = facing_normal(&ray.direction, &hit.normal);
+                LightInteraction::Scatter {
+                    direction: CosineHemisphereDistribution::oriented_towards(normal).sample(rng),
+                    attenuation: diffuse.albedo(hit.uv),
+                }
+            }
+            Material::Mirror(mirror) => mirror_interaction(mirror, &ray.direction, &hit.normal),
+            Material::Dielectric(dielectric) => {
+                dielectric_interaction(dielectric, &ray.direction, &hit.normal, rng)
+            }
+            Material::Light(_) => break,
+        };
+        match interaction {
+            LightInteraction::Scatter {
+                direction,
+                attenuation,
+            } => {
+                power *= attenuation;
+                ray = Ray {
+                    origin: hit.position,
+                    direction,
+                    time: ray.time,
+                };
+            }
+            LightInteraction::Emit { .. } => break,
+        }
+    }
+}
+
+/// Follows `ray` through mirrors and dielectrics to the first diffuse surface. Returns the light
+/// found on the way, including direct lighting at the diffuse surface, and the surface itself.
+fn visible_point<F, R>(
+    mut ray: Ray<F>,
+    scene: &Scene<F>,
+    depth: usize,
+    rng: &mut R,
+) -> (Three<F>, Option<VisiblePoint<F>>)
+where
+    R: Rng,
+    F: Float + FloatConst + SampleUniform + MulAssign + AddAssign,
+    Standard: Distribution<F>,
+{
+    let t_min = F::from(1e-3f64).unwrap();
+    let mut radiance = Three::zeros();
+    let mut beta = Three::ones();
+    for _ in 0..depth {
+        let hit = match ray.shoot_at(scene, t_min, F::infinity()) {
+            Some(hit) => hit,
+            None => {
+                // only the camera and mirrors come before, so nothing else finds this light
+                if let Some(environment) = scene.environment() {
+                    radiance += beta * environment.radiance(&ray.direction);
+                }
+                break;
+            }
+        };
+        let interaction = match scene.material_for(hit.object_index) {
+            Material::Diffuse(diffuse) => {
+                radiance += beta * direct_lighting(diffuse, &ray, &hit, scene, rng);
+                let point = VisiblePoint {
+                    position: hit.position,
+                    normal: facing_normal(&ray.direction, &hit.normal),
+                    rgb: diffuse.albedo(hit.uv),
+                    beta,
+                };
+                return (radiance, Some(point));
+            }
+            Material::Mirror(mirror) => mirror_interaction(mirror, &ray.direction, &hit.normal),
+            Material::Dielectric(dielectric) => {
+                dielectric_interaction(dielectric, &ray.direction, &hit.normal, rng)
+            }
+            Material::Light(light) => {
+                let area = scene.object(hit.object_index).area();
+                light_interaction(light, area, &ray.direction, &hit)
+            }
+        };
+        match interaction {
+            LightInteraction::Scatter {
+                direction,
+                attenuation,
+            } => {
+                beta *= attenuation;
+                ray = Ray {
+                    origin: hit.position,
+                    direction,
+                    time: ray.time,
+                };
+            }
+            LightInteraction::Emit { emission } => {
+                radiance += beta * emission;
+                break;
+            }
+        }
+    }
+    (radiance, None)
+}
+
+/// Light arriving straight from lights and the environment at a diffuse `hit`, from sampling
+/// them and from a cosine weighted bounce combined with multiple importance sampling.
+fn direct_lighting<F, R>(
+    diffuse: &Diffuse<F>,
+    ray: &Ray<F>,
+    hit: &Hit<F>,
+    scene: &Scene<F>,
+    rng: &mut R,
+) -> Three<F>
+where
+    R: Rng,
+    F: Float + FloatConst + SampleUniform + AddAssign,
+    Standard: Distribution<F>,
+{
+    let mut radiance = delta_light_contribution(diffuse, ray, hit, scene, rng)
+        + area_light_contribution(diffuse, ray, hit, scene, rng)
+        + environment_contribution(diffuse, ray, hit, scene, rng);
+
+    let dist =
+        CosineHemisphereDistribution::oriented_towards(facing_normal(&ray.direction, &hit.normal));
+    let direction = dist.sample(rng);
+    let bounce = Ray {
+        origin: hit.position,
+        direction,
+        time: ray.time,
+    };
+    let light_hit = match bounce.shoot_at(scene, F::from(1e-3f64).unwrap(), F::infinity()) {
+        Some(light_hit) => light_hit,
+        None => {
+            if let Some(environment) = scene.environment() {
+                let weight = match scene.environment_sampler() {
+                    Some(sampler) => {
+                        power_heuristic(dist.pdf(&direction), sampler.pdf(environment, &direction))
+                    }
+                    None => F::one(),
+                };
+                radiance += diffuse.albedo(hit.uv) * environment.radiance(&direction) * weight;
+            }
+            return radiance;
+        }
+    };
+    if let Material::Light(light) = scene.material_for(light_hit.object_index) {
+        let area = scene.object(light_hit.object_index).area();
+        if let LightInteraction::Emit { emission } =
+            light_interaction(light, area, &direction, &light_hit)
+        {
+            let weight = power_heuristic(
+                dist.pdf(&direction),
+                area_light_pdf(scene, &hit.position, &direction, &light_hit),
+            );
+            // f * cos_theta / pdf is just the albedo
+            radiance += diffuse.albedo(hit.uv) * emission * weight;
+        }
+    }
+    radiance
+}
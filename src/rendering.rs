@@ -1,9 +1,10 @@
+use crate::aov::{AovSample, Aovs};
 use crate::data::{Camera, Three};
 use crate::scene::{Scene, SceneTracer};
 use crossbeam::channel;
 use image::{Rgb, RgbImage};
 use indicatif::{ProgressBar, ProgressStyle};
-use num_traits::{cast, Float};
+use num_traits::{cast, Float, FloatConst};
 use rand::{prelude::Rng, SeedableRng};
 use rand_distr::{uniform::SampleUniform, Distribution, Standard};
 use rayon::prelude::*;
@@ -17,11 +18,125 @@ pub fn render<T, F, R>(
 ) -> RgbImage
 where
     T: SceneTracer<F> + Send + Sync + Default + 'static,
-    F: Float + SampleUniform + Send + Sync + AddAssign + 'static,
+    F: Float + FloatConst + SampleUniform + Send + Sync + AddAssign + 'static,
     R: Rng + SeedableRng,
     Standard: Distribution<F>,
     Three<F>: Into<Rgb<u8>>,
 {
+    render_seeded::<T, F, R>(tracer, scene, camera, num_samples, 0)
+}
+
+/// Like `render`, but with different random numbers for every `seed`. `render` uses seed 0.
+pub fn render_seeded<T, F, R>(
+    tracer: T,
+    scene: Scene<F>,
+    camera: Camera<F>,
+    num_samples: usize,
+    seed: u64,
+) -> RgbImage
+where
+    T: SceneTracer<F> + Send + Sync + Default + 'static,
+    F: Float + FloatConst + SampleUniform + Send + Sync + AddAssign + 'static,
+    R: Rng + SeedableRng,
+    Standard: Distribution<F>,
+    Three<F>: Into<Rgb<u8>>,
+{
+    let buffers = render_samples::<T, F, R>(tracer, scene, camera, num_samples, false, seed);
+    to_image(camera.width, camera.height, &buffers.color)
+}
+
+/// Like `render_seeded`, but also produces the first hit `Aovs` of every pixel in the same pass.
+pub fn render_with_aovs<T, F, R>(
+    tracer: T,
+    scene: Scene<F>,
+    camera: Camera<F>,
+    num_samples: usize,
+    seed: u64,
+) -> (RgbImage, Aovs<F>)
+where
+    T: SceneTracer<F> + Send + Sync + Default + 'static,
+    F: Float + FloatConst + SampleUniform + Send + Sync + AddAssign + 'static,
+    R: Rng + SeedableRng,
+    Standard: Distribution<F>,
+    Three<F>: Into<Rgb<u8>>,
+{
+    let buffers = render_buffers::<T, F, R>(tracer, scene, camera, num_samples, seed);
+    (buffers.image(), buffers.aovs)
+}
+
+/// Float results of a render, before they are turned into an image.
+#[derive(Debug, Clone)]
+pub struct RenderBuffers<F> {
+    pub width: usize,
+    pub height: usize,
+    /// Mean color of each pixel.
+    pub color: Vec<Three<F>>,
+    /// Variance of the mean luminance of each pixel, i.e. how noisy it still is. `None` if the
+    /// tracer splatted light onto other pixels, as then pixels aren't averages of their own
+    /// samples.
+    pub variance: Option<Vec<F>>,
+    pub aovs: Aovs<F>,
+}
+
+/// Like `render_with_aovs`, but keeps the colors as floats along with their variance, e.g. for
+/// `Denoiser`.
+pub fn render_buffers<T, F, R>(
+    tracer: T,
+    scene: Scene<F>,
+    camera: Camera<F>,
+    num_samples: usize,
+    seed: u64,
+) -> RenderBuffers<F>
+where
+    T: SceneTracer<F> + Send + Sync + Default + 'static,
+    F: Float + FloatConst + SampleUniform + Send + Sync + AddAssign + 'static,
+    R: Rng + SeedableRng,
+    Standard: Distribution<F>,
+{
+    let buffers = render_samples::<T, F, R>(tracer, scene, camera, num_samples, true, seed);
+    RenderBuffers {
+        width: camera.width,
+        height: camera.height,
+        color: buffers.color,
+        variance: buffers.variance,
+        aovs: buffers.aovs.unwrap(),
+    }
+}
+
+impl<F> RenderBuffers<F>
+where
+    F: Float,
+    Three<F>: Into<Rgb<u8>>,
+{
+    pub fn image(&self) -> RgbImage {
+        to_image(self.width, self.height, &self.color)
+    }
+}
+
+struct Samples<F> {
+    color: Vec<Three<F>>,
+    variance: Option<Vec<F>>,
+    aovs: Option<Aovs<F>>,
+}
+
+/// Mean color of every pixel, and its `Aovs` if `with_aovs` is set.
+fn render_samples<T, F, R>(
+    tracer: T,
+    mut scene: Scene<F>,
+    camera: Camera<F>,
+    num_samples: usize,
+    with_aovs: bool,
+    seed: u64,
+) -> Samples<F>
+where
+    T: SceneTracer<F> + Send + Sync + Default + 'static,
+    F: Float + FloatConst + SampleUniform + Send + Sync + AddAssign + 'static,
+    R: Rng + SeedableRng,
+    Standard: Distribution<F>,
+{
+    scene.build_light_sampler();
+    scene.build_bvh();
+
     let num_pixels = camera.width * camera.height;
     let num_rays = num_pixels * num_samples;
 
@@ -31,15 +146,22 @@ where
         (0..num_rays)
             .into_par_iter()
             .map(|ray_idx| {
-                let mut rng = R::seed_from_u64(ray_idx as u64);
+                let mut rng = seeded_rng::<R>(seed, ray_idx as u64);
                 let pixel_idx = ray_idx % num_pixels;
                 let y: F = cast(pixel_idx / camera.width).unwrap();
                 let x: F = cast(pixel_idx % camera.width).unwrap();
                 let jx = x + Standard.sample(&mut rng);
                 let jy = y + Standard.sample(&mut rng);
-                let ray = camera.ray_through(jx, jy);
-                let opt_color = tracer.trace(ray, &scene, &mut rng);
-                (pixel_idx, opt_color.unwrap_or(Three::zeros()))
+                let ray = camera.ray_through(jx, jy, camera.sample_time(&mut rng));
+                let aov = if with_aovs {
+                    AovSample::trace(&ray, &scene)
+                } else {
+                    None
+                };
+                let mut splats = Vec::new();
+                let opt_color =
+                    tracer.trace_with_splats(ray, &scene, &camera, &mut rng, &mut splats);
+                (ray_idx, opt_color.unwrap_or(Three::zeros()), splats, aov)
             })
             .for_each_with(sender, |s, x| s.send(x).unwrap());
     });
@@ -50,39 +172,151 @@ where
     pb.set_draw_rate(1); // NOTE: indicatif drawing is bottleneck with rayon because of high speeds
 
     let mut colors = camera.empty_image();
-    for (pixel_idx, color) in receiver.iter() {
+    let mut luminance_squares = vec![F::zero(); num_pixels];
+    let mut splatted = false;
+    let mut aovs = if with_aovs {
+        Some(Aovs::new(camera.width, camera.height))
+    } else {
+        None
+    };
+    for (ray_idx, color, splats, aov) in receiver.iter() {
+        let pixel_idx = ray_idx % num_pixels;
         colors[pixel_idx] += color;
+        luminance_squares[pixel_idx] += color.luminance() * color.luminance();
+        for (splat_idx, splat) in splats {
+            colors[splat_idx] += splat;
+            splatted = true;
+        }
+        if let (Some(aovs), Some(aov)) = (&mut aovs, aov) {
+            aovs.add(pixel_idx, &aov, ray_idx / num_pixels);
+        }
         pb.inc(1);
     }
 
     t.join().unwrap();
 
-    let mut img = RgbImage::new(camera.width as u32, camera.height as u32);
-    for x in 0..camera.width {
-        for y in 0..camera.height {
-            let mean_color = &colors[y * camera.width + x] / cast(num_samples).unwrap();
-            img.put_pixel(x as u32, y as u32, mean_color.into());
+    let n: F = cast(num_samples).unwrap();
+    for color in colors.iter_mut() {
+        *color = *color / n;
+    }
+    // sample variance of the luminance, divided by n again for the variance of the mean
+    let variance = (!splatted).then(|| {
+        colors
+            .iter()
+            .zip(luminance_squares.iter())
+            .map(|(mean, &squares)| {
+                if num_samples > 1 {
+                    let mean = mean.luminance();
+                    ((squares - n * mean * mean) / (n - F::one())).max(F::zero()) / n
+                } else {
+                    F::zero()
+                }
+            })
+            .collect()
+    });
+    if let Some(aovs) = &mut aovs {
+        aovs.finish();
+    }
+    Samples {
+        color: colors,
+        variance,
+        aovs,
+    }
+}
+
+/// Random number generator for the sample numbered `idx` of a render with the given `seed`.
+/// Seed 0 seeds it with `idx` itself.
+pub(crate) fn seeded_rng<R: SeedableRng>(seed: u64, idx: u64) -> R {
+    // spread the bits of nearby seeds apart, so they don't just shift which samples get which
+    // random numbers
+    R::seed_from_u64(idx ^ seed.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+}
+
+pub(crate) fn to_image<F>(width: usize, height: usize, colors: &[Three<F>]) -> RgbImage
+where
+    F: Float,
+    Three<F>: Into<Rgb<u8>>,
+{
+    let mut img = RgbImage::new(width as u32, height as u32);
+    for x in 0..width {
+        for y in 0..height {
+            img.put_pixel(x as u32, y as u32, colors[y * width + x].into());
         }
     }
     img
 }
 
-impl Into<Rgb<u8>> for Three<f32> {
-    fn into(self) -> Rgb<u8> {
+impl From<Three<f32>> for Rgb<u8> {
+    fn from(c: Three<f32>) -> Self {
         Rgb([
-            (self.x.clamp(0.0, 1.0) * 255.0).round() as u8,
-            (self.y.clamp(0.0, 1.0) * 255.0).round() as u8,
-            (self.z.clamp(0.0, 1.0) * 255.0).round() as u8,
+            (c.x.clamp(0.0, 1.0) * 255.0).round() as u8,
+            (c.y.clamp(0.0, 1.0) * 255.0).round() as u8,
+            (c.z.clamp(0.0, 1.0) * 255.0).round() as u8,
         ])
     }
 }
 
-impl Into<Rgb<u8>> for Three<f64> {
-    fn into(self) -> Rgb<u8> {
+impl From<Three<f64>> for Rgb<u8> {
+    fn from(c: Three<f64>) -> Self {
         Rgb([
-            (self.x.clamp(0.0, 1.0) * 255.0).round() as u8,
-            (self.y.clamp(0.0, 1.0) * 255.0).round() as u8,
-            (self.z.clamp(0.0, 1.0) * 255.0).round() as u8,
+            (c.x.clamp(0.0, 1.0) * 255.0).round() as u8,
+            (c.y.clamp(0.0, 1.0) * 255.0).round() as u8,
+            (c.z.clamp(0.0, 1.0) * 255.0).round() as u8,
         ])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bidirectional::BidirectionalPathTracer;
+    use crate::data::{Diffuse, FieldOfView, ImageShape, Light};
+    use crate::shapes::Sphere;
+    use crate::tracer::PathTracer;
+    use rand_xorshift::XorShiftRng;
+
+    fn buffers<T>(tracer: T, seed: u64) -> RenderBuffers<f64>
+    where
+        T: SceneTracer<f64> + Send + Sync + Default + 'static,
+    {
+        let mut scene = Scene::new();
+        let gray = scene.add_material(Diffuse::rgb(0.5, 0.5, 0.5));
+        let light = scene.add_material(Light::new(Three::ones(), 4.0));
+        scene.add_object(Sphere::unit_at(0.0, 0.0, -3.0), gray);
+        scene.add_object(Sphere::new(Three::new(0.0, 3.0, -3.0), 0.5), light);
+        let camera = Camera::new(
+            FieldOfView::Degrees(60.0),
+            ImageShape {
+                width: 4,
+                height: 4,
+            },
+        );
+        render_buffers::<_, f64, XorShiftRng>(tracer, scene, camera, 2, seed)
+    }
+
+    fn colors(seed: u64) -> Vec<Three<f64>> {
+        let tracer = PathTracer {
+            depth: 4,
+            ..Default::default()
+        };
+        buffers(tracer, seed).color
+    }
+
+    #[test]
+    fn buffers_follow_the_seed() {
+        assert_eq!(colors(1), colors(1));
+        assert_ne!(colors(1), colors(2));
+    }
+
+    #[test]
+    fn splatted_renders_have_no_variance() {
+        let tracer = PathTracer {
+            depth: 4,
+            ..Default::default()
+        };
+        assert!(buffers(tracer, 1).variance.is_some());
+        // light splatted from other pixels' paths isn't counted in a pixel's own samples
+        let tracer = BidirectionalPathTracer { depth: 4 };
+        assert!(buffers(tracer, 1).variance.is_none());
+    }
+}
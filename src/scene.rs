@@ -1,21 +1,66 @@
 use crate::{
-    data::{CanHit, Hit, Material, Ray, Three},
-    shapes::Object,
+    bvh::Bvh,
+    data::{Aabb, Bounded, Camera, CanHit, Hit, Light, Material, Ray, Surface, Three},
+    debug::count_intersection_test,
+    light_sampling::{
+        emitted_power, Emitter, LightSampler, LightSamplers, LightSelection, PowerLightSampler,
+    },
+    lights::{DeltaLight, EmissionSample, Environment, EnvironmentSampler},
+    pdf::{CosineHemisphereDistribution, HemisphereDistribution},
+    scene_graph::Node,
+    shapes::{Instance, Object},
+    transform::Transform,
 };
-use num_traits::Float;
+use num_traits::{Float, FloatConst};
 use rand::Rng;
+use rand_distr::{uniform::SampleUniform, Distribution, Standard};
+use std::collections::HashMap;
 
 pub trait SceneTracer<F> {
     fn trace<R>(&self, ray: Ray<F>, scene: &Scene<F>, rng: &mut R) -> Option<Three<F>>
     where
         R: Rng;
+
+    /// Like `trace`, but tracers that follow light from the lights to the camera can also add
+    /// light to other pixels of the image, by pushing `(pixel_idx, color)` onto `splats`.
+    fn trace_with_splats<R>(
+        &self,
+        ray: Ray<F>,
+        scene: &Scene<F>,
+        _camera: &Camera<F>,
+        rng: &mut R,
+        _splats: &mut Vec<(usize, Three<F>)>,
+    ) -> Option<Three<F>>
+    where
+        R: Rng,
+    {
+        self.trace(ray, scene, rng)
+    }
 }
 
+#[derive(Clone)]
 pub struct Scene<F> {
     objects: Vec<Object<F>>,
     emissive_objects: Vec<(usize, Object<F>)>,
+    object_emissive_idx: Vec<Option<usize>>,
     object_material_idx: Vec<MaterialIdx>,
     materials: Vec<Material<F>>,
+    material_names: HashMap<String, MaterialIdx>,
+    object_names: HashMap<String, usize>,
+    lights: Vec<DeltaLight<F>>,
+    environment: Option<Environment<F>>,
+    environment_sampler: Option<EnvironmentSampler<F>>,
+    light_selection: LightSelection,
+    light_sampler: Option<LightSamplers<F>>,
+    emitter_sampler: Option<PowerLightSampler<F>>,
+    /// Top level hierarchy over the objects, from `build_bvh`.
+    bvh: Option<Bvh<F>>,
+    /// Indices of the objects in `bvh`, by their index in it.
+    bounded_objects: Vec<usize>,
+    /// Objects without finite bounds, like planes, which every ray is tested against.
+    unbounded_objects: Vec<usize>,
+    /// Whether instances moved since `bvh` was built or refit.
+    bvh_outdated: bool,
 }
 
 impl<F> Scene<F>
@@ -26,8 +71,21 @@ where
         Self {
             objects: Vec::new(),
             emissive_objects: Vec::new(),
+            object_emissive_idx: Vec::new(),
             object_material_idx: Vec::new(),
             materials: Vec::new(),
+            material_names: HashMap::new(),
+            object_names: HashMap::new(),
+            lights: Vec::new(),
+            environment: None,
+            environment_sampler: None,
+            light_selection: LightSelection::default(),
+            light_sampler: None,
+            emitter_sampler: None,
+            bvh: None,
+            bounded_objects: Vec::new(),
+            unbounded_objects: Vec::new(),
+            bvh_outdated: false,
         }
     }
 
@@ -37,44 +95,425 @@ where
         idx
     }
 
+    /// Adds a material that `material_named` finds by `name`.
+    pub fn add_named_material<S, M>(&mut self, name: S, material: M) -> MaterialIdx
+    where
+        S: Into<String>,
+        M: Into<Material<F>>,
+    {
+        let idx = self.add_material(material);
+        self.material_names.insert(name.into(), idx);
+        idx
+    }
+
+    /// The last material added with this name.
+    pub fn material_named(&self, name: &str) -> Option<MaterialIdx> {
+        self.material_names.get(name).copied()
+    }
+
+    /// Index of the last object added with this name.
+    pub fn object_named(&self, name: &str) -> Option<usize> {
+        self.object_names.get(name).copied()
+    }
+
+    pub fn add_light<L: Into<DeltaLight<F>>>(&mut self, light: L) {
+        self.lights.push(light.into());
+        self.emitter_sampler = None;
+    }
+
+    /// Sets the light rays that leave the scene see.
+    pub fn set_environment(&mut self, environment: Environment<F>) {
+        self.environment = Some(environment);
+        self.environment_sampler = None;
+    }
+
+    /// Chooses how `build_light_sampler` picks emissive objects for direct lighting.
+    pub fn set_light_selection(&mut self, selection: LightSelection) {
+        self.light_selection = selection;
+        self.light_sampler = None;
+    }
+
+    pub fn object(&self, obj_idx: usize) -> &Object<F> {
+        &self.objects[obj_idx]
+    }
+
+    pub fn material_idx(&self, obj_idx: usize) -> MaterialIdx {
+        self.object_material_idx[obj_idx]
+    }
+
+    pub fn material_for(&self, obj_idx: usize) -> &Material<F> {
+        let mat_idx = self.object_material_idx[obj_idx];
+        &self.materials[mat_idx.0]
+    }
+
+    pub fn material(&self, mat_idx: MaterialIdx) -> &Material<F> {
+        &self.materials[mat_idx.0]
+    }
+
+    pub fn emissive_objects(&self) -> &[(usize, Object<F>)] {
+        &self.emissive_objects
+    }
+
+    /// Index into `emissive_objects` of the object at `obj_idx`, if it is emissive.
+    pub fn emissive_index(&self, obj_idx: usize) -> Option<usize> {
+        self.object_emissive_idx[obj_idx]
+    }
+
+    /// The sampler built by the last call to `build_light_sampler`. This is `None` if objects
+    /// were added since, in which case tracers only find lights by hitting them.
+    pub fn light_sampler(&self) -> Option<&LightSamplers<F>> {
+        self.light_sampler.as_ref()
+    }
+
+    pub fn lights(&self) -> &[DeltaLight<F>] {
+        &self.lights
+    }
+
+    pub fn environment(&self) -> Option<&Environment<F>> {
+        self.environment.as_ref()
+    }
+
+    /// The sampler for the environment built by the last call to `build_light_sampler`, or
+    /// `None` if the environment was set since.
+    pub fn environment_sampler(&self) -> Option<&EnvironmentSampler<F>> {
+        self.environment_sampler.as_ref()
+    }
+}
+
+impl<F> Scene<F>
+where
+    F: Float,
+{
+    /// Adds an object made of the material at `mat_idx`.
+    ///
+    /// Panics if the material is a light but points can't be sampled uniformly on the object,
+    /// see `Object::check_light_sampling`.
     pub fn add_object<O: Into<Object<F>>>(&mut self, obj: O, mat_idx: MaterialIdx) {
         let obj = obj.into();
         let obj_idx = self.objects.len();
+        if self.materials[mat_idx.0].is_emissive() {
+            if let Err(reason) = obj.check_light_sampling() {
+                panic!("object {} can't be a light: {}", obj_idx, reason);
+            }
+        }
         self.objects.push(obj.clone());
         self.object_material_idx.push(mat_idx);
         if self.material_for(obj_idx).is_emissive() {
+            self.object_emissive_idx
+                .push(Some(self.emissive_objects.len()));
             self.emissive_objects.push((obj_idx, obj));
+        } else {
+            self.object_emissive_idx.push(None);
         }
+        self.light_sampler = None;
+        self.emitter_sampler = None;
+        self.bvh = None;
     }
 
-    pub fn material_for(&self, obj_idx: usize) -> &Material<F> {
-        let mat_idx = self.object_material_idx[obj_idx];
-        &self.materials[mat_idx.0]
+    /// Adds an object that `object_named` finds by `name`.
+    pub fn add_named_object<S, O>(&mut self, name: S, obj: O, mat_idx: MaterialIdx)
+    where
+        S: Into<String>,
+        O: Into<Object<F>>,
+    {
+        self.object_names.insert(name.into(), self.objects.len());
+        self.add_object(obj, mat_idx);
     }
 
-    pub fn emissive_objects(&self) -> &[(usize, Object<F>)] {
-        &self.emissive_objects
+    /// Adds the shapes of a tree of nodes, each as an `Instance` placed by the transforms of
+    /// its node and the nodes above it. `object_named` finds them by the same paths that
+    /// `root.find` finds their nodes by, like `"house/door"`, which leave out the root's name.
+    pub fn add_node(&mut self, root: &Node<F>) {
+        root.visit_objects(|path, object, transform, material| {
+            let instance = Instance::new(object.clone(), *transform);
+            self.add_named_object(path, instance, material);
+        });
+    }
+
+    /// Moves the instance at `obj_idx`, e.g. between frames of an animation. The next
+    /// `build_bvh` refits the hierarchy around it instead of building it again, so to render
+    /// frames call it before handing `render` a clone of the scene.
+    ///
+    /// Panics if the object isn't an `Instance`, or if it's a light and the transform doesn't
+    /// scale it evenly.
+    pub fn set_instance_transform(&mut self, obj_idx: usize, transform: Transform<F>) {
+        let set = |obj: &mut Object<F>| match obj {
+            Object::Instance(instance) => instance.transform = transform,
+            _ => panic!("object {} isn't an instance", obj_idx),
+        };
+        set(&mut self.objects[obj_idx]);
+        if let Some(emissive_idx) = self.object_emissive_idx[obj_idx] {
+            if let Err(reason) = self.objects[obj_idx].check_light_sampling() {
+                panic!("object {} can't be a light: {}", obj_idx, reason);
+            }
+            set(&mut self.emissive_objects[emissive_idx].1);
+            self.light_sampler = None;
+            self.emitter_sampler = None;
+        }
+        self.bvh_outdated = true;
+    }
+
+    /// Builds the top level bounding volume hierarchy over the objects that rays go through, or
+    /// refits it if only instances moved since it was built. `render` calls this automatically,
+    /// and until it's called rays are tested against every object.
+    ///
+    /// Meshes have hierarchies of their own, and instances of them transform rays into the
+    /// mesh's space to go through its hierarchy.
+    pub fn build_bvh(&mut self) {
+        let bounds: Vec<Aabb<F>> = self.objects.iter().map(|obj| obj.bounds()).collect();
+        match &mut self.bvh {
+            Some(bvh) if self.bvh_outdated => {
+                let bounded: Vec<Aabb<F>> =
+                    self.bounded_objects.iter().map(|&i| bounds[i]).collect();
+                bvh.refit(&bounded);
+            }
+            Some(_) => {}
+            None => {
+                let (bounded, unbounded): (Vec<usize>, Vec<usize>) =
+                    (0..self.objects.len()).partition(|&i| bounds[i].is_finite());
+                let bounded_bounds: Vec<Aabb<F>> = bounded.iter().map(|&i| bounds[i]).collect();
+                self.bvh = Some(Bvh::new(&bounded_bounds));
+                self.bounded_objects = bounded;
+                self.unbounded_objects = unbounded;
+            }
+        }
+        self.bvh_outdated = false;
+    }
+}
+
+impl<F> Scene<F>
+where
+    F: Float + FloatConst + SampleUniform,
+    Standard: Distribution<F>,
+{
+    /// Builds the light sampler used for direct lighting from the current emissive objects and
+    /// environment, and the distribution light paths are started from. `render` calls this
+    /// automatically.
+    pub fn build_light_sampler(&mut self) {
+        let emitters: Vec<(&Object<F>, &Light<F>)> = self
+            .emissive_objects
+            .iter()
+            .map(|(obj_idx, obj)| (obj, self.light_of(*obj_idx)))
+            .collect();
+        let light_sampler = LightSamplers::build(self.light_selection, &emitters);
+
+        let powers: Vec<F> = emitters
+            .iter()
+            .map(|(obj, light)| emitted_power(obj, light))
+            .chain(self.lights.iter().map(|light| light.emitted_power()))
+            .collect();
+        self.light_sampler = Some(light_sampler);
+        self.emitter_sampler = Some(PowerLightSampler::new(&powers));
+        self.environment_sampler = self.environment.as_ref().map(EnvironmentSampler::new);
+    }
+
+    fn light_of(&self, obj_idx: usize) -> &Light<F> {
+        match self.material_for(obj_idx) {
+            Material::Light(light) => light,
+            _ => unreachable!(),
+        }
+    }
+
+    fn emitter_idx(&self, emitter: Emitter) -> usize {
+        match emitter {
+            Emitter::Area(light_idx) => light_idx,
+            Emitter::Delta(light_idx) => self.emissive_objects.len() + light_idx,
+        }
+    }
+
+    /// Chooses a light to start a light path from, proportional to its emitted power, using the
+    /// uniform random number `u`. Returns the light and the probability it had of being chosen.
+    pub fn sample_emitter(&self, u: F) -> Option<(Emitter, F)> {
+        let sampler = self.emitter_sampler.as_ref()?;
+        let (idx, pmf) = sampler.sample(&Three::zeros(), u)?;
+        let num_area = self.emissive_objects.len();
+        let emitter = if idx < num_area {
+            Emitter::Area(idx)
+        } else {
+            Emitter::Delta(idx - num_area)
+        };
+        Some((emitter, pmf))
+    }
+
+    /// Probability that `sample_emitter` chooses `emitter`.
+    pub fn emitter_pmf(&self, emitter: Emitter) -> F {
+        match &self.emitter_sampler {
+            Some(sampler) => sampler.pmf(&Three::zeros(), self.emitter_idx(emitter)),
+            None => F::zero(),
+        }
+    }
+
+    /// Samples a ray of light leaving `emitter` at `time`. Emissive objects emit from a uniformly
+    /// random point on their surface where they are then, in a cosine weighted direction.
+    pub fn sample_emission<R: Rng>(
+        &self,
+        emitter: Emitter,
+        time: F,
+        rng: &mut R,
+    ) -> Option<EmissionSample<F>> {
+        let light_idx = match emitter {
+            Emitter::Area(light_idx) => light_idx,
+            Emitter::Delta(light_idx) => return self.lights[light_idx].sample_emission(rng),
+        };
+        let (obj_idx, obj) = &self.emissive_objects[light_idx];
+        let light = self.light_of(*obj_idx);
+        let placed = obj.at_time(time);
+        let origin = placed.sample_point_on_surface(rng);
+        let mut normal = placed.normal_at_point(&origin);
+        let mut pdf_direction = F::one();
+        if light.two_sided {
+            // pick a side to emit from
+            pdf_direction = F::from(0.5f64).unwrap();
+            if rng.gen_bool(0.5) {
+                normal = -normal;
+            }
+        }
+        let dist = CosineHemisphereDistribution::oriented_towards(normal);
+        let direction = dist.sample(rng);
+        pdf_direction = pdf_direction * dist.pdf(&direction);
+        Some(EmissionSample {
+            origin,
+            normal: Some(normal),
+            direction,
+            radiance: light.radiance(obj.area(), placed.uv_at_point(&origin)),
+            pdf_position: obj.area().recip(),
+            pdf_direction,
+        })
+    }
+
+    /// Densities with which `sample_emission` would emit from `point` on `emitter` along
+    /// `direction` at `time`, as `(pdf_position, pdf_direction)`.
+    pub fn emission_pdf(
+        &self,
+        emitter: Emitter,
+        point: &Three<F>,
+        direction: &Three<F>,
+        time: F,
+    ) -> (F, F) {
+        let light_idx = match emitter {
+            Emitter::Area(light_idx) => light_idx,
+            Emitter::Delta(light_idx) => {
+                return (F::one(), self.lights[light_idx].emission_pdf(direction))
+            }
+        };
+        let (obj_idx, obj) = &self.emissive_objects[light_idx];
+        let light = self.light_of(*obj_idx);
+        let normal = obj.at_time(time).normal_at_point(point);
+        let cos_theta = direction.dot(&normal);
+        let pdf_direction = if light.two_sided {
+            cos_theta.abs() * F::FRAC_1_PI() * F::from(0.5f64).unwrap()
+        } else {
+            cos_theta.max(F::zero()) * F::FRAC_1_PI()
+        };
+        (obj.area().recip(), pdf_direction)
+    }
+}
+
+impl<F> Default for Scene<F>
+where
+    F: Clone,
+{
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl<F> CanHit<Scene<F>, F> for Ray<F>
 where
-    F: Float,
+    F: Float + FloatConst,
 {
     fn shoot_at(&self, scene: &Scene<F>, t_min: F, mut t_max: F) -> Option<Hit<F>> {
-        let mut opt_hit = None;
-        for (i, obj) in scene.objects.iter().enumerate() {
-            if let Some(mut hit) = self.shoot_at(obj, t_min, t_max) {
-                if hit.distance < t_max {
-                    hit.object_index = i;
-                    opt_hit = Some(hit);
-                    t_max = hit.distance;
+        // the hierarchy counts the tests it makes itself
+        let shoot_at_object = |obj_idx: usize, t_max: F| {
+            self.shoot_at(&scene.objects[obj_idx], t_min, t_max)
+                .filter(|hit| hit.distance < t_max)
+                .map(|hit| Hit {
+                    object_index: obj_idx,
+                    ..hit
+                })
+        };
+        let bvh = match &scene.bvh {
+            Some(bvh) => bvh,
+            None => {
+                let mut opt_hit = None;
+                for obj_idx in 0..scene.objects.len() {
+                    count_intersection_test();
+                    if let Some(hit) = shoot_at_object(obj_idx, t_max) {
+                        t_max = hit.distance;
+                        opt_hit = Some(hit);
+                    }
                 }
+                return opt_hit;
+            }
+        };
+        let mut opt_hit = None;
+        for &obj_idx in &scene.unbounded_objects {
+            count_intersection_test();
+            if let Some(hit) = shoot_at_object(obj_idx, t_max) {
+                t_max = hit.distance;
+                opt_hit = Some(hit);
             }
         }
-        opt_hit
+        bvh.shoot(self, t_min, t_max, |i, t_max| {
+            shoot_at_object(scene.bounded_objects[i], t_max)
+        })
+        .or(opt_hit)
     }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct MaterialIdx(usize);
+
+impl MaterialIdx {
+    /// Position of the material in the order materials were added to the scene.
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Diffuse;
+    use crate::shapes::Sphere;
+    use std::sync::Arc;
+
+    fn hit_index(scene: &Scene<f64>, origin: Three<f64>) -> Option<usize> {
+        let ray = Ray::new(origin, Three::new(0.0, 0.0, -1.0));
+        ray.shoot_at(scene, 1e-9, f64::INFINITY)
+            .map(|hit| hit.object_index)
+    }
+
+    #[test]
+    fn moved_instances_are_hit_where_they_moved_to() {
+        let mut scene = Scene::<f64>::new();
+        let gray = scene.add_material(Diffuse::rgb(0.5, 0.5, 0.5));
+        let ball = Arc::new(Object::from(Sphere::new(Three::zeros(), 1.0)));
+        for i in 0..10 {
+            let offset = Three::new(3.0 * i as f64, 0.0, 0.0);
+            scene.add_object(
+                Instance::new(ball.clone(), Transform::translation(offset)),
+                gray,
+            );
+        }
+        scene.build_bvh();
+        let old_place = Three::new(9.0, 0.0, 10.0);
+        assert_eq!(hit_index(&scene, old_place), Some(3));
+
+        scene.set_instance_transform(3, Transform::translation(Three::new(0.0, 100.0, 0.0)));
+        scene.build_bvh();
+        assert_eq!(hit_index(&scene, old_place), None);
+        assert_eq!(hit_index(&scene, Three::new(0.0, 100.0, 10.0)), Some(3));
+        // the others stay where they were
+        assert_eq!(hit_index(&scene, Three::new(12.0, 0.0, 10.0)), Some(4));
+    }
+
+    #[test]
+    #[should_panic(expected = "isn't an instance")]
+    fn only_instances_can_be_moved() {
+        let mut scene = Scene::<f64>::new();
+        let gray = scene.add_material(Diffuse::rgb(0.5, 0.5, 0.5));
+        scene.add_object(Sphere::new(Three::zeros(), 1.0), gray);
+        scene.set_instance_transform(0, Transform::translation(Three::ones()));
+    }
+}
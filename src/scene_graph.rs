@@ -0,0 +1,234 @@
+//! Assembling scenes from named, nested parts.
+
+use crate::scene::MaterialIdx;
+use crate::shapes::Object;
+use crate::transform::Transform;
+use num_traits::Float;
+use std::sync::Arc;
+
+/// A named part of a scene, placed relative to its parent, with an optional shape and any
+/// number of children. `Scene::add_node` adds the shapes of a whole tree of nodes.
+///
+/// Nodes are found by paths of names from a node down, without the node's own name, e.g.
+/// `"house/door"` for the door of a house below the root. The empty path is the node itself.
+///
+/// Shapes are shared, so cloning a node to place an asset again doesn't copy its meshes.
+#[derive(Debug, Clone)]
+pub struct Node<F> {
+    pub name: String,
+    /// From the node's space to its parent's.
+    pub transform: Transform<F>,
+    pub object: Option<(Arc<Object<F>>, MaterialIdx)>,
+    /// Replaces the materials of the node's shape and everything below it, unless a node closer
+    /// to a shape replaces them again.
+    pub material: Option<MaterialIdx>,
+    pub children: Vec<Node<F>>,
+}
+
+impl<F> Node<F>
+where
+    F: Float,
+{
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            transform: Transform::identity(),
+            object: None,
+            material: None,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn with_transform(self, transform: Transform<F>) -> Self {
+        Self { transform, ..self }
+    }
+
+    pub fn with_object<O: Into<Object<F>>>(self, object: O, material: MaterialIdx) -> Self {
+        self.with_shared_object(Arc::new(object.into()), material)
+    }
+
+    /// Like `with_object`, for a shape that other nodes or instances use too.
+    pub fn with_shared_object(self, object: Arc<Object<F>>, material: MaterialIdx) -> Self {
+        Self {
+            object: Some((object, material)),
+            ..self
+        }
+    }
+
+    /// Overrides the materials of everything in this part of the scene.
+    pub fn with_material(self, material: MaterialIdx) -> Self {
+        Self {
+            material: Some(material),
+            ..self
+        }
+    }
+
+    pub fn with_child(mut self, child: Node<F>) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    /// The node at a `/` separated path of names below this one, e.g. `"house/door"`, or this
+    /// node for `""`.
+    pub fn find(&self, path: &str) -> Option<&Node<F>> {
+        if path.is_empty() {
+            return Some(self);
+        }
+        path.split('/').try_fold(self, |node, name| {
+            node.children.iter().find(|child| child.name == name)
+        })
+    }
+
+    pub fn find_mut(&mut self, path: &str) -> Option<&mut Node<F>> {
+        if path.is_empty() {
+            return Some(self);
+        }
+        path.split('/').try_fold(self, |node, name| {
+            node.children.iter_mut().find(|child| child.name == name)
+        })
+    }
+
+    /// Calls `visit` with every shape in the tree, the path `find` finds its node by, its
+    /// transform to this node's parent's space, and the material it's drawn with.
+    pub fn visit_objects<V>(&self, mut visit: V)
+    where
+        V: FnMut(&str, &Arc<Object<F>>, &Transform<F>, MaterialIdx),
+    {
+        self.visit_below("", &Transform::identity(), None, &mut visit);
+    }
+
+    fn visit_below<V>(
+        &self,
+        path: &str,
+        parent_transform: &Transform<F>,
+        parent_material: Option<MaterialIdx>,
+        visit: &mut V,
+    ) where
+        V: FnMut(&str, &Arc<Object<F>>, &Transform<F>, MaterialIdx),
+    {
+        let transform = *parent_transform * self.transform;
+        let material_override = self.material.or(parent_material);
+        if let Some((object, material)) = &self.object {
+            visit(
+                path,
+                object,
+                &transform,
+                material_override.unwrap_or(*material),
+            );
+        }
+        for child in &self.children {
+            let child_path = if path.is_empty() {
+                child.name.clone()
+            } else {
+                format!("{}/{}", path, child.name)
+            };
+            child.visit_below(&child_path, &transform, material_override, visit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Bounded, Diffuse, Three};
+    use crate::scene::Scene;
+    use crate::shapes::Sphere;
+
+    fn ball() -> Sphere<f64> {
+        Sphere::new(Three::zeros(), 1.0)
+    }
+
+    /// A street with a house with a door, each a unit ball of its own material.
+    fn street(scene: &mut Scene<f64>) -> Node<f64> {
+        let [street, house, door] = ["street", "house", "door"]
+            .map(|name| scene.add_named_material(name, Diffuse::rgb(0.5, 0.5, 0.5)));
+        Node::new("street")
+            .with_object(ball(), street)
+            .with_transform(Transform::translation(Three::new(1.0, 0.0, 0.0)))
+            .with_child(
+                Node::new("house")
+                    .with_object(ball(), house)
+                    .with_transform(Transform::scaling(Three::from(2.0)))
+                    .with_child(
+                        Node::new("door")
+                            .with_object(ball(), door)
+                            .with_transform(Transform::translation(Three::new(0.0, 1.0, 0.0))),
+                    ),
+            )
+    }
+
+    fn materials_by_path(root: &Node<f64>) -> Vec<(String, MaterialIdx)> {
+        let mut materials = Vec::new();
+        root.visit_objects(|path, _, _, material| materials.push((path.to_string(), material)));
+        materials
+    }
+
+    #[test]
+    fn transforms_compose_down_the_tree() {
+        let mut scene = Scene::new();
+        let root = street(&mut scene);
+        let mut transforms = Vec::new();
+        root.visit_objects(|path, _, transform, _| {
+            transforms.push((path.to_string(), transform.point(&Three::zeros())));
+        });
+        let expected = [
+            ("", Three::new(1.0, 0.0, 0.0)),
+            ("house", Three::new(1.0, 0.0, 0.0)),
+            // moved up a unit in the house's space, which is twice as big
+            ("house/door", Three::new(1.0, 2.0, 0.0)),
+        ];
+        assert_eq!(transforms.len(), expected.len());
+        for ((path, center), (expected_path, expected_center)) in transforms.iter().zip(expected) {
+            assert_eq!(path, expected_path);
+            assert!((*center - expected_center).length() < 1e-9, "{:?}", center);
+        }
+    }
+
+    #[test]
+    fn the_closest_material_override_wins() {
+        let mut scene = Scene::new();
+        let red = scene.add_material(Diffuse::rgb(1.0, 0.0, 0.0));
+        let green = scene.add_material(Diffuse::rgb(0.0, 1.0, 0.0));
+        let mut root = street(&mut scene);
+        let own = |name| scene.material_named(name).unwrap();
+        let [street, house, door] = ["street", "house", "door"].map(own);
+        assert_eq!(
+            materials_by_path(&root),
+            [
+                ("".to_string(), street),
+                ("house".to_string(), house),
+                ("house/door".to_string(), door),
+            ]
+        );
+
+        root.material = Some(red);
+        root.find_mut("house/door").unwrap().material = Some(green);
+        assert_eq!(
+            materials_by_path(&root),
+            [
+                ("".to_string(), red),
+                ("house".to_string(), red),
+                ("house/door".to_string(), green),
+            ]
+        );
+    }
+
+    #[test]
+    fn objects_are_named_by_the_paths_that_find_their_nodes() {
+        let mut scene = Scene::new();
+        let root = street(&mut scene);
+        scene.add_node(&root);
+        for path in ["", "house", "house/door"] {
+            let node = root.find(path).unwrap();
+            let obj_idx = scene.object_named(path).unwrap();
+            assert_eq!(scene.material_idx(obj_idx), node.object.as_ref().unwrap().1);
+        }
+        let door = scene.object_named("house/door").unwrap();
+        let bounds = scene.object(door).bounds();
+        assert!((bounds.center() - Three::new(1.0, 2.0, 0.0)).length() < 1e-9);
+        // the root's name isn't part of the paths
+        assert!(root.find("street/house").is_none());
+        assert!(scene.object_named("street/house").is_none());
+        assert!(root.find("house/window").is_none());
+    }
+}
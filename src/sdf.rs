@@ -0,0 +1,219 @@
+//! Signed distance functions to build `SdfShape`s from, and ways to combine them.
+//!
+//! Each function returns a closure from a point to its distance from the shape, negative
+//! inside. Unless noted otherwise they're exact distances, or at least never overestimate them,
+//! so shapes made of them have a Lipschitz bound of 1.
+
+use crate::data::Three;
+use num_traits::Float;
+
+pub fn sphere<F: Float>(center: Three<F>, radius: F) -> impl Fn(&Three<F>) -> F + Clone {
+    move |p| (*p - center).length() - radius
+}
+
+/// A box of half its size along each axis around `center`, with edges rounded off by
+/// `rounding`, which is included in the size.
+pub fn round_box<F: Float>(
+    center: Three<F>,
+    half_size: Three<F>,
+    rounding: F,
+) -> impl Fn(&Three<F>) -> F + Clone {
+    move |p| {
+        let offset = *p - center;
+        let q = Three::new(
+            offset.x.abs() - half_size.x + rounding,
+            offset.y.abs() - half_size.y + rounding,
+            offset.z.abs() - half_size.z + rounding,
+        );
+        let outside = Three::new(q.x.max(F::zero()), q.y.max(F::zero()), q.z.max(F::zero()));
+        outside.length() + q.x.max(q.y).max(q.z).min(F::zero()) - rounding
+    }
+}
+
+/// A ring around `center`, lying flat in the xz plane.
+pub fn torus<F: Float>(
+    center: Three<F>,
+    major_radius: F,
+    minor_radius: F,
+) -> impl Fn(&Three<F>) -> F + Clone {
+    move |p| {
+        let offset = *p - center;
+        let ring = (offset.x * offset.x + offset.z * offset.z).sqrt() - major_radius;
+        (ring * ring + offset.y * offset.y).sqrt() - minor_radius
+    }
+}
+
+/// A line segment from `a` to `b`, thickened by `radius`.
+pub fn capsule<F: Float>(a: Three<F>, b: Three<F>, radius: F) -> impl Fn(&Three<F>) -> F + Clone {
+    move |p| {
+        let (pa, ba) = (*p - a, b - a);
+        let along = (pa.dot(&ba) / ba.length_squared())
+            .max(F::zero())
+            .min(F::one());
+        (pa - ba * along).length() - radius
+    }
+}
+
+/// The power 8 Mandelbulb fractal, about 1.2 across around the origin, found in `iterations`
+/// steps. This is an estimate that can be a little too large close to the surface, so give
+/// shapes of it a Lipschitz bound of around 2.
+pub fn mandelbulb<F: Float>(iterations: usize) -> impl Fn(&Three<F>) -> F + Clone {
+    let power = F::from(8.0f64).unwrap();
+    let escape = F::from(2.0f64).unwrap();
+    move |p| {
+        let mut z = *p;
+        let mut derivative = F::one();
+        let mut radius = z.length();
+        for _ in 0..iterations {
+            if radius > escape {
+                break;
+            }
+            // raise z to the power in spherical coordinates, then add the point again
+            let theta = (z.z / radius).acos() * power;
+            let phi = z.y.atan2(z.x) * power;
+            derivative = radius.powf(power - F::one()) * power * derivative + F::one();
+            let scaled = radius.powf(power);
+            z = Three::new(
+                theta.sin() * phi.cos(),
+                theta.sin() * phi.sin(),
+                theta.cos(),
+            ) * scaled
+                + *p;
+            radius = z.length();
+        }
+        F::from(0.5f64).unwrap() * radius.ln() * radius / derivative
+    }
+}
+
+/// Everything inside either shape.
+pub fn union<F: Float, A, B>(a: A, b: B) -> impl Fn(&Three<F>) -> F + Clone
+where
+    A: Fn(&Three<F>) -> F + Clone,
+    B: Fn(&Three<F>) -> F + Clone,
+{
+    move |p| a(p).min(b(p))
+}
+
+/// What's inside both shapes.
+pub fn intersection<F: Float, A, B>(a: A, b: B) -> impl Fn(&Three<F>) -> F + Clone
+where
+    A: Fn(&Three<F>) -> F + Clone,
+    B: Fn(&Three<F>) -> F + Clone,
+{
+    move |p| a(p).max(b(p))
+}
+
+/// `a` with `b` cut out of it.
+pub fn subtraction<F: Float, A, B>(a: A, b: B) -> impl Fn(&Three<F>) -> F + Clone
+where
+    A: Fn(&Three<F>) -> F + Clone,
+    B: Fn(&Three<F>) -> F + Clone,
+{
+    move |p| a(p).max(-b(p))
+}
+
+/// Like `union`, but blending the shapes together where they're closer than `smoothness`.
+pub fn smooth_union<F: Float, A, B>(a: A, b: B, smoothness: F) -> impl Fn(&Three<F>) -> F + Clone
+where
+    A: Fn(&Three<F>) -> F + Clone,
+    B: Fn(&Three<F>) -> F + Clone,
+{
+    move |p| {
+        let (da, db) = (a(p), b(p));
+        let h = blend(db - da, smoothness);
+        db + (da - db) * h - smoothness * h * (F::one() - h)
+    }
+}
+
+/// Like `subtraction`, but with the edges of the cut rounded by `smoothness`.
+pub fn smooth_subtraction<F: Float, A, B>(
+    a: A,
+    b: B,
+    smoothness: F,
+) -> impl Fn(&Three<F>) -> F + Clone
+where
+    A: Fn(&Three<F>) -> F + Clone,
+    B: Fn(&Three<F>) -> F + Clone,
+{
+    move |p| {
+        let (da, db) = (a(p), -b(p));
+        let h = blend(da - db, smoothness);
+        db + (da - db) * h + smoothness * h * (F::one() - h)
+    }
+}
+
+/// Like `intersection`, but with the edges where the shapes meet rounded by `smoothness`.
+pub fn smooth_intersection<F: Float, A, B>(
+    a: A,
+    b: B,
+    smoothness: F,
+) -> impl Fn(&Three<F>) -> F + Clone
+where
+    A: Fn(&Three<F>) -> F + Clone,
+    B: Fn(&Three<F>) -> F + Clone,
+{
+    move |p| {
+        let (da, db) = (a(p), b(p));
+        let h = blend(da - db, smoothness);
+        db + (da - db) * h + smoothness * h * (F::one() - h)
+    }
+}
+
+/// How much of the first of two shapes to take, from 0 to 1, when it's `difference` ahead of
+/// the second.
+fn blend<F: Float>(difference: F, smoothness: F) -> F {
+    let half = F::from(0.5f64).unwrap();
+    (half + half * difference / smoothness)
+        .max(F::zero())
+        .min(F::one())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points() -> impl Iterator<Item = Three<f64>> {
+        (0..125).map(|i| {
+            let coordinate = |n: usize| -1.5 + 0.75 * (n % 5) as f64;
+            Three::new(coordinate(i), coordinate(i / 5), coordinate(i / 25))
+        })
+    }
+
+    #[test]
+    fn smooth_union_without_smoothness_is_union() {
+        let a = sphere(Three::new(-0.5, 0.0, 0.0), 0.7);
+        let b = round_box(Three::new(0.5, 0.2, 0.0), Three::from(0.5), 0.1);
+        let smooth = smooth_union(a.clone(), b.clone(), 0.0);
+        let sharp = union(a.clone(), b.clone());
+        for p in points() {
+            assert_eq!(smooth(&p), sharp(&p), "{:?}", p);
+            assert_eq!(sharp(&p), a(&p).min(b(&p)));
+        }
+    }
+
+    #[test]
+    fn smooth_blends_only_add_material_where_shapes_are_close() {
+        let a = sphere(Three::new(-0.6, 0.0, 0.0), 0.5);
+        let b = sphere(Three::new(0.6, 0.0, 0.0), 0.5);
+        let smooth = smooth_union(a.clone(), b.clone(), 0.3);
+        let sharp = union(a, b);
+        for p in points() {
+            assert!(smooth(&p) <= sharp(&p) + 1e-12, "{:?}", p);
+            assert!(sharp(&p) - smooth(&p) <= 0.3 * 0.25 + 1e-12, "{:?}", p);
+        }
+        // the gap between the spheres fills in
+        let middle = Three::zeros();
+        assert!(sharp(&middle) > 0.0 && smooth(&middle) < sharp(&middle));
+    }
+
+    #[test]
+    fn primitives_are_zero_on_their_surface() {
+        let z = Three::new(0.0, 0.0, 1.0);
+        assert_eq!(sphere(z, 2.0)(&Three::new(0.0, 0.0, 3.0)), 0.0);
+        assert_eq!(sphere(z, 2.0)(&z), -2.0);
+        let corner = round_box(Three::zeros(), Three::from(1.0), 0.0)(&Three::from(1.0));
+        assert!(corner.abs() < 1e-12);
+        assert!(torus(Three::zeros(), 1.0, 0.25)(&Three::new(1.25, 0.0, 0.0)).abs() < 1e-12);
+        assert!((capsule(-z, z, 0.5)(&Three::new(0.0, 0.0, 2.0)) - 0.5).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,259 @@
+use super::cylinder::{cap_uv, Part};
+use super::even_scale;
+use super::frame::{azimuth, disk_distance, quadratic_roots, Frame};
+use crate::data::{Aabb, Bounded, CanHit, Hit, Ray, Surface, Three};
+use crate::transform::Transform;
+use num_traits::{Float, FloatConst};
+use rand::Rng;
+use rand_distr::{uniform::SampleUniform, Distribution, Standard};
+
+/// A round cone narrowing to a point, open at its base unless it's `capped`.
+#[derive(Debug, Clone)]
+pub struct Cone<F> {
+    /// Stands on the origin along z, with its apex at `height`.
+    pub(super) frame: Frame<F>,
+    pub(super) radius: F,
+    pub(super) height: F,
+    /// How far around the axis it goes, in radians.
+    pub(super) sweep: F,
+    pub(super) capped: bool,
+}
+
+impl<F> Cone<F>
+where
+    F: Float + FloatConst,
+{
+    /// The cone from the center of its `base` to its `apex`.
+    pub fn new(base: Three<F>, apex: Three<F>, radius: F) -> Self {
+        Self {
+            frame: Frame::new(base, apex - base),
+            radius,
+            height: (apex - base).length(),
+            sweep: F::TAU(),
+            capped: false,
+        }
+    }
+
+    /// The cone moved by `transform`, which has to scale evenly to keep it round.
+    pub fn transformed(&self, transform: &Transform<F>) -> Self {
+        let scale = even_scale(transform, "cone");
+        Self {
+            frame: self.frame.transformed(transform),
+            radius: self.radius * scale,
+            height: self.height * scale,
+            ..self.clone()
+        }
+    }
+
+    /// Closes the base with a disk.
+    pub fn capped(self) -> Self {
+        Self {
+            capped: true,
+            ..self
+        }
+    }
+
+    /// Only keeps the part `degrees` around the axis, starting from an arbitrary side.
+    pub fn swept(self, degrees: F) -> Self {
+        Self {
+            sweep: degrees.to_radians().min(F::TAU()),
+            ..self
+        }
+    }
+
+    fn in_sweep(&self, local: &Three<F>) -> bool {
+        self.sweep >= F::TAU() || azimuth(local) <= self.sweep
+    }
+
+    /// Radius of the cone at `z`.
+    fn radius_at(&self, z: F) -> F {
+        self.radius * (F::one() - z / self.height)
+    }
+
+    /// The part a point on the surface is on, i.e. the one it's closest to.
+    fn part_at(&self, local: &Three<F>) -> Part {
+        let side = ((local.x * local.x + local.y * local.y).sqrt() - self.radius_at(local.z)).abs()
+            * self.height
+            / self.slant();
+        if !self.capped || side <= local.z.abs() {
+            Part::Side
+        } else {
+            Part::Bottom
+        }
+    }
+
+    /// Length of the side from the base to the apex.
+    fn slant(&self) -> F {
+        (self.radius * self.radius + self.height * self.height).sqrt()
+    }
+
+    fn local_normal(&self, local: &Three<F>, part: Part) -> Three<F> {
+        match part {
+            Part::Side => {
+                // leans outwards by the cone's slope, pointing up at the apex
+                let radial = Three::new(local.x, local.y, F::zero());
+                let length = radial.length();
+                let radial = if length > F::zero() {
+                    radial / length
+                } else {
+                    radial
+                };
+                (radial * self.height + Three::new(F::zero(), F::zero(), self.radius)).normalized()
+            }
+            _ => Three::new(F::zero(), F::zero(), -F::one()),
+        }
+    }
+
+    fn local_uv(&self, local: &Three<F>, part: Part) -> (F, F) {
+        match part {
+            Part::Side => (azimuth(local) / self.sweep, local.z / self.height),
+            _ => cap_uv(local, self.radius),
+        }
+    }
+}
+
+impl<F> CanHit<Cone<F>, F> for Ray<F>
+where
+    F: Float + FloatConst,
+{
+    fn shoot_at(&self, cone: &Cone<F>, t_min: F, t_max: F) -> Option<Hit<F>> {
+        let ray = cone.frame.local_ray(self);
+        let (o, d) = (&ray.origin, &ray.direction);
+        let mut nearest = None;
+        let mut consider = |t: F, part: Part| {
+            if t_min <= t && t < nearest.map_or(t_max, |(t, _)| t) {
+                nearest = Some((t, part));
+            }
+        };
+        // x² + y² = (k (h - z))², with the slope k
+        let k = cone.radius / cone.height;
+        let k2 = k * k;
+        let h = cone.height - o.z;
+        let a = d.x * d.x + d.y * d.y - k2 * d.z * d.z;
+        let b = F::from(2.0f64).unwrap() * (o.x * d.x + o.y * d.y + k2 * h * d.z);
+        let c = o.x * o.x + o.y * o.y - k2 * h * h;
+        for t in quadratic_roots(a, b, c).into_iter().flatten() {
+            let p = ray.origin + ray.direction * t;
+            // the equation also has a mirrored cone above the apex
+            if F::zero() <= p.z && p.z <= cone.height && cone.in_sweep(&p) {
+                consider(t, Part::Side);
+            }
+        }
+        if cone.capped {
+            let up = Three::new(F::zero(), F::zero(), F::one());
+            if let Some(t) = disk_distance(&ray, &Three::zeros(), &up, cone.radius) {
+                if cone.in_sweep(&(ray.origin + ray.direction * t)) {
+                    consider(t, Part::Bottom);
+                }
+            }
+        }
+        let (distance, part) = nearest?;
+        let local = ray.origin + ray.direction * distance;
+        Some(Hit {
+            position: self.origin + self.direction * distance,
+            distance,
+            normal: cone.frame.vector(&cone.local_normal(&local, part)),
+            uv: cone.local_uv(&local, part),
+            object_index: 0,
+        })
+    }
+}
+
+impl<F> Surface<F> for Cone<F>
+where
+    F: Float + FloatConst + SampleUniform,
+    Standard: Distribution<F>,
+{
+    fn sample_point_on_surface<R: Rng>(&self, rng: &mut R) -> Three<F> {
+        let side = self.sweep * self.radius * self.slant() / F::from(2.0f64).unwrap();
+        let u: F = Standard.sample(rng);
+        let v: F = Standard.sample(rng);
+        let w: F = Standard.sample(rng);
+        let phi = self.sweep * v;
+        // the side and the cap both get wider linearly, away from the apex and from the center
+        let s = w.sqrt();
+        let local = if u * self.area() < side {
+            Three::new(
+                self.radius * s * phi.cos(),
+                self.radius * s * phi.sin(),
+                self.height * (F::one() - s),
+            )
+        } else {
+            Three::new(
+                self.radius * s * phi.cos(),
+                self.radius * s * phi.sin(),
+                F::zero(),
+            )
+        };
+        self.frame.point(&local)
+    }
+
+    fn normal_at_point(&self, point: &Three<F>) -> Three<F> {
+        let local = self.frame.local_point(point);
+        self.frame
+            .vector(&self.local_normal(&local, self.part_at(&local)))
+    }
+
+    fn uv_at_point(&self, point: &Three<F>) -> (F, F) {
+        let local = self.frame.local_point(point);
+        self.local_uv(&local, self.part_at(&local))
+    }
+
+    fn area(&self) -> F {
+        let half = F::from(0.5f64).unwrap();
+        let side = self.sweep * self.radius * self.slant() * half;
+        if self.capped {
+            side + self.sweep * self.radius * self.radius * half
+        } else {
+            side
+        }
+    }
+}
+
+impl<F> Bounded<F> for Cone<F>
+where
+    F: Float,
+{
+    fn bounds(&self) -> Aabb<F> {
+        self.frame.bounds(&Aabb {
+            min: Three::new(-self.radius, -self.radius, F::zero()),
+            max: Three::new(self.radius, self.radius, self.height),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shoot(cone: &Cone<f64>, origin: Three<f64>, direction: Three<f64>) -> Option<Hit<f64>> {
+        let ray = Ray::new(origin, direction.normalized());
+        ray.shoot_at(cone, 1e-6, f64::INFINITY)
+    }
+
+    fn assert_near(a: Three<f64>, b: Three<f64>) {
+        assert!((a - b).length() < 1e-9, "{:?} is not {:?}", a, b);
+    }
+
+    #[test]
+    fn rays_hit_the_side_and_base() {
+        let z = Three::new(0.0, 0.0, 1.0);
+        let cone = Cone::new(Three::zeros(), z, 1.0);
+        let x = Three::new(1.0, 0.0, 0.0);
+        let hit = shoot(&cone, Three::new(-3.0, 0.0, 0.5), x).unwrap();
+        assert!((hit.distance - 2.5).abs() < 1e-9);
+        assert_near(hit.normal, Three::new(-1.0, 0.0, 1.0).normalized());
+        // the other half of the double cone above the apex isn't part of it
+        assert!(shoot(&cone, Three::new(-3.0, 0.0, 1.5), x).is_none());
+        // from inside it meets the side from within
+        let hit = shoot(&cone, Three::new(0.0, 0.0, 0.5), x).unwrap();
+        assert!((hit.distance - 0.5).abs() < 1e-9);
+
+        // the open base lets rays in to meet the side from within
+        let hit = shoot(&cone, Three::new(0.2, 0.0, -5.0), z).unwrap();
+        assert!((hit.distance - 5.8).abs() < 1e-9);
+        let hit = shoot(&cone.clone().capped(), z * -5.0, z).unwrap();
+        assert!((hit.distance - 5.0).abs() < 1e-9);
+        assert_near(hit.normal, -z);
+    }
+}
@@ -0,0 +1,392 @@
+use super::Object;
+use crate::data::{Aabb, Bounded, CanHit, Hit, Ray, Surface, Three};
+use num_traits::{Float, FloatConst};
+use rand::{Rng, SeedableRng};
+use rand_distr::{uniform::SampleUniform, Distribution, Standard};
+use rand_xorshift::XorShiftRng;
+
+/// Points picked on the shapes to find the solid's area, and tried to find one on it.
+const SAMPLES: usize = 4096;
+
+/// Constructive solid geometry: the solid made by combining two closed shapes, like a lens cut
+/// out of a prism or a box with a hole drilled through it.
+///
+/// The shapes' normals have to point outwards, which is how they tell where the ray goes in and
+/// out of them.
+#[derive(Debug, Clone)]
+pub struct Csg<F> {
+    pub(super) operation: Operation,
+    pub(super) left: Box<Object<F>>,
+    pub(super) right: Box<Object<F>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Operation {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl Operation {
+    fn inside(self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            Operation::Union => in_left || in_right,
+            Operation::Intersection => in_left && in_right,
+            Operation::Difference => in_left && !in_right,
+        }
+    }
+}
+
+impl<F> Csg<F>
+where
+    F: Float + FloatConst,
+{
+    /// Everything inside either shape.
+    pub fn union<A: Into<Object<F>>, B: Into<Object<F>>>(left: A, right: B) -> Self {
+        Self::new(Operation::Union, left.into(), right.into())
+    }
+
+    /// Only what's inside both shapes.
+    pub fn intersection<A: Into<Object<F>>, B: Into<Object<F>>>(left: A, right: B) -> Self {
+        Self::new(Operation::Intersection, left.into(), right.into())
+    }
+
+    /// `left` with `right` cut out of it.
+    pub fn difference<A: Into<Object<F>>, B: Into<Object<F>>>(left: A, right: B) -> Self {
+        Self::new(Operation::Difference, left.into(), right.into())
+    }
+
+    fn new(operation: Operation, left: Object<F>, right: Object<F>) -> Self {
+        Self {
+            operation,
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    /// The outward normal of the solid where it's bounded by `right`, which is flipped where
+    /// `right` is cut away.
+    fn right_facing(&self, normal: Three<F>) -> Three<F> {
+        if self.operation == Operation::Difference {
+            -normal
+        } else {
+            normal
+        }
+    }
+}
+
+impl<F> Csg<F>
+where
+    F: Float + FloatConst + SampleUniform,
+    Standard: Distribution<F>,
+{
+    /// Whether a point on the surface of one of the shapes is on the surface of the solid too.
+    fn keeps(&self, point: &Three<F>, on_left: bool) -> bool {
+        let other = if on_left { &self.right } else { &self.left };
+        let inside = contains(other, point);
+        match (self.operation, on_left) {
+            (Operation::Union, _) => !inside,
+            (Operation::Intersection, _) => inside,
+            (Operation::Difference, true) => !inside,
+            (Operation::Difference, false) => inside,
+        }
+    }
+
+    /// A point on either shape, uniformly over both of their surfaces, and whether it's on `left`.
+    fn sample_either<R: Rng>(&self, rng: &mut R) -> (Three<F>, bool) {
+        let left_area = self.left.area();
+        let u: F = Standard.sample(rng);
+        if u * (left_area + self.right.area()) < left_area {
+            (self.left.sample_point_on_surface(rng), true)
+        } else {
+            (self.right.sample_point_on_surface(rng), false)
+        }
+    }
+
+    /// Whether a point on the solid's surface is on `left`, rather than on `right`.
+    fn on_left(&self, point: &Three<F>) -> bool {
+        on_surface(&self.left, point) || !on_surface(&self.right, point)
+    }
+}
+
+/// Where a ray crosses the surface of a shape, one after another along it.
+struct Crossings<'a, F> {
+    ray: &'a Ray<F>,
+    object: &'a Object<F>,
+    next: Option<Hit<F>>,
+}
+
+impl<'a, F> Crossings<'a, F>
+where
+    F: Float + FloatConst,
+{
+    fn new(ray: &'a Ray<F>, object: &'a Object<F>, t_min: F) -> Self {
+        Self {
+            ray,
+            object,
+            next: ray.shoot_at(object, t_min, F::infinity()),
+        }
+    }
+
+    fn advance(&mut self) {
+        if let Some(hit) = self.next {
+            // just past the last crossing, so it isn't found again
+            let step = F::epsilon().sqrt() * hit.distance.abs().max(F::one());
+            self.next = self
+                .ray
+                .shoot_at(self.object, hit.distance + step, F::infinity());
+        }
+    }
+
+    /// Whether the ray is inside the shape just before the next crossing.
+    fn inside(&self) -> bool {
+        self.next.is_some_and(|hit| exits(self.ray, &hit))
+    }
+}
+
+fn exits<F: Float>(ray: &Ray<F>, hit: &Hit<F>) -> bool {
+    hit.normal.dot(&ray.direction) > F::zero()
+}
+
+/// Whether a point is inside a closed shape, by whether a ray from it leaves the shape first.
+fn contains<F: Float + FloatConst>(object: &Object<F>, point: &Three<F>) -> bool {
+    // an arbitrary direction that's unlikely to graze the edges of boxes and meshes
+    let direction = Three::new(
+        F::from(0.57f64).unwrap(),
+        F::from(0.61f64).unwrap(),
+        F::from(0.55f64).unwrap(),
+    )
+    .normalized();
+    let ray = Ray::new(*point, direction);
+    Crossings::new(&ray, object, F::epsilon().sqrt()).inside()
+}
+
+/// Whether a point is on the surface of a shape, by looking for it along the shape's normal.
+fn on_surface<F: Float + FloatConst + SampleUniform>(object: &Object<F>, point: &Three<F>) -> bool
+where
+    Standard: Distribution<F>,
+{
+    let normal = object.normal_at_point(point);
+    let reach = F::epsilon().sqrt() * point.length().max(F::one());
+    let ray = Ray {
+        origin: *point + normal * reach,
+        direction: -normal,
+        time: F::zero(),
+    };
+    ray.shoot_at(object, F::zero(), reach + reach).is_some()
+}
+
+impl<F> CanHit<Csg<F>, F> for Ray<F>
+where
+    F: Float + FloatConst,
+{
+    fn shoot_at(&self, csg: &Csg<F>, t_min: F, t_max: F) -> Option<Hit<F>> {
+        let mut left = Crossings::new(self, &csg.left, t_min);
+        let mut right = Crossings::new(self, &csg.right, t_min);
+        let (mut in_left, mut in_right) = (left.inside(), right.inside());
+        let mut inside = csg.operation.inside(in_left, in_right);
+        loop {
+            // step to whichever surface the ray crosses next, until that changes the solid
+            let on_left = match (left.next, right.next) {
+                (Some(l), Some(r)) => l.distance <= r.distance,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => return None,
+            };
+            let crossings = if on_left { &mut left } else { &mut right };
+            let hit = crossings.next?;
+            if hit.distance >= t_max {
+                return None;
+            }
+            if on_left {
+                in_left = !exits(self, &hit);
+            } else {
+                in_right = !exits(self, &hit);
+            }
+            let now_inside = csg.operation.inside(in_left, in_right);
+            if now_inside != inside {
+                return Some(Hit {
+                    normal: if on_left {
+                        hit.normal
+                    } else {
+                        csg.right_facing(hit.normal)
+                    },
+                    ..hit
+                });
+            }
+            inside = now_inside;
+            crossings.advance();
+        }
+    }
+}
+
+/// The solid's surface is whatever is left of the shapes' surfaces, which isn't known exactly, so
+/// its area is estimated and scenes don't let solids be lights.
+impl<F> Surface<F> for Csg<F>
+where
+    F: Float + FloatConst + SampleUniform,
+    Standard: Distribution<F>,
+{
+    /// Picks points on either shape until one is left on the solid's surface. Solids with next
+    /// to no surface left give up after a while, returning a point on one of the shapes.
+    fn sample_point_on_surface<R: Rng>(&self, rng: &mut R) -> Three<F> {
+        let mut point = Three::zeros();
+        for _ in 0..SAMPLES {
+            let (candidate, on_left) = self.sample_either(rng);
+            point = candidate;
+            if self.keeps(&point, on_left) {
+                break;
+            }
+        }
+        point
+    }
+
+    fn normal_at_point(&self, point: &Three<F>) -> Three<F> {
+        if self.on_left(point) {
+            self.left.normal_at_point(point)
+        } else {
+            self.right_facing(self.right.normal_at_point(point))
+        }
+    }
+
+    fn uv_at_point(&self, point: &Three<F>) -> (F, F) {
+        if self.on_left(point) {
+            self.left.uv_at_point(point)
+        } else {
+            self.right.uv_at_point(point)
+        }
+    }
+
+    /// The shapes' total area times the share of points on them that are left on the solid,
+    /// from the same points every time.
+    fn area(&self) -> F {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let kept = (0..SAMPLES)
+            .filter(|_| {
+                let (point, on_left) = self.sample_either(&mut rng);
+                self.keeps(&point, on_left)
+            })
+            .count();
+        (self.left.area() + self.right.area()) * F::from(kept).unwrap() / F::from(SAMPLES).unwrap()
+    }
+}
+
+impl<F> Bounded<F> for Csg<F>
+where
+    F: Float,
+{
+    fn bounds(&self) -> Aabb<F> {
+        let (left, right) = (self.left.bounds(), self.right.bounds());
+        match self.operation {
+            Operation::Union => left.union(&right),
+            Operation::Intersection => Aabb {
+                min: Three::new(
+                    left.min.x.max(right.min.x),
+                    left.min.y.max(right.min.y),
+                    left.min.z.max(right.min.z),
+                ),
+                max: Three::new(
+                    left.max.x.min(right.max.x),
+                    left.max.y.min(right.max.y),
+                    left.max.z.min(right.max.z),
+                ),
+            },
+            Operation::Difference => left,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::{Cuboid, Cylinder, Prism, Sphere};
+
+    fn shoot(csg: &Csg<f64>, origin: Three<f64>, direction: Three<f64>) -> Option<Hit<f64>> {
+        let ray = Ray::new(origin, direction.normalized());
+        ray.shoot_at(csg, 1e-6, f64::INFINITY)
+    }
+
+    fn assert_hit(hit: Option<Hit<f64>>, distance: f64, normal: Three<f64>) {
+        let hit = hit.expect("no hit");
+        assert!((hit.distance - distance).abs() < 1e-6, "{:?}", hit);
+        assert!((hit.normal - normal).length() < 1e-6, "{:?}", hit);
+    }
+
+    /// Two unit spheres overlapping from x = -0.5 to 0.5.
+    fn lens() -> Csg<f64> {
+        Csg::intersection(
+            Sphere::new(Three::new(-0.5, 0.0, 0.0), 1.0),
+            Sphere::new(Three::new(0.5, 0.0, 0.0), 1.0),
+        )
+    }
+
+    #[test]
+    fn differences_cut_holes() {
+        let z = Three::new(0.0, 0.0, 1.0);
+        let x = Three::new(1.0, 0.0, 0.0);
+        let drilled = Csg::difference(
+            Cuboid::new(Three::from(-1.0), Three::from(1.0)),
+            Cylinder::new(z * -2.0, z * 2.0, 0.5).capped(),
+        );
+        // straight down the hole
+        assert!(shoot(&drilled, z * 5.0, -z).is_none());
+        assert_hit(shoot(&drilled, Three::new(0.75, 0.0, 5.0), -z), 4.0, z);
+        assert_hit(shoot(&drilled, Three::new(-5.0, 0.0, 0.0), x), 4.0, -x);
+        // from inside the hole the walls face into it
+        assert_hit(shoot(&drilled, Three::zeros(), x), 0.5, -x);
+        // from inside the solid it's left through the wall of the hole
+        assert_hit(shoot(&drilled, Three::new(-0.75, 0.0, 0.0), x), 0.25, x);
+    }
+
+    #[test]
+    fn lenses_are_entered_and_left_through_the_other_sphere() {
+        let x = Three::new(1.0, 0.0, 0.0);
+        let y = Three::new(0.0, 1.0, 0.0);
+        let lens = lens();
+        assert_hit(shoot(&lens, x * -5.0, x), 4.5, -x);
+        assert_hit(shoot(&lens, Three::zeros(), x), 0.5, x);
+        assert_hit(shoot(&lens, Three::zeros(), -x), 0.5, -x);
+        // beside the middle it's the far sphere's surface
+        let side = 0.51f64.sqrt();
+        assert_hit(
+            shoot(&lens, Three::new(0.2, -5.0, 0.0), y),
+            5.0 - side,
+            Three::new(0.7, -side, 0.0),
+        );
+        // through only one of the spheres
+        assert!(shoot(&lens, Three::new(1.2, -5.0, 0.0), y).is_none());
+    }
+
+    #[test]
+    fn prisms_with_a_bite_out_of_them_face_outwards() {
+        let z = Three::new(0.0, 0.0, 1.0);
+        let bitten = Csg::difference(
+            Prism::unit_facing_pos_z(),
+            Sphere::new(Three::new(0.0, 0.3, 0.0), 0.2),
+        );
+        // the front face is still there beside the bite
+        assert_hit(shoot(&bitten, Three::new(0.3, 0.2, 5.0), -z), 5.0, z);
+        // and the bite's bottom faces out of the prism
+        assert_hit(shoot(&bitten, Three::new(0.0, 0.3, 5.0), -z), 5.2, z);
+        let hit = shoot(&bitten, Three::new(0.0, 0.3, 5.0), -z).unwrap();
+        assert!((bitten.normal_at_point(&hit.position) - z).length() < 1e-6);
+        // the back face is a whole triangle
+        assert_hit(shoot(&bitten, Three::new(0.0, 0.3, -5.0), z), 4.0, -z);
+    }
+
+    #[test]
+    fn area_and_points_are_only_whats_left_on_the_solid() {
+        let lens = lens();
+        // two caps half a radius high, each 2π R h
+        let area = 2.0 * std::f64::consts::PI;
+        assert!((lens.area() - area).abs() < 0.1 * area, "{}", lens.area());
+        let mut rng = XorShiftRng::seed_from_u64(1);
+        for _ in 0..100 {
+            let p = lens.sample_point_on_surface(&mut rng);
+            let distances = [-0.5, 0.5].map(|x| (p - Three::new(x, 0.0, 0.0)).length());
+            assert!(distances.iter().all(|&d| d <= 1.0 + 1e-9), "{:?}", p);
+            assert!(distances.iter().any(|&d| (d - 1.0).abs() < 1e-9), "{:?}", p);
+        }
+        assert!(Object::from(lens).check_light_sampling().is_err());
+    }
+}
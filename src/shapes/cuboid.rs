@@ -0,0 +1,247 @@
+use super::even_scale;
+use crate::data::{Aabb, Bounded, CanHit, Hit, Ray, Surface, Three};
+use crate::transform::Transform;
+use num_traits::Float;
+use rand::Rng;
+use rand_distr::{uniform::SampleUniform, Distribution, Standard};
+
+/// A box, which starts out aligned with the axes and can be turned by `transformed`.
+#[derive(Debug, Clone)]
+pub struct Cuboid<F> {
+    pub(super) center: Three<F>,
+    /// Half the box's size along each of its `axes`.
+    pub(super) half_size: Three<F>,
+    pub(super) axes: [Three<F>; 3],
+}
+
+impl<F> Cuboid<F>
+where
+    F: Float,
+{
+    /// The box between two opposite corners.
+    pub fn new(min: Three<F>, max: Three<F>) -> Self {
+        let half = F::from(0.5f64).unwrap();
+        Self {
+            center: (min + max) * half,
+            half_size: Three::new(
+                (max.x - min.x).abs() * half,
+                (max.y - min.y).abs() * half,
+                (max.z - min.z).abs() * half,
+            ),
+            axes: [
+                Three::new(F::one(), F::zero(), F::zero()),
+                Three::new(F::zero(), F::one(), F::zero()),
+                Three::new(F::zero(), F::zero(), F::one()),
+            ],
+        }
+    }
+
+    /// The box moved by `transform`, which has to scale evenly to keep its corners square.
+    pub fn transformed(&self, transform: &Transform<F>) -> Self {
+        let scale = even_scale(transform, "box");
+        Self {
+            center: transform.point(&self.center),
+            half_size: self.half_size * scale,
+            axes: self.axes.map(|axis| transform.vector(&axis).normalized()),
+        }
+    }
+
+    /// A point relative to the box's center, along its axes.
+    fn local(&self, point: &Three<F>) -> Three<F> {
+        let offset = point - &self.center;
+        Three::new(
+            offset.dot(&self.axes[0]),
+            offset.dot(&self.axes[1]),
+            offset.dot(&self.axes[2]),
+        )
+    }
+
+    /// The axis of the face a local point is on, i.e. the one it's furthest out along.
+    fn face_axis(&self, local: &Three<F>) -> usize {
+        (0..3)
+            .map(|i| (i, local.axis(i).abs() / self.half_size.axis(i)))
+            .fold((0, F::neg_infinity()), |best, (i, reach)| {
+                if reach > best.1 {
+                    (i, reach)
+                } else {
+                    best
+                }
+            })
+            .0
+    }
+
+    fn normal_at_local(&self, local: &Three<F>) -> Three<F> {
+        let i = self.face_axis(local);
+        if local.axis(i) < F::zero() {
+            -self.axes[i]
+        } else {
+            self.axes[i]
+        }
+    }
+
+    /// Where a local point is on its face, from 0 to 1 along the face's next two axes.
+    fn uv_at_local(&self, local: &Three<F>) -> (F, F) {
+        let i = self.face_axis(local);
+        let (j, k) = ((i + 1) % 3, (i + 2) % 3);
+        let half = F::from(0.5f64).unwrap();
+        (
+            (local.axis(j) / self.half_size.axis(j) + F::one()) * half,
+            (local.axis(k) / self.half_size.axis(k) + F::one()) * half,
+        )
+    }
+}
+
+impl<F> CanHit<Cuboid<F>, F> for Ray<F>
+where
+    F: Float,
+{
+    fn shoot_at(&self, cuboid: &Cuboid<F>, t_min: F, t_max: F) -> Option<Hit<F>> {
+        // slab test in the box's own frame
+        let origin = cuboid.local(&self.origin);
+        let mut near = F::neg_infinity();
+        let mut far = F::infinity();
+        for (i, axis) in cuboid.axes.iter().enumerate() {
+            let direction = self.direction.dot(axis);
+            let t0 = (-cuboid.half_size.axis(i) - origin.axis(i)) / direction;
+            let t1 = (cuboid.half_size.axis(i) - origin.axis(i)) / direction;
+            // NaNs, from rays along a face, don't narrow the interval
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+        }
+        if near > far {
+            return None;
+        }
+        let distance = [near, far].into_iter().find(|&t| t_min <= t && t < t_max)?;
+        let position = self.origin + self.direction * distance;
+        let local = cuboid.local(&position);
+        Some(Hit {
+            position,
+            distance,
+            normal: cuboid.normal_at_local(&local),
+            uv: cuboid.uv_at_local(&local),
+            object_index: 0,
+        })
+    }
+}
+
+impl<F> Surface<F> for Cuboid<F>
+where
+    F: Float + SampleUniform,
+    Standard: Distribution<F>,
+{
+    fn sample_point_on_surface<R: Rng>(&self, rng: &mut R) -> Three<F> {
+        // pick one of the six faces proportional to its area
+        let h = &self.half_size;
+        let face_areas = [h.y * h.z, h.z * h.x, h.x * h.y];
+        let total = face_areas[0] + face_areas[1] + face_areas[2];
+        let u: F = Standard.sample(rng);
+        let mut remaining = u * total;
+        let mut i = 2;
+        for (axis, area) in face_areas.iter().enumerate() {
+            if remaining < *area {
+                i = axis;
+                break;
+            }
+            remaining = remaining - *area;
+        }
+        let (j, k) = ((i + 1) % 3, (i + 2) % 3);
+        let side: F = Standard.sample(rng);
+        let a: F = Standard.sample(rng);
+        let b: F = Standard.sample(rng);
+        let two = F::from(2.0f64).unwrap();
+        let sign = if side < F::from(0.5f64).unwrap() {
+            -F::one()
+        } else {
+            F::one()
+        };
+        self.center
+            + self.axes[i] * (sign * h.axis(i))
+            + self.axes[j] * ((two * a - F::one()) * h.axis(j))
+            + self.axes[k] * ((two * b - F::one()) * h.axis(k))
+    }
+
+    fn normal_at_point(&self, point: &Three<F>) -> Three<F> {
+        self.normal_at_local(&self.local(point))
+    }
+
+    fn uv_at_point(&self, point: &Three<F>) -> (F, F) {
+        self.uv_at_local(&self.local(point))
+    }
+
+    fn area(&self) -> F {
+        let h = &self.half_size;
+        F::from(8.0f64).unwrap() * (h.x * h.y + h.y * h.z + h.z * h.x)
+    }
+}
+
+impl<F> Bounded<F> for Cuboid<F>
+where
+    F: Float,
+{
+    fn bounds(&self) -> Aabb<F> {
+        let [a, b, c] = self.axes;
+        let h = &self.half_size;
+        let extent =
+            |i: usize| a.axis(i).abs() * h.x + b.axis(i).abs() * h.y + c.axis(i).abs() * h.z;
+        let extent = Three::new(extent(0), extent(1), extent(2));
+        Aabb {
+            min: self.center - extent,
+            max: self.center + extent,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    const SAMPLES: usize = 10_000;
+
+    /// Checks that points sampled on `cuboid` are on its faces, and land on each face as often
+    /// as its share of the area.
+    fn assert_even_over_faces(cuboid: &Cuboid<f64>) {
+        let h = cuboid.half_size;
+        let face_areas = [4.0 * h.y * h.z, 4.0 * h.z * h.x, 4.0 * h.x * h.y];
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let mut per_face = [0usize; 6];
+        for _ in 0..SAMPLES {
+            let local = cuboid.local(&cuboid.sample_point_on_surface(&mut rng));
+            let axis = cuboid.face_axis(&local);
+            assert!(
+                (local.axis(axis).abs() - h.axis(axis)).abs() < 1e-9,
+                "{:?}",
+                local
+            );
+            for other in [(axis + 1) % 3, (axis + 2) % 3] {
+                assert!(
+                    local.axis(other).abs() <= h.axis(other) + 1e-9,
+                    "{:?}",
+                    local
+                );
+            }
+            per_face[2 * axis + (local.axis(axis) > 0.0) as usize] += 1;
+        }
+        for (face, &count) in per_face.iter().enumerate() {
+            let share = face_areas[face / 2] / cuboid.area();
+            let fraction = count as f64 / SAMPLES as f64;
+            assert!((fraction - share).abs() < 0.02, "{:?}", per_face);
+        }
+    }
+
+    #[test]
+    fn area_and_points_cover_all_six_faces() {
+        let cuboid = Cuboid::new(Three::zeros(), Three::new(1.0, 2.0, 3.0));
+        // two faces of each of 1x2, 2x3 and 3x1
+        assert!((cuboid.area() - 22.0).abs() < 1e-9);
+        assert_even_over_faces(&cuboid);
+
+        let turned = cuboid.transformed(
+            &(Transform::rotation(&Three::new(1.0, 1.0, 0.0), 30.0)
+                * Transform::scaling(Three::from(2.0))),
+        );
+        assert!((turned.area() - 88.0).abs() < 1e-9);
+        assert_even_over_faces(&turned);
+    }
+}
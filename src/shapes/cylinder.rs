@@ -0,0 +1,283 @@
+use super::even_scale;
+use super::frame::{azimuth, disk_distance, quadratic_roots, Frame};
+use crate::data::{Aabb, Bounded, CanHit, Hit, Ray, Surface, Three};
+use crate::transform::Transform;
+use num_traits::{Float, FloatConst};
+use rand::Rng;
+use rand_distr::{uniform::SampleUniform, Distribution, Standard};
+
+/// A round tube, like a pipe, open at both ends unless it's `capped`.
+#[derive(Debug, Clone)]
+pub struct Cylinder<F> {
+    /// Stands on the origin along z.
+    pub(super) frame: Frame<F>,
+    pub(super) radius: F,
+    pub(super) height: F,
+    /// How far around the axis it goes, in radians.
+    pub(super) sweep: F,
+    pub(super) capped: bool,
+}
+
+/// Which part of a cylinder or cone a point is on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Part {
+    Side,
+    Bottom,
+    Top,
+}
+
+impl<F> Cylinder<F>
+where
+    F: Float + FloatConst,
+{
+    /// The cylinder from the center of its `base` to the center of its `top`.
+    pub fn new(base: Three<F>, top: Three<F>, radius: F) -> Self {
+        Self {
+            frame: Frame::new(base, top - base),
+            radius,
+            height: (top - base).length(),
+            sweep: F::TAU(),
+            capped: false,
+        }
+    }
+
+    /// The cylinder moved by `transform`, which has to scale evenly to keep it round.
+    pub fn transformed(&self, transform: &Transform<F>) -> Self {
+        let scale = even_scale(transform, "cylinder");
+        Self {
+            frame: self.frame.transformed(transform),
+            radius: self.radius * scale,
+            height: self.height * scale,
+            ..self.clone()
+        }
+    }
+
+    /// Closes both ends with disks.
+    pub fn capped(self) -> Self {
+        Self {
+            capped: true,
+            ..self
+        }
+    }
+
+    /// Only keeps the part `degrees` around the axis, starting from an arbitrary side.
+    pub fn swept(self, degrees: F) -> Self {
+        Self {
+            sweep: degrees.to_radians().min(F::TAU()),
+            ..self
+        }
+    }
+
+    fn in_sweep(&self, local: &Three<F>) -> bool {
+        self.sweep >= F::TAU() || azimuth(local) <= self.sweep
+    }
+
+    /// The part a point on the surface is on, i.e. the one it's closest to.
+    fn part_at(&self, local: &Three<F>) -> Part {
+        let side = ((local.x * local.x + local.y * local.y).sqrt() - self.radius).abs();
+        if !self.capped || side <= local.z.abs().min((local.z - self.height).abs()) {
+            Part::Side
+        } else if local.z.abs() < (local.z - self.height).abs() {
+            Part::Bottom
+        } else {
+            Part::Top
+        }
+    }
+
+    fn local_normal(&self, local: &Three<F>, part: Part) -> Three<F> {
+        match part {
+            Part::Side => Three::new(local.x, local.y, F::zero()).normalized(),
+            Part::Bottom => Three::new(F::zero(), F::zero(), -F::one()),
+            Part::Top => Three::new(F::zero(), F::zero(), F::one()),
+        }
+    }
+
+    fn local_uv(&self, local: &Three<F>, part: Part) -> (F, F) {
+        match part {
+            Part::Side => (azimuth(local) / self.sweep, local.z / self.height),
+            Part::Bottom | Part::Top => cap_uv(local, self.radius),
+        }
+    }
+}
+
+impl<F> CanHit<Cylinder<F>, F> for Ray<F>
+where
+    F: Float + FloatConst,
+{
+    fn shoot_at(&self, cylinder: &Cylinder<F>, t_min: F, t_max: F) -> Option<Hit<F>> {
+        let ray = cylinder.frame.local_ray(self);
+        let (o, d) = (&ray.origin, &ray.direction);
+        let mut nearest = None;
+        let mut consider = |t: F, part: Part| {
+            if t_min <= t && t < nearest.map_or(t_max, |(t, _)| t) {
+                nearest = Some((t, part));
+            }
+        };
+        let a = d.x * d.x + d.y * d.y;
+        let b = F::from(2.0f64).unwrap() * (o.x * d.x + o.y * d.y);
+        let c = o.x * o.x + o.y * o.y - cylinder.radius * cylinder.radius;
+        for t in quadratic_roots(a, b, c).into_iter().flatten() {
+            let p = ray.origin + ray.direction * t;
+            if F::zero() <= p.z && p.z <= cylinder.height && cylinder.in_sweep(&p) {
+                consider(t, Part::Side);
+            }
+        }
+        if cylinder.capped {
+            let up = Three::new(F::zero(), F::zero(), F::one());
+            for (z, part) in [(F::zero(), Part::Bottom), (cylinder.height, Part::Top)] {
+                let center = up * z;
+                if let Some(t) = disk_distance(&ray, &center, &up, cylinder.radius) {
+                    if cylinder.in_sweep(&(ray.origin + ray.direction * t)) {
+                        consider(t, part);
+                    }
+                }
+            }
+        }
+        let (distance, part) = nearest?;
+        let local = ray.origin + ray.direction * distance;
+        Some(Hit {
+            position: self.origin + self.direction * distance,
+            distance,
+            normal: cylinder.frame.vector(&cylinder.local_normal(&local, part)),
+            uv: cylinder.local_uv(&local, part),
+            object_index: 0,
+        })
+    }
+}
+
+impl<F> Surface<F> for Cylinder<F>
+where
+    F: Float + FloatConst + SampleUniform,
+    Standard: Distribution<F>,
+{
+    fn sample_point_on_surface<R: Rng>(&self, rng: &mut R) -> Three<F> {
+        let side = self.sweep * self.radius * self.height;
+        let u: F = Standard.sample(rng);
+        let v: F = Standard.sample(rng);
+        let w: F = Standard.sample(rng);
+        let phi = self.sweep * v;
+        let local = if u * self.area() < side {
+            Three::new(
+                self.radius * phi.cos(),
+                self.radius * phi.sin(),
+                self.height * w,
+            )
+        } else {
+            // either cap, with the square root spreading points evenly over it
+            let z = if u * self.area() < side + (self.area() - side) / F::from(2.0f64).unwrap() {
+                F::zero()
+            } else {
+                self.height
+            };
+            let r = self.radius * w.sqrt();
+            Three::new(r * phi.cos(), r * phi.sin(), z)
+        };
+        self.frame.point(&local)
+    }
+
+    fn normal_at_point(&self, point: &Three<F>) -> Three<F> {
+        let local = self.frame.local_point(point);
+        self.frame
+            .vector(&self.local_normal(&local, self.part_at(&local)))
+    }
+
+    fn uv_at_point(&self, point: &Three<F>) -> (F, F) {
+        let local = self.frame.local_point(point);
+        self.local_uv(&local, self.part_at(&local))
+    }
+
+    fn area(&self) -> F {
+        let side = self.sweep * self.radius * self.height;
+        if self.capped {
+            side + self.sweep * self.radius * self.radius
+        } else {
+            side
+        }
+    }
+}
+
+impl<F> Bounded<F> for Cylinder<F>
+where
+    F: Float,
+{
+    fn bounds(&self) -> Aabb<F> {
+        self.frame.bounds(&Aabb {
+            min: Three::new(-self.radius, -self.radius, F::zero()),
+            max: Three::new(self.radius, self.radius, self.height),
+        })
+    }
+}
+
+/// Where a point on a cap of `radius` around the z axis is, from 0 to 1 along x and y.
+pub(super) fn cap_uv<F: Float>(local: &Three<F>, radius: F) -> (F, F) {
+    let half = F::from(0.5f64).unwrap();
+    (
+        (local.x / radius + F::one()) * half,
+        (local.y / radius + F::one()) * half,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shoot(
+        cylinder: &Cylinder<f64>,
+        origin: Three<f64>,
+        direction: Three<f64>,
+    ) -> Option<Hit<f64>> {
+        let ray = Ray::new(origin, direction.normalized());
+        ray.shoot_at(cylinder, 1e-6, f64::INFINITY)
+    }
+
+    fn assert_near(a: Three<f64>, b: Three<f64>) {
+        assert!((a - b).length() < 1e-9, "{:?} is not {:?}", a, b);
+    }
+
+    #[test]
+    fn rays_hit_the_side_and_caps() {
+        let z = Three::new(0.0, 0.0, 1.0);
+        let cylinder = Cylinder::new(Three::zeros(), z * 2.0, 1.0);
+        let hit = shoot(
+            &cylinder,
+            Three::new(-3.0, 0.0, 1.0),
+            Three::new(1.0, 0.0, 0.0),
+        )
+        .unwrap();
+        assert!((hit.distance - 2.0).abs() < 1e-9);
+        assert_near(hit.normal, Three::new(-1.0, 0.0, 0.0));
+        // from inside it meets the side from within
+        let hit = shoot(
+            &cylinder,
+            Three::new(0.0, 0.0, 1.0),
+            Three::new(0.0, 1.0, 0.0),
+        )
+        .unwrap();
+        assert!((hit.distance - 1.0).abs() < 1e-9);
+        assert_near(hit.normal, Three::new(0.0, 1.0, 0.0));
+
+        // open ends let rays along the axis through
+        assert!(shoot(&cylinder, z * 5.0, -z).is_none());
+        let hit = shoot(&cylinder.clone().capped(), z * 5.0, -z).unwrap();
+        assert!((hit.distance - 3.0).abs() < 1e-9);
+        assert_near(hit.normal, z);
+        // past the ends it's missed
+        assert!(shoot(
+            &cylinder,
+            Three::new(-3.0, 0.0, 2.5),
+            Three::new(1.0, 0.0, 0.0)
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn swept_cylinders_are_cut_open() {
+        let cylinder = Cylinder::new(Three::zeros(), Three::new(0.0, 0.0, 2.0), 1.0).swept(90.0);
+        let kept = cylinder.frame.point(&Three::new(0.6, 0.6, 1.0));
+        let cut = cylinder.frame.point(&Three::new(-0.6, -0.6, 1.0));
+        let center = cylinder.frame.point(&Three::new(0.0, 0.0, 1.0));
+        let hit = shoot(&cylinder, center, kept - center).unwrap();
+        assert!((hit.distance - 1.0).abs() < 1e-9);
+        assert!(shoot(&cylinder, center, cut - center).is_none());
+    }
+}
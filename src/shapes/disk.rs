@@ -0,0 +1,149 @@
+use super::even_scale;
+use crate::data::{Aabb, Bounded, CanHit, Hit, Ray, Surface, Three};
+use crate::transform::Transform;
+use num_traits::{Float, FloatConst};
+use rand::Rng;
+use rand_distr::{uniform::SampleUniform, Distribution, Standard};
+
+/// A flat, round shape, like a spot light's lamp or a table top.
+#[derive(Debug, Clone)]
+pub struct Disk<F> {
+    pub(super) center: Three<F>,
+    pub(super) normal: Three<F>,
+    pub(super) radius: F,
+}
+
+impl<F> Disk<F>
+where
+    F: Float,
+{
+    pub fn new(center: Three<F>, normal: Three<F>, radius: F) -> Self {
+        Self {
+            center,
+            normal: normal.normalized(),
+            radius,
+        }
+    }
+
+    /// The disk moved by `transform`, which has to scale evenly to keep it round.
+    pub fn transformed(&self, transform: &Transform<F>) -> Self {
+        Self {
+            center: transform.point(&self.center),
+            normal: transform.normal(&self.normal).normalized(),
+            radius: self.radius * even_scale(transform, "disk"),
+        }
+    }
+
+    /// Where a point is on the square around the disk, from 0 to 1 along two axes in its plane.
+    fn coordinates(&self, point: &Three<F>) -> (F, F) {
+        let (u_axis, v_axis) = self.normal.orthonormal_basis();
+        let offset = (point - &self.center) / self.radius;
+        let half = F::from(0.5f64).unwrap();
+        (
+            (offset.dot(&u_axis) + F::one()) * half,
+            (offset.dot(&v_axis) + F::one()) * half,
+        )
+    }
+}
+
+impl<F> CanHit<Disk<F>, F> for Ray<F>
+where
+    F: Float,
+{
+    fn shoot_at(&self, disk: &Disk<F>, t_min: F, t_max: F) -> Option<Hit<F>> {
+        let denom = disk.normal.dot(&self.direction);
+        let distance = disk.normal.dot(&(disk.center - self.origin)) / denom;
+        if !distance.is_finite() || distance < t_min || distance >= t_max {
+            return None;
+        }
+        let position = self.origin + self.direction * distance;
+        if (position - disk.center).length_squared() > disk.radius * disk.radius {
+            return None;
+        }
+        Some(Hit {
+            position,
+            distance,
+            normal: disk.normal,
+            uv: disk.coordinates(&position),
+            object_index: 0,
+        })
+    }
+}
+
+impl<F> Surface<F> for Disk<F>
+where
+    F: Float + FloatConst + SampleUniform,
+    Standard: Distribution<F>,
+{
+    fn sample_point_on_surface<R: Rng>(&self, rng: &mut R) -> Three<F> {
+        // the square root spreads points evenly over the area instead of bunching at the center
+        let r: F = Standard.sample(rng);
+        let angle: F = Standard.sample(rng);
+        let r = r.sqrt() * self.radius;
+        let angle = angle * F::TAU();
+        let (u_axis, v_axis) = self.normal.orthonormal_basis();
+        self.center + u_axis * (r * angle.cos()) + v_axis * (r * angle.sin())
+    }
+
+    fn normal_at_point(&self, _point: &Three<F>) -> Three<F> {
+        self.normal
+    }
+
+    fn uv_at_point(&self, point: &Three<F>) -> (F, F) {
+        self.coordinates(point)
+    }
+
+    fn area(&self) -> F {
+        F::PI() * self.radius * self.radius
+    }
+}
+
+impl<F> Bounded<F> for Disk<F>
+where
+    F: Float,
+{
+    fn bounds(&self) -> Aabb<F> {
+        // along each axis the rim reaches as far as the plane is tilted away from it
+        let reach = |n: F| self.radius * (F::one() - n * n).max(F::zero()).sqrt();
+        let extent = Three::new(
+            reach(self.normal.x),
+            reach(self.normal.y),
+            reach(self.normal.z),
+        );
+        Aabb {
+            min: self.center - extent,
+            max: self.center + extent,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn area_and_points_cover_the_disk() {
+        let disk = Disk::new(Three::new(0.0, 1.0, 0.0), Three::new(1.0, 1.0, 0.0), 2.0);
+        assert!((disk.area() - 4.0 * PI).abs() < 1e-9);
+
+        let samples = 10_000;
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let (u_axis, _) = disk.normal.orthonormal_basis();
+        // the inner circle of radius √2 has half the area, as does each side of a diameter
+        let (mut inner, mut one_side) = (0, 0);
+        for _ in 0..samples {
+            let offset = disk.sample_point_on_surface(&mut rng) - disk.center;
+            assert!(offset.dot(&disk.normal).abs() < 1e-9, "{:?}", offset);
+            assert!(offset.length() <= 2.0 + 1e-9, "{:?}", offset);
+            inner += (offset.length() < 2.0f64.sqrt()) as usize;
+            one_side += (offset.dot(&u_axis) > 0.0) as usize;
+        }
+        for count in [inner, one_side] {
+            let fraction = count as f64 / samples as f64;
+            assert!((fraction - 0.5).abs() < 0.02, "{} {}", inner, one_side);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use crate::data::{Aabb, Ray, Three};
+use crate::transform::Transform;
+use num_traits::{Float, FloatConst};
+
+/// Position and orientation of a shape that's simplest to intersect in its own space, like a
+/// cylinder standing on the origin along z. Frames only rotate and move, so distances along a
+/// ray stay the same in both spaces.
+#[derive(Debug, Clone)]
+pub(super) struct Frame<F> {
+    pub(super) origin: Three<F>,
+    /// The shape's x, y and z axes.
+    pub(super) axes: [Three<F>; 3],
+}
+
+impl<F> Frame<F>
+where
+    F: Float,
+{
+    /// A frame at `origin` whose z axis points along `z`.
+    pub(super) fn new(origin: Three<F>, z: Three<F>) -> Self {
+        let z = z.normalized();
+        let (x, y) = z.orthonormal_basis();
+        Self {
+            origin,
+            axes: [x, y, z],
+        }
+    }
+
+    /// The frame moved by `transform`, which mustn't stretch it unevenly.
+    pub(super) fn transformed(&self, transform: &Transform<F>) -> Self {
+        Self {
+            origin: transform.point(&self.origin),
+            axes: self.axes.map(|axis| transform.vector(&axis).normalized()),
+        }
+    }
+
+    pub(super) fn local_vector(&self, v: &Three<F>) -> Three<F> {
+        Three::new(
+            v.dot(&self.axes[0]),
+            v.dot(&self.axes[1]),
+            v.dot(&self.axes[2]),
+        )
+    }
+
+    pub(super) fn local_point(&self, p: &Three<F>) -> Three<F> {
+        self.local_vector(&(p - &self.origin))
+    }
+
+    pub(super) fn local_ray(&self, ray: &Ray<F>) -> Ray<F> {
+        Ray {
+            origin: self.local_point(&ray.origin),
+            direction: self.local_vector(&ray.direction),
+            time: ray.time,
+        }
+    }
+
+    pub(super) fn vector(&self, v: &Three<F>) -> Three<F> {
+        self.axes[0] * v.x + self.axes[1] * v.y + self.axes[2] * v.z
+    }
+
+    pub(super) fn point(&self, p: &Three<F>) -> Three<F> {
+        self.origin + self.vector(p)
+    }
+
+    /// A box around a box given in the frame's space.
+    pub(super) fn bounds(&self, local: &Aabb<F>) -> Aabb<F> {
+        let corners = [local.min, local.max];
+        let points: Vec<Three<F>> = (0..8)
+            .map(|i| {
+                self.point(&Three::new(
+                    corners[i & 1].x,
+                    corners[(i >> 1) & 1].y,
+                    corners[(i >> 2) & 1].z,
+                ))
+            })
+            .collect();
+        Aabb::around(&points)
+    }
+}
+
+/// Angle of a point around the z axis, from 0 to 2π.
+pub(super) fn azimuth<F: Float + FloatConst>(p: &Three<F>) -> F {
+    let phi = p.y.atan2(p.x);
+    if phi < F::zero() {
+        phi + F::TAU()
+    } else {
+        phi
+    }
+}
+
+/// Distances along a ray where it meets a quadric with these coefficients, nearest first.
+pub(super) fn quadratic_roots<F: Float>(a: F, b: F, c: F) -> Option<[F; 2]> {
+    let discriminant = b * b - F::from(4.0f64).unwrap() * a * c;
+    if discriminant < F::zero() || a == F::zero() {
+        return None;
+    }
+    // avoids cancelling out digits when b and the square root are close
+    let q = -F::from(0.5f64).unwrap() * (b + discriminant.sqrt().copysign(b));
+    let (t0, t1) = (q / a, c / q);
+    Some(if t0 < t1 { [t0, t1] } else { [t1, t0] })
+}
+
+/// Distance along a ray to where it crosses a disk, if it does.
+pub(super) fn disk_distance<F: Float>(
+    ray: &Ray<F>,
+    center: &Three<F>,
+    normal: &Three<F>,
+    radius: F,
+) -> Option<F> {
+    let distance = normal.dot(&(center - &ray.origin)) / normal.dot(&ray.direction);
+    let position = ray.origin + ray.direction * distance;
+    Some(distance)
+        .filter(|d| d.is_finite() && (position - *center).length_squared() <= radius * radius)
+}
+
+/// Distances along a ray where it's inside a box, if it ever is.
+pub(super) fn clip<F: Float>(ray: &Ray<F>, bounds: &Aabb<F>) -> Option<(F, F)> {
+    let mut near = F::neg_infinity();
+    let mut far = F::infinity();
+    for i in 0..3 {
+        let inv = ray.direction.axis(i).recip();
+        let t0 = (bounds.min.axis(i) - ray.origin.axis(i)) * inv;
+        let t1 = (bounds.max.axis(i) - ray.origin.axis(i)) * inv;
+        near = near.max(t0.min(t1));
+        far = far.min(t0.max(t1));
+    }
+    Some((near, far)).filter(|(near, far)| near <= far)
+}
@@ -0,0 +1,518 @@
+use super::frame::clip;
+use crate::data::{Aabb, Bounded, CanHit, Hit, Ray, Surface, Three};
+use image::{DynamicImage, GenericImageView, ImageResult};
+use num_traits::{cast, Float};
+use rand::Rng;
+use rand_distr::{uniform::SampleUniform, Distribution, Standard};
+use std::path::Path;
+
+/// A landscape of heights over a grid in the xz plane, like terrain from an elevation map.
+///
+/// Each grid cell is two triangles between the heights at its corners, but normals and texture
+/// coordinates are interpolated smoothly across them. UVs go from 0 to 1 over the whole grid,
+/// lined up so that an image texture drapes over the grid like the image it was made from.
+#[derive(Debug, Clone)]
+pub struct Heightfield<F> {
+    /// Where the grid starts, at its lowest x and z.
+    corner: Three<F>,
+    /// Size of a grid cell along x and z.
+    cell: (F, F),
+    columns: usize,
+    rows: usize,
+    /// Height of each grid point, row by row along x, from the lowest z up.
+    heights: Vec<F>,
+    normals: Vec<Three<F>>,
+    /// Lowest and highest height in each cell, for skipping the cells a ray passes over.
+    cell_ranges: Vec<(F, F)>,
+    /// Running total of triangle areas, two per cell, for sampling points uniformly.
+    cumulative_area: Vec<F>,
+    bounds: Aabb<F>,
+}
+
+impl<F> Heightfield<F>
+where
+    F: Float,
+{
+    /// A grid of `columns` by `rows` points spread evenly from `corner` to `corner + size` in x
+    /// and z, lying on x first. `heights` are multiplied by `size.y` and lifted by `corner.y`.
+    pub fn new(
+        columns: usize,
+        rows: usize,
+        heights: Vec<F>,
+        corner: Three<F>,
+        size: Three<F>,
+    ) -> Self {
+        assert!(columns >= 2 && rows >= 2, "a heightfield needs 2x2 points");
+        assert_eq!(heights.len(), columns * rows);
+        let heights: Vec<F> = heights.iter().map(|&h| corner.y + h * size.y).collect();
+        let cell = (
+            size.x / cast(columns - 1).unwrap(),
+            size.z / cast(rows - 1).unwrap(),
+        );
+        let mut field = Self {
+            corner,
+            cell,
+            columns,
+            rows,
+            heights,
+            normals: Vec::new(),
+            cell_ranges: Vec::new(),
+            cumulative_area: Vec::new(),
+            bounds: Aabb::empty(),
+        };
+        field.normals = (0..rows)
+            .flat_map(|j| (0..columns).map(move |i| (i, j)))
+            .map(|(i, j)| field.vertex_normal(i, j))
+            .collect();
+        let mut total = F::zero();
+        for j in 0..rows - 1 {
+            for i in 0..columns - 1 {
+                let [v00, v10, v01, v11] = field.cell_corners(i, j);
+                field.cell_ranges.push((
+                    v00.y.min(v10.y).min(v01.y).min(v11.y),
+                    v00.y.max(v10.y).max(v01.y).max(v11.y),
+                ));
+                for (a, b, c) in [(v00, v10, v11), (v00, v11, v01)] {
+                    total = total + (b - a).cross(&(c - a)).length() * F::from(0.5f64).unwrap();
+                    field.cumulative_area.push(total);
+                }
+            }
+        }
+        let (low, high) = field
+            .heights
+            .iter()
+            .fold((F::infinity(), F::neg_infinity()), |(low, high), &h| {
+                (low.min(h), high.max(h))
+            });
+        field.bounds = Aabb {
+            min: Three::new(corner.x, low, corner.z),
+            max: Three::new(corner.x + size.x, high, corner.z + size.z),
+        };
+        field
+    }
+
+    /// A grid with a point per pixel of a grayscale image, from 0 for black to 1 for white
+    /// before scaling. The image's top row is along the lowest z, i.e. furthest from a camera
+    /// looking down the z axis.
+    pub fn from_image(image: &DynamicImage, corner: Three<F>, size: Three<F>) -> Self {
+        // widening 8 bit images to 16 bits would leave white short of 1, so read them as they are
+        let heights = match image {
+            DynamicImage::ImageLuma16(_)
+            | DynamicImage::ImageLumaA16(_)
+            | DynamicImage::ImageRgb16(_)
+            | DynamicImage::ImageRgba16(_) => {
+                let scale = F::from(u16::MAX).unwrap().recip();
+                image
+                    .to_luma16()
+                    .pixels()
+                    .map(|p| cast::<u16, F>(p[0]).unwrap() * scale)
+                    .collect()
+            }
+            _ => {
+                let scale = F::from(u8::MAX).unwrap().recip();
+                image
+                    .to_luma8()
+                    .pixels()
+                    .map(|p| cast::<u8, F>(p[0]).unwrap() * scale)
+                    .collect()
+            }
+        };
+        Self::new(
+            image.width() as usize,
+            image.height() as usize,
+            heights,
+            corner,
+            size,
+        )
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P, corner: Three<F>, size: Three<F>) -> ImageResult<Self> {
+        Ok(Self::from_image(&image::open(path)?, corner, size))
+    }
+
+    fn height(&self, i: usize, j: usize) -> F {
+        self.heights[j * self.columns + i]
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> Three<F> {
+        Three::new(
+            self.corner.x + self.cell.0 * cast(i).unwrap(),
+            self.height(i, j),
+            self.corner.z + self.cell.1 * cast(j).unwrap(),
+        )
+    }
+
+    /// The corners of a cell, at its lowest x and z, then higher x, higher z, and both higher.
+    fn cell_corners(&self, i: usize, j: usize) -> [Three<F>; 4] {
+        [
+            self.vertex(i, j),
+            self.vertex(i + 1, j),
+            self.vertex(i, j + 1),
+            self.vertex(i + 1, j + 1),
+        ]
+    }
+
+    /// Normal at a grid point, from the slope between its neighbors.
+    fn vertex_normal(&self, i: usize, j: usize) -> Three<F> {
+        let (left, right) = (i.saturating_sub(1), (i + 1).min(self.columns - 1));
+        let (back, front) = (j.saturating_sub(1), (j + 1).min(self.rows - 1));
+        let slope_x = (self.height(right, j) - self.height(left, j))
+            / (self.cell.0 * cast(right - left).unwrap());
+        let slope_z = (self.height(i, front) - self.height(i, back))
+            / (self.cell.1 * cast(front - back).unwrap());
+        Three::new(-slope_x, F::one(), -slope_z).normalized()
+    }
+
+    /// The cell a point is over, clamped to the grid, and how far across it the point is.
+    fn locate(&self, point: &Three<F>) -> (usize, usize, F, F) {
+        let locate_along = |offset: F, cell: F, cells: usize| {
+            let x = offset / cell;
+            let index = cast::<F, usize>(x.max(F::zero()).floor())
+                .unwrap_or(0)
+                .min(cells - 1);
+            (index, x - cast(index).unwrap())
+        };
+        let (i, fx) = locate_along(point.x - self.corner.x, self.cell.0, self.columns - 1);
+        let (j, fz) = locate_along(point.z - self.corner.z, self.cell.1, self.rows - 1);
+        (i, j, fx, fz)
+    }
+
+    fn normal_at(&self, point: &Three<F>) -> Three<F> {
+        let (i, j, fx, fz) = self.locate(point);
+        let normal = |i: usize, j: usize| self.normals[j * self.columns + i];
+        let (gx, gz) = (F::one() - fx, F::one() - fz);
+        (normal(i, j) * (gx * gz)
+            + normal(i + 1, j) * (fx * gz)
+            + normal(i, j + 1) * (gx * fz)
+            + normal(i + 1, j + 1) * (fx * fz))
+            .normalized()
+    }
+
+    fn uv_at(&self, point: &Three<F>) -> (F, F) {
+        let (i, j, fx, fz) = self.locate(point);
+        (
+            (cast::<usize, F>(i).unwrap() + fx) / cast(self.columns - 1).unwrap(),
+            F::one() - (cast::<usize, F>(j).unwrap() + fz) / cast(self.rows - 1).unwrap(),
+        )
+    }
+
+    /// Distance along a ray to where it hits one of the two triangles of a cell, if it does.
+    fn hit_cell(&self, ray: &Ray<F>, i: usize, j: usize, t_min: F, t_max: F) -> Option<F> {
+        let [v00, v10, v01, v11] = self.cell_corners(i, j);
+        [(v00, v10, v11), (v00, v11, v01)]
+            .into_iter()
+            .filter_map(|(a, b, c)| triangle_distance(ray, a, b, c))
+            .filter(|&t| t_min <= t && t < t_max)
+            .reduce(F::min)
+    }
+}
+
+/// Distance along a ray to a triangle, by the Möller-Trumbore algorithm.
+fn triangle_distance<F: Float>(ray: &Ray<F>, a: Three<F>, b: Three<F>, c: Three<F>) -> Option<F> {
+    let (ab, ac) = (b - a, c - a);
+    let pvec = ray.direction.cross(&ac);
+    let determinant = ab.dot(&pvec);
+    if determinant == F::zero() {
+        return None;
+    }
+    let tvec = ray.origin - a;
+    let u = tvec.dot(&pvec) / determinant;
+    if u < F::zero() || u > F::one() {
+        return None;
+    }
+    let qvec = tvec.cross(&ab);
+    let v = ray.direction.dot(&qvec) / determinant;
+    if v < F::zero() || u + v > F::one() {
+        return None;
+    }
+    Some(ac.dot(&qvec) / determinant)
+}
+
+impl<F> CanHit<Heightfield<F>, F> for Ray<F>
+where
+    F: Float,
+{
+    fn shoot_at(&self, field: &Heightfield<F>, t_min: F, t_max: F) -> Option<Hit<F>> {
+        let (near, far) = clip(self, &field.bounds)?;
+        let end = far.min(t_max);
+        let mut enter = near.max(t_min);
+        if enter > end {
+            return None;
+        }
+        // walk through the cells under the ray in order, like a line drawn across the grid
+        let (i, j, _, _) = field.locate(&(self.origin + self.direction * enter));
+        let (mut i, mut j) = (i as isize, j as isize);
+        let along = |origin: F, direction: F, start: F, cell: F, index: isize| {
+            if direction == F::zero() {
+                return (0, F::infinity(), F::infinity());
+            }
+            let step = if direction > F::zero() { 1 } else { -1 };
+            let boundary = start + cell * cast(index + step.max(0)).unwrap();
+            (
+                step,
+                (boundary - origin) / direction,
+                cell / direction.abs(),
+            )
+        };
+        let (step_x, mut next_x, delta_x) = along(
+            self.origin.x,
+            self.direction.x,
+            field.corner.x,
+            field.cell.0,
+            i,
+        );
+        let (step_z, mut next_z, delta_z) = along(
+            self.origin.z,
+            self.direction.z,
+            field.corner.z,
+            field.cell.1,
+            j,
+        );
+        let (columns, rows) = (field.columns as isize - 1, field.rows as isize - 1);
+        loop {
+            let exit = next_x.min(next_z).min(end);
+            let (low, high) = field.cell_ranges[(j * columns + i) as usize];
+            let (y_enter, y_exit) = (
+                self.origin.y + self.direction.y * enter,
+                self.origin.y + self.direction.y * exit,
+            );
+            // only look for a hit where the ray isn't all above or below the cell
+            if y_enter.min(y_exit) <= high && y_enter.max(y_exit) >= low {
+                if let Some(distance) = field.hit_cell(self, i as usize, j as usize, t_min, t_max) {
+                    let position = self.origin + self.direction * distance;
+                    return Some(Hit {
+                        position,
+                        distance,
+                        normal: field.normal_at(&position),
+                        uv: field.uv_at(&position),
+                        object_index: 0,
+                    });
+                }
+            }
+            if exit >= end {
+                return None;
+            }
+            if next_x < next_z {
+                i += step_x;
+                next_x = next_x + delta_x;
+            } else {
+                j += step_z;
+                next_z = next_z + delta_z;
+            }
+            if i < 0 || i >= columns || j < 0 || j >= rows {
+                return None;
+            }
+            enter = exit;
+        }
+    }
+}
+
+impl<F> Surface<F> for Heightfield<F>
+where
+    F: Float + SampleUniform,
+    Standard: Distribution<F>,
+{
+    fn sample_point_on_surface<R: Rng>(&self, rng: &mut R) -> Three<F> {
+        // pick a triangle proportional to its area so the point is uniform over the field
+        let u: F = Standard.sample(rng);
+        let index = self
+            .cumulative_area
+            .partition_point(|&area| area < u * self.area())
+            .min(self.cumulative_area.len() - 1);
+        let cells = self.columns - 1;
+        let [v00, v10, v01, v11] = self.cell_corners(index / 2 % cells, index / 2 / cells);
+        let (a, b, c) = if index % 2 == 0 {
+            (v00, v10, v11)
+        } else {
+            (v00, v11, v01)
+        };
+        let u1: F = Standard.sample(rng);
+        let u2: F = Standard.sample(rng);
+        let su1 = u1.sqrt();
+        a + (b - a) * (su1 * (F::one() - u2)) + (c - a) * (su1 * u2)
+    }
+
+    fn normal_at_point(&self, point: &Three<F>) -> Three<F> {
+        self.normal_at(point)
+    }
+
+    fn uv_at_point(&self, point: &Three<F>) -> (F, F) {
+        self.uv_at(point)
+    }
+
+    fn area(&self) -> F {
+        self.cumulative_area.last().copied().unwrap_or(F::zero())
+    }
+}
+
+impl<F> Bounded<F> for Heightfield<F>
+where
+    F: Float,
+{
+    fn bounds(&self) -> Aabb<F> {
+        self.bounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    fn shoot(
+        field: &Heightfield<f64>,
+        origin: Three<f64>,
+        direction: Three<f64>,
+    ) -> Option<Hit<f64>> {
+        let ray = Ray::new(origin, direction.normalized());
+        ray.shoot_at(field, 1e-9, f64::INFINITY)
+    }
+
+    fn assert_near(a: Three<f64>, b: Three<f64>) {
+        assert!((a - b).length() < 1e-9, "{:?} is not {:?}", a, b);
+    }
+
+    /// A 3x3 grid of unit cells from the origin, flat but for a point of height 1 in the middle.
+    ///
+    /// In the cell at the lowest x and z the surface is at height x where z > x and at height z
+    /// where x > z.
+    fn bump() -> Heightfield<f64> {
+        let mut heights = vec![0.0; 9];
+        heights[4] = 1.0;
+        Heightfield::new(3, 3, heights, Three::zeros(), Three::new(2.0, 1.0, 2.0))
+    }
+
+    #[test]
+    fn rays_hit_from_above_and_from_the_side() {
+        let field = bump();
+        let down = Three::new(0.0, -1.0, 0.0);
+        let hit = shoot(&field, Three::new(0.75, 5.0, 0.25), down).unwrap();
+        assert!((hit.distance - 4.75).abs() < 1e-9);
+        assert_near(hit.position, Three::new(0.75, 0.25, 0.25));
+        // beside the grid there's nothing to hit
+        assert!(shoot(&field, Three::new(-0.5, 5.0, 0.5), down).is_none());
+
+        // along x, with nothing moving it across rows
+        let along_x = Three::new(1.0, 0.0, 0.0);
+        let hit = shoot(&field, Three::new(-1.0, 0.5, 0.75), along_x).unwrap();
+        assert!((hit.distance - 1.5).abs() < 1e-9);
+        // and along z from the far side
+        let hit = shoot(
+            &field,
+            Three::new(0.75, 0.5, 3.0),
+            -Three::new(0.0, 0.0, 1.0),
+        )
+        .unwrap();
+        assert_near(hit.position, Three::new(0.75, 0.5, 1.25));
+
+        // skimming up the slope, meeting it where x = 0.11 + 0.01 x
+        let hit = shoot(
+            &field,
+            Three::new(-1.0, 0.1, 0.75),
+            Three::new(1.0, 0.01, 0.0),
+        )
+        .unwrap();
+        let x = 0.11 / 0.99;
+        assert_near(hit.position, Three::new(x, x, 0.75));
+        // and skimming just over the top
+        assert!(shoot(
+            &field,
+            Three::new(-1.0, 1.01, 1.0),
+            Three::new(1.0, 0.001, 0.0)
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn normals_are_blended_across_cells() {
+        let field = bump();
+        let corners = [
+            Three::new(0.0, 1.0, 0.0),
+            Three::new(0.0, 1.0, -1.0).normalized(),
+            Three::new(-1.0, 1.0, 0.0).normalized(),
+            Three::new(0.0, 1.0, 0.0),
+        ];
+        let blend = corners.iter().fold(Three::zeros(), |sum, &n| sum + n);
+        let hit = shoot(
+            &field,
+            Three::new(0.5, 5.0, 0.5),
+            Three::new(0.0, -1.0, 0.0),
+        )
+        .unwrap();
+        assert_near(hit.normal, blend.normalized());
+        assert_near(field.normal_at_point(&hit.position), blend.normalized());
+        // from the peak the slopes to either side cancel out
+        assert_near(
+            field.normal_at_point(&Three::new(1.0, 1.0, 1.0)),
+            Three::new(0.0, 1.0, 0.0),
+        );
+    }
+
+    #[test]
+    fn flat_fields_have_the_area_of_their_footprint() {
+        let field = Heightfield::new(
+            3,
+            4,
+            vec![0.5; 12],
+            Three::new(-1.0, 0.0, 2.0),
+            Three::new(2.0, 3.0, 3.0),
+        );
+        assert!((field.area() - 6.0).abs() < 1e-9);
+        let bounds = field.bounds();
+        assert_near(bounds.min, Three::new(-1.0, 1.5, 2.0));
+        assert_near(bounds.max, Three::new(1.0, 1.5, 5.0));
+    }
+
+    #[test]
+    fn images_are_draped_with_their_top_row_furthest_away() {
+        // a white top left pixel on a 3x2 black image
+        let image = image::GrayImage::from_raw(3, 2, vec![255, 0, 0, 0, 0, 0]).unwrap();
+        let field = Heightfield::from_image(
+            &DynamicImage::ImageLuma8(image),
+            Three::zeros(),
+            Three::new(2.0, 1.0, 1.0),
+        );
+        let down = Three::new(0.0, -1.0, 0.0);
+        // near the lowest x and z the ground slopes down from the white pixel
+        let hit = shoot(&field, Three::new(0.1, 5.0, 0.1), down).unwrap();
+        assert_near(hit.position, Three::new(0.1, 0.9, 0.1));
+        // which is at the top left of the image's texture coordinates
+        assert!((hit.uv.0 - 0.05).abs() < 1e-9 && (hit.uv.1 - 0.9).abs() < 1e-9);
+        let hit = shoot(&field, Three::new(1.9, 5.0, 0.9), down).unwrap();
+        assert_near(hit.position, Three::new(1.9, 0.0, 0.9));
+        assert!((hit.uv.0 - 0.95).abs() < 1e-9 && (hit.uv.1 - 0.1).abs() < 1e-9);
+
+        // 16 bit images reach the full height too
+        let image = image::ImageBuffer::from_raw(2, 2, vec![u16::MAX, 0, 0, 0]).unwrap();
+        let field = Heightfield::<f64>::from_image(
+            &DynamicImage::ImageLuma16(image),
+            Three::zeros(),
+            Three::from(1.0),
+        );
+        assert!((field.bounds().max.y - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sampled_points_are_on_the_surface_and_spread_evenly() {
+        let field = bump();
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let mut per_cell = [0usize; 4];
+        let samples = 10_000;
+        for _ in 0..samples {
+            let p = field.sample_point_on_surface(&mut rng);
+            let hit = shoot(
+                &field,
+                Three::new(p.x, 5.0, p.z),
+                Three::new(0.0, -1.0, 0.0),
+            );
+            assert!((hit.unwrap().position.y - p.y).abs() < 1e-9, "{:?}", p);
+            per_cell[(p.x >= 1.0) as usize + 2 * (p.z >= 1.0) as usize] += 1;
+        }
+        // every cell has the same slopes, only turned around
+        for count in per_cell {
+            let fraction = count as f64 / samples as f64;
+            assert!((fraction - 0.25).abs() < 0.02, "{:?}", per_cell);
+        }
+    }
+}
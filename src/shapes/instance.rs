@@ -0,0 +1,127 @@
+use super::Object;
+use crate::data::{Aabb, Bounded, CanHit, Hit, Ray, Surface, Three};
+use crate::transform::Transform;
+use num_traits::{Float, FloatConst};
+use rand::Rng;
+use rand_distr::{uniform::SampleUniform, Distribution, Standard};
+use std::sync::Arc;
+
+/// A shape placed by a transform. The shape is shared, so a forest of instances of one tree mesh
+/// only keeps the mesh in memory once.
+#[derive(Debug, Clone)]
+pub struct Instance<F> {
+    pub object: Arc<Object<F>>,
+    /// From the shape's own space to the scene's.
+    pub transform: Transform<F>,
+}
+
+impl<F> Instance<F>
+where
+    F: Float,
+{
+    pub fn new(object: Arc<Object<F>>, transform: Transform<F>) -> Self {
+        Self { object, transform }
+    }
+
+    /// The instance moved by `transform` after its own.
+    pub fn transformed(&self, transform: &Transform<F>) -> Self {
+        Self {
+            object: self.object.clone(),
+            transform: *transform * self.transform,
+        }
+    }
+}
+
+impl<F> CanHit<Instance<F>, F> for Ray<F>
+where
+    F: Float + FloatConst,
+{
+    fn shoot_at(&self, instance: &Instance<F>, t_min: F, t_max: F) -> Option<Hit<F>> {
+        shoot_transformed(self, &instance.object, &instance.transform, t_min, t_max)
+    }
+}
+
+/// Shoots a ray at a shape placed by `transform`.
+pub(super) fn shoot_transformed<F: Float + FloatConst>(
+    ray: &Ray<F>,
+    object: &Object<F>,
+    transform: &Transform<F>,
+    t_min: F,
+    t_max: F,
+) -> Option<Hit<F>> {
+    // shapes expect normalized directions, so distances in the shape's space are stretched
+    let local = transform.inverse().ray(ray);
+    let stretch = local.direction.length();
+    let local = Ray {
+        direction: local.direction / stretch,
+        ..local
+    };
+    local
+        .shoot_at(object, t_min * stretch, t_max * stretch)
+        .map(|hit| {
+            let distance = hit.distance / stretch;
+            Hit {
+                position: ray.origin + ray.direction * distance,
+                distance,
+                normal: transform.normal(&hit.normal).normalized(),
+                ..hit
+            }
+        })
+}
+
+/// Areas and sampled points are only uniform for transforms that scale evenly, which `Scene`
+/// checks for instances that are lights.
+impl<F> Surface<F> for Instance<F>
+where
+    F: Float + FloatConst + SampleUniform,
+    Standard: Distribution<F>,
+{
+    fn sample_point_on_surface<R: Rng>(&self, rng: &mut R) -> Three<F> {
+        self.transform
+            .point(&self.object.sample_point_on_surface(rng))
+    }
+
+    fn normal_at_point(&self, point: &Three<F>) -> Three<F> {
+        let local = self.transform.inverse().point(point);
+        self.transform
+            .normal(&self.object.normal_at_point(&local))
+            .normalized()
+    }
+
+    fn uv_at_point(&self, point: &Three<F>) -> (F, F) {
+        self.object
+            .uv_at_point(&self.transform.inverse().point(point))
+    }
+
+    fn area(&self) -> F {
+        let scale = self.transform.determinant().abs().cbrt();
+        self.object.area() * scale * scale
+    }
+}
+
+impl<F> Bounded<F> for Instance<F>
+where
+    F: Float,
+{
+    fn bounds(&self) -> Aabb<F> {
+        self.transform.bounds(&self.object.bounds())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::Sphere;
+
+    #[test]
+    fn only_evenly_scaled_instances_can_be_lights() {
+        let sphere = Arc::new(Object::from(Sphere::unit_at(0.0, 0.0, 0.0)));
+        let even = Instance::new(sphere.clone(), Transform::scaling(Three::from(2.0)));
+        assert!(Object::from(even.clone()).check_light_sampling().is_ok());
+        assert!((even.area() - 16.0 * std::f64::consts::PI).abs() < 1e-9);
+
+        let squashed = Transform::scaling(Three::new(1.0, 0.5, 1.0));
+        let nested = Instance::new(Arc::new(Object::from(even)), squashed);
+        assert!(Object::from(nested).check_light_sampling().is_err());
+    }
+}
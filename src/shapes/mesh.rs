@@ -0,0 +1,171 @@
+use super::triangle::Triangle;
+use crate::bvh::Bvh;
+use crate::data::{Aabb, Bounded, CanHit, Hit, Ray, Surface, Three};
+use crate::transform::Transform;
+use num_traits::Float;
+use rand::Rng;
+use rand_distr::{uniform::SampleUniform, Distribution, Standard};
+
+/// Many triangles that make up one object, like a model loaded from a file.
+#[derive(Debug, Clone)]
+pub struct Mesh<F> {
+    triangles: Vec<Triangle<F>>,
+    bvh: Bvh<F>,
+    /// Running total of triangle areas, for sampling points uniformly.
+    cumulative_area: Vec<F>,
+}
+
+impl<F> Mesh<F>
+where
+    F: Float,
+{
+    pub fn new(triangles: Vec<Triangle<F>>) -> Self {
+        let bounds: Vec<Aabb<F>> = triangles.iter().map(|tri| tri.bounds()).collect();
+        let cumulative_area = triangles
+            .iter()
+            .scan(F::zero(), |total, tri| {
+                *total = *total + tri.v01.cross(&tri.v02).length() * F::from(0.5f64).unwrap();
+                Some(*total)
+            })
+            .collect();
+        Self {
+            bvh: Bvh::new(&bounds),
+            triangles,
+            cumulative_area,
+        }
+    }
+
+    /// Builds a mesh from shared vertices and triangles given as three indices into them each.
+    pub fn indexed(vertices: &[Three<F>], indices: &[[usize; 3]]) -> Self {
+        Self::new(
+            indices
+                .iter()
+                .map(|&[i0, i1, i2]| Triangle::new(vertices[i0], vertices[i1], vertices[i2]))
+                .collect(),
+        )
+    }
+
+    pub fn triangles(&self) -> &[Triangle<F>] {
+        &self.triangles
+    }
+
+    /// The mesh with its vertices moved by `transform`. Use an `Instance` instead to place the
+    /// same mesh many times.
+    pub fn transformed(&self, transform: &Transform<F>) -> Self {
+        Self::new(
+            self.triangles
+                .iter()
+                .map(|tri| tri.transformed(transform))
+                .collect(),
+        )
+    }
+
+    /// The triangle a point on the surface of the mesh is on, i.e. whose plane it is closest to
+    /// among the triangles around it.
+    fn triangle_containing(&self, point: &Three<F>) -> &Triangle<F> {
+        let mut closest = (0, F::infinity());
+        self.bvh
+            .visit_near(point, F::from(1e-3f64).unwrap(), |tri_idx| {
+                let tri = &self.triangles[tri_idx];
+                let distance = tri.normal().dot(&(point - &tri.v0)).abs();
+                if distance < closest.1 {
+                    closest = (tri_idx, distance);
+                }
+            });
+        &self.triangles[closest.0]
+    }
+}
+
+impl<F> CanHit<Mesh<F>, F> for Ray<F>
+where
+    F: Float,
+{
+    fn shoot_at(&self, mesh: &Mesh<F>, t_min: F, t_max: F) -> Option<Hit<F>> {
+        mesh.bvh.shoot(self, t_min, t_max, |tri_idx, t_max| {
+            self.shoot_at(&mesh.triangles[tri_idx], t_min, t_max)
+        })
+    }
+}
+
+impl<F> Surface<F> for Mesh<F>
+where
+    F: Float + SampleUniform,
+    Standard: Distribution<F>,
+{
+    fn sample_point_on_surface<R: Rng>(&self, rng: &mut R) -> Three<F> {
+        // pick a triangle proportional to its area so the point is uniform over the mesh
+        let u: F = Standard.sample(rng);
+        let target = u * self.area();
+        let tri_idx = self
+            .cumulative_area
+            .partition_point(|&area| area < target)
+            .min(self.triangles.len().saturating_sub(1));
+        match self.triangles.get(tri_idx) {
+            Some(tri) => tri.sample_point_on_surface(rng),
+            // an empty mesh, e.g. from an empty file, has no area for lights to be sampled on,
+            // so this only happens when asked for directly
+            None => Three::zeros(),
+        }
+    }
+
+    fn normal_at_point(&self, point: &Three<F>) -> Three<F> {
+        self.triangle_containing(point).normal_at_point(point)
+    }
+
+    fn uv_at_point(&self, point: &Three<F>) -> (F, F) {
+        self.triangle_containing(point).uv_at_point(point)
+    }
+
+    fn area(&self) -> F {
+        self.cumulative_area.last().copied().unwrap_or(F::zero())
+    }
+}
+
+impl<F> Bounded<F> for Mesh<F>
+where
+    F: Float,
+{
+    fn bounds(&self) -> Aabb<F> {
+        self.bvh.bounds()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    #[test]
+    fn empty_mesh_has_no_area_and_samples_without_panicking() {
+        let mesh = Mesh::<f64>::new(Vec::new());
+        assert_eq!(mesh.area(), 0.0);
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        mesh.sample_point_on_surface(&mut rng);
+    }
+
+    #[test]
+    fn vertex_normals_are_interpolated_on_the_front_side() {
+        let triangle = Triangle::new(
+            Three::zeros(),
+            Three::new(1.0, 0.0, 0.0),
+            Three::new(0.0, 1.0, 0.0),
+        );
+        // pointing away from the winding's normal, which is +z
+        let normals = [
+            Three::new(0.0, 0.0, -1.0),
+            Three::new(-1.0, 0.0, -1.0),
+            Three::new(0.0, -1.0, -1.0),
+        ];
+        let mesh = Mesh::new(vec![
+            triangle.with_normals(normals[0], normals[1], normals[2])
+        ]);
+        let ray = Ray::new(Three::new(0.25, 0.25, 1.0), Three::new(0.0, 0.0, -1.0));
+        let hit = ray.shoot_at(&mesh, 1e-3, f64::INFINITY).unwrap();
+        let expected =
+            -(normals[0] * 0.5 + normals[1].normalized() * 0.25 + normals[2].normalized() * 0.25)
+                .normalized();
+        assert!((hit.normal - expected).length() < 1e-9, "{:?}", hit.normal);
+        assert!((mesh.normal_at_point(&hit.position) - expected).length() < 1e-9);
+    }
+}
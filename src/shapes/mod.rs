@@ -1,17 +1,44 @@
+mod cone;
+mod csg;
+mod cuboid;
+mod cylinder;
+mod disk;
+mod frame;
+mod heightfield;
+mod instance;
+mod mesh;
+mod moving_instance;
 mod plane;
 mod prism;
+mod quad;
+mod sdf_shape;
 mod sphere;
+mod torus;
 mod triangle;
 
+pub use cone::Cone;
+pub use csg::Csg;
+pub use cuboid::Cuboid;
+pub use cylinder::Cylinder;
+pub use disk::Disk;
+pub use heightfield::Heightfield;
+pub use instance::Instance;
+pub use mesh::Mesh;
+pub use moving_instance::MovingInstance;
 pub use plane::Plane;
 pub use prism::Prism;
+pub use quad::Quad;
+pub use sdf_shape::SdfShape;
 pub use sphere::Sphere;
+pub use torus::Torus;
 pub use triangle::Triangle;
 
-use crate::data::{CanHit, Hit, Ray, Surface, Three};
-use num_traits::Float;
+use crate::data::{Aabb, Bounded, CanHit, Hit, Ray, Surface, Three};
+use crate::transform::Transform;
+use num_traits::{Float, FloatConst};
 use rand::Rng;
 use rand_distr::{uniform::SampleUniform, Distribution, Standard};
+use std::borrow::Cow;
 
 #[derive(Debug, Clone)]
 pub enum Object<F> {
@@ -19,11 +46,78 @@ pub enum Object<F> {
     Sphere(Sphere<F>),
     Triangle(Triangle<F>),
     Prism(Prism<F>),
+    Mesh(Mesh<F>),
+    Instance(Instance<F>),
+    Cuboid(Cuboid<F>),
+    Quad(Quad<F>),
+    Disk(Disk<F>),
+    Cylinder(Cylinder<F>),
+    Cone(Cone<F>),
+    Torus(Torus<F>),
+    SdfShape(SdfShape<F>),
+    Heightfield(Heightfield<F>),
+    MovingInstance(MovingInstance<F>),
+    Csg(Csg<F>),
 }
 
-impl<F> CanHit<Object<F>, F> for Ray<F>
+impl<F> Object<F>
 where
     F: Float,
+{
+    /// Checks that `area` and `sample_point_on_surface` are exact for this object, which lights
+    /// need to be sampled without bias. Returns why they aren't otherwise.
+    pub fn check_light_sampling(&self) -> Result<(), &'static str> {
+        match self {
+            // areas grow by different amounts depending on which way the surface faces
+            Object::Instance(instance) if instance.transform.uniform_scale().is_none() => {
+                Err("instances of lights have to scale their shape evenly")
+            }
+            Object::Instance(instance) => instance.object.check_light_sampling(),
+            // lights are sampled where they are at each ray's time, but their area is fixed
+            Object::MovingInstance(instance) if !instance.scales_steadily() => Err(
+                "moving lights have to scale their shape evenly, and by the same amount \
+                     throughout",
+            ),
+            Object::MovingInstance(instance) => instance.object.check_light_sampling(),
+            Object::Plane(_) => Err("planes are infinite, so points can't be sampled on them"),
+            Object::Csg(_) => {
+                Err("the surface left of a solid isn't known exactly, so it can't be sampled")
+            }
+            Object::SdfShape(_) => {
+                Err("the surface of a distance field is only estimated, so it can't be sampled")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The object where it is at `time`, which is where lights are sampled for rays traced
+    /// then. Moving instances are placed by their motion, and other objects don't move.
+    pub fn at_time(&self, time: F) -> Cow<'_, Object<F>> {
+        match self {
+            Object::MovingInstance(instance) => Cow::Owned(Object::Instance(Instance::new(
+                instance.object.clone(),
+                instance.motion.at(time),
+            ))),
+            _ => Cow::Borrowed(self),
+        }
+    }
+}
+
+/// How much `transform` scales a round shape, which only keeps its shape under transforms that
+/// scale evenly.
+fn even_scale<F: Float>(transform: &Transform<F>, shape: &str) -> F {
+    transform.uniform_scale().unwrap_or_else(|| {
+        panic!(
+            "a {} can only be moved by transforms that scale it evenly, place it with an \
+             `Instance` otherwise",
+            shape
+        )
+    })
+}
+
+impl<F> CanHit<Object<F>, F> for Ray<F>
+where
+    F: Float + FloatConst,
 {
     fn shoot_at(&self, obj: &Object<F>, t_min: F, t_max: F) -> Option<Hit<F>> {
         match obj {
@@ -31,13 +125,25 @@ where
             Object::Sphere(obj) => self.shoot_at(obj, t_min, t_max),
             Object::Triangle(obj) => self.shoot_at(obj, t_min, t_max),
             Object::Prism(obj) => self.shoot_at(obj, t_min, t_max),
+            Object::Mesh(obj) => self.shoot_at(obj, t_min, t_max),
+            Object::Instance(obj) => self.shoot_at(obj, t_min, t_max),
+            Object::Cuboid(obj) => self.shoot_at(obj, t_min, t_max),
+            Object::Quad(obj) => self.shoot_at(obj, t_min, t_max),
+            Object::Disk(obj) => self.shoot_at(obj, t_min, t_max),
+            Object::Cylinder(obj) => self.shoot_at(obj, t_min, t_max),
+            Object::Cone(obj) => self.shoot_at(obj, t_min, t_max),
+            Object::Torus(obj) => self.shoot_at(obj, t_min, t_max),
+            Object::SdfShape(obj) => self.shoot_at(obj, t_min, t_max),
+            Object::Heightfield(obj) => self.shoot_at(obj, t_min, t_max),
+            Object::MovingInstance(obj) => self.shoot_at(obj, t_min, t_max),
+            Object::Csg(obj) => self.shoot_at(obj, t_min, t_max),
         }
     }
 }
 
 impl<F> Surface<F> for Object<F>
 where
-    F: Float + SampleUniform,
+    F: Float + FloatConst + SampleUniform,
     Standard: Distribution<F>,
 {
     fn sample_point_on_surface<R: Rng>(&self, rng: &mut R) -> Three<F> {
@@ -46,6 +152,18 @@ where
             Object::Sphere(obj) => obj.sample_point_on_surface(rng),
             Object::Triangle(obj) => obj.sample_point_on_surface(rng),
             Object::Prism(obj) => obj.sample_point_on_surface(rng),
+            Object::Mesh(obj) => obj.sample_point_on_surface(rng),
+            Object::Instance(obj) => obj.sample_point_on_surface(rng),
+            Object::Cuboid(obj) => obj.sample_point_on_surface(rng),
+            Object::Quad(obj) => obj.sample_point_on_surface(rng),
+            Object::Disk(obj) => obj.sample_point_on_surface(rng),
+            Object::Cylinder(obj) => obj.sample_point_on_surface(rng),
+            Object::Cone(obj) => obj.sample_point_on_surface(rng),
+            Object::Torus(obj) => obj.sample_point_on_surface(rng),
+            Object::SdfShape(obj) => obj.sample_point_on_surface(rng),
+            Object::Heightfield(obj) => obj.sample_point_on_surface(rng),
+            Object::MovingInstance(obj) => obj.sample_point_on_surface(rng),
+            Object::Csg(obj) => obj.sample_point_on_surface(rng),
         }
     }
 
@@ -55,30 +173,319 @@ where
             Object::Sphere(obj) => obj.normal_at_point(point),
             Object::Triangle(obj) => obj.normal_at_point(point),
             Object::Prism(obj) => obj.normal_at_point(point),
+            Object::Mesh(obj) => obj.normal_at_point(point),
+            Object::Instance(obj) => obj.normal_at_point(point),
+            Object::Cuboid(obj) => obj.normal_at_point(point),
+            Object::Quad(obj) => obj.normal_at_point(point),
+            Object::Disk(obj) => obj.normal_at_point(point),
+            Object::Cylinder(obj) => obj.normal_at_point(point),
+            Object::Cone(obj) => obj.normal_at_point(point),
+            Object::Torus(obj) => obj.normal_at_point(point),
+            Object::SdfShape(obj) => obj.normal_at_point(point),
+            Object::Heightfield(obj) => obj.normal_at_point(point),
+            Object::MovingInstance(obj) => obj.normal_at_point(point),
+            Object::Csg(obj) => obj.normal_at_point(point),
+        }
+    }
+
+    fn uv_at_point(&self, point: &Three<F>) -> (F, F) {
+        match self {
+            Object::Plane(obj) => obj.uv_at_point(point),
+            Object::Sphere(obj) => obj.uv_at_point(point),
+            Object::Triangle(obj) => obj.uv_at_point(point),
+            Object::Prism(obj) => obj.uv_at_point(point),
+            Object::Mesh(obj) => obj.uv_at_point(point),
+            Object::Instance(obj) => obj.uv_at_point(point),
+            Object::Cuboid(obj) => obj.uv_at_point(point),
+            Object::Quad(obj) => obj.uv_at_point(point),
+            Object::Disk(obj) => obj.uv_at_point(point),
+            Object::Cylinder(obj) => obj.uv_at_point(point),
+            Object::Cone(obj) => obj.uv_at_point(point),
+            Object::Torus(obj) => obj.uv_at_point(point),
+            Object::SdfShape(obj) => obj.uv_at_point(point),
+            Object::Heightfield(obj) => obj.uv_at_point(point),
+            Object::MovingInstance(obj) => obj.uv_at_point(point),
+            Object::Csg(obj) => obj.uv_at_point(point),
+        }
+    }
+
+    fn area(&self) -> F {
+        match self {
+            Object::Plane(obj) => obj.area(),
+            Object::Sphere(obj) => obj.area(),
+            Object::Triangle(obj) => obj.area(),
+            Object::Prism(obj) => obj.area(),
+            Object::Mesh(obj) => obj.area(),
+            Object::Instance(obj) => obj.area(),
+            Object::Cuboid(obj) => obj.area(),
+            Object::Quad(obj) => obj.area(),
+            Object::Disk(obj) => obj.area(),
+            Object::Cylinder(obj) => obj.area(),
+            Object::Cone(obj) => obj.area(),
+            Object::Torus(obj) => obj.area(),
+            Object::SdfShape(obj) => obj.area(),
+            Object::Heightfield(obj) => obj.area(),
+            Object::MovingInstance(obj) => obj.area(),
+            Object::Csg(obj) => obj.area(),
+        }
+    }
+}
+
+impl<F> Bounded<F> for Object<F>
+where
+    F: Float,
+{
+    fn bounds(&self) -> Aabb<F> {
+        match self {
+            Object::Plane(obj) => obj.bounds(),
+            Object::Sphere(obj) => obj.bounds(),
+            Object::Triangle(obj) => obj.bounds(),
+            Object::Prism(obj) => obj.bounds(),
+            Object::Mesh(obj) => obj.bounds(),
+            Object::Instance(obj) => obj.bounds(),
+            Object::Cuboid(obj) => obj.bounds(),
+            Object::Quad(obj) => obj.bounds(),
+            Object::Disk(obj) => obj.bounds(),
+            Object::Cylinder(obj) => obj.bounds(),
+            Object::Cone(obj) => obj.bounds(),
+            Object::Torus(obj) => obj.bounds(),
+            Object::SdfShape(obj) => obj.bounds(),
+            Object::Heightfield(obj) => obj.bounds(),
+            Object::MovingInstance(obj) => obj.bounds(),
+            Object::Csg(obj) => obj.bounds(),
         }
     }
 }
 
-impl<F> Into<Object<F>> for Plane<F> {
-    fn into(self) -> Object<F> {
-        Object::Plane(self)
+impl<F> From<Plane<F>> for Object<F> {
+    fn from(obj: Plane<F>) -> Self {
+        Object::Plane(obj)
+    }
+}
+
+impl<F> From<Sphere<F>> for Object<F> {
+    fn from(obj: Sphere<F>) -> Self {
+        Object::Sphere(obj)
+    }
+}
+
+impl<F> From<Triangle<F>> for Object<F> {
+    fn from(obj: Triangle<F>) -> Self {
+        Object::Triangle(obj)
+    }
+}
+
+impl<F> From<Prism<F>> for Object<F> {
+    fn from(obj: Prism<F>) -> Self {
+        Object::Prism(obj)
     }
 }
 
-impl<F> Into<Object<F>> for Sphere<F> {
-    fn into(self) -> Object<F> {
-        Object::Sphere(self)
+impl<F> From<Mesh<F>> for Object<F> {
+    fn from(obj: Mesh<F>) -> Self {
+        Object::Mesh(obj)
     }
 }
 
-impl<F> Into<Object<F>> for Triangle<F> {
-    fn into(self) -> Object<F> {
-        Object::Triangle(self)
+impl<F> From<Instance<F>> for Object<F> {
+    fn from(obj: Instance<F>) -> Self {
+        Object::Instance(obj)
     }
 }
 
-impl<F> Into<Object<F>> for Prism<F> {
-    fn into(self) -> Object<F> {
-        Object::Prism(self)
+impl<F> From<Cuboid<F>> for Object<F> {
+    fn from(obj: Cuboid<F>) -> Self {
+        Object::Cuboid(obj)
+    }
+}
+
+impl<F> From<Quad<F>> for Object<F> {
+    fn from(obj: Quad<F>) -> Self {
+        Object::Quad(obj)
+    }
+}
+
+impl<F> From<Disk<F>> for Object<F> {
+    fn from(obj: Disk<F>) -> Self {
+        Object::Disk(obj)
+    }
+}
+
+impl<F> From<Cylinder<F>> for Object<F> {
+    fn from(obj: Cylinder<F>) -> Self {
+        Object::Cylinder(obj)
+    }
+}
+
+impl<F> From<Cone<F>> for Object<F> {
+    fn from(obj: Cone<F>) -> Self {
+        Object::Cone(obj)
+    }
+}
+
+impl<F> From<Torus<F>> for Object<F> {
+    fn from(obj: Torus<F>) -> Self {
+        Object::Torus(obj)
+    }
+}
+
+impl<F> From<SdfShape<F>> for Object<F> {
+    fn from(obj: SdfShape<F>) -> Self {
+        Object::SdfShape(obj)
+    }
+}
+
+impl<F> From<Heightfield<F>> for Object<F> {
+    fn from(obj: Heightfield<F>) -> Self {
+        Object::Heightfield(obj)
+    }
+}
+
+impl<F> From<MovingInstance<F>> for Object<F> {
+    fn from(obj: MovingInstance<F>) -> Self {
+        Object::MovingInstance(obj)
+    }
+}
+
+impl<F> From<Csg<F>> for Object<F> {
+    fn from(obj: Csg<F>) -> Self {
+        Object::Csg(obj)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_distr::UnitSphere;
+    use rand_xorshift::XorShiftRng;
+    use std::sync::Arc;
+
+    #[test]
+    fn transformed_shapes_match_their_instances() {
+        let transform = Transform::translation(Three::new(0.5, -1.0, 2.0))
+            * Transform::rotation(&Three::new(1.0, 2.0, 0.5), 40.0)
+            * Transform::scaling(Three::from(1.5));
+        let origin = Three::zeros();
+        let z = Three::new(0.0, 0.0, 1.0);
+        let shapes: Vec<(Object<f64>, Object<f64>)> = vec![
+            (
+                Sphere::new(origin, 1.0).into(),
+                Sphere::new(origin, 1.0).transformed(&transform).into(),
+            ),
+            (
+                Cuboid::new(Three::from(-1.0), Three::new(1.0, 0.5, 0.8)).into(),
+                Cuboid::new(Three::from(-1.0), Three::new(1.0, 0.5, 0.8))
+                    .transformed(&transform)
+                    .into(),
+            ),
+            (
+                Disk::new(origin, z, 2.0).into(),
+                Disk::new(origin, z, 2.0).transformed(&transform).into(),
+            ),
+            (
+                Quad::new(Three::from(-1.0), Three::new(2.0, 0.0, 0.0), z * 2.0).into(),
+                Quad::new(Three::from(-1.0), Three::new(2.0, 0.0, 0.0), z * 2.0)
+                    .transformed(&transform)
+                    .into(),
+            ),
+            (
+                Plane::facing_pos_y().into(),
+                Plane::facing_pos_y().transformed(&transform).into(),
+            ),
+            (
+                Cylinder::new(-z, z, 1.0).capped().into(),
+                Cylinder::new(-z, z, 1.0)
+                    .capped()
+                    .transformed(&transform)
+                    .into(),
+            ),
+            (
+                Cone::new(-z, z, 1.0).capped().into(),
+                Cone::new(-z, z, 1.0)
+                    .capped()
+                    .transformed(&transform)
+                    .into(),
+            ),
+            (
+                Torus::new(origin, z, 1.0, 0.4).into(),
+                Torus::new(origin, z, 1.0, 0.4)
+                    .transformed(&transform)
+                    .into(),
+            ),
+        ];
+        let mut rng = XorShiftRng::seed_from_u64(3);
+        for (shape, transformed) in shapes {
+            let instance = Instance::new(Arc::new(shape), transform);
+            let mut hits = 0;
+            for _ in 0..200 {
+                let target = transform.point(&Three::from(UnitSphere.sample(&mut rng)));
+                let origin = target + Three::from(UnitSphere.sample(&mut rng)) * 8.0;
+                let ray = Ray::new(origin, (target - origin).normalized());
+                let expected = ray.shoot_at(&instance, 1e-6, f64::INFINITY);
+                let hit = ray.shoot_at(&transformed, 1e-6, f64::INFINITY);
+                match (expected, hit) {
+                    (Some(expected), Some(hit)) => {
+                        hits += 1;
+                        assert!((expected.distance - hit.distance).abs() < 1e-6);
+                        assert!((expected.normal - hit.normal).length() < 1e-6);
+                    }
+                    (None, None) => {}
+                    (expected, hit) => panic!("{:?} is not {:?}", hit, expected),
+                }
+            }
+            assert!(hits > 50, "{:?}", transformed);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "scale it evenly")]
+    fn round_shapes_refuse_uneven_scales() {
+        Sphere::unit_at(0.0, 0.0, 0.0).transformed(&Transform::scaling(Three::new(1.0, 2.0, 1.0)));
+    }
+
+    #[test]
+    fn planes_sample_without_panicking_but_cant_be_lights() {
+        let plane = Object::<f64>::from(Plane::facing_pos_y());
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        assert_eq!(plane.sample_point_on_surface(&mut rng), Three::zeros());
+        assert!(plane.check_light_sampling().is_err());
+    }
+
+    #[test]
+    fn moving_lights_are_sampled_where_they_are_at_the_time() {
+        use crate::transform::{Keyframe, Motion};
+        let sphere = Arc::new(Object::from(Sphere::new(Three::zeros(), 0.5)));
+        let motion = Motion::new(vec![
+            Keyframe::new(0.0).with_scale(Three::from(2.0)),
+            Keyframe::new(1.0)
+                .with_scale(Three::from(2.0))
+                .with_translation(Three::new(10.0, 0.0, 0.0)),
+        ]);
+        let moving = Object::from(MovingInstance::new(sphere.clone(), motion));
+        assert!(moving.check_light_sampling().is_ok());
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        for &(time, x) in &[(0.0, 0.0), (0.5, 5.0), (1.0, 10.0)] {
+            let placed = moving.at_time(time);
+            assert!((placed.area() - moving.area()).abs() < 1e-9);
+            for _ in 0..20 {
+                let point = placed.sample_point_on_surface(&mut rng);
+                let offset = point - Three::new(x, 0.0, 0.0);
+                assert!(
+                    (offset.length() - 1.0).abs() < 1e-9,
+                    "{:?} at {}",
+                    point,
+                    time
+                );
+                assert!((placed.normal_at_point(&point) - offset).length() < 1e-9);
+            }
+        }
+
+        let growing = Motion::new(vec![
+            Keyframe::new(0.0),
+            Keyframe::new(1.0).with_scale(Three::from(2.0)),
+        ]);
+        let growing = Object::from(MovingInstance::new(sphere, growing));
+        assert!(growing.check_light_sampling().is_err());
     }
 }
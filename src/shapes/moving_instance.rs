@@ -0,0 +1,94 @@
+use super::instance::shoot_transformed;
+use super::Object;
+use crate::data::{Aabb, Bounded, CanHit, Hit, Ray, Surface, Three};
+use crate::transform::Motion;
+use num_traits::{Float, FloatConst};
+use rand::Rng;
+use rand_distr::{uniform::SampleUniform, Distribution, Standard};
+use std::sync::Arc;
+
+/// A shape moved by a transform that changes over time, which rays find where it is at their
+/// `time`, so it blurs across the image while the camera's shutter is open.
+#[derive(Debug, Clone)]
+pub struct MovingInstance<F> {
+    pub object: Arc<Object<F>>,
+    /// From the shape's own space to the scene's, over time.
+    pub motion: Motion<F>,
+}
+
+impl<F> MovingInstance<F>
+where
+    F: Float,
+{
+    pub fn new(object: Arc<Object<F>>, motion: Motion<F>) -> Self {
+        Self { object, motion }
+    }
+
+    /// Whether every keyframe scales the shape evenly and by the same amount, so its area stays
+    /// the same while it moves.
+    pub(super) fn scales_steadily(&self) -> bool {
+        let mut scales = self
+            .motion
+            .keyframes()
+            .iter()
+            .map(|keyframe| keyframe.transform().uniform_scale());
+        let first = match scales.next().flatten() {
+            Some(first) => first,
+            None => return false,
+        };
+        let tolerance = first * F::from(1e-6f64).unwrap();
+        scales.all(|scale| scale.is_some_and(|scale| (scale - first).abs() <= tolerance))
+    }
+}
+
+impl<F> CanHit<MovingInstance<F>, F> for Ray<F>
+where
+    F: Float + FloatConst,
+{
+    fn shoot_at(&self, instance: &MovingInstance<F>, t_min: F, t_max: F) -> Option<Hit<F>> {
+        let transform = instance.motion.at(self.time);
+        shoot_transformed(self, &instance.object, &transform, t_min, t_max)
+    }
+}
+
+/// Points on the surface are where the shape is at time 0. Lights are sampled on
+/// `Object::at_time` instead, which places the shape where it is at the ray's time.
+impl<F> Surface<F> for MovingInstance<F>
+where
+    F: Float + FloatConst + SampleUniform,
+    Standard: Distribution<F>,
+{
+    fn sample_point_on_surface<R: Rng>(&self, rng: &mut R) -> Three<F> {
+        self.motion
+            .at(F::zero())
+            .point(&self.object.sample_point_on_surface(rng))
+    }
+
+    fn normal_at_point(&self, point: &Three<F>) -> Three<F> {
+        let transform = self.motion.at(F::zero());
+        let local = transform.inverse().point(point);
+        transform
+            .normal(&self.object.normal_at_point(&local))
+            .normalized()
+    }
+
+    fn uv_at_point(&self, point: &Three<F>) -> (F, F) {
+        let local = self.motion.at(F::zero()).inverse().point(point);
+        self.object.uv_at_point(&local)
+    }
+
+    fn area(&self) -> F {
+        let scale = self.motion.at(F::zero()).determinant().abs().cbrt();
+        self.object.area() * scale * scale
+    }
+}
+
+impl<F> Bounded<F> for MovingInstance<F>
+where
+    F: Float,
+{
+    /// Around everywhere the shape goes, so acceleration structures find it at any time.
+    fn bounds(&self) -> Aabb<F> {
+        self.motion.bounds(&self.object.bounds())
+    }
+}
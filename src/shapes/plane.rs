@@ -1,4 +1,5 @@
-use crate::data::{CanHit, Hit, Ray, Surface, Three};
+use crate::data::{Aabb, Bounded, CanHit, Hit, Ray, Surface, Three};
+use crate::transform::Transform;
 use num_traits::Float;
 use rand::Rng;
 
@@ -43,10 +44,11 @@ where
         }
     }
 
-    pub fn shifted_back(&self, dist: F) -> Self {
+    /// The plane moved by `transform`.
+    pub fn transformed(&self, transform: &Transform<F>) -> Self {
         Self {
-            center: self.center - self.normal * dist,
-            normal: self.normal,
+            center: transform.point(&self.center),
+            normal: transform.normal(&self.normal).normalized(),
         }
     }
 }
@@ -57,16 +59,16 @@ where
 {
     fn shoot_at(&self, plane: &Plane<F>, t_min: F, t_max: F) -> Option<Hit<F>> {
         let denom = plane.normal.dot(&self.direction);
-        let origin_to_center = &plane.center - &self.origin;
+        let origin_to_center = plane.center - self.origin;
         Some(origin_to_center.dot(&plane.normal) / denom)
             .filter(|&v| v.is_finite() && t_min <= v && v < t_max)
             .map(|distance| {
-                let offset = &self.direction * distance;
-                let position = &self.origin + &offset;
+                let position = self.origin + self.direction * distance;
                 Hit {
                     position,
                     distance,
                     normal: plane.normal,
+                    uv: plane.uv_at_point(&position),
                     object_index: 0,
                 }
             })
@@ -77,11 +79,33 @@ impl<F> Surface<F> for Plane<F>
 where
     F: Float,
 {
+    /// There's no uniform point on an infinite plane, so this is always its center. Scenes
+    /// don't let planes be lights for this reason.
     fn sample_point_on_surface<R: Rng>(&self, _rng: &mut R) -> Three<F> {
-        todo!("sample random offset... could be infinitely far from center")
+        self.center
     }
 
     fn normal_at_point(&self, _point: &Three<F>) -> Three<F> {
         self.normal
     }
+
+    /// Distances from the plane's center along two axes in the plane, so textures tile over it.
+    fn uv_at_point(&self, point: &Three<F>) -> (F, F) {
+        let (u_axis, v_axis) = self.normal.orthonormal_basis();
+        let offset = point - &self.center;
+        (offset.dot(&u_axis), offset.dot(&v_axis))
+    }
+
+    fn area(&self) -> F {
+        F::infinity()
+    }
+}
+
+impl<F> Bounded<F> for Plane<F>
+where
+    F: Float,
+{
+    fn bounds(&self) -> Aabb<F> {
+        Aabb::infinite()
+    }
 }
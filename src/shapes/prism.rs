@@ -1,7 +1,8 @@
 use super::triangle::Triangle;
-use crate::data::{CanHit, Hit, Ray, Surface, Three};
+use crate::data::{Aabb, Bounded, CanHit, Hit, Ray, Surface, Three};
+use crate::transform::Transform;
 use num_traits::Float;
-use rand::prelude::{Rng, SliceRandom};
+use rand::Rng;
 use rand_distr::{uniform::SampleUniform, Distribution, Standard};
 
 #[derive(Debug, Clone)]
@@ -47,27 +48,24 @@ where
         }
     }
 
-    fn center(&self) -> Three<F> {
-        let (v0, v1, _v2) = self.triangles[0].vertices();
-        let (v3, v4, _v5) = self.triangles[1].vertices();
-        (v0 + v1 + v3 + v4) * F::from(0.25f64).unwrap()
-    }
-
-    pub fn rotated_around(&self, axis: &Three<F>, angle: F) -> Self {
-        let center = self.center();
-        let mut t = self.shifted(-center);
-        for tri in t.triangles.iter_mut() {
-            *tri = tri.rotated_around(axis, angle);
+    /// The prism with its corners moved by `transform`.
+    pub fn transformed(&self, transform: &Transform<F>) -> Self {
+        Self {
+            triangles: self.triangles.clone().map(|tri| tri.transformed(transform)),
         }
-        t.shifted(center)
     }
 
-    pub fn shifted(&self, offset: Three<F>) -> Self {
-        let mut t = self.clone();
-        for tri in t.triangles.iter_mut() {
-            *tri = tri.shifted(offset);
-        }
-        t
+    /// The triangle a point on the surface of the prism is on, i.e. whose plane it is closest to.
+    fn triangle_containing(&self, point: &Three<F>) -> &Triangle<F> {
+        let distance_to_plane = |tri: &Triangle<F>| tri.normal().dot(&(point - &tri.v0)).abs();
+        self.triangles
+            .iter()
+            .min_by(|a, b| {
+                distance_to_plane(a)
+                    .partial_cmp(&distance_to_plane(b))
+                    .unwrap()
+            })
+            .unwrap()
     }
 }
 
@@ -96,13 +94,42 @@ where
     Standard: Distribution<F>,
 {
     fn sample_point_on_surface<R: Rng>(&self, rng: &mut R) -> Three<F> {
-        self.triangles
-            .choose(rng)
-            .unwrap()
+        // pick a triangle proportional to its area so the point is uniform over the prism
+        let u: F = Standard.sample(rng);
+        let mut remaining = u * self.area();
+        let (last, rest) = self.triangles.split_last().unwrap();
+        rest.iter()
+            .find(|tri| {
+                remaining = remaining - tri.area();
+                remaining <= F::zero()
+            })
+            // rounding can leave a little over after the others, which is the last one's
+            .unwrap_or(last)
             .sample_point_on_surface(rng)
     }
 
-    fn normal_at_point(&self, _point: &Three<F>) -> Three<F> {
-        todo!("find triangle its on and return its normal")
+    fn normal_at_point(&self, point: &Three<F>) -> Three<F> {
+        self.triangle_containing(point).normal()
+    }
+
+    fn uv_at_point(&self, point: &Three<F>) -> (F, F) {
+        self.triangle_containing(point).uv_at_point(point)
+    }
+
+    fn area(&self) -> F {
+        self.triangles
+            .iter()
+            .fold(F::zero(), |area, tri| area + tri.area())
+    }
+}
+
+impl<F> Bounded<F> for Prism<F>
+where
+    F: Float,
+{
+    fn bounds(&self) -> Aabb<F> {
+        self.triangles
+            .iter()
+            .fold(Aabb::empty(), |aabb, tri| aabb.union(&tri.bounds()))
     }
 }
@@ -0,0 +1,149 @@
+use crate::data::{Aabb, Bounded, CanHit, Hit, Ray, Surface, Three};
+use crate::transform::Transform;
+use num_traits::Float;
+use rand::Rng;
+use rand_distr::{uniform::SampleUniform, Distribution, Standard};
+
+/// A flat parallelogram, like a rectangular wall or area light, spanned by two edges from a
+/// corner. It faces along `u × v`.
+#[derive(Debug, Clone)]
+pub struct Quad<F> {
+    pub(super) corner: Three<F>,
+    pub(super) u: Three<F>,
+    pub(super) v: Three<F>,
+}
+
+impl<F> Quad<F>
+where
+    F: Float,
+{
+    pub fn new(corner: Three<F>, u: Three<F>, v: Three<F>) -> Self {
+        Self { corner, u, v }
+    }
+
+    /// The quad with its corners moved by `transform`, which keeps it a parallelogram.
+    pub fn transformed(&self, transform: &Transform<F>) -> Self {
+        Self {
+            corner: transform.point(&self.corner),
+            u: transform.vector(&self.u),
+            v: transform.vector(&self.v),
+        }
+    }
+
+    pub fn normal(&self) -> Three<F> {
+        self.u.cross(&self.v).normalized()
+    }
+
+    /// How far along `u` and `v` a point in the quad's plane is, from 0 to 1 inside the quad.
+    fn coordinates(&self, point: &Three<F>) -> (F, F) {
+        let n = self.u.cross(&self.v);
+        let w = n / n.length_squared();
+        let offset = point - &self.corner;
+        (w.dot(&offset.cross(&self.v)), w.dot(&self.u.cross(&offset)))
+    }
+}
+
+impl<F> CanHit<Quad<F>, F> for Ray<F>
+where
+    F: Float,
+{
+    fn shoot_at(&self, quad: &Quad<F>, t_min: F, t_max: F) -> Option<Hit<F>> {
+        let normal = quad.normal();
+        let denom = normal.dot(&self.direction);
+        let distance = normal.dot(&(quad.corner - self.origin)) / denom;
+        if !distance.is_finite() || distance < t_min || distance >= t_max {
+            return None;
+        }
+        let position = self.origin + self.direction * distance;
+        let (a, b) = quad.coordinates(&position);
+        if a < F::zero() || a > F::one() || b < F::zero() || b > F::one() {
+            return None;
+        }
+        Some(Hit {
+            position,
+            distance,
+            normal,
+            uv: (a, b),
+            object_index: 0,
+        })
+    }
+}
+
+impl<F> Surface<F> for Quad<F>
+where
+    F: Float + SampleUniform,
+    Standard: Distribution<F>,
+{
+    fn sample_point_on_surface<R: Rng>(&self, rng: &mut R) -> Three<F> {
+        let a: F = Standard.sample(rng);
+        let b: F = Standard.sample(rng);
+        self.corner + self.u * a + self.v * b
+    }
+
+    fn normal_at_point(&self, _point: &Three<F>) -> Three<F> {
+        self.normal()
+    }
+
+    fn uv_at_point(&self, point: &Three<F>) -> (F, F) {
+        self.coordinates(point)
+    }
+
+    fn area(&self) -> F {
+        self.u.cross(&self.v).length()
+    }
+}
+
+impl<F> Bounded<F> for Quad<F>
+where
+    F: Float,
+{
+    fn bounds(&self) -> Aabb<F> {
+        Aabb::around(&[
+            self.corner,
+            self.corner + self.u,
+            self.corner + self.v,
+            self.corner + self.u + self.v,
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    #[test]
+    fn area_and_points_cover_the_parallelogram() {
+        // slanted, with a base of 2 and a height of 1
+        let quad = Quad::new(
+            Three::new(1.0, 0.0, -1.0),
+            Three::new(2.0, 0.0, 0.0),
+            Three::new(1.0, 1.0, 0.0),
+        );
+        assert!((quad.area() - 2.0).abs() < 1e-9);
+
+        let samples = 10_000;
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let mut per_quarter = [0usize; 4];
+        for _ in 0..samples {
+            let p = quad.sample_point_on_surface(&mut rng);
+            assert!(
+                quad.normal().dot(&(p - quad.corner)).abs() < 1e-9,
+                "{:?}",
+                p
+            );
+            let (a, b) = quad.coordinates(&p);
+            assert!(
+                (0.0..=1.0).contains(&a) && (0.0..=1.0).contains(&b),
+                "{:?}",
+                p
+            );
+            per_quarter[(a >= 0.5) as usize + 2 * (b >= 0.5) as usize] += 1;
+        }
+        for count in per_quarter {
+            let fraction = count as f64 / samples as f64;
+            assert!((fraction - 0.25).abs() < 0.02, "{:?}", per_quarter);
+        }
+    }
+}
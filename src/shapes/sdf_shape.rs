@@ -0,0 +1,302 @@
+use super::frame::clip;
+use crate::data::{Aabb, Bounded, CanHit, Hit, Ray, Surface, Three};
+use num_traits::{Float, FloatConst};
+use rand::Rng;
+use rand_distr::{uniform::SampleUniform, Distribution, Standard};
+use std::fmt;
+use std::sync::Arc;
+
+/// Steps a ray takes towards an `SdfShape` before giving up on hitting it.
+const MAX_STEPS: usize = 512;
+
+/// Grid points along each axis of the bounds for estimating the surface's area.
+const AREA_GRID: usize = 32;
+
+type DistanceFunction<F> = dyn Fn(&Three<F>) -> F + Send + Sync;
+
+/// A shape given by a signed distance function, which is negative inside the shape, positive
+/// outside, and never changes faster than `lipschitz` times the distance moved, like the blends
+/// and fractals in `sdf`. Rays find it by sphere tracing, i.e. by repeatedly stepping as far as
+/// the distance function says is safe.
+#[derive(Clone)]
+pub struct SdfShape<F> {
+    pub(super) distance: Arc<DistanceFunction<F>>,
+    pub(super) lipschitz: F,
+    pub(super) bounds: Aabb<F>,
+    /// How close to the surface counts as on it.
+    pub(super) precision: F,
+    pub(super) area: F,
+}
+
+impl<F> SdfShape<F>
+where
+    F: Float + FloatConst,
+{
+    /// The shape where `distance` is negative, which has to fit in `bounds`.
+    pub fn new<D>(distance: D, lipschitz: F, bounds: Aabb<F>) -> Self
+    where
+        D: Fn(&Three<F>) -> F + Send + Sync + 'static,
+    {
+        let mut shape = Self {
+            distance: Arc::new(distance),
+            lipschitz,
+            bounds,
+            precision: bounds.diagonal().length() * F::from(1e-5f64).unwrap(),
+            area: F::zero(),
+        };
+        shape.area = shape.estimate_area();
+        shape
+    }
+
+    /// Sets how close to the surface rays stop, which is a hundred thousandth of the bounds'
+    /// diagonal by default. Smaller is sharper but slower, especially for fractals.
+    pub fn with_precision(self, precision: F) -> Self {
+        Self { precision, ..self }
+    }
+
+    fn distance(&self, point: &Three<F>) -> F {
+        (self.distance)(point)
+    }
+
+    /// The gradient of the distance function, by central differences. It points outwards.
+    fn gradient(&self, point: &Three<F>) -> Three<F> {
+        let h = self.precision;
+        let axis = |i: usize| {
+            let mut offset = Three::zeros();
+            match i {
+                0 => offset.x = h,
+                1 => offset.y = h,
+                _ => offset.z = h,
+            }
+            (self.distance(&(*point + offset)) - self.distance(&(*point - offset))) / (h + h)
+        };
+        Three::new(axis(0), axis(1), axis(2))
+    }
+
+    fn cell_size(&self) -> Three<F> {
+        self.bounds.diagonal() / F::from(AREA_GRID).unwrap()
+    }
+
+    /// Area by the coarea formula: the volume of a thin shell around the surface, weighted by
+    /// how fast the distance changes across it, divided by the shell's thickness.
+    fn estimate_area(&self) -> F {
+        let cell = self.cell_size();
+        let shell = cell.x.max(cell.y).max(cell.z);
+        let half = F::from(0.5f64).unwrap();
+        let mut total = F::zero();
+        for i in 0..AREA_GRID {
+            for j in 0..AREA_GRID {
+                for k in 0..AREA_GRID {
+                    let index = Three::new(
+                        F::from(i).unwrap() + half,
+                        F::from(j).unwrap() + half,
+                        F::from(k).unwrap() + half,
+                    );
+                    let point = self.bounds.min + cell * index;
+                    if self.distance(&point).abs() < shell {
+                        total = total + self.gradient(&point).length();
+                    }
+                }
+            }
+        }
+        total * cell.x * cell.y * cell.z / (shell + shell)
+    }
+
+    /// Longitude and latitude around the center of the bounds, from 0 to 1.
+    fn uv_at(&self, point: &Three<F>) -> (F, F) {
+        let direction = (*point - self.bounds.center()).normalized();
+        let half = F::from(0.5f64).unwrap();
+        (
+            half + direction.z.atan2(direction.x) * F::FRAC_1_PI() * half,
+            half + direction.y.max(-F::one()).min(F::one()).asin() * F::FRAC_1_PI(),
+        )
+    }
+}
+
+impl<F> fmt::Debug for SdfShape<F>
+where
+    F: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SdfShape")
+            .field("lipschitz", &self.lipschitz)
+            .field("bounds", &self.bounds)
+            .field("precision", &self.precision)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F> CanHit<SdfShape<F>, F> for Ray<F>
+where
+    F: Float + FloatConst,
+{
+    fn shoot_at(&self, shape: &SdfShape<F>, t_min: F, t_max: F) -> Option<Hit<F>> {
+        let (near, far) = clip(self, &shape.bounds)?;
+        let end = far.min(t_max);
+        let mut t = near.max(t_min);
+        // a ray leaving the surface starts on it, so it has to get away before it can hit
+        let mut left_surface = false;
+        let min_step = shape.precision * F::from(0.5f64).unwrap();
+        for _ in 0..MAX_STEPS {
+            if t > end {
+                return None;
+            }
+            let position = self.origin + self.direction * t;
+            let distance = shape.distance(&position);
+            if distance.abs() < shape.precision {
+                if left_surface {
+                    return Some(Hit {
+                        position,
+                        distance: t,
+                        normal: shape.gradient(&position).normalized(),
+                        uv: shape.uv_at(&position),
+                        object_index: 0,
+                    });
+                }
+            } else {
+                left_surface = true;
+            }
+            t = t + (distance.abs() / shape.lipschitz).max(min_step);
+        }
+        None
+    }
+}
+
+impl<F> Surface<F> for SdfShape<F>
+where
+    F: Float + FloatConst + SampleUniform,
+    Standard: Distribution<F>,
+{
+    fn sample_point_on_surface<R: Rng>(&self, rng: &mut R) -> Three<F> {
+        // points spread evenly through the shell around the surface are spread evenly over it
+        // too, once they're moved onto it
+        let cell = self.cell_size();
+        let shell = cell.x.max(cell.y).max(cell.z);
+        let size = self.bounds.diagonal();
+        let mut point = self.bounds.center();
+        for _ in 0..10_000 {
+            let (u, v, w): (F, F, F) = (
+                Standard.sample(rng),
+                Standard.sample(rng),
+                Standard.sample(rng),
+            );
+            point = self.bounds.min + size * Three::new(u, v, w);
+            if self.distance(&point).abs() < shell {
+                break;
+            }
+        }
+        for _ in 0..4 {
+            let gradient = self.gradient(&point);
+            let slope = gradient.length_squared();
+            if slope == F::zero() {
+                break;
+            }
+            point = point - gradient * (self.distance(&point) / slope);
+        }
+        point
+    }
+
+    fn normal_at_point(&self, point: &Three<F>) -> Three<F> {
+        self.gradient(point).normalized()
+    }
+
+    fn uv_at_point(&self, point: &Three<F>) -> (F, F) {
+        self.uv_at(point)
+    }
+
+    fn area(&self) -> F {
+        self.area
+    }
+}
+
+impl<F> Bounded<F> for SdfShape<F>
+where
+    F: Float,
+{
+    fn bounds(&self) -> Aabb<F> {
+        self.bounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdf;
+    use crate::shapes::{Object, Sphere};
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    fn ball() -> SdfShape<f64> {
+        SdfShape::new(
+            sdf::sphere(Three::zeros(), 1.0),
+            1.0,
+            Aabb {
+                min: Three::from(-1.1),
+                max: Three::from(1.1),
+            },
+        )
+    }
+
+    #[test]
+    fn sphere_traced_hits_match_the_analytic_sphere() {
+        let shape = ball();
+        let sphere = Sphere::new(Three::zeros(), 1.0);
+        let mut hits = 0;
+        for i in 0..50 {
+            let y = -1.2 + 2.4 * i as f64 / 49.0;
+            let ray = Ray::new(Three::new(0.3, y, 4.0), Three::new(0.0, 0.0, -1.0));
+            match (
+                ray.shoot_at(&sphere, 1e-6, f64::INFINITY),
+                ray.shoot_at(&shape, 1e-6, f64::INFINITY),
+            ) {
+                (Some(expected), Some(hit)) => {
+                    hits += 1;
+                    assert!((hit.distance - expected.distance).abs() < 1e-3, "{:?}", hit);
+                    assert!((hit.normal - expected.normal).length() < 1e-3, "{:?}", hit);
+                }
+                (None, None) => {}
+                (expected, hit) => panic!("{:?} is not {:?}", hit, expected),
+            }
+        }
+        assert!(hits > 30);
+    }
+
+    #[test]
+    fn rays_going_past_miss() {
+        let shape = ball();
+        let ray = Ray::new(Three::new(1.05, 0.0, 4.0), Three::new(0.0, 0.0, -1.0));
+        assert!(ray.shoot_at(&shape, 1e-6, f64::INFINITY).is_none());
+        let away = Ray::new(Three::new(0.0, 0.0, 4.0), Three::new(0.0, 0.0, 1.0));
+        assert!(away.shoot_at(&shape, 1e-6, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn hits_stay_within_t_min_and_t_max() {
+        let shape = ball();
+        let ray = Ray::new(Three::new(0.0, 0.0, 4.0), Three::new(0.0, 0.0, -1.0));
+        assert!(ray.shoot_at(&shape, 1e-6, 2.9).is_none());
+        let hit = ray.shoot_at(&shape, 1e-6, 3.1).unwrap();
+        assert!((hit.distance - 3.0).abs() < 1e-3, "{:?}", hit);
+        // past the front, the ray finds the back of the sphere
+        let hit = ray.shoot_at(&shape, 3.5, f64::INFINITY).unwrap();
+        assert!((hit.distance - 5.0).abs() < 1e-3, "{:?}", hit);
+        assert!(hit.normal.z < -0.999, "{:?}", hit);
+    }
+
+    #[test]
+    fn area_is_estimated_but_it_cant_be_a_light() {
+        let shape = ball();
+        let area = 4.0 * std::f64::consts::PI;
+        assert!(
+            (shape.area() - area).abs() < 0.05 * area,
+            "{}",
+            shape.area()
+        );
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        for _ in 0..20 {
+            let p = shape.sample_point_on_surface(&mut rng);
+            assert!((p.length() - 1.0).abs() < 1e-6, "{:?}", p);
+        }
+        assert!(Object::from(shape).check_light_sampling().is_err());
+    }
+}
@@ -1,5 +1,7 @@
-use crate::data::{CanHit, Hit, Ray, Surface, Three};
-use num_traits::Float;
+use super::even_scale;
+use crate::data::{Aabb, Bounded, CanHit, Hit, Ray, Surface, Three};
+use crate::transform::Transform;
+use num_traits::{Float, FloatConst};
 use rand::Rng;
 use rand_distr::{uniform::SampleUniform, Distribution, UnitSphere};
 
@@ -20,20 +22,30 @@ where
         }
     }
 
-    pub fn scaled(&self, scalar: F) -> Self {
+    pub fn new(center: Three<F>, radius: F) -> Self {
         Self {
-            center: self.center,
-            radius_squared: self.radius_squared * scalar.powi(2),
+            center,
+            radius_squared: radius * radius,
+        }
+    }
+
+    /// The sphere moved by `transform`, which has to scale evenly to keep it round. Place the
+    /// sphere with an `Instance` to squash it into an ellipsoid.
+    pub fn transformed(&self, transform: &Transform<F>) -> Self {
+        let scale = even_scale(transform, "sphere");
+        Self {
+            center: transform.point(&self.center),
+            radius_squared: self.radius_squared * scale * scale,
         }
     }
 }
 
 impl<F> CanHit<Sphere<F>, F> for Ray<F>
 where
-    F: Float,
+    F: Float + FloatConst,
 {
     fn shoot_at(&self, sphere: &Sphere<F>, t_min: F, t_max: F) -> Option<Hit<F>> {
-        let center_to_origin = &self.origin - &sphere.center;
+        let center_to_origin = self.origin - sphere.center;
         let a = F::one(); // self.direction.length_squared();
         let half_b = center_to_origin.dot(&self.direction);
         let c = center_to_origin.length_squared() - sphere.radius_squared;
@@ -47,13 +59,13 @@ where
         let near_root = Some((-half_b - sqrtd) * a.recip()).filter(|&v| t_min <= v && v < t_max);
         let far_root = Some((-half_b + sqrtd) * a.recip()).filter(|&v| t_min <= v && v < t_max);
         near_root.or(far_root).map(|distance| {
-            let offset = &self.direction * distance;
-            let position = &self.origin + &offset;
-            let normal = (&position - &sphere.center).normalized();
+            let position = self.origin + self.direction * distance;
+            let normal = (position - sphere.center).normalized();
             Hit {
                 position,
                 distance,
                 normal,
+                uv: uv_from_normal(&normal),
                 object_index: 0,
             }
         })
@@ -62,15 +74,44 @@ where
 
 impl<F> Surface<F> for Sphere<F>
 where
-    F: Float + SampleUniform,
+    F: Float + FloatConst + SampleUniform,
 {
     fn sample_point_on_surface<R: Rng>(&self, rng: &mut R) -> Three<F> {
         let distance = self.radius_squared.sqrt();
         let direction = Three::from(UnitSphere.sample(rng));
-        &self.center + &(&direction * distance)
+        self.center + direction * distance
     }
 
     fn normal_at_point(&self, point: &Three<F>) -> Three<F> {
         (point - &self.center).normalized()
     }
+
+    fn uv_at_point(&self, point: &Three<F>) -> (F, F) {
+        uv_from_normal(&self.normal_at_point(point))
+    }
+
+    fn area(&self) -> F {
+        F::from(4.0f64).unwrap() * F::PI() * self.radius_squared
+    }
+}
+
+impl<F> Bounded<F> for Sphere<F>
+where
+    F: Float,
+{
+    fn bounds(&self) -> Aabb<F> {
+        let radius = Three::from(self.radius_squared.sqrt());
+        Aabb {
+            min: self.center - radius,
+            max: self.center + radius,
+        }
+    }
+}
+
+/// Longitude & latitude of the point on a sphere with outward `normal`, scaled to [0, 1].
+fn uv_from_normal<F: Float + FloatConst>(normal: &Three<F>) -> (F, F) {
+    let half = F::from(0.5f64).unwrap();
+    let u = half + normal.z.atan2(normal.x) * F::FRAC_1_PI() * half;
+    let v = half + normal.y.max(-F::one()).min(F::one()).asin() * F::FRAC_1_PI();
+    (u, v)
 }
@@ -0,0 +1,521 @@
+use super::even_scale;
+use super::frame::{azimuth, disk_distance, Frame};
+use crate::data::{Aabb, Bounded, CanHit, Hit, Ray, Surface, Three};
+use crate::transform::Transform;
+use num_traits::{Float, FloatConst};
+use rand::Rng;
+use rand_distr::{uniform::SampleUniform, Distribution, Standard};
+
+/// A ring, i.e. a tube of radius `minor_radius` bent into a circle of radius `major_radius`.
+#[derive(Debug, Clone)]
+pub struct Torus<F> {
+    /// Centered on the origin, going around the z axis.
+    pub(super) frame: Frame<F>,
+    pub(super) major_radius: F,
+    pub(super) minor_radius: F,
+    /// How far around the axis it goes, in radians.
+    pub(super) sweep: F,
+    /// Whether the ends of a partial ring are closed.
+    pub(super) capped: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Part {
+    Tube,
+    Start,
+    End,
+}
+
+impl<F> Torus<F>
+where
+    F: Float + FloatConst,
+{
+    /// The ring around `center`, going around `axis`.
+    pub fn new(center: Three<F>, axis: Three<F>, major_radius: F, minor_radius: F) -> Self {
+        Self {
+            frame: Frame::new(center, axis),
+            major_radius,
+            minor_radius,
+            sweep: F::TAU(),
+            capped: false,
+        }
+    }
+
+    /// The torus moved by `transform`, which has to scale evenly to keep it round.
+    pub fn transformed(&self, transform: &Transform<F>) -> Self {
+        let scale = even_scale(transform, "torus");
+        Self {
+            frame: self.frame.transformed(transform),
+            major_radius: self.major_radius * scale,
+            minor_radius: self.minor_radius * scale,
+            ..self.clone()
+        }
+    }
+
+    /// Closes the ends of a `swept` ring with disks.
+    pub fn capped(self) -> Self {
+        Self {
+            capped: true,
+            ..self
+        }
+    }
+
+    /// Only keeps the part `degrees` around the axis, starting from an arbitrary side.
+    pub fn swept(self, degrees: F) -> Self {
+        Self {
+            sweep: degrees.to_radians().min(F::TAU()),
+            ..self
+        }
+    }
+
+    fn in_sweep(&self, local: &Three<F>) -> bool {
+        self.sweep >= F::TAU() || azimuth(local) <= self.sweep
+    }
+
+    fn has_caps(&self) -> bool {
+        self.capped && self.sweep < F::TAU()
+    }
+
+    /// Center and outward normal of the disk closing the start or the end of the ring.
+    fn cap(&self, part: Part) -> (Three<F>, Three<F>) {
+        let phi = if part == Part::Start {
+            F::zero()
+        } else {
+            self.sweep
+        };
+        let (sin, cos) = phi.sin_cos();
+        let center = Three::new(cos, sin, F::zero()) * self.major_radius;
+        let along = Three::new(-sin, cos, F::zero());
+        (center, if part == Part::Start { -along } else { along })
+    }
+
+    /// The point on the circle through the middle of the tube that's closest to `local`.
+    fn ring_point(&self, local: &Three<F>) -> Three<F> {
+        let radial = Three::new(local.x, local.y, F::zero());
+        let length = radial.length();
+        if length > F::zero() {
+            radial * (self.major_radius / length)
+        } else {
+            Three::new(self.major_radius, F::zero(), F::zero())
+        }
+    }
+
+    /// The part a point on the surface is on, i.e. the one it's closest to.
+    fn part_at(&self, local: &Three<F>) -> Part {
+        let tube = ((local - &self.ring_point(local)).length() - self.minor_radius).abs();
+        if !self.has_caps() {
+            return Part::Tube;
+        }
+        [Part::Start, Part::End]
+            .into_iter()
+            .map(|part| {
+                let (center, normal) = self.cap(part);
+                (part, normal.dot(&(local - &center)).abs())
+            })
+            .fold((Part::Tube, tube), |best, (part, distance)| {
+                if distance < best.1 {
+                    (part, distance)
+                } else {
+                    best
+                }
+            })
+            .0
+    }
+
+    fn local_normal(&self, local: &Three<F>, part: Part) -> Three<F> {
+        match part {
+            Part::Tube => (local - &self.ring_point(local)).normalized(),
+            _ => self.cap(part).1,
+        }
+    }
+
+    fn local_uv(&self, local: &Three<F>, part: Part) -> (F, F) {
+        match part {
+            Part::Tube => {
+                // around the ring, then around the tube starting from its outside
+                let radial = (local.x * local.x + local.y * local.y).sqrt() - self.major_radius;
+                let theta = local.z.atan2(radial);
+                let theta = if theta < F::zero() {
+                    theta + F::TAU()
+                } else {
+                    theta
+                };
+                (azimuth(local) / self.sweep, theta / F::TAU())
+            }
+            _ => {
+                let (center, _) = self.cap(part);
+                let outwards = center.normalized();
+                let offset = (local - &center) / self.minor_radius;
+                let half = F::from(0.5f64).unwrap();
+                (
+                    (offset.dot(&outwards) + F::one()) * half,
+                    (offset.z + F::one()) * half,
+                )
+            }
+        }
+    }
+}
+
+impl<F> CanHit<Torus<F>, F> for Ray<F>
+where
+    F: Float + FloatConst,
+{
+    fn shoot_at(&self, torus: &Torus<F>, t_min: F, t_max: F) -> Option<Hit<F>> {
+        let ray = torus.frame.local_ray(self);
+        let (big, small) = (torus.major_radius, torus.minor_radius);
+        // start from where the ray enters the sphere around the torus, so the quartic's
+        // coefficients stay small enough to be accurate
+        let outer = big + small;
+        let m = ray.origin.dot(&ray.direction);
+        let discriminant = m * m - (ray.origin.length_squared() - outer * outer);
+        if discriminant < F::zero() {
+            return None;
+        }
+        let start = (-m - discriminant.sqrt()).max(F::zero());
+        if start >= t_max {
+            return None;
+        }
+        let o = ray.origin + ray.direction * start;
+        let d = &ray.direction;
+
+        // (|p|² + R² - r²)² = 4R² (x² + y²), with p = o + t d and |d| = 1
+        let two = F::from(2.0f64).unwrap();
+        let four = F::from(4.0f64).unwrap();
+        let m = o.dot(d);
+        let k = o.length_squared() + big * big - small * small;
+        let r2 = four * big * big;
+        let coefficients = [
+            four * m,
+            four * m * m + two * k - r2 * (d.x * d.x + d.y * d.y),
+            four * m * k - two * r2 * (o.x * d.x + o.y * d.y),
+            k * k - r2 * (o.x * o.x + o.y * o.y),
+        ];
+
+        let mut nearest = None;
+        let mut consider = |t: F, part: Part| {
+            if t_min <= t && t < nearest.map_or(t_max, |(t, _)| t) {
+                nearest = Some((t, part));
+            }
+        };
+        for t in quartic_roots(coefficients) {
+            let t = t + start;
+            if torus.in_sweep(&(ray.origin + ray.direction * t)) {
+                consider(t, Part::Tube);
+            }
+        }
+        if torus.has_caps() {
+            for part in [Part::Start, Part::End] {
+                let (center, normal) = torus.cap(part);
+                if let Some(t) = disk_distance(&ray, &center, &normal, small) {
+                    consider(t, part);
+                }
+            }
+        }
+        let (distance, part) = nearest?;
+        let local = ray.origin + ray.direction * distance;
+        Some(Hit {
+            position: self.origin + self.direction * distance,
+            distance,
+            normal: torus.frame.vector(&torus.local_normal(&local, part)),
+            uv: torus.local_uv(&local, part),
+            object_index: 0,
+        })
+    }
+}
+
+impl<F> Surface<F> for Torus<F>
+where
+    F: Float + FloatConst + SampleUniform,
+    Standard: Distribution<F>,
+{
+    fn sample_point_on_surface<R: Rng>(&self, rng: &mut R) -> Three<F> {
+        let (big, small) = (self.major_radius, self.minor_radius);
+        let u: F = Standard.sample(rng);
+        let tube = self.sweep * big * F::TAU() * small;
+        if u * self.area() < tube {
+            // the outside of the tube has more area than the inside, so reject points on the
+            // inside more often
+            let theta = loop {
+                let theta: F = Standard.sample(rng);
+                let theta = F::TAU() * theta;
+                let keep: F = Standard.sample(rng);
+                if keep * (big + small) <= big + small * theta.cos() {
+                    break theta;
+                }
+            };
+            let phi: F = Standard.sample(rng);
+            let phi = self.sweep * phi;
+            let ring = big + small * theta.cos();
+            self.frame.point(&Three::new(
+                ring * phi.cos(),
+                ring * phi.sin(),
+                small * theta.sin(),
+            ))
+        } else {
+            let part = if u * self.area() < tube + F::PI() * small * small {
+                Part::Start
+            } else {
+                Part::End
+            };
+            let (center, _) = self.cap(part);
+            let r: F = Standard.sample(rng);
+            let r = small * r.sqrt();
+            let angle: F = Standard.sample(rng);
+            let angle = F::TAU() * angle;
+            let (sin, cos) = angle.sin_cos();
+            let point = center
+                + center.normalized() * (r * cos)
+                + Three::new(F::zero(), F::zero(), r * sin);
+            self.frame.point(&point)
+        }
+    }
+
+    fn normal_at_point(&self, point: &Three<F>) -> Three<F> {
+        let local = self.frame.local_point(point);
+        self.frame
+            .vector(&self.local_normal(&local, self.part_at(&local)))
+    }
+
+    fn uv_at_point(&self, point: &Three<F>) -> (F, F) {
+        let local = self.frame.local_point(point);
+        self.local_uv(&local, self.part_at(&local))
+    }
+
+    fn area(&self) -> F {
+        let tube = self.sweep * self.major_radius * F::TAU() * self.minor_radius;
+        if self.has_caps() {
+            tube + F::from(2.0f64).unwrap() * F::PI() * self.minor_radius * self.minor_radius
+        } else {
+            tube
+        }
+    }
+}
+
+impl<F> Bounded<F> for Torus<F>
+where
+    F: Float,
+{
+    fn bounds(&self) -> Aabb<F> {
+        let outer = self.major_radius + self.minor_radius;
+        self.frame.bounds(&Aabb {
+            min: Three::new(-outer, -outer, -self.minor_radius),
+            max: Three::new(outer, outer, self.minor_radius),
+        })
+    }
+}
+
+/// Real roots of t⁴ + b t³ + c t² + d t + e, by splitting it into two quadratics (Ferrari's
+/// method), then polishing them with Newton's method.
+fn quartic_roots<F: Float>([b, c, d, e]: [F; 4]) -> Vec<F> {
+    let f = |x: f64| F::from(x).unwrap();
+    // substituting t = y - b/4 leaves y⁴ + p y² + q y + r
+    let b2 = b * b;
+    let p = c - f(3.0 / 8.0) * b2;
+    let q = d - f(0.5) * b * c + f(0.125) * b2 * b;
+    let r = e - f(0.25) * b * d + f(1.0 / 16.0) * b2 * c - f(3.0 / 256.0) * b2 * b2;
+
+    let mut ys = Vec::with_capacity(4);
+    let mut quadratic = |linear: F, constant: F| {
+        let discriminant = linear * linear - f(4.0) * constant;
+        if discriminant >= F::zero() {
+            let root = discriminant.sqrt();
+            ys.push(f(0.5) * (-linear - root));
+            ys.push(f(0.5) * (-linear + root));
+        }
+    };
+    // (y² + s y + A)(y² - s y + B) matches it when z = s² solves this cubic
+    let z = largest_cubic_root(f(2.0) * p, p * p - f(4.0) * r, -q * q);
+    if z <= F::epsilon() * (p.abs() + r.abs().sqrt() + F::one()) {
+        // no odd powers, so it's a quadratic in y²
+        let discriminant = p * p - f(4.0) * r;
+        if discriminant >= F::zero() {
+            for w in [
+                f(0.5) * (-p - discriminant.sqrt()),
+                f(0.5) * (-p + discriminant.sqrt()),
+            ] {
+                if w >= F::zero() {
+                    ys.push(-w.sqrt());
+                    ys.push(w.sqrt());
+                }
+            }
+        }
+    } else {
+        let s = z.sqrt();
+        quadratic(s, f(0.5) * (p + z - q / s));
+        quadratic(-s, f(0.5) * (p + z + q / s));
+    }
+
+    ys.into_iter()
+        .map(|y| {
+            let mut t = y - f(0.25) * b;
+            for _ in 0..2 {
+                let value = (((t + b) * t + c) * t + d) * t + e;
+                let slope = ((f(4.0) * t + f(3.0) * b) * t + f(2.0) * c) * t + d;
+                if slope != F::zero() {
+                    t = t - value / slope;
+                }
+            }
+            t
+        })
+        .collect()
+}
+
+/// The largest real root of z³ + a z² + b z + c.
+fn largest_cubic_root<F: Float>(a: F, b: F, c: F) -> F {
+    let f = |x: f64| F::from(x).unwrap();
+    let q = (a * a - f(3.0) * b) / f(9.0);
+    let r = (f(2.0) * a * a * a - f(9.0) * a * b + f(27.0) * c) / f(54.0);
+    let q3 = q * q * q;
+    let mut z = if r * r < q3 {
+        // three real roots
+        let theta = (r / q3.sqrt()).acos();
+        let scale = -f(2.0) * q.sqrt();
+        [0.0, 1.0, -1.0]
+            .into_iter()
+            .map(|k| {
+                scale * ((theta + f(k * 2.0 * std::f64::consts::PI)) / f(3.0)).cos() - a / f(3.0)
+            })
+            .fold(F::neg_infinity(), F::max)
+    } else {
+        let big = -r.signum() * (r.abs() + (r * r - q3).sqrt()).cbrt();
+        let small = if big == F::zero() { F::zero() } else { q / big };
+        big + small - a / f(3.0)
+    };
+    for _ in 0..2 {
+        let value = ((z + a) * z + b) * z + c;
+        let slope = (f(3.0) * z + f(2.0) * a) * z + b;
+        if slope != F::zero() {
+            z = z - value / slope;
+        }
+    }
+    z
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The distinct real roots, smallest first.
+    fn distinct(mut roots: Vec<f64>) -> Vec<f64> {
+        roots.sort_by(f64::total_cmp);
+        roots.dedup_by(|a, b| (*a - *b).abs() < 1e-6);
+        roots
+    }
+
+    fn assert_roots(coefficients: [f64; 4], expected: &[f64]) {
+        let roots = distinct(quartic_roots(coefficients));
+        assert_eq!(
+            roots.len(),
+            expected.len(),
+            "{:?} for {:?}",
+            roots,
+            coefficients
+        );
+        for (root, expected) in roots.iter().zip(expected) {
+            assert!(
+                (root - expected).abs() < 1e-6,
+                "{:?} for {:?}",
+                roots,
+                coefficients
+            );
+        }
+    }
+
+    fn ray(origin: Three<f64>, direction: Three<f64>) -> Ray<f64> {
+        Ray::new(origin, direction.normalized())
+    }
+
+    /// How far a point is from the surface of a ring around z through the origin.
+    fn off_tube<F: Float>(torus: &Torus<F>, p: &Three<F>) -> F {
+        let around = p.x.hypot(p.y) - torus.major_radius;
+        (around * around + p.z * p.z).sqrt() - torus.minor_radius
+    }
+
+    #[test]
+    fn quartic_roots_are_found() {
+        // (t - 1)(t - 2)(t - 3)(t - 4)
+        assert_roots([-10.0, 35.0, -50.0, 24.0], &[1.0, 2.0, 3.0, 4.0]);
+        // (t² + 1)(t - 2)(t + 3) has two complex roots
+        assert_roots([1.0, -5.0, 1.0, -6.0], &[-3.0, 2.0]);
+        // (t² - 1)(t² - 4) has no odd powers
+        assert_roots([0.0, -5.0, 0.0, 4.0], &[-2.0, -1.0, 1.0, 2.0]);
+        // (t - 1)²(t + 2)² touches zero twice
+        assert_roots([2.0, -3.0, -4.0, 4.0], &[-2.0, 1.0]);
+        // (t² + 1)(t² + 4) never reaches zero
+        assert_roots([0.0, 5.0, 0.0, 4.0], &[]);
+    }
+
+    #[test]
+    fn largest_cubic_root_is_found() {
+        // (z - 1)(z - 2)(z - 3)
+        assert!((largest_cubic_root(-6.0, 11.0, -6.0) - 3.0).abs() < 1e-9);
+        // (z - 2)(z² + 1) has one real root
+        assert!((largest_cubic_root(-2.0, 1.0, -2.0) - 2.0).abs() < 1e-9);
+        // (z + 1)³
+        assert!((largest_cubic_root(3.0, 3.0, 1.0) + 1.0).abs() < 1e-4);
+        // z³ - z, with roots -1, 0 and 1
+        assert!((largest_cubic_root(0.0, -1.0, 0.0) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rays_across_the_tube_hit_it_in_f32() {
+        let torus = Torus::<f32>::new(Three::zeros(), Three::new(0.0, 0.0, 1.0), 1.0, 0.25);
+        for i in 0..=100 {
+            let z = -0.24 + 0.48 * i as f32 / 100.0;
+            let ray = Ray::new(Three::new(-3.0, 0.0, z), Three::new(1.0, 0.0, 0.0));
+            let hit = ray.shoot_at(&torus, 1e-4, f32::INFINITY).unwrap();
+            let expected = 2.0 - (0.0625 - z * z).sqrt();
+            assert!(
+                (hit.distance - expected).abs() < 1e-3,
+                "{} at z = {}",
+                hit.distance,
+                z
+            );
+            assert!(off_tube(&torus, &hit.position).abs() < 1e-4);
+            assert!(hit.normal.x < 0.0);
+        }
+    }
+
+    #[test]
+    fn grazing_rays_touch_or_miss_the_top_of_the_tube() {
+        let torus = Torus::new(Three::zeros(), Three::new(0.0, 0.0, 1.0), 1.0, 0.25);
+        let x = Three::new(1.0, 0.0, 0.0);
+        if let Some(hit) = ray(Three::new(-3.0, 0.0, 0.25), x).shoot_at(&torus, 1e-6, f64::INFINITY)
+        {
+            assert!(off_tube(&torus, &hit.position).abs() < 1e-3);
+            assert!((hit.position.x + 1.0).abs() < 1e-2, "{:?}", hit.position);
+        }
+        assert!(ray(Three::new(-3.0, 0.0, 0.26), x)
+            .shoot_at(&torus, 1e-6, f64::INFINITY)
+            .is_none());
+        // through the hole without touching the tube
+        assert!(ray(Three::new(0.0, 0.0, 3.0), Three::new(0.0, 0.0, -1.0))
+            .shoot_at(&torus, 1e-6, f64::INFINITY)
+            .is_none());
+    }
+
+    #[test]
+    fn rays_from_inside_the_tube_leave_it() {
+        let torus = Torus::new(Three::zeros(), Three::new(0.0, 0.0, 1.0), 1.0, 0.25);
+        let inside = Three::new(1.0, 0.0, 0.0);
+        for direction in [
+            Three::new(0.0, 0.0, 1.0),
+            Three::new(1.0, 0.0, 0.0),
+            Three::new(-1.0, 0.0, 0.0),
+            Three::new(1.0, 0.0, -1.0),
+        ] {
+            let hit = ray(inside, direction)
+                .shoot_at(&torus, 1e-6, f64::INFINITY)
+                .unwrap();
+            assert!((hit.distance - 0.25).abs() < 1e-9, "{:?}", hit);
+            // normals point out of the tube
+            assert!((hit.normal - direction.normalized()).length() < 1e-6);
+        }
+        // along the tube it comes out on the far side of the ring
+        let hit = ray(inside, Three::new(0.0, 1.0, 0.0))
+            .shoot_at(&torus, 1e-6, f64::INFINITY)
+            .unwrap();
+        assert!(off_tube(&torus, &hit.position).abs() < 1e-9);
+        assert!((hit.distance - (1.25f64.powi(2) - 1.0).sqrt()).abs() < 1e-9);
+    }
+}
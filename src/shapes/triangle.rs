@@ -1,4 +1,5 @@
-use crate::data::{CanHit, Hit, Ray, Surface, Three};
+use crate::data::{Aabb, Bounded, CanHit, Hit, Ray, Surface, Three};
+use crate::transform::Transform;
 use num_traits::Float;
 use rand::Rng;
 use rand_distr::{uniform::SampleUniform, Distribution, Standard};
@@ -8,6 +9,9 @@ pub struct Triangle<F> {
     pub(super) v0: Three<F>,
     pub(super) v01: Three<F>,
     pub(super) v02: Three<F>,
+    pub(super) uvs: [(F, F); 3],
+    /// Normals of each vertex, interpolated across the triangle for smooth shading.
+    pub(super) normals: Option<[Three<F>; 3]>,
 }
 
 impl<F> Triangle<F>
@@ -19,6 +23,31 @@ where
             v0,
             v01: v1 - v0,
             v02: v2 - v0,
+            uvs: [
+                (F::zero(), F::zero()),
+                (F::one(), F::zero()),
+                (F::zero(), F::one()),
+            ],
+            normals: None,
+        }
+    }
+
+    /// Sets the texture coordinates of each vertex, which are interpolated across the triangle.
+    /// By default they are `(0, 0)`, `(1, 0)` and `(0, 1)`.
+    pub fn with_uvs(self, uv0: (F, F), uv1: (F, F), uv2: (F, F)) -> Self {
+        Self {
+            uvs: [uv0, uv1, uv2],
+            ..self
+        }
+    }
+
+    /// Sets the normal of each vertex, which are interpolated across the triangle so that meshes
+    /// of flat triangles look smooth. The interpolated normal is kept on the side of the
+    /// triangle's own normal, so the winding still decides which side is the front.
+    pub fn with_normals(self, n0: Three<F>, n1: Three<F>, n2: Three<F>) -> Self {
+        Self {
+            normals: Some([n0.normalized(), n1.normalized(), n2.normalized()]),
+            ..self
         }
     }
 
@@ -36,34 +65,83 @@ where
         )
     }
 
-    pub fn shifted(&self, offset: Three<F>) -> Self {
+    /// The triangle with its vertices moved by `transform`, keeping its texture coordinates.
+    pub fn transformed(&self, transform: &Transform<F>) -> Self {
+        let (v0, v1, v2) = self.vertices();
         Self {
-            v0: self.v0 + offset,
-            v01: self.v01,
-            v02: self.v02,
+            uvs: self.uvs,
+            normals: self
+                .normals
+                .map(|normals| normals.map(|n| transform.normal(&n).normalized())),
+            ..Self::new(
+                transform.point(&v0),
+                transform.point(&v1),
+                transform.point(&v2),
+            )
         }
     }
 
-    pub fn scaled(&self, scalar: F) -> Self {
-        let (v0, v1, v2) = self.vertices();
-        Self::new(v0 * scalar, v1 * scalar, v2 * scalar)
+    pub fn vertices(&self) -> (Three<F>, Three<F>, Three<F>) {
+        (self.v0, self.v01 + self.v0, self.v02 + self.v0)
     }
 
-    pub fn rotated_around(&self, axis: &Three<F>, angle: F) -> Self {
-        let (v0, v1, v2) = self.vertices();
-        let v0 = v0.rotate(axis, angle);
-        let v1 = v1.rotate(axis, angle);
-        let v2 = v2.rotate(axis, angle);
-        Self::new(v0, v1, v2)
+    pub fn uvs(&self) -> [(F, F); 3] {
+        self.uvs
     }
 
-    pub fn vertices(&self) -> (Three<F>, Three<F>, Three<F>) {
-        (self.v0, self.v01 + self.v0, self.v02 + self.v0)
+    /// The vertex normals, if it has them.
+    pub fn normals(&self) -> Option<[Three<F>; 3]> {
+        self.normals
     }
 
     pub fn normal(&self) -> Three<F> {
         self.v01.cross(&self.v02).normalized()
     }
+
+    /// Texture coordinates at barycentric coordinates `b1` (towards v1) and `b2` (towards v2).
+    pub(super) fn interpolate_uv(&self, b1: F, b2: F) -> (F, F) {
+        let b0 = F::one() - b1 - b2;
+        let [uv0, uv1, uv2] = self.uvs;
+        (
+            uv0.0 * b0 + uv1.0 * b1 + uv2.0 * b2,
+            uv0.1 * b0 + uv1.1 * b1 + uv2.1 * b2,
+        )
+    }
+
+    /// Shading normal at barycentric coordinates `b1` and `b2`, from the vertex normals if the
+    /// triangle has them.
+    pub(super) fn interpolate_normal(&self, b1: F, b2: F) -> Three<F> {
+        let normal = self.normal();
+        let [n0, n1, n2] = match self.normals {
+            Some(normals) => normals,
+            None => return normal,
+        };
+        let shading = n0 * (F::one() - b1 - b2) + n1 * b1 + n2 * b2;
+        if shading.length_squared() <= F::zero() {
+            return normal;
+        }
+        let shading = shading.normalized();
+        if shading.dot(&normal) < F::zero() {
+            -shading
+        } else {
+            shading
+        }
+    }
+
+    /// Barycentric coordinates (towards v1, towards v2) of a point in the triangle's plane.
+    pub(super) fn barycentric(&self, point: &Three<F>) -> (F, F) {
+        let p = point - &self.v0;
+        let d00 = self.v01.dot(&self.v01);
+        let d01 = self.v01.dot(&self.v02);
+        let d11 = self.v02.dot(&self.v02);
+        let d20 = p.dot(&self.v01);
+        let d21 = p.dot(&self.v02);
+        let denom = d00 * d11 - d01 * d01;
+        (
+            (d11 * d20 - d01 * d21) / denom,
+            (d00 * d21 - d01 * d20) / denom,
+        )
+    }
 }
 
 impl<F> CanHit<Triangle<F>, F> for Ray<F>
@@ -97,11 +175,12 @@ where
             return None;
         }
         let position = self.origin + self.direction * distance;
-        let normal = triangle.normal();
+        let normal = triangle.interpolate_normal(u, v);
         Some(Hit {
             position,
             distance,
             normal,
+            uv: triangle.interpolate_uv(u, v),
             object_index: 0,
         })
     }
@@ -113,19 +192,34 @@ where
     Standard: Distribution<F>,
 {
     fn sample_point_on_surface<R: Rng>(&self, rng: &mut R) -> Three<F> {
-        let (v0, v1, v2) = self.vertices();
+        // source: https://pharr.org/matt/blog/2019/02/27/triangle-sampling-1
+        let u1: F = Standard.sample(rng);
+        let u2: F = Standard.sample(rng);
+        let su1 = u1.sqrt();
+        self.v0 + self.v01 * (su1 * (F::one() - u2)) + self.v02 * (su1 * u2)
+    }
 
-        let t1: F = Standard.sample(rng);
-        let p1 = v0 * (F::one() - t1) + v1 * t1;
-        let t2: F = Standard.sample(rng);
-        let p2 = v0 * (F::one() - t2) + v2 * t2;
-        let t3: F = Standard.sample(rng);
-        let p3 = v1 * (F::one() - t3) * v2 * t3;
+    fn normal_at_point(&self, point: &Three<F>) -> Three<F> {
+        let (b1, b2) = self.barycentric(point);
+        self.interpolate_normal(b1, b2)
+    }
+
+    fn uv_at_point(&self, point: &Three<F>) -> (F, F) {
+        let (b1, b2) = self.barycentric(point);
+        self.interpolate_uv(b1, b2)
+    }
 
-        (p1 + p2 + p3) / F::from(3.0f64).unwrap()
+    fn area(&self) -> F {
+        self.v01.cross(&self.v02).length() * F::from(0.5f64).unwrap()
     }
+}
 
-    fn normal_at_point(&self, _point: &Three<F>) -> Three<F> {
-        self.normal()
+impl<F> Bounded<F> for Triangle<F>
+where
+    F: Float,
+{
+    fn bounds(&self) -> Aabb<F> {
+        let (v0, v1, v2) = self.vertices();
+        Aabb::around(&[v0, v1, v2])
     }
 }
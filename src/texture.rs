@@ -0,0 +1,122 @@
+use crate::data::Three;
+use image::{DynamicImage, ImageResult};
+use num_traits::{cast, Float};
+use std::{path::Path, sync::Arc};
+
+/// A color that varies over a surface, looked up by the `uv` coordinates of a `Hit`.
+#[derive(Debug, Clone)]
+pub enum Texture<F> {
+    Constant(Three<F>),
+    Image(ImageTexture<F>),
+}
+
+/// An image wrapped around a surface, with `(0, 0)` at its bottom left corner and `(1, 1)` at its
+/// top right. Coordinates outside of that range repeat the image.
+///
+/// Texel values are used as-is, matching how `render` writes colors, so an emissive sign with
+/// radiance 1 reproduces the image it was made from.
+#[derive(Debug, Clone)]
+pub struct ImageTexture<F> {
+    width: usize,
+    height: usize,
+    texels: Arc<Vec<Three<F>>>,
+}
+
+impl<F> Texture<F>
+where
+    F: Float,
+{
+    pub fn lookup(&self, uv: (F, F)) -> Three<F> {
+        match self {
+            Texture::Constant(rgb) => *rgb,
+            Texture::Image(image) => image.lookup(uv),
+        }
+    }
+
+    /// The mean color over the whole texture.
+    pub fn average(&self) -> Three<F> {
+        match self {
+            Texture::Constant(rgb) => *rgb,
+            Texture::Image(image) => image.average(),
+        }
+    }
+}
+
+impl<F> ImageTexture<F>
+where
+    F: Float,
+{
+    pub fn open<P: AsRef<Path>>(path: P) -> ImageResult<Self> {
+        Ok(Self::from_image(&image::open(path)?))
+    }
+
+    pub fn from_image(image: &DynamicImage) -> Self {
+        let rgb = image.to_rgb8();
+        let scale = F::from(255.0f64).unwrap().recip();
+        let texels = rgb
+            .pixels()
+            .map(|p| {
+                Three::new(
+                    cast::<u8, F>(p[0]).unwrap() * scale,
+                    cast::<u8, F>(p[1]).unwrap() * scale,
+                    cast::<u8, F>(p[2]).unwrap() * scale,
+                )
+            })
+            .collect();
+        Self {
+            width: rgb.width() as usize,
+            height: rgb.height() as usize,
+            texels: Arc::new(texels),
+        }
+    }
+
+    /// An image from its texels, row by row from the top.
+    pub(crate) fn from_texels(width: usize, height: usize, texels: Vec<Three<F>>) -> Self {
+        assert_eq!(texels.len(), width * height);
+        Self {
+            width,
+            height,
+            texels: Arc::new(texels),
+        }
+    }
+
+    /// Width and height in texels.
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn texel(&self, x: isize, y: isize) -> Three<F> {
+        let x = x.rem_euclid(self.width as isize) as usize;
+        let y = y.rem_euclid(self.height as isize) as usize;
+        self.texels[y * self.width + x]
+    }
+
+    /// Bilinearly filtered color at `uv`.
+    pub fn lookup(&self, (u, v): (F, F)) -> Three<F> {
+        let half = F::from(0.5f64).unwrap();
+        // image rows go top to bottom, but v goes bottom to top
+        let x = u * cast(self.width).unwrap() - half;
+        let y = (F::one() - v) * cast(self.height).unwrap() - half;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0.to_isize().unwrap_or(0), y0.to_isize().unwrap_or(0));
+
+        let top = self.texel(x0, y0) * (F::one() - tx) + self.texel(x0 + 1, y0) * tx;
+        let bottom = self.texel(x0, y0 + 1) * (F::one() - tx) + self.texel(x0 + 1, y0 + 1) * tx;
+        top * (F::one() - ty) + bottom * ty
+    }
+
+    pub fn average(&self) -> Three<F> {
+        let sum = self
+            .texels
+            .iter()
+            .fold(Three::zeros(), |sum, &texel| sum + texel);
+        sum / cast(self.texels.len().max(1)).unwrap()
+    }
+}
+
+impl<F> From<ImageTexture<F>> for Texture<F> {
+    fn from(image: ImageTexture<F>) -> Self {
+        Texture::Image(image)
+    }
+}
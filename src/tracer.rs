@@ -1,22 +1,53 @@
 use crate::data::{
-    CanHit, Dielectric, Diffuse, Light, LightInteraction, Material, Mirror, Ray, Three,
+    CanHit, Dielectric, Diffuse, Hit, Light, LightInteraction, Material, Mirror, Ray, Surface,
+    Three,
 };
-use crate::pdf::{CosineHemisphereDistribution, HemisphereDistribution};
+use crate::light_sampling::LightSampler;
+use crate::lights::LightSource;
+use crate::pdf::{power_heuristic, CosineHemisphereDistribution, HemisphereDistribution};
 use crate::scene::{Scene, SceneTracer};
 use num_traits::{Float, FloatConst, ToPrimitive};
 use rand::prelude::*;
 use rand_distr::uniform::SampleUniform;
 use rand_distr::{Distribution, Standard};
-use std::ops::{Mul, MulAssign};
+use std::ops::{AddAssign, Mul, MulAssign};
 
 #[derive(Default, Debug, Clone, Copy)]
 pub struct PathTracer {
+    /// Maximum number of bounces in a path.
     pub depth: usize,
+    /// Bounces after which Russian roulette randomly ends paths that carry little light, making
+    /// up for it by boosting the ones that go on. `None`, the default, turns it off.
+    pub roulette_depth: Option<usize>,
+    /// Maximum number of diffuse bounces in a path, on top of `depth`.
+    pub diffuse_depth: Option<usize>,
+    /// Maximum number of mirror reflections and reflections off glass in a path.
+    pub specular_depth: Option<usize>,
+    /// Maximum number of refractions through glass in a path.
+    pub transmission_depth: Option<usize>,
+}
+
+/// The kinds of scattering that have separate depth limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lobe {
+    Diffuse,
+    Specular,
+    Transmission,
+}
+
+impl PathTracer {
+    fn lobe_depth(&self, lobe: Lobe) -> Option<usize> {
+        match lobe {
+            Lobe::Diffuse => self.diffuse_depth,
+            Lobe::Specular => self.specular_depth,
+            Lobe::Transmission => self.transmission_depth,
+        }
+    }
 }
 
 impl<F> SceneTracer<F> for PathTracer
 where
-    F: Float + SampleUniform + MulAssign + Mul + FloatConst,
+    F: Float + SampleUniform + MulAssign + AddAssign + Mul + FloatConst,
     Standard: Distribution<F>,
 {
     fn trace<R>(&self, mut ray: Ray<F>, scene: &Scene<F>, rng: &mut R) -> Option<Three<F>>
@@ -26,36 +57,301 @@ where
         let t_min = F::from(1e-3f64).unwrap();
         let t_max = F::infinity();
 
+        let mut radiance: Three<F> = Three::zeros();
         let mut light_attenuation: Three<F> = Three::ones();
-        for _ in 0..self.depth {
+        // origin & pdf of the last diffuse bounce, so emission it finds can be weighted against
+        // the light sampling that could have found it too
+        let mut last_diffuse: Option<(Three<F>, F)> = None;
+        // bounces so far of each lobe, indexed by `Lobe as usize`
+        let mut lobe_bounces = [0usize; 3];
+        for bounce in 0..self.depth {
             match ray.shoot_at(scene, t_min, t_max) {
                 Some(hit) => {
                     let material = scene.material_for(hit.object_index);
-                    match material_interaction(material, &ray.direction, &hit.normal, rng) {
+                    // sampling lights from the last hit would make paths longer than `depth`
+                    // and light sampling is a diffuse bounce too, so it's off when those run out
+                    let is_last_bounce = bounce + 1 == self.depth
+                        || self.lobe_depth(Lobe::Diffuse)
+                            == Some(lobe_bounces[Lobe::Diffuse as usize]);
+                    if let (Material::Diffuse(diffuse), false) = (material, is_last_bounce) {
+                        radiance += light_attenuation
+                            * (delta_light_contribution(diffuse, &ray, &hit, scene, rng)
+                                + area_light_contribution(diffuse, &ray, &hit, scene, rng)
+                                + environment_contribution(diffuse, &ray, &hit, scene, rng));
+                    }
+                    match material_interaction(scene, &ray.direction, &hit, rng) {
                         LightInteraction::Scatter {
                             direction,
                             attenuation,
                         } => {
+                            let lobe = lobe_of(material, &ray.direction, &hit.normal, &direction);
+                            lobe_bounces[lobe as usize] += 1;
+                            if let Some(limit) = self.lobe_depth(lobe) {
+                                if lobe_bounces[lobe as usize] > limit {
+                                    break;
+                                }
+                            }
+                            last_diffuse = match material {
+                                Material::Diffuse(_) => Some((
+                                    hit.position,
+                                    direction.dot(&hit.normal).abs() * F::FRAC_1_PI(),
+                                )),
+                                _ => None,
+                            };
                             light_attenuation *= attenuation;
                             ray.origin = hit.position;
                             ray.direction = direction;
+
+                            if self.roulette_depth.is_some_and(|depth| bounce + 1 >= depth) {
+                                // paths carrying less light are more likely to end, and the
+                                // survivors carry the light of the ones that didn't
+                                let survival = light_attenuation
+                                    .max_component()
+                                    .min(F::from(0.95f64).unwrap());
+                                if survival <= Standard.sample(rng) {
+                                    break;
+                                }
+                                light_attenuation /= survival;
+                            }
                         }
                         LightInteraction::Emit { emission } => {
-                            return Some(light_attenuation * emission)
+                            let weight = match last_diffuse {
+                                Some((origin, bsdf_pdf)) => power_heuristic(
+                                    bsdf_pdf,
+                                    area_light_pdf(scene, &origin, &ray.direction, &hit),
+                                ),
+                                None => F::one(),
+                            };
+                            radiance += light_attenuation * emission * weight;
+                            break;
                         }
                     }
                 }
-                None => break,
+                None => {
+                    if let Some(environment) = scene.environment() {
+                        let weight = match (last_diffuse, scene.environment_sampler()) {
+                            (Some((_, bsdf_pdf)), Some(sampler)) => {
+                                power_heuristic(bsdf_pdf, sampler.pdf(environment, &ray.direction))
+                            }
+                            _ => F::one(),
+                        };
+                        radiance +=
+                            light_attenuation * environment.radiance(&ray.direction) * weight;
+                    }
+                    break;
+                }
             }
         }
-        None
+        Some(radiance)
     }
 }
 
-pub(crate) fn material_interaction<F, R>(
+/// The lobe a scattered `direction` was sampled from.
+fn lobe_of<F: Float>(
     material: &Material<F>,
     in_direction: &Three<F>,
     normal: &Three<F>,
+    direction: &Three<F>,
+) -> Lobe {
+    match material {
+        Material::Diffuse(_) => Lobe::Diffuse,
+        // refracted rays continue to the same side of the surface they came from
+        Material::Dielectric(_) if in_direction.dot(normal) * direction.dot(normal) > F::zero() => {
+            Lobe::Transmission
+        }
+        _ => Lobe::Specular,
+    }
+}
+
+/// Direct lighting from the scene's delta lights, which BSDF sampling can never hit.
+pub(crate) fn delta_light_contribution<F, R>(
+    diffuse: &Diffuse<F>,
+    ray: &Ray<F>,
+    hit: &Hit<F>,
+    scene: &Scene<F>,
+    rng: &mut R,
+) -> Three<F>
+where
+    R: Rng,
+    F: Float + AddAssign + FloatConst,
+{
+    let t_min = F::from(1e-3f64).unwrap();
+    let normal = facing_normal(&ray.direction, &hit.normal);
+    let f = diffuse.albedo(hit.uv) * F::FRAC_1_PI();
+
+    let mut radiance = Three::zeros();
+    for light in scene.lights() {
+        let sample = match light.sample_incident(&hit.position, rng) {
+            Some(sample) => sample,
+            None => continue,
+        };
+        let cos_theta = sample.direction.dot(&normal);
+        if cos_theta <= F::zero() {
+            continue;
+        }
+        let shadow_ray = Ray {
+            origin: hit.position,
+            direction: sample.direction,
+            time: ray.time,
+        };
+        if shadow_ray
+            .shoot_at(scene, t_min, sample.distance - t_min)
+            .is_none()
+        {
+            radiance += f * sample.radiance * (cos_theta / sample.pdf);
+        }
+    }
+    radiance
+}
+
+/// Direct lighting from an emissive object picked by the scene's light sampler, weighted
+/// against the chance of the diffuse bounce finding the same light.
+pub(crate) fn area_light_contribution<F, R>(
+    diffuse: &Diffuse<F>,
+    ray: &Ray<F>,
+    hit: &Hit<F>,
+    scene: &Scene<F>,
+    rng: &mut R,
+) -> Three<F>
+where
+    R: Rng,
+    F: Float + FloatConst + SampleUniform,
+    Standard: Distribution<F>,
+{
+    let t_min = F::from(1e-3f64).unwrap();
+    let (light_idx, pmf) = match scene
+        .light_sampler()
+        .and_then(|sampler| sampler.sample(&hit.position, Standard.sample(rng)))
+    {
+        Some(choice) => choice,
+        None => return Three::zeros(),
+    };
+    let (obj_idx, obj) = &scene.emissive_objects()[light_idx];
+    let light = match scene.material_for(*obj_idx) {
+        Material::Light(light) => light,
+        _ => unreachable!(),
+    };
+
+    // moving lights are sampled where the ray finds them
+    let placed = obj.at_time(ray.time);
+    let point = placed.sample_point_on_surface(rng);
+    let to_light = point - hit.position;
+    let distance = to_light.length();
+    let direction = to_light / distance;
+    let light_normal = placed.normal_at_point(&point);
+    let cos_theta = direction.dot(&facing_normal(&ray.direction, &hit.normal));
+    let cos_light = direction.dot(&light_normal).abs();
+    if cos_theta <= F::zero()
+        || cos_light <= F::zero()
+        || !light.emits_towards(&direction, &light_normal)
+    {
+        return Three::zeros();
+    }
+    let emission = light.radiance(obj.area(), placed.uv_at_point(&point));
+
+    let shadow_ray = Ray {
+        origin: hit.position,
+        direction,
+        time: ray.time,
+    };
+    if shadow_ray
+        .shoot_at(scene, t_min, distance - t_min)
+        .is_some()
+    {
+        return Three::zeros();
+    }
+
+    let light_pdf = pmf * distance * distance / (cos_light * obj.area());
+    let bsdf_pdf = cos_theta * F::FRAC_1_PI();
+    let f = diffuse.albedo(hit.uv) * F::FRAC_1_PI();
+    f * emission * (cos_theta * power_heuristic(light_pdf, bsdf_pdf) / light_pdf)
+}
+
+/// Light from the scene's environment reaching a diffuse `hit` from a direction chosen by its
+/// `EnvironmentSampler`, weighted against the chance of the diffuse bounce escaping that way.
+pub(crate) fn environment_contribution<F, R>(
+    diffuse: &Diffuse<F>,
+    ray: &Ray<F>,
+    hit: &Hit<F>,
+    scene: &Scene<F>,
+    rng: &mut R,
+) -> Three<F>
+where
+    R: Rng,
+    F: Float + FloatConst + SampleUniform,
+    Standard: Distribution<F>,
+{
+    let (environment, sampler) = match (scene.environment(), scene.environment_sampler()) {
+        (Some(environment), Some(sampler)) => (environment, sampler),
+        _ => return Three::zeros(),
+    };
+    let sample = match sampler.sample(environment, rng) {
+        Some(sample) => sample,
+        None => return Three::zeros(),
+    };
+    let cos_theta = sample
+        .direction
+        .dot(&facing_normal(&ray.direction, &hit.normal));
+    if cos_theta <= F::zero() {
+        return Three::zeros();
+    }
+    let shadow_ray = Ray {
+        origin: hit.position,
+        direction: sample.direction,
+        time: ray.time,
+    };
+    if shadow_ray
+        .shoot_at(scene, F::from(1e-3f64).unwrap(), F::infinity())
+        .is_some()
+    {
+        return Three::zeros();
+    }
+
+    let bsdf_pdf = cos_theta * F::FRAC_1_PI();
+    let f = diffuse.albedo(hit.uv) * F::FRAC_1_PI();
+    f * sample.radiance * (cos_theta * power_heuristic(sample.pdf, bsdf_pdf) / sample.pdf)
+}
+
+/// Solid angle density with which the scene's light sampler would pick the emissive `hit` when
+/// lighting `origin`.
+pub(crate) fn area_light_pdf<F>(
+    scene: &Scene<F>,
+    origin: &Three<F>,
+    direction: &Three<F>,
+    hit: &Hit<F>,
+) -> F
+where
+    F: Float + FloatConst + SampleUniform,
+    Standard: Distribution<F>,
+{
+    let (sampler, light_idx) = match (
+        scene.light_sampler(),
+        scene.emissive_index(hit.object_index),
+    ) {
+        (Some(sampler), Some(light_idx)) => (sampler, light_idx),
+        _ => return F::zero(),
+    };
+    let cos_light = direction.dot(&hit.normal).abs();
+    if cos_light <= F::zero() {
+        return F::zero();
+    }
+    let area = scene.object(hit.object_index).area();
+    sampler.pmf(origin, light_idx) * hit.distance * hit.distance / (cos_light * area)
+}
+
+/// `normal` flipped if needed to be on the same side of the surface that `direction` came from.
+pub(crate) fn facing_normal<F: Float>(direction: &Three<F>, normal: &Three<F>) -> Three<F> {
+    if direction.dot(normal) > F::zero() {
+        -*normal
+    } else {
+        *normal
+    }
+}
+
+pub(crate) fn material_interaction<F, R>(
+    scene: &Scene<F>,
+    in_direction: &Three<F>,
+    hit: &Hit<F>,
     rng: &mut R,
 ) -> LightInteraction<F>
 where
@@ -63,17 +359,20 @@ where
     F: Float + SampleUniform + MulAssign + FloatConst,
     Standard: Distribution<F>,
 {
-    match material {
-        Material::Diffuse(m) => diffuse_interaction(m, normal, rng),
+    let normal = &hit.normal;
+    match scene.material_for(hit.object_index) {
+        Material::Diffuse(m) => diffuse_interaction(m, hit, rng),
         Material::Mirror(m) => mirror_interaction(m, in_direction, normal),
         Material::Dielectric(m) => dielectric_interaction(m, in_direction, normal, rng),
-        Material::Light(m) => light_interaction(m),
+        Material::Light(m) => {
+            light_interaction(m, scene.object(hit.object_index).area(), in_direction, hit)
+        }
     }
 }
 
 pub(crate) fn diffuse_interaction<F, R>(
     diffuse: &Diffuse<F>,
-    normal: &Three<F>,
+    hit: &Hit<F>,
     rng: &mut R,
 ) -> LightInteraction<F>
 where
@@ -81,13 +380,13 @@ where
     F: Float + SampleUniform + MulAssign + FloatConst,
     Standard: Distribution<F>,
 {
-    let dist = CosineHemisphereDistribution::oriented_towards(*normal);
+    let dist = CosineHemisphereDistribution::oriented_towards(hit.normal);
     let direction = dist.sample(rng);
     let pdf = dist.pdf(&direction);
-    let f = &diffuse.rgb * F::FRAC_1_PI();
-    let cos_theta = direction.dot(normal).abs();
+    let f = diffuse.albedo(hit.uv) * F::FRAC_1_PI();
+    let cos_theta = direction.dot(&hit.normal).abs();
     LightInteraction::Scatter {
-        attenuation: &f * (cos_theta / pdf),
+        attenuation: f * (cos_theta / pdf),
         direction,
     }
 }
@@ -157,18 +456,80 @@ where
     }
 }
 
-pub(crate) fn light_interaction<F>(diffuse_light: &Light<F>) -> LightInteraction<F>
+pub(crate) fn light_interaction<F>(
+    diffuse_light: &Light<F>,
+    area: F,
+    in_direction: &Three<F>,
+    hit: &Hit<F>,
+) -> LightInteraction<F>
 where
-    F: Float,
+    F: Float + FloatConst,
 {
-    LightInteraction::Emit {
-        emission: diffuse_light.rgb * diffuse_light.power,
-    }
+    let emission = if diffuse_light.emits_towards(in_direction, &hit.normal) {
+        diffuse_light.radiance(area, hit.uv)
+    } else {
+        Three::zeros()
+    };
+    LightInteraction::Emit { emission }
 }
 
 fn reflect<F>(d: &Three<F>, n: &Three<F>) -> Three<F>
 where
     F: Float,
 {
-    d - &(n * (d.dot(&n) * F::from(2.0f64).unwrap()))
+    d - &(n * (d.dot(n) * F::from(2.0f64).unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lights::Environment;
+    use crate::shapes::Sphere;
+    use crate::texture::Texture;
+    use rand_xorshift::XorShiftRng;
+
+    /// A mirror ball left of a gray ball, under a white sky.
+    fn balls() -> Scene<f64> {
+        let mut scene = Scene::new();
+        let mirror = scene.add_material(Mirror::perfect());
+        let gray = scene.add_material(Diffuse::rgb(0.5, 0.5, 0.5));
+        scene.add_object(Sphere::new(Three::new(-1.5, 0.0, 0.0), 1.0), mirror);
+        scene.add_object(Sphere::new(Three::new(1.5, 0.0, 0.0), 1.0), gray);
+        scene.set_environment(Environment::new(Texture::Constant(Three::ones())));
+        scene.build_light_sampler();
+        scene
+    }
+
+    fn trace_at(tracer: PathTracer, scene: &Scene<f64>, x: f64) -> Three<f64> {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let ray = Ray::new(Three::new(x, 0.0, 5.0), Three::new(0.0, 0.0, -1.0));
+        tracer.trace(ray, scene, &mut rng).unwrap()
+    }
+
+    #[test]
+    fn lobe_depths_only_end_their_own_bounces() {
+        let scene = balls();
+        let tracer = PathTracer {
+            depth: 4,
+            ..Default::default()
+        };
+        let no_specular = PathTracer {
+            specular_depth: Some(0),
+            ..tracer
+        };
+        // the mirror shows the sky, unless it can't reflect at all
+        assert_eq!(trace_at(tracer, &scene, -1.5), Three::ones());
+        assert_eq!(trace_at(no_specular, &scene, -1.5), Three::zeros());
+        // while the gray ball is lit the same either way
+        let gray = trace_at(tracer, &scene, 1.5);
+        assert!(gray.x > 0.0);
+        assert_eq!(trace_at(no_specular, &scene, 1.5), gray);
+
+        let no_diffuse = PathTracer {
+            diffuse_depth: Some(0),
+            ..tracer
+        };
+        assert_eq!(trace_at(no_diffuse, &scene, 1.5), Three::zeros());
+        assert_eq!(trace_at(no_diffuse, &scene, -1.5), Three::ones());
+    }
 }
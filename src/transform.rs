@@ -0,0 +1,500 @@
+//! Affine transforms for placing shapes.
+
+use crate::data::{Aabb, Ray, Three};
+use num_traits::Float;
+use std::ops::Mul;
+
+/// A 4x4 affine transform of points, directions and normals, with its inverse kept alongside.
+///
+/// Transforms combine like matrices: `a * b` applies `b` first, then `a`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform<F> {
+    /// Row major, acting on column vectors.
+    matrix: [[F; 4]; 4],
+    inverse: [[F; 4]; 4],
+}
+
+impl<F> Transform<F>
+where
+    F: Float,
+{
+    pub fn identity() -> Self {
+        Self {
+            matrix: identity(),
+            inverse: identity(),
+        }
+    }
+
+    pub fn translation(offset: Three<F>) -> Self {
+        let mut matrix = identity();
+        let mut inverse = identity();
+        for (i, d) in [offset.x, offset.y, offset.z].into_iter().enumerate() {
+            matrix[i][3] = d;
+            inverse[i][3] = -d;
+        }
+        Self { matrix, inverse }
+    }
+
+    /// Scales by `factors` along each axis, which can't be zero.
+    pub fn scaling(factors: Three<F>) -> Self {
+        let mut matrix = identity();
+        let mut inverse = identity();
+        for (i, s) in [factors.x, factors.y, factors.z].into_iter().enumerate() {
+            matrix[i][i] = s;
+            inverse[i][i] = s.recip();
+        }
+        Self { matrix, inverse }
+    }
+
+    /// Rotates by `degrees` around `axis` through the origin, the same way as `Three::rotate`,
+    /// which is clockwise looking down the axis.
+    pub fn rotation(axis: &Three<F>, degrees: F) -> Self {
+        let axis = axis.normalized();
+        let columns = [
+            Three::new(F::one(), F::zero(), F::zero()),
+            Three::new(F::zero(), F::one(), F::zero()),
+            Three::new(F::zero(), F::zero(), F::one()),
+        ]
+        .map(|basis| basis.rotate(&axis, degrees));
+        let mut matrix = identity();
+        for (j, column) in columns.iter().enumerate() {
+            matrix[0][j] = column.x;
+            matrix[1][j] = column.y;
+            matrix[2][j] = column.z;
+        }
+        // rotations are orthogonal, so the inverse is the transpose
+        let mut inverse = identity();
+        for (i, row) in inverse.iter_mut().enumerate().take(3) {
+            for (j, value) in row.iter_mut().enumerate().take(3) {
+                *value = matrix[j][i];
+            }
+        }
+        Self { matrix, inverse }
+    }
+
+    /// A transform from its matrix, given row by row, or `None` if it can't be inverted.
+    pub fn from_matrix(matrix: [[F; 4]; 4]) -> Option<Self> {
+        Some(Self {
+            matrix,
+            inverse: invert(&matrix)?,
+        })
+    }
+
+    /// From the space of a camera at `eye` looking at `target` to the world. The camera looks
+    /// along its z axis with y up and x to the right, which is left handed like pbrt and Mitsuba.
+    /// `None` if `up` is parallel to the view direction.
+    pub fn looking_at(eye: Three<F>, target: Three<F>, up: Three<F>) -> Option<Self> {
+        let forward = (target - eye).normalized();
+        let right = up.normalized().cross(&forward);
+        if right.length() == F::zero() || !right.length().is_finite() {
+            return None;
+        }
+        let right = right.normalized();
+        let up = forward.cross(&right);
+        let mut matrix = identity();
+        for (j, column) in [right, up, forward, eye].iter().enumerate() {
+            matrix[0][j] = column.x;
+            matrix[1][j] = column.y;
+            matrix[2][j] = column.z;
+        }
+        Self::from_matrix(matrix)
+    }
+
+    pub fn matrix(&self) -> &[[F; 4]; 4] {
+        &self.matrix
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    pub fn point(&self, p: &Three<F>) -> Three<F> {
+        let m = &self.matrix;
+        let x = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+        let y = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+        let z = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        Three::new(x / w, y / w, z / w)
+    }
+
+    /// Transforms a direction, which ignores the translation. The result isn't normalized.
+    pub fn vector(&self, v: &Three<F>) -> Three<F> {
+        let m = &self.matrix;
+        Three::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+
+    /// Transforms a surface normal, which needs the inverse transpose to stay perpendicular to
+    /// the surface. The result isn't normalized.
+    pub fn normal(&self, n: &Three<F>) -> Three<F> {
+        let m = &self.inverse;
+        Three::new(
+            m[0][0] * n.x + m[1][0] * n.y + m[2][0] * n.z,
+            m[0][1] * n.x + m[1][1] * n.y + m[2][1] * n.z,
+            m[0][2] * n.x + m[1][2] * n.y + m[2][2] * n.z,
+        )
+    }
+
+    /// Transforms a ray, keeping `t` along it the same, so its direction isn't normalized.
+    pub fn ray(&self, ray: &Ray<F>) -> Ray<F> {
+        Ray {
+            origin: self.point(&ray.origin),
+            direction: self.vector(&ray.direction),
+            time: ray.time,
+        }
+    }
+
+    /// A box around the transformed corners of `bounds`.
+    pub fn bounds(&self, bounds: &Aabb<F>) -> Aabb<F> {
+        let corners = [bounds.min, bounds.max];
+        if corners
+            .iter()
+            .any(|c| !(c.x.is_finite() && c.y.is_finite() && c.z.is_finite()))
+        {
+            return Aabb::infinite();
+        }
+        let points: Vec<Three<F>> = (0..8)
+            .map(|i| {
+                let corner = Three::new(
+                    corners[i & 1].x,
+                    corners[(i >> 1) & 1].y,
+                    corners[(i >> 2) & 1].z,
+                );
+                self.point(&corner)
+            })
+            .collect();
+        Aabb::around(&points)
+    }
+
+    /// The factor this transform scales every direction by, if it does so evenly, i.e. it only
+    /// rotates, mirrors and translates besides. Areas on shapes transformed otherwise grow
+    /// differently depending on which way the surface faces.
+    pub fn uniform_scale(&self) -> Option<F> {
+        let columns =
+            [0, 1, 2].map(|j| Three::new(self.matrix[0][j], self.matrix[1][j], self.matrix[2][j]));
+        let scale = columns[0].length();
+        let tolerance = F::from(1e-6f64).unwrap() * scale * scale;
+        let even = columns
+            .iter()
+            .all(|column| (column.dot(column) - scale * scale).abs() <= tolerance)
+            && (0..3).all(|i| columns[i].dot(&columns[(i + 1) % 3]).abs() <= tolerance);
+        let affine = self.matrix[3][0] == F::zero()
+            && self.matrix[3][1] == F::zero()
+            && self.matrix[3][2] == F::zero();
+        (even && affine).then_some(scale)
+    }
+
+    /// The same transform in another float type.
+    pub fn cast<G: Float>(&self) -> Transform<G> {
+        let cast = |m: &[[F; 4]; 4]| m.map(|row| row.map(|x| G::from(x).unwrap()));
+        Transform {
+            matrix: cast(&self.matrix),
+            inverse: cast(&self.inverse),
+        }
+    }
+
+    /// Determinant of the linear part, i.e. how much volumes grow. It's negative for transforms
+    /// that mirror.
+    pub fn determinant(&self) -> F {
+        let m = &self.matrix;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+}
+
+impl<F> Default for Transform<F>
+where
+    F: Float,
+{
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl<F> Mul for Transform<F>
+where
+    F: Float,
+{
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Self {
+            matrix: multiply(&self.matrix, &other.matrix),
+            inverse: multiply(&other.inverse, &self.inverse),
+        }
+    }
+}
+
+/// The transform of a `Motion` at one time: scaling, then rotating, then translating.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe<F> {
+    pub time: F,
+    scale: Three<F>,
+    /// Unit quaternion `(w, x, y, z)` of the rotation.
+    rotation: [F; 4],
+    translation: Three<F>,
+}
+
+impl<F> Keyframe<F>
+where
+    F: Float,
+{
+    /// A keyframe at `time` that doesn't change anything yet.
+    pub fn new(time: F) -> Self {
+        Self {
+            time,
+            scale: Three::from(F::one()),
+            rotation: [F::one(), F::zero(), F::zero(), F::zero()],
+            translation: Three::zeros(),
+        }
+    }
+
+    /// Scales by `factors` along each axis, which can't be zero.
+    pub fn with_scale(self, factors: Three<F>) -> Self {
+        Self {
+            scale: factors,
+            ..self
+        }
+    }
+
+    /// Rotates by `degrees` around `axis`, like `Transform::rotation`.
+    pub fn with_rotation(self, axis: &Three<F>, degrees: F) -> Self {
+        let half = degrees.to_radians() / F::from(2.0f64).unwrap();
+        let axis = axis.normalized() * half.sin();
+        Self {
+            rotation: [half.cos(), axis.x, axis.y, axis.z],
+            ..self
+        }
+    }
+
+    pub fn with_translation(self, offset: Three<F>) -> Self {
+        Self {
+            translation: offset,
+            ..self
+        }
+    }
+
+    pub fn transform(&self) -> Transform<F> {
+        let [w, x, y, z] = self.rotation;
+        let sin_half = (x * x + y * y + z * z).sqrt();
+        let rotation = if sin_half == F::zero() {
+            Transform::identity()
+        } else {
+            let degrees = (sin_half.atan2(w) * F::from(2.0f64).unwrap()).to_degrees();
+            Transform::rotation(&(Three::new(x, y, z) / sin_half), degrees)
+        };
+        Transform::translation(self.translation) * rotation * Transform::scaling(self.scale)
+    }
+
+    /// The keyframe `t` of the way to `other`, turning the shortest way around.
+    fn interpolate(&self, other: &Self, t: F) -> Self {
+        let lerp = |a: Three<F>, b: Three<F>| a + (b - a) * t;
+        let (q0, mut q1) = (self.rotation, other.rotation);
+        let mut cos_angle = (0..4).fold(F::zero(), |sum, i| sum + q0[i] * q1[i]);
+        if cos_angle < F::zero() {
+            // the same rotation, but on the near side of q0
+            q1 = q1.map(|c| -c);
+            cos_angle = -cos_angle;
+        }
+        let (w0, w1) = if cos_angle > F::from(0.9995f64).unwrap() {
+            // so close that slerp divides by almost zero, and lerp is as good
+            (F::one() - t, t)
+        } else {
+            let angle = cos_angle.acos();
+            (
+                ((F::one() - t) * angle).sin() / angle.sin(),
+                (t * angle).sin() / angle.sin(),
+            )
+        };
+        let q: [F; 4] = std::array::from_fn(|i| q0[i] * w0 + q1[i] * w1);
+        let length = q.iter().fold(F::zero(), |sum, &c| sum + c * c).sqrt();
+        Self {
+            time: self.time + (other.time - self.time) * t,
+            scale: lerp(self.scale, other.scale),
+            rotation: q.map(|c| c / length),
+            translation: lerp(self.translation, other.translation),
+        }
+    }
+}
+
+/// A transform that changes over time, for shapes that move while the camera's shutter is open.
+///
+/// Between keyframes, translations and scales change linearly, and rotations turn at a steady
+/// rate the shortest way around, so spins need a keyframe at least every half turn. Before the
+/// first keyframe and after the last, the transform stays put.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Motion<F> {
+    keyframes: Vec<Keyframe<F>>,
+}
+
+impl<F> Motion<F>
+where
+    F: Float,
+{
+    /// Panics if there are no `keyframes`. They don't have to be in order.
+    pub fn new(mut keyframes: Vec<Keyframe<F>>) -> Self {
+        assert!(!keyframes.is_empty(), "a motion needs keyframes");
+        keyframes.sort_by(|a, b| {
+            a.time
+                .partial_cmp(&b.time)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        Self { keyframes }
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe<F>] {
+        &self.keyframes
+    }
+
+    pub fn at(&self, time: F) -> Transform<F> {
+        let next = self.keyframes.partition_point(|key| key.time <= time);
+        if next == 0 {
+            return self.keyframes[0].transform();
+        }
+        let (a, b) = match self.keyframes.get(next) {
+            Some(b) => (&self.keyframes[next - 1], b),
+            None => return self.keyframes[next - 1].transform(),
+        };
+        a.interpolate(b, (time - a.time) / (b.time - a.time))
+            .transform()
+    }
+
+    /// A box around `bounds` everywhere the motion takes it.
+    pub fn bounds(&self, bounds: &Aabb<F>) -> Aabb<F> {
+        const STEPS: usize = 32;
+        let mut swept = self.keyframes[0].transform().bounds(bounds);
+        for pair in self.keyframes.windows(2) {
+            for step in 1..=STEPS {
+                let t = F::from(step).unwrap() / F::from(STEPS).unwrap();
+                let transform = pair[0].interpolate(&pair[1], t).transform();
+                swept = swept.union(&transform.bounds(bounds));
+            }
+        }
+        if !swept.is_finite() {
+            return Aabb::infinite();
+        }
+        // corners swing along arcs between the steps, which bulge out by at most this much
+        let bulge = F::from(1.0 - (std::f64::consts::PI / (2 * STEPS) as f64).cos()).unwrap();
+        let margin = Three::from(swept.diagonal().length() * bulge);
+        Aabb {
+            min: swept.min - margin,
+            max: swept.max + margin,
+        }
+    }
+}
+
+fn identity<F: Float>() -> [[F; 4]; 4] {
+    let mut m = [[F::zero(); 4]; 4];
+    for (i, row) in m.iter_mut().enumerate() {
+        row[i] = F::one();
+    }
+    m
+}
+
+fn multiply<F: Float>(a: &[[F; 4]; 4], b: &[[F; 4]; 4]) -> [[F; 4]; 4] {
+    let mut m = [[F::zero(); 4]; 4];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..4).fold(F::zero(), |sum, k| sum + a[i][k] * b[k][j]);
+        }
+    }
+    m
+}
+
+fn invert<F: Float>(matrix: &[[F; 4]; 4]) -> Option<[[F; 4]; 4]> {
+    // Gauss-Jordan elimination with partial pivoting
+    let mut a = *matrix;
+    let mut inv = identity();
+    for col in 0..4 {
+        let pivot = (col..4).max_by(|&i, &j| {
+            a[i][col]
+                .abs()
+                .partial_cmp(&a[j][col].abs())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })?;
+        if !a[pivot][col].is_normal() {
+            return None;
+        }
+        a.swap(col, pivot);
+        inv.swap(col, pivot);
+        let scale = a[col][col].recip();
+        for k in 0..4 {
+            a[col][k] = a[col][k] * scale;
+            inv[col][k] = inv[col][k] * scale;
+        }
+        for row in 0..4 {
+            if row != col {
+                let factor = a[row][col];
+                for k in 0..4 {
+                    a[row][k] = a[row][k] - factor * a[col][k];
+                    inv[row][k] = inv[row][k] - factor * inv[col][k];
+                }
+            }
+        }
+    }
+    Some(inv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: Three<f64>, b: Three<f64>) {
+        assert!((a - b).length() < 1e-9, "{:?} is not {:?}", a, b);
+    }
+
+    #[test]
+    fn uniform_scale_is_only_found_for_even_scales() {
+        let y = Three::new(0.0, 1.0, 0.0);
+        let even = Transform::translation(Three::new(1.0, 2.0, 3.0))
+            * Transform::rotation(&y, 30.0)
+            * Transform::scaling(Three::new(-2.0, 2.0, 2.0));
+        assert!((even.uniform_scale().unwrap() - 2.0).abs() < 1e-9);
+        let squashed =
+            Transform::rotation(&y, 30.0) * Transform::scaling(Three::new(1.0, 0.5, 1.0));
+        assert_eq!(squashed.uniform_scale(), None);
+    }
+
+    #[test]
+    fn looking_at_maps_the_camera_axes() {
+        let eye = Three::new(0.0, 0.0, 5.0);
+        let transform =
+            Transform::looking_at(eye, Three::zeros(), Three::new(0.0, 2.0, 0.0)).unwrap();
+        assert_near(transform.point(&Three::zeros()), eye);
+        assert_near(
+            transform.vector(&Three::new(0.0, 0.0, 1.0)),
+            Three::new(0.0, 0.0, -1.0),
+        );
+        assert_near(
+            transform.vector(&Three::new(0.0, 1.0, 0.0)),
+            Three::new(0.0, 1.0, 0.0),
+        );
+        // left handed, so x ends up to the left looking along -z
+        assert_near(
+            transform.vector(&Three::new(1.0, 0.0, 0.0)),
+            Three::new(-1.0, 0.0, 0.0),
+        );
+        assert_eq!(
+            Transform::looking_at(eye, Three::zeros(), Three::new(0.0, 0.0, 1.0)),
+            None
+        );
+    }
+
+    #[test]
+    fn cast_keeps_the_inverse() {
+        let transform = Transform::rotation(&Three::new(1.0, 1.0, 0.0), 70.0)
+            * Transform::scaling(Three::new(1.0, 2.0, 3.0));
+        let cast: Transform<f32> = transform.cast();
+        let p = cast
+            .inverse()
+            .point(&cast.point(&Three::new(1.0, 2.0, 3.0)));
+        assert!((p - Three::new(1.0, 2.0, 3.0)).length() < 1e-5);
+    }
+}
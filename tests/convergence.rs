@@ -0,0 +1,186 @@
+//! Checks that every estimator converges to the same image as the path tracer, on a white
+//! furnace: a gray sphere inside a sphere that glows evenly all around it. Every point of the
+//! gray sphere sees the glow in all directions, so it reflects its albedo times the glow.
+
+use image::RgbImage;
+use rand_xorshift::XorShiftRng;
+use teeracer::*;
+
+const GLOW: f64 = 0.5;
+const ALBEDO: f64 = 0.5;
+const SIZE: usize = 16;
+
+fn furnace() -> (Scene<f64>, Camera<f64>) {
+    let mut scene = Scene::new();
+    let gray = scene.add_material(Diffuse::rgb(ALBEDO, ALBEDO, ALBEDO));
+    let glow = scene.add_material(Light::new(Three::new(GLOW, GLOW, GLOW), 1.0));
+    scene.add_object(Sphere::unit_at(0.0, 0.0, 0.0), gray);
+    scene.add_object(Sphere::new(Three::new(0.0, 0.0, 0.0), 10.0), glow);
+    let camera = Camera::new(
+        FieldOfView::Degrees(30.0),
+        ImageShape {
+            width: SIZE,
+            height: SIZE,
+        },
+    )
+    .at(0.0, 0.0, 4.0);
+    (scene, camera)
+}
+
+fn tracer() -> PathTracer {
+    PathTracer {
+        depth: 4,
+        ..Default::default()
+    }
+}
+
+/// Mean of all channels over the pixels from `from` up to `to` along both axes, from 0 to 1.
+fn mean(image: &RgbImage, from: u32, to: u32) -> f64 {
+    let mut total = 0.0;
+    for x in from..to {
+        for y in from..to {
+            total += image
+                .get_pixel(x, y)
+                .0
+                .iter()
+                .map(|&c| c as f64)
+                .sum::<f64>();
+        }
+    }
+    total / (3.0 * 255.0 * ((to - from) * (to - from)) as f64)
+}
+
+/// Checks the middle of the image shows the gray sphere and its corner shows the glow.
+fn assert_furnace(image: &RgbImage, tolerance: f64) {
+    let middle = mean(image, SIZE as u32 / 2 - 2, SIZE as u32 / 2 + 2);
+    let corner = mean(image, 0, 2);
+    assert!(
+        (middle - ALBEDO * GLOW).abs() < tolerance,
+        "sphere is {} instead of {}",
+        middle,
+        ALBEDO * GLOW
+    );
+    assert!(
+        (corner - GLOW).abs() < tolerance,
+        "glow is {} instead of {}",
+        corner,
+        GLOW
+    );
+}
+
+#[test]
+fn path_tracer() {
+    let (scene, camera) = furnace();
+    let image = render::<_, f64, XorShiftRng>(tracer(), scene, camera, 64);
+    assert_furnace(&image, 0.01);
+}
+
+#[test]
+fn path_tracer_with_russian_roulette() {
+    // roulette from the first bounce on ends half of the paths off the gray sphere
+    let tracer = PathTracer {
+        roulette_depth: Some(1),
+        ..tracer()
+    };
+    let (scene, camera) = furnace();
+    let image = render::<_, f64, XorShiftRng>(tracer, scene, camera, 64);
+    assert_furnace(&image, 0.01);
+}
+
+#[test]
+fn bidirectional_path_tracer() {
+    let (scene, camera) = furnace();
+    let image =
+        render::<_, f64, XorShiftRng>(BidirectionalPathTracer { depth: 4 }, scene, camera, 64);
+    assert_furnace(&image, 0.03);
+}
+
+#[test]
+fn photon_mapper() {
+    let (mut scene, camera) = furnace();
+    let tracer = PhotonMapper::build::<XorShiftRng>(&mut scene, &camera, 4, 200_000, 0.2);
+    let image = render::<_, f64, XorShiftRng>(tracer, scene, camera, 16);
+    assert_furnace(&image, 0.03);
+}
+
+#[test]
+fn stochastic_progressive_photon_mapper() {
+    let (scene, camera) = furnace();
+    let tracer = StochasticProgressivePhotonMapper::new(4, 16, 20_000, 0.5);
+    let image = tracer.render::<XorShiftRng>(scene, camera);
+    assert_furnace(&image, 0.03);
+}
+
+#[test]
+fn metropolis_light_transport() {
+    let (scene, camera) = furnace();
+    let mut mlt = MetropolisLightTransport::new(tracer(), 256);
+    mlt.num_bootstrap = 10_000;
+    mlt.num_chains = 64;
+    let image = mlt.render::<f64, XorShiftRng>(scene, camera);
+    assert_furnace(&image, 0.05);
+}
+
+#[test]
+fn metropolis_light_transport_with_more_chains_than_mutations() {
+    let (scene, camera) = furnace();
+    let mlt = MetropolisLightTransport::new(tracer(), 1);
+    let image = mlt.render::<f64, XorShiftRng>(scene, camera);
+    assert!(mean(&image, 0, SIZE as u32) > 0.0);
+}
+
+/// The same furnace with the glow coming from the environment instead of a sphere, which
+/// tracers sample directly as well as finding it when paths escape.
+fn environment_furnace() -> (Scene<f64>, Camera<f64>) {
+    let (_, camera) = furnace();
+    let mut scene = Scene::new();
+    let gray = scene.add_material(Diffuse::rgb(ALBEDO, ALBEDO, ALBEDO));
+    scene.add_object(Sphere::unit_at(0.0, 0.0, 0.0), gray);
+    scene.set_environment(Environment::new(Texture::Constant(Three::new(
+        GLOW, GLOW, GLOW,
+    ))));
+    (scene, camera)
+}
+
+#[test]
+fn path_tracer_under_environment() {
+    let (scene, camera) = environment_furnace();
+    let image = render::<_, f64, XorShiftRng>(tracer(), scene, camera, 64);
+    assert_furnace(&image, 0.01);
+}
+
+#[test]
+fn bidirectional_path_tracer_under_environment() {
+    let (scene, camera) = environment_furnace();
+    let image =
+        render::<_, f64, XorShiftRng>(BidirectionalPathTracer { depth: 4 }, scene, camera, 64);
+    assert_furnace(&image, 0.03);
+}
+
+/// The gray sphere is convex, so all the light on it comes straight from the environment, which
+/// photon mappers find without photons.
+#[test]
+fn photon_mapper_under_environment() {
+    let (mut scene, camera) = environment_furnace();
+    let tracer = PhotonMapper::build::<XorShiftRng>(&mut scene, &camera, 4, 1000, 0.2);
+    let image = render::<_, f64, XorShiftRng>(tracer, scene, camera, 16);
+    assert_furnace(&image, 0.03);
+}
+
+#[test]
+fn stochastic_progressive_photon_mapper_under_environment() {
+    let (scene, camera) = environment_furnace();
+    let tracer = StochasticProgressivePhotonMapper::new(4, 16, 1000, 0.5);
+    let image = tracer.render::<XorShiftRng>(scene, camera);
+    assert_furnace(&image, 0.03);
+}
+
+#[test]
+fn metropolis_light_transport_over_bidirectional_under_environment() {
+    let (scene, camera) = environment_furnace();
+    let mut mlt = MetropolisLightTransport::new(BidirectionalPathTracer { depth: 4 }, 256);
+    mlt.num_bootstrap = 10_000;
+    mlt.num_chains = 64;
+    let image = mlt.render::<f64, XorShiftRng>(scene, camera);
+    assert_furnace(&image, 0.05);
+}